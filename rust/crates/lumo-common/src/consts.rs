@@ -2,7 +2,7 @@ use bitcoin::Amount;
 use once_cell::sync::Lazy;
 use std::path::PathBuf;
 
-/// Static data directories - computed once at startup
+/// Static data directories - computed on first use, see `configure_data_dir`
 pub static ROOT_DATA_DIR: Lazy<PathBuf> = Lazy::new(data_dir_init);
 pub static WALLET_DATA_DIR: Lazy<PathBuf> = Lazy::new(wallet_data_dir_init);
 
@@ -16,21 +16,16 @@ pub static DUST_LIMIT_SATS: u64 = 546;
 pub static DUST_LIMIT_AMOUNT: Amount = Amount::from_sat(DUST_LIMIT_SATS);
 
 fn data_dir_init() -> PathBuf {
-    let dir = dirs::data_dir()
-        .expect("failed to get data directory")
-        .join("lumo");
-
-    init_dir(dir).expect("failed to initialize data directory")
+    crate::data_dir::root_data_dir()
 }
 
 fn wallet_data_dir_init() -> PathBuf {
     let dir = ROOT_DATA_DIR.join("wallets");
-    init_dir(dir).expect("failed to initialize wallet data directory")
-}
-
-fn init_dir(dir: PathBuf) -> Result<PathBuf, std::io::Error> {
-    if !dir.exists() {
-        std::fs::create_dir_all(&dir)?;
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::error!(
+            "Failed to create wallet data directory {}: {e}",
+            dir.display()
+        );
     }
-    Ok(dir)
+    dir
 }
@@ -0,0 +1,277 @@
+use once_cell::sync::{Lazy, OnceCell};
+use std::path::PathBuf;
+
+use crate::consts::ROOT_DATA_DIR;
+use crate::error::{LumoError, Result};
+
+/// Environment variable overriding the data directory
+pub const DATA_DIR_ENV: &str = "LUMO_DATA_DIR";
+
+/// Environment variable selecting a profile
+pub const PROFILE_ENV: &str = "LUMO_PROFILE";
+
+static CONFIGURED_DATA_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Where lumo keeps its databases
+///
+/// Each named profile gets its own directory under `<data dir>/profiles/`, the
+/// default profile uses the data directory itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DataDirOptions {
+    /// Overrides `LUMO_DATA_DIR` and the platform data directory
+    pub data_dir: Option<PathBuf>,
+    /// Overrides `LUMO_PROFILE`
+    pub profile: Option<String>,
+}
+
+impl DataDirOptions {
+    /// The directory these options point to, without creating it
+    pub fn resolve(&self) -> Result<PathBuf> {
+        let base = self
+            .data_dir
+            .clone()
+            .or_else(|| env_var(DATA_DIR_ENV).map(PathBuf::from))
+            .or_else(default_data_dir)
+            .ok_or_else(|| {
+                LumoError::InvalidConfig(format!(
+                    "Unable to find a data directory, set {DATA_DIR_ENV} or pass --data-dir"
+                ))
+            })?;
+
+        let profile = self
+            .profile
+            .clone()
+            .or_else(|| env_var(PROFILE_ENV))
+            .filter(|profile| profile != "default");
+
+        match profile {
+            Some(profile) => {
+                validate_profile(&profile)?;
+                Ok(base.join("profiles").join(profile))
+            }
+            None => Ok(base),
+        }
+    }
+}
+
+/// Choose the data directory, must be called before anything touches `ROOT_DATA_DIR`
+///
+/// Returns the resolved directory, which is created if needed. Configuring the
+/// directory that's already in use again is allowed, any other is an error.
+pub fn configure_data_dir(options: DataDirOptions) -> Result<PathBuf> {
+    let dir = options.resolve()?;
+    if let Some(in_use) = Lazy::get(&ROOT_DATA_DIR).or(CONFIGURED_DATA_DIR.get()) {
+        if *in_use == dir {
+            return Ok(dir);
+        }
+        return Err(LumoError::InvalidConfig(
+            "The data directory is already in use and can't be changed".to_string(),
+        ));
+    }
+
+    std::fs::create_dir_all(&dir)?;
+    CONFIGURED_DATA_DIR.set(dir.clone()).map_err(|_| {
+        LumoError::InvalidConfig("The data directory was already configured".to_string())
+    })?;
+
+    Ok(dir)
+}
+
+/// Profiles that already have a directory under `data_dir`
+pub fn list_profiles(data_dir: &std::path::Path) -> Result<Vec<String>> {
+    let profiles_dir = data_dir.join("profiles");
+    if !profiles_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut profiles = Vec::new();
+    for entry in std::fs::read_dir(profiles_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            profiles.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    profiles.sort();
+    Ok(profiles)
+}
+
+/// Resolve `ROOT_DATA_DIR` on first use
+///
+/// `configure_data_dir` (through `lumo::init_with_options`) resolves and creates
+/// the directory up front and reports any problem as an error. Only code that
+/// skips it falls back to the default options here, and panics if those don't
+/// point anywhere rather than writing to an unexpected directory.
+pub(crate) fn root_data_dir() -> PathBuf {
+    if let Some(dir) = CONFIGURED_DATA_DIR.get() {
+        return dir.clone();
+    }
+
+    #[cfg(feature = "test-dirs")]
+    return test_root_dir();
+
+    #[cfg(not(feature = "test-dirs"))]
+    {
+        let dir = DataDirOptions::default()
+            .resolve()
+            .unwrap_or_else(|e| panic!("No data directory, call lumo::init first: {e}"));
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            tracing::error!("Failed to create data directory {}: {e}", dir.display());
+        }
+        dir
+    }
+}
+
+/// Data directory of the process wide database and wallet stores in tests
+///
+/// Statics are never dropped, so each run removes the directories left by runs
+/// that ended over a day ago. Tests that don't need those globals use a
+/// [`TestDir`] of their own instead.
+#[cfg(feature = "test-dirs")]
+fn test_root_dir() -> PathBuf {
+    remove_stale_test_dirs();
+    let dir = unique_temp_dir("run");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::error!("Failed to create test directory {}: {e}", dir.display());
+    }
+    dir
+}
+
+/// A new empty temporary directory for one test, removed when dropped
+#[cfg(feature = "test-dirs")]
+#[derive(Debug)]
+pub struct TestDir {
+    path: PathBuf,
+}
+
+#[cfg(feature = "test-dirs")]
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path = unique_temp_dir(name);
+        std::fs::create_dir_all(&path).expect("failed to create test directory");
+        Self { path }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    pub fn join(&self, path: impl AsRef<std::path::Path>) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+#[cfg(feature = "test-dirs")]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(feature = "test-dirs")]
+const TEST_DIR_PREFIX: &str = "lumo-test-";
+
+#[cfg(feature = "test-dirs")]
+fn unique_temp_dir(name: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    std::env::temp_dir().join(format!(
+        "{TEST_DIR_PREFIX}{name}-{}-{nanos}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+#[cfg(feature = "test-dirs")]
+fn remove_stale_test_dirs() {
+    const STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(TEST_DIR_PREFIX)
+            && entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > STALE_AFTER);
+        if stale {
+            let _ = std::fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+fn default_data_dir() -> Option<PathBuf> {
+    dirs::data_dir()
+        .map(|dir| dir.join("lumo"))
+        .or_else(|| dirs::home_dir().map(|dir| dir.join(".lumo")))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+// Profile names become directory names
+fn validate_profile(profile: &str) -> Result<()> {
+    let valid = !profile.is_empty()
+        && profile.len() <= 64
+        && profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(LumoError::InvalidConfig(format!(
+            "Invalid profile name: {profile}. Use letters, digits, '-' and '_'"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_data_dir() {
+        let options = DataDirOptions {
+            data_dir: Some(PathBuf::from("/srv/lumo")),
+            profile: None,
+        };
+        assert_eq!(options.resolve().unwrap(), PathBuf::from("/srv/lumo"));
+
+        let work = DataDirOptions {
+            profile: Some("work".to_string()),
+            ..options.clone()
+        };
+        assert_eq!(
+            work.resolve().unwrap(),
+            PathBuf::from("/srv/lumo/profiles/work")
+        );
+
+        let default = DataDirOptions {
+            profile: Some("default".to_string()),
+            ..options
+        };
+        assert_eq!(default.resolve().unwrap(), PathBuf::from("/srv/lumo"));
+    }
+
+    #[test]
+    fn test_invalid_profile_names() {
+        for profile in ["../personal", "work/old", ""] {
+            assert!(validate_profile(profile).is_err(), "{profile}");
+        }
+        assert!(validate_profile("personal_2").is_ok());
+    }
+}
@@ -1,7 +1,9 @@
 pub mod consts;
+pub mod data_dir;
 pub mod error;
 pub mod logging;
 
 pub use consts::*;
+pub use data_dir::{configure_data_dir, DataDirOptions};
 pub use error::{LumoError, Result};
 pub use logging::setup_logging;
@@ -1,3 +1,4 @@
+use crate::bip21::{Bip21Error, Bip21Uri};
 use crate::{Amount, Network};
 use bdk_wallet::chain::bitcoin::Address as BdkAddress;
 use bitcoin::address::{NetworkChecked, NetworkUnchecked};
@@ -42,6 +43,9 @@ pub enum AddressError {
 
     #[error("Invalid amount in BIP21 URI: {0}")]
     InvalidAmount(String),
+
+    #[error("Invalid BIP21 URI: {0}")]
+    InvalidUri(String),
 }
 
 impl Address {
@@ -72,21 +76,13 @@ impl Address {
         }
 
         // Check what network it's actually valid for
-        for test_network in [
-            Network::Mainnet,
-            Network::Testnet,
-            Network::Signet,
-            Network::Regtest,
-        ] {
-            if unchecked.is_valid_for_network(test_network.to_bitcoin_network()) {
-                return Err(AddressError::WrongNetwork {
-                    expected: network,
-                    actual: test_network,
-                });
-            }
+        match detect_network(&unchecked) {
+            Some(actual) => Err(AddressError::WrongNetwork {
+                expected: network,
+                actual,
+            }),
+            None => Err(AddressError::UnsupportedNetwork),
         }
-
-        Err(AddressError::UnsupportedNetwork)
     }
 
     /// Get the address as a string
@@ -117,39 +113,36 @@ impl AddressWithNetwork {
     pub fn from_string(input: &str) -> Result<Self, AddressError> {
         let input = input.trim();
 
-        // Handle bitcoin: URI prefix
-        let input = input.strip_prefix("bitcoin:").unwrap_or(input);
-
-        // Extract address and amount from BIP21 URI
-        let (address_str, amount) = extract_amount_from_uri(input)?;
-
-        // Parse as unchecked to detect network
-        let unchecked: BdkAddress<NetworkUnchecked> = address_str
-            .parse()
-            .map_err(|_| AddressError::InvalidFormat)?;
+        if Bip21Uri::is_uri(input) {
+            let uri: Bip21Uri = input.parse().map_err(|err| match err {
+                Bip21Error::Address(err) => err,
+                Bip21Error::InvalidAmount(amount) => AddressError::InvalidAmount(amount),
+                err => AddressError::InvalidUri(err.to_string()),
+            })?;
+            return Ok(Self {
+                network: uri.network(),
+                address: uri.address,
+                amount: uri.amount,
+            });
+        }
 
-        // Try each network to find the correct one
-        for network in [
-            Network::Mainnet,
-            Network::Testnet,
-            Network::Signet,
-            Network::Regtest,
-        ] {
-            if unchecked.is_valid_for_network(network.to_bitcoin_network()) {
-                let checked = unchecked
-                    .clone()
-                    .require_network(network.to_bitcoin_network())
-                    .expect("just validated");
-
-                return Ok(Self {
-                    address: Address::new(checked),
-                    network,
-                    amount,
-                });
-            }
+        if input.is_empty() {
+            return Err(AddressError::EmptyAddress);
         }
 
-        Err(AddressError::UnsupportedNetwork)
+        // Parse as unchecked to detect network
+        let unchecked: BdkAddress<NetworkUnchecked> =
+            input.parse().map_err(|_| AddressError::InvalidFormat)?;
+        let network = detect_network(&unchecked).ok_or(AddressError::UnsupportedNetwork)?;
+        let checked = unchecked
+            .require_network(network.to_bitcoin_network())
+            .expect("just validated");
+
+        Ok(Self {
+            address: Address::new(checked),
+            network,
+            amount: None,
+        })
     }
 
     /// Check if address is valid for given network
@@ -161,33 +154,16 @@ impl AddressWithNetwork {
     }
 }
 
-/// Extract amount from BIP21 URI (bitcoin:address?amount=0.001)
-fn extract_amount_from_uri(uri: &str) -> Result<(&str, Option<Amount>), AddressError> {
-    // Find the ?amount= part
-    let Some(amount_pos) = uri.find("?amount=") else {
-        return Ok((uri, None));
-    };
-
-    let address_part = &uri[..amount_pos];
-    let amount_start = amount_pos + 8; // Skip "?amount="
-
-    // Find the end of the amount (next & or end of string)
-    let amount_end = uri[amount_start..]
-        .find('&')
-        .map(|pos| amount_start + pos)
-        .unwrap_or(uri.len());
-
-    let amount_str = &uri[amount_start..amount_end];
-
-    // Parse the amount
-    let amount_btc: f64 = amount_str
-        .parse()
-        .map_err(|_| AddressError::InvalidAmount(amount_str.to_string()))?;
-
-    let amount = Amount::from_btc(amount_btc)
-        .map_err(|_| AddressError::InvalidAmount(amount_str.to_string()))?;
-
-    Ok((address_part, Some(amount)))
+/// First network an address is valid for, mainnet, testnet, signet then regtest
+pub(crate) fn detect_network(unchecked: &BdkAddress<NetworkUnchecked>) -> Option<Network> {
+    [
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+    ]
+    .into_iter()
+    .find(|network| unchecked.is_valid_for_network(network.to_bitcoin_network()))
 }
 
 /// Validate address string for given network
@@ -258,6 +234,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bip21_uri_errors() {
+        let address = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        assert!(matches!(
+            AddressWithNetwork::from_string(&format!("bitcoin:{address}?amount=0.1.2")),
+            Err(AddressError::InvalidAmount(_))
+        ));
+        assert!(matches!(
+            AddressWithNetwork::from_string(&format!("bitcoin:{address}?req-foo=1")),
+            Err(AddressError::InvalidUri(_))
+        ));
+    }
+
     #[test]
     fn test_validate_address() {
         let addr_str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
@@ -0,0 +1,397 @@
+//! BIP21 `bitcoin:` payment URIs
+//!
+//! Parsing is strict: amounts are exact decimal BTC, values are percent-decoded
+//! as UTF-8, and a `req-` parameter this type doesn't understand makes the whole
+//! URI invalid, as BIP21 requires. Optional parameters it doesn't know are kept,
+//! so a URI survives a parse and print round trip.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bdk_wallet::chain::bitcoin::Address as BdkAddress;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::Denomination;
+use serde::{Deserialize, Serialize};
+
+use crate::address::detect_network;
+use crate::{Address, AddressError, Amount, Network};
+
+const SCHEME: &str = "bitcoin:";
+
+/// Prefix marking a parameter the payer must understand to pay
+const REQUIRED_PREFIX: &str = "req-";
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Bip21Error {
+    #[error("Not a bitcoin: URI")]
+    MissingScheme,
+
+    #[error(transparent)]
+    Address(#[from] AddressError),
+
+    #[error("Invalid amount: {0}")]
+    InvalidAmount(String),
+
+    #[error("Invalid percent-encoding in {0}")]
+    InvalidEncoding(String),
+
+    #[error("Parameter given twice: {0}")]
+    DuplicateParameter(String),
+
+    #[error("Unsupported required parameter: {0}")]
+    UnknownRequired(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip21Uri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    /// Name of the recipient
+    pub label: Option<String>,
+    /// What the payment is for
+    pub message: Option<String>,
+    /// BOLT11 invoice to pay over lightning instead
+    pub lightning: Option<String>,
+    /// BIP78 payjoin endpoint
+    pub payjoin: Option<String>,
+    /// `pjos=0`, the receiver may not change the sender's outputs
+    pub payjoin_output_substitution_disabled: bool,
+    /// Optional parameters not listed above, in the order they appeared
+    pub extras: Vec<(String, String)>,
+}
+
+impl Bip21Uri {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+            lightning: None,
+            payjoin: None,
+            payjoin_output_substitution_disabled: false,
+            extras: Vec::new(),
+        }
+    }
+
+    pub fn with_amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    pub fn with_payjoin(mut self, endpoint: impl Into<String>) -> Self {
+        self.payjoin = Some(endpoint.into());
+        self
+    }
+
+    /// Network of the address, mainnet first, then testnet, signet and regtest
+    pub fn network(&self) -> Network {
+        let unchecked = self.address.clone().into_unchecked();
+        detect_network(&unchecked).unwrap_or(Network::Mainnet)
+    }
+
+    /// Whether the address can be paid from `network`
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        self.address
+            .clone()
+            .into_unchecked()
+            .is_valid_for_network(network.to_bitcoin_network())
+    }
+
+    /// Whether `input` looks like a URI rather than a bare address
+    pub fn is_uri(input: &str) -> bool {
+        let input = input.trim();
+        input.len() >= SCHEME.len() && input[..SCHEME.len()].eq_ignore_ascii_case(SCHEME)
+    }
+}
+
+impl FromStr for Bip21Uri {
+    type Err = Bip21Error;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let uri = uri.trim();
+        if !Self::is_uri(uri) {
+            return Err(Bip21Error::MissingScheme);
+        }
+        let rest = &uri[SCHEME.len()..];
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let address = address.trim();
+        if address.is_empty() {
+            return Err(AddressError::EmptyAddress.into());
+        }
+        let unchecked: BdkAddress<NetworkUnchecked> =
+            address.parse().map_err(|_| AddressError::InvalidFormat)?;
+        let network = detect_network(&unchecked).ok_or(AddressError::UnsupportedNetwork)?;
+        let checked = unchecked
+            .require_network(network.to_bitcoin_network())
+            .map_err(|_| AddressError::UnsupportedNetwork)?;
+
+        let mut parsed = Self::new(Address::new(checked));
+        let mut seen = Vec::new();
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = percent_decode(key)?;
+            let value = percent_decode(value)?;
+
+            let lowercase = key.to_ascii_lowercase();
+            let known = matches!(
+                lowercase.as_str(),
+                "amount" | "label" | "message" | "lightning" | "pj" | "pjos"
+            );
+            if known {
+                if seen.contains(&lowercase) {
+                    return Err(Bip21Error::DuplicateParameter(key));
+                }
+                seen.push(lowercase.clone());
+            }
+
+            match lowercase.as_str() {
+                "amount" => parsed.amount = Some(parse_amount(&value)?),
+                "label" => parsed.label = Some(value),
+                "message" => parsed.message = Some(value),
+                "lightning" => parsed.lightning = Some(value),
+                "pj" => parsed.payjoin = Some(value),
+                "pjos" => parsed.payjoin_output_substitution_disabled = value == "0",
+                _ if lowercase.starts_with(REQUIRED_PREFIX) => {
+                    return Err(Bip21Error::UnknownRequired(key))
+                }
+                _ => parsed.extras.push((key, value)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Bip21Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{SCHEME}{}", self.address)?;
+
+        let mut params = Vec::new();
+        if let Some(amount) = self.amount {
+            params.push(("amount", amount.0.to_string_in(Denomination::Bitcoin)));
+        }
+        let optional = [
+            ("label", &self.label),
+            ("message", &self.message),
+            ("lightning", &self.lightning),
+            ("pj", &self.payjoin),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                params.push((key, percent_encode(value)));
+            }
+        }
+        if self.payjoin_output_substitution_disabled {
+            params.push(("pjos", "0".to_string()));
+        }
+
+        let extras = self
+            .extras
+            .iter()
+            .map(|(key, value)| (percent_encode(key), percent_encode(value)));
+        let params = params
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .chain(extras);
+
+        for (i, (key, value)) in params.enumerate() {
+            let separator = if i == 0 { '?' } else { '&' };
+            write!(f, "{separator}{key}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Bip21Uri {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bip21Uri {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uri = String::deserialize(deserializer)?;
+        uri.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Exact decimal BTC, at most 8 decimal places and no more than 21M
+fn parse_amount(value: &str) -> Result<Amount, Bip21Error> {
+    let amount = bitcoin::Amount::from_str_in(value, Denomination::Bitcoin)
+        .map_err(|_| Bip21Error::InvalidAmount(value.to_string()))?;
+    if amount > bitcoin::Amount::MAX_MONEY {
+        return Err(Bip21Error::InvalidAmount(value.to_string()));
+    }
+    Ok(Amount::from(amount))
+}
+
+fn percent_decode(value: &str) -> Result<String, Bip21Error> {
+    let invalid = || Bip21Error::InvalidEncoding(value.to_string());
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// Encode all but characters that can stand in a query value unescaped
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b':'
+            | b'/'
+            | b'@'
+            | b'!'
+            | b'$'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b','
+            | b';' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    #[test]
+    fn test_parse_all_parameters() {
+        let uri: Bip21Uri = format!(
+            "BITCOIN:{ADDRESS}?amount=20.3&label=Luke-Jr&message=Donation%20for%20project%20xyz\
+             &lightning=lnbc1&pj=https://example.com/pj&pjos=0&somethingyoudontunderstand=50"
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(uri.address.to_string(), ADDRESS);
+        assert_eq!(uri.network(), Network::Mainnet);
+        assert_eq!(uri.amount, Some(Amount::from_sat(2_030_000_000)));
+        assert_eq!(uri.label.as_deref(), Some("Luke-Jr"));
+        assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(uri.lightning.as_deref(), Some("lnbc1"));
+        assert_eq!(uri.payjoin.as_deref(), Some("https://example.com/pj"));
+        assert!(uri.payjoin_output_substitution_disabled);
+        assert_eq!(
+            uri.extras,
+            vec![("somethingyoudontunderstand".to_string(), "50".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_amounts_are_exact() {
+        let amount = |value: &str| format!("bitcoin:{ADDRESS}?amount={value}").parse::<Bip21Uri>();
+
+        assert_eq!(
+            amount("0.00000001").unwrap().amount,
+            Some(Amount::from_sat(1))
+        );
+        // 0.1 + 0.2 would be off as an f64
+        assert_eq!(
+            amount("0.3").unwrap().amount,
+            Some(Amount::from_sat(30_000_000))
+        );
+        assert!(matches!(
+            amount("0.000000001"),
+            Err(Bip21Error::InvalidAmount(_))
+        ));
+        assert!(amount("-1").is_err());
+        assert!(amount("1e-3").is_err());
+        assert!(amount("21000001").is_err());
+        assert!(amount("").is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_uris() {
+        assert_eq!(
+            ADDRESS.parse::<Bip21Uri>().unwrap_err(),
+            Bip21Error::MissingScheme
+        );
+        assert_eq!(
+            format!("bitcoin:{ADDRESS}?req-somethingyoudontunderstand=50")
+                .parse::<Bip21Uri>()
+                .unwrap_err(),
+            Bip21Error::UnknownRequired("req-somethingyoudontunderstand".to_string())
+        );
+        assert!(matches!(
+            format!("bitcoin:{ADDRESS}?amount=1&amount=2").parse::<Bip21Uri>(),
+            Err(Bip21Error::DuplicateParameter(_))
+        ));
+        assert!(matches!(
+            format!("bitcoin:{ADDRESS}?label=%zz").parse::<Bip21Uri>(),
+            Err(Bip21Error::InvalidEncoding(_))
+        ));
+        assert!(matches!(
+            "bitcoin:notanaddress".parse::<Bip21Uri>(),
+            Err(Bip21Error::Address(AddressError::InvalidFormat))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let address = Address::from_string(ADDRESS, Network::Mainnet).unwrap();
+        let uri = Bip21Uri::new(address)
+            .with_amount(Amount::from_sat(100_000))
+            .with_label("Café & Bar")
+            .with_message("Order #42");
+
+        let encoded = uri.to_string();
+        assert_eq!(
+            encoded,
+            format!(
+                "bitcoin:{ADDRESS}?amount=0.001&label=Caf%C3%A9%20%26%20Bar&message=Order%20%2342"
+            )
+        );
+        assert_eq!(encoded.parse::<Bip21Uri>().unwrap(), uri);
+
+        let with_extras: Bip21Uri =
+            format!("bitcoin:{ADDRESS}?foo=a%26b&pj=https://x.example/p?v=1")
+                .parse()
+                .unwrap();
+        assert_eq!(
+            with_extras.to_string().parse::<Bip21Uri>().unwrap(),
+            with_extras
+        );
+
+        let json = serde_json::to_string(&uri).unwrap();
+        assert_eq!(serde_json::from_str::<Bip21Uri>(&json).unwrap(), uri);
+    }
+}
@@ -17,9 +17,9 @@ impl FeeRate {
     }
 }
 
-impl Into<bitcoin::FeeRate> for FeeRate {
-    fn into(self) -> bitcoin::FeeRate {
-        self.0
+impl From<FeeRate> for bitcoin::FeeRate {
+    fn from(fee_rate: FeeRate) -> Self {
+        fee_rate.0
     }
 }
 
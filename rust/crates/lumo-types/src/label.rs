@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// What a label refers to (BIP329)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+impl LabelType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LabelType::Tx => "tx",
+            LabelType::Addr => "addr",
+            LabelType::Pubkey => "pubkey",
+            LabelType::Input => "input",
+            LabelType::Output => "output",
+            LabelType::Xpub => "xpub",
+        }
+    }
+}
+
+impl std::fmt::Display for LabelType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for LabelType {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tx" => Ok(LabelType::Tx),
+            "addr" => Ok(LabelType::Addr),
+            "pubkey" => Ok(LabelType::Pubkey),
+            "input" => Ok(LabelType::Input),
+            "output" => Ok(LabelType::Output),
+            "xpub" => Ok(LabelType::Xpub),
+            _ => Err(eyre::eyre!(
+                "Invalid label type: {s}. Valid options: tx, addr, pubkey, input, output, xpub"
+            )),
+        }
+    }
+}
+
+/// A BIP329 label record, one JSON object per line in an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    /// Txid, address, pubkey, outpoint (`txid:vout`) or xpub, depending on the type
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub label: String,
+    /// Key origin of the wallet the label came from, e.g. `wpkh([d34db33f/84'/0'/0'])`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Only meaningful for outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    pub fn new(
+        label_type: LabelType,
+        reference: impl Into<String>,
+        label: impl Into<String>,
+    ) -> Self {
+        Self {
+            label_type,
+            reference: reference.into(),
+            label: label.into(),
+            origin: None,
+            spendable: None,
+        }
+    }
+
+    /// Write labels in the BIP329 JSON lines format
+    pub fn to_jsonl(labels: &[Label]) -> String {
+        labels
+            .iter()
+            .filter_map(|label| serde_json::to_string(label).ok())
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// Read labels from the BIP329 JSON lines format, skipping blank lines
+    pub fn from_jsonl(data: &str) -> Result<Vec<Label>, serde_json::Error> {
+        data.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_str)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_jsonl_roundtrip() {
+        let mut output = Label::new(
+            LabelType::Output,
+            "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0",
+            "Change",
+        );
+        output.spendable = Some(false);
+
+        let labels = vec![
+            Label::new(
+                LabelType::Tx,
+                "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+                "Rent",
+            ),
+            output,
+        ];
+
+        let jsonl = Label::to_jsonl(&labels);
+        assert_eq!(jsonl.lines().count(), 2);
+        assert!(jsonl.starts_with(r#"{"type":"tx","ref":"#));
+        assert_eq!(Label::from_jsonl(&jsonl).unwrap(), labels);
+    }
+
+    #[test]
+    fn test_label_type_parsing() {
+        assert_eq!("ADDR".parse::<LabelType>().unwrap(), LabelType::Addr);
+        assert!("utxo".parse::<LabelType>().is_err());
+    }
+}
@@ -1,11 +1,17 @@
 pub mod address;
 pub mod amount;
+pub mod bip21;
 pub mod fees;
+pub mod label;
+pub mod message;
 pub mod network;
 pub mod transaction;
 
 pub use address::{validate_address, Address, AddressError, AddressInfo, AddressWithNetwork};
 pub use amount::Amount;
+pub use bip21::{Bip21Error, Bip21Uri};
 pub use fees::FeeRate;
+pub use label::{Label, LabelType};
+pub use message::{verify_message, MessageError};
 pub use network::Network;
 pub use transaction::{Transaction, TransactionDetails};
@@ -0,0 +1,424 @@
+//! Signed messages proving control of an address
+//!
+//! Segwit v0 and taproot addresses use BIP322 simple signatures: the witness
+//! that spends a virtual `to_spend` output committing to the message, encoded
+//! as base64. P2PKH and P2SH-wrapped P2WPKH addresses use the legacy
+//! `signmessage` format, a base64 recoverable signature. Some wallets sign
+//! native P2WPKH addresses that way too, so verifying accepts it for them.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bitcoin::absolute::LockTime;
+use bitcoin::address::AddressType;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::opcodes::{all::OP_RETURN, OP_0};
+use bitcoin::script::Builder;
+use bitcoin::secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::sign_message::{signed_msg_hash, MessageSignature};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    ecdsa, taproot, CompressedPublicKey, OutPoint, PrivateKey, Script, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Witness,
+};
+
+use crate::Address;
+
+/// Tag of the BIP322 message hash
+const MESSAGE_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Length of a legacy recoverable signature
+const LEGACY_SIGNATURE_LEN: usize = 65;
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum MessageError {
+    #[error("Message signing isn't supported for {0} addresses")]
+    UnsupportedAddress(String),
+
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Key doesn't belong to the address")]
+    WrongKey,
+
+    #[error("Failed to compute the signature hash: {0}")]
+    Sighash(String),
+}
+
+/// Sign `message` for `address` with `key`, returning the base64 signature
+pub fn sign_message(
+    address: &Address,
+    message: &str,
+    key: &PrivateKey,
+) -> Result<String, MessageError> {
+    let secp = Secp256k1::new();
+    let script_pubkey = address.script_pubkey();
+
+    let witness = match address.address_type() {
+        Some(AddressType::P2pkh | AddressType::P2sh) => {
+            if !address.is_related_to_pubkey(&key.public_key(&secp)) {
+                return Err(MessageError::WrongKey);
+            }
+            let digest = signed_msg_hash(message).to_byte_array();
+            let signature = secp.sign_ecdsa_recoverable(&Message::from_digest(digest), &key.inner);
+            let signature = MessageSignature::new(signature, key.compressed);
+            return Ok(BASE64.encode(signature.serialize()));
+        }
+        Some(AddressType::P2wpkh) => {
+            let pubkey = CompressedPublicKey::from_private_key(&secp, key)
+                .map_err(|_| MessageError::WrongKey)?;
+            if ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()) != script_pubkey {
+                return Err(MessageError::WrongKey);
+            }
+            let to_sign = to_sign(&to_spend(&script_pubkey, message));
+            let sighash = SighashCache::new(&to_sign)
+                .p2wpkh_signature_hash(
+                    0,
+                    &script_pubkey,
+                    bitcoin::Amount::ZERO,
+                    EcdsaSighashType::All,
+                )
+                .map_err(|e| MessageError::Sighash(e.to_string()))?;
+            let signature = ecdsa::Signature {
+                signature: secp
+                    .sign_ecdsa_low_r(&Message::from_digest(sighash.to_byte_array()), &key.inner),
+                sighash_type: EcdsaSighashType::All,
+            };
+            Witness::p2wpkh(&signature, &pubkey.0)
+        }
+        Some(AddressType::P2tr) => {
+            let keypair = Keypair::from_secret_key(&secp, &key.inner);
+            let (internal_key, _) = keypair.x_only_public_key();
+            if ScriptBuf::new_p2tr(&secp, internal_key, None) != script_pubkey {
+                return Err(MessageError::WrongKey);
+            }
+            let to_spend = to_spend(&script_pubkey, message);
+            let to_sign = to_sign(&to_spend);
+            let sighash = SighashCache::new(&to_sign)
+                .taproot_key_spend_signature_hash(
+                    0,
+                    &Prevouts::All(&to_spend.output),
+                    TapSighashType::Default,
+                )
+                .map_err(|e| MessageError::Sighash(e.to_string()))?;
+            let tweaked = keypair.tap_tweak(&secp, None).to_keypair();
+            let signature = taproot::Signature {
+                signature: secp.sign_schnorr_no_aux_rand(
+                    &Message::from_digest(sighash.to_byte_array()),
+                    &tweaked,
+                ),
+                sighash_type: TapSighashType::Default,
+            };
+            Witness::p2tr_key_spend(&signature)
+        }
+        _ => return Err(unsupported(address)),
+    };
+
+    Ok(BASE64.encode(bitcoin::consensus::serialize(&witness)))
+}
+
+/// Check a base64 `signature` of `message` against `address`
+///
+/// Returns `Ok(false)` for a well-formed signature that wasn't made by the
+/// address's key, or not over this message.
+pub fn verify_message(
+    address: &Address,
+    message: &str,
+    signature: &str,
+) -> Result<bool, MessageError> {
+    let bytes = BASE64
+        .decode(signature.trim())
+        .map_err(|_| MessageError::InvalidSignature("not base64".to_string()))?;
+
+    match address.address_type() {
+        Some(AddressType::P2pkh | AddressType::P2sh) => verify_legacy(address, message, &bytes),
+        Some(AddressType::P2wpkh) if bytes.len() == LEGACY_SIGNATURE_LEN => {
+            verify_legacy(address, message, &bytes)
+        }
+        Some(AddressType::P2wpkh | AddressType::P2tr) => {
+            let witness: Witness = bitcoin::consensus::deserialize(&bytes).map_err(|_| {
+                MessageError::InvalidSignature("not a BIP322 simple signature".to_string())
+            })?;
+            verify_simple(address, message, &witness)
+        }
+        _ => Err(unsupported(address)),
+    }
+}
+
+/// BIP322 tagged hash of a message
+pub fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(MESSAGE_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+/// BIP322 virtual transaction creating an output to `script_pubkey` that commits to `message`
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    let script_sig = Builder::new()
+        .push_opcode(OP_0)
+        .push_slice(message_hash(message).to_byte_array())
+        .into_script();
+
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    }
+}
+
+/// BIP322 virtual transaction spending the output of `to_spend`, without its witness
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.compute_txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+fn verify_legacy(address: &Address, message: &str, bytes: &[u8]) -> Result<bool, MessageError> {
+    let signature = MessageSignature::from_slice(bytes)
+        .map_err(|e| MessageError::InvalidSignature(e.to_string()))?;
+    let secp = Secp256k1::verification_only();
+
+    // A signature that recovers no key can't be from this address
+    let Ok(pubkey) = signature.recover_pubkey(&secp, signed_msg_hash(message)) else {
+        return Ok(false);
+    };
+    Ok(address.is_related_to_pubkey(&pubkey))
+}
+
+fn verify_simple(
+    address: &Address,
+    message: &str,
+    witness: &Witness,
+) -> Result<bool, MessageError> {
+    let secp = Secp256k1::verification_only();
+    let script_pubkey = address.script_pubkey();
+    let to_spend = to_spend(&script_pubkey, message);
+    let to_sign = to_sign(&to_spend);
+    let mut cache = SighashCache::new(&to_sign);
+
+    if script_pubkey.is_p2wpkh() {
+        let [signature, pubkey] = witness.to_vec().try_into().map_err(|_| {
+            MessageError::InvalidSignature("P2WPKH witness needs two items".to_string())
+        })?;
+        let signature = ecdsa::Signature::from_slice(&signature)
+            .map_err(|e| MessageError::InvalidSignature(e.to_string()))?;
+        let pubkey = CompressedPublicKey::from_slice(&pubkey)
+            .map_err(|e| MessageError::InvalidSignature(e.to_string()))?;
+        if ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()) != script_pubkey {
+            return Ok(false);
+        }
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                0,
+                &script_pubkey,
+                bitcoin::Amount::ZERO,
+                signature.sighash_type,
+            )
+            .map_err(|e| MessageError::Sighash(e.to_string()))?;
+        let digest = Message::from_digest(sighash.to_byte_array());
+        return Ok(secp
+            .verify_ecdsa(&digest, &signature.signature, &pubkey.0)
+            .is_ok());
+    }
+
+    let [signature] = witness.to_vec().try_into().map_err(|_| {
+        MessageError::InvalidSignature("only taproot key path spends are supported".to_string())
+    })?;
+    let signature = taproot::Signature::from_slice(&signature)
+        .map_err(|e| MessageError::InvalidSignature(e.to_string()))?;
+    let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+        .map_err(|e| MessageError::InvalidSignature(e.to_string()))?;
+    let sighash = cache
+        .taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(&to_spend.output),
+            signature.sighash_type,
+        )
+        .map_err(|e| MessageError::Sighash(e.to_string()))?;
+    let digest = Message::from_digest(sighash.to_byte_array());
+    Ok(secp
+        .verify_schnorr(&signature.signature, &digest, &output_key)
+        .is_ok())
+}
+
+fn unsupported(address: &Address) -> MessageError {
+    let address_type = address
+        .address_type()
+        .map_or_else(|| "this type of".to_string(), |kind| kind.to_string());
+    MessageError::UnsupportedAddress(address_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Network;
+
+    // Key and address from the BIP322 test vectors
+    const WIF: &str = "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k";
+    const P2WPKH: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+    const P2TR: &str = "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3";
+
+    fn address(address: &str) -> Address {
+        Address::from_string(address, Network::Mainnet).unwrap()
+    }
+
+    fn key() -> PrivateKey {
+        WIF.parse().unwrap()
+    }
+
+    #[test]
+    fn test_message_hash_vectors() {
+        assert_eq!(
+            message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn test_virtual_transaction_vectors() {
+        let script_pubkey = address(P2WPKH).script_pubkey();
+
+        let to_spend_empty = to_spend(&script_pubkey, "");
+        assert_eq!(
+            to_spend_empty.compute_txid().to_string(),
+            "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7"
+        );
+        assert_eq!(
+            to_sign(&to_spend_empty).compute_txid().to_string(),
+            "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6"
+        );
+
+        let to_spend_hello = to_spend(&script_pubkey, "Hello World");
+        assert_eq!(
+            to_spend_hello.compute_txid().to_string(),
+            "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b"
+        );
+        assert_eq!(
+            to_sign(&to_spend_hello).compute_txid().to_string(),
+            "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf"
+        );
+    }
+
+    #[test]
+    fn test_verify_bip322_vectors() {
+        let p2wpkh = address(P2WPKH);
+        let signature = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert_eq!(verify_message(&p2wpkh, "Hello World", signature), Ok(true));
+        assert_eq!(
+            verify_message(&p2wpkh, "Hello World!", signature),
+            Ok(false)
+        );
+
+        let p2tr = address(P2TR);
+        let signature =
+            "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ==";
+        assert_eq!(verify_message(&p2tr, "Hello World", signature), Ok(true));
+        assert_eq!(verify_message(&p2tr, "", signature), Ok(false));
+    }
+
+    #[test]
+    fn test_sign_and_verify_round_trip() {
+        let secp = Secp256k1::new();
+        let key = key();
+        let pubkey = CompressedPublicKey::from_private_key(&secp, &key).unwrap();
+        let p2pkh = Address::new(bitcoin::Address::p2pkh(pubkey, bitcoin::Network::Bitcoin));
+        let p2sh = Address::new(bitcoin::Address::p2shwpkh(
+            &pubkey,
+            bitcoin::Network::Bitcoin,
+        ));
+
+        for address in [address(P2WPKH), address(P2TR), p2pkh, p2sh] {
+            let signature = sign_message(&address, "I control this address", &key).unwrap();
+            assert_eq!(
+                verify_message(&address, "I control this address", &signature),
+                Ok(true),
+                "{address}"
+            );
+            assert_eq!(
+                verify_message(&address, "I don't", &signature),
+                Ok(false),
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_legacy_signature_for_p2wpkh() {
+        // Some wallets sign segwit addresses with the legacy format
+        let secp = Secp256k1::new();
+        let key = key();
+        let digest = signed_msg_hash("Hello World").to_byte_array();
+        let signature = secp.sign_ecdsa_recoverable(&Message::from_digest(digest), &key.inner);
+        let signature = BASE64.encode(MessageSignature::new(signature, true).serialize());
+
+        assert_eq!(
+            verify_message(&address(P2WPKH), "Hello World", &signature),
+            Ok(true)
+        );
+        assert_eq!(
+            verify_message(&address(P2WPKH), "Goodbye", &signature),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_sign_rejects_wrong_key() {
+        let other: PrivateKey = "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            sign_message(&address(P2WPKH), "Hello World", &other),
+            Err(MessageError::WrongKey)
+        );
+        assert_eq!(
+            sign_message(&address(P2TR), "Hello World", &other),
+            Err(MessageError::WrongKey)
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_signatures() {
+        let p2wpkh = address(P2WPKH);
+        assert!(matches!(
+            verify_message(&p2wpkh, "Hello World", "not base64!"),
+            Err(MessageError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            verify_message(&p2wpkh, "Hello World", &BASE64.encode([1, 2, 3])),
+            Err(MessageError::InvalidSignature(_))
+        ));
+
+        let p2wsh = address("bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3");
+        assert!(matches!(
+            verify_message(&p2wsh, "Hello World", &BASE64.encode([0; 65])),
+            Err(MessageError::UnsupportedAddress(_))
+        ));
+    }
+}
@@ -57,6 +57,23 @@ impl Network {
     }
 }
 
+impl std::str::FromStr for Network {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "testnet4" => Ok(Network::Testnet4),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(eyre::eyre!(
+                "Invalid network: {s}. Valid options: mainnet, testnet, testnet4, signet, regtest"
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,4 +104,19 @@ mod tests {
         let back = Network::from_bitcoin_network(bitcoin_network);
         assert_eq!(back, Network::Testnet4);
     }
+
+    #[test]
+    fn test_parse_network() {
+        for network in [
+            Network::Mainnet,
+            Network::Testnet,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            assert_eq!(network.to_string().parse::<Network>().unwrap(), network);
+        }
+        assert_eq!("Signet".parse::<Network>().unwrap(), Network::Signet);
+        assert!("bitcoin".parse::<Network>().is_err());
+    }
 }
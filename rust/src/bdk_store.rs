@@ -12,7 +12,7 @@ pub struct BDKStore {
     pub conn: bdk_wallet::rusqlite::Connection,
 }
 
-fn sqlite_data_path(wallet_id: &WalletId) -> PathBuf {
+pub(crate) fn sqlite_data_path(wallet_id: &WalletId) -> PathBuf {
     let db = format!(
         "bdk_wallet_sqlite_{}.db",
         wallet_id.to_string().to_lowercase()
@@ -20,6 +20,24 @@ fn sqlite_data_path(wallet_id: &WalletId) -> PathBuf {
     ROOT_DATA_DIR.join(db)
 }
 
+/// Where a rescan builds the new sqlite store before it replaces the old one
+pub(crate) fn rescan_data_path(wallet_id: &WalletId) -> PathBuf {
+    let db = format!(
+        "bdk_wallet_sqlite_{}.rescan.db",
+        wallet_id.to_string().to_lowercase()
+    );
+    ROOT_DATA_DIR.join(db)
+}
+
+/// Lock file guarding a wallet's sqlite store against other lumo processes
+pub(crate) fn lock_path(wallet_id: &WalletId) -> PathBuf {
+    let lock = format!(
+        "bdk_wallet_sqlite_{}.lock",
+        wallet_id.to_string().to_lowercase()
+    );
+    ROOT_DATA_DIR.join(lock)
+}
+
 impl BDKStore {
     pub fn try_new(id: &WalletId, network: impl Into<Network>) -> Result<Self> {
         // Create database file path
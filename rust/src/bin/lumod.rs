@@ -0,0 +1,83 @@
+use clap::Parser;
+use lumo::daemon::{Daemon, DaemonConfig};
+use lumo::{init_with_options, DataDirOptions};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "lumod")]
+#[command(about = "Lumo wallet daemon, serves loaded wallets over a local JSON-RPC API")]
+#[command(version)]
+struct Cli {
+    /// Data directory (defaults to $LUMO_DATA_DIR or the platform data directory)
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+    /// Profile with its own wallets and settings (defaults to $LUMO_PROFILE)
+    #[arg(long)]
+    profile: Option<String>,
+    /// Loopback address to serve JSON-RPC on
+    #[arg(long, default_value_t = SocketAddr::from(([127, 0, 0, 1], lumo::daemon::DEFAULT_RPC_PORT)))]
+    listen: SocketAddr,
+    /// Only serve on the Unix socket in the data directory
+    #[arg(long)]
+    no_tcp: bool,
+    /// Loopback address to serve a bitcoind compatible wallet RPC on, e.g. 127.0.0.1:8332
+    #[arg(long)]
+    bitcoind_listen: Option<SocketAddr>,
+    /// Loopback address to serve a BIP78 payjoin endpoint on, for invoices created with --payjoin
+    ///
+    /// Payers don't need the cookie, so put it behind an https reverse proxy or
+    /// an onion service rather than exposing lumod itself.
+    #[arg(long)]
+    payjoin_listen: Option<SocketAddr>,
+    /// Seconds between background syncs of each loaded wallet, 0 turns them off
+    ///
+    /// Wallets with unconfirmed transactions are synced more often, and failing
+    /// syncs back off.
+    #[arg(long, default_value = "60")]
+    sync_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    init_with_options(DataDirOptions {
+        data_dir: cli.data_dir,
+        profile: cli.profile,
+    })?;
+
+    let config = DaemonConfig {
+        listen: (!cli.no_tcp).then_some(cli.listen),
+        sync_interval: (cli.sync_interval > 0).then(|| Duration::from_secs(cli.sync_interval)),
+        bitcoind_listen: cli.bitcoind_listen,
+        payjoin_listen: cli.payjoin_listen,
+        ..DaemonConfig::default()
+    };
+
+    let daemon = Daemon::start(config.clone()).await?;
+    println!("🚀 lumod started");
+    if let Some(socket) = &config.socket {
+        println!("   Socket: {}", socket.display());
+    }
+    if let Some(addr) = daemon.local_addr() {
+        println!("   JSON-RPC: http://{}", addr);
+    }
+    if let Some(addr) = daemon.bitcoind_addr() {
+        println!("   bitcoind RPC: http://{}/wallet/<name>", addr);
+    }
+    if let Some(addr) = daemon.payjoin_addr() {
+        println!("   Payjoin: http://{}", addr);
+    }
+    println!("   Cookie: {}", lumo::daemon::auth::cookie_path().display());
+
+    daemon
+        .serve(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    println!("👋 lumod stopped");
+    Ok(())
+}
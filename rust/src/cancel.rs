@@ -0,0 +1,66 @@
+//! Cooperative cancellation for long running wallet operations
+
+use std::future::Future;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// Cancels every operation it's handed to, clones share the same state
+#[derive(Debug, Clone)]
+pub struct CancelToken(Arc<watch::Sender<bool>>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once the token is cancelled
+    pub async fn cancelled(&self) {
+        let mut receiver = self.0.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+
+    /// Run `future` to completion, or drop it and return `None` once cancelled
+    pub async fn run<F: Future>(&self, future: F) -> Option<F::Output> {
+        tokio::select! {
+            biased;
+            _ = self.cancelled() => None,
+            output = future => Some(output),
+        }
+    }
+}
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_stops_a_pending_future() {
+        let token = CancelToken::new();
+        assert_eq!(token.run(async { 1 }).await, Some(1));
+
+        let clone = token.clone();
+        let pending = tokio::spawn(async move { clone.run(std::future::pending::<()>()).await });
+        token.cancel();
+        assert_eq!(pending.await.unwrap(), None);
+        assert!(token.is_cancelled());
+
+        // Already cancelled, nothing runs
+        assert_eq!(token.run(async { 2 }).await, None);
+    }
+}
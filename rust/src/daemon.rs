@@ -0,0 +1,41 @@
+//! `lumod`, a daemon that keeps wallets loaded and synced behind a local JSON-RPC API
+//!
+//! Requests are JSON-RPC 2.0 over HTTP/1.1, served on a Unix socket in the data
+//! directory and on a loopback TCP port. Every request must carry the cookie the
+//! daemon writes to the data directory on startup as HTTP basic auth, the same
+//! scheme bitcoind uses.
+//!
+//! Optionally a second loopback port speaks bitcoind's own wallet RPC, see
+//! [`bitcoind`], so tools written against Bitcoin Core can use lumo wallets,
+//! and a third serves a BIP78 payjoin endpoint to payers, see [`payjoin`].
+
+pub mod auth;
+pub mod bitcoind;
+pub mod client;
+pub mod error;
+pub mod http;
+pub mod methods;
+pub mod payjoin;
+pub mod protocol;
+pub mod server;
+
+use std::path::PathBuf;
+
+use lumo_common::ROOT_DATA_DIR;
+
+pub use client::{DaemonClient, Endpoint, EventStream};
+pub use error::DaemonError;
+pub use server::{Daemon, DaemonConfig};
+
+/// TCP port `lumod` listens on unless told otherwise
+pub const DEFAULT_RPC_PORT: u16 = 7332;
+
+/// Unix socket the daemon listens on
+pub fn socket_path() -> PathBuf {
+    ROOT_DATA_DIR.join("lumod.sock") // ~/.lumo/lumod.sock
+}
+
+/// Held by the running daemon, only one may serve a data directory
+pub fn lock_path() -> PathBuf {
+    ROOT_DATA_DIR.join("lumod.lock")
+}
@@ -0,0 +1,122 @@
+//! Cookie authentication
+//!
+//! The daemon writes a random `__cookie__:<password>` line to the data directory
+//! on startup, readable only by the user running it. Clients send it back as
+//! HTTP basic auth, so anyone who can read the data directory can use the API.
+
+use std::path::{Path, PathBuf};
+
+use base64::{engine::general_purpose, Engine as _};
+use lumo_common::ROOT_DATA_DIR;
+use rand::Rng;
+
+use crate::daemon::error::DaemonError;
+
+pub const COOKIE_USER: &str = "__cookie__";
+
+/// Where the running daemon keeps its cookie
+pub fn cookie_path() -> PathBuf {
+    ROOT_DATA_DIR.join("lumod.cookie") // ~/.lumo/lumod.cookie
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cookie(String);
+
+// Keep the password out of logs
+impl std::fmt::Debug for Cookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cookie(..)")
+    }
+}
+
+impl Cookie {
+    pub fn generate() -> Self {
+        let password = rand::rng().random::<[u8; 32]>();
+        Self(format!("{COOKIE_USER}:{}", hex::encode(password)))
+    }
+
+    pub fn read(path: &Path) -> Result<Self, DaemonError> {
+        let cookie = std::fs::read_to_string(path)?;
+        let cookie = cookie.trim();
+        if !cookie.contains(':') {
+            return Err(DaemonError::Unauthorized(format!(
+                "Invalid cookie file: {}",
+                path.display()
+            )));
+        }
+        Ok(Self(cookie.to_string()))
+    }
+
+    /// Write the cookie, readable only by the current user
+    pub fn write(&self, path: &Path) -> Result<(), DaemonError> {
+        // A stale cookie may have been created with other permissions
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        use std::io::Write;
+        let mut file = options.open(path)?;
+        file.write_all(self.0.as_bytes())?;
+        Ok(())
+    }
+
+    /// Value for the `Authorization` header
+    pub fn authorization(&self) -> String {
+        format!("Basic {}", general_purpose::STANDARD.encode(&self.0))
+    }
+
+    /// Check an `Authorization` header against this cookie
+    pub fn check(&self, authorization: Option<&str>) -> bool {
+        let Some(encoded) = authorization.and_then(|value| value.strip_prefix("Basic ")) else {
+            return false;
+        };
+        let Ok(credentials) = general_purpose::STANDARD.decode(encoded.trim()) else {
+            return false;
+        };
+
+        constant_time_eq(&credentials, self.0.as_bytes())
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lumo_common::data_dir::TestDir;
+
+    #[test]
+    fn test_cookie_auth() {
+        let dir = TestDir::new("cookie");
+        let path = dir.join("lumod.cookie");
+        let cookie = Cookie::generate();
+        cookie.write(&path).unwrap();
+
+        let read = Cookie::read(&path).unwrap();
+        assert_eq!(read, cookie);
+        assert!(cookie.check(Some(&read.authorization())));
+
+        assert!(!cookie.check(None));
+        assert!(!cookie.check(Some(&Cookie::generate().authorization())));
+        assert!(!cookie.check(Some("Basic not-base64!")));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
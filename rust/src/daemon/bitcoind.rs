@@ -0,0 +1,1095 @@
+//! Bitcoin Core wallet RPC compatibility
+//!
+//! Serves the part of bitcoind's wallet RPC that existing tools rely on, backed
+//! by lumo wallets. Every loaded wallet is an RPC wallet reached at
+//! `/wallet/<name or id>`, and `/` works when exactly one wallet is loaded, the
+//! same rules bitcoind applies to multiwallet nodes. Params may be positional or
+//! named, amounts are in BTC and fee rates in sat/vB, and responses use
+//! bitcoind's `{result, error, id}` envelope and HTTP status codes.
+
+use std::collections::{HashMap, HashSet};
+
+use bdk_wallet::chain::{ChainPosition, ConfirmationBlockTime};
+use bdk_wallet::error::CreateTxError;
+use bdk_wallet::KeychainKind;
+use bitcoin::psbt::Psbt;
+use bitcoin::{Denomination, OutPoint, ScriptBuf, Txid};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::daemon::http::HttpResponse;
+use crate::daemon::methods::DEFAULT_FEE_RATE;
+use crate::daemon::protocol::{Request, RpcError};
+use crate::daemon::server::DaemonState;
+use crate::database::Database;
+use crate::wallet::{Wallet, WalletId};
+use crate::wallet_manager::WalletManager;
+use lumo_types::{Address, FeeRate, LabelType, Network};
+
+type MethodResult = Result<Value, RpcError>;
+
+/// bitcoind's response envelope, `result` and `error` are always present
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LegacyResponse {
+    pub result: Value,
+    pub error: Option<RpcError>,
+    pub id: Value,
+}
+
+impl LegacyResponse {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        match result {
+            Ok(result) => Self {
+                result,
+                error: None,
+                id,
+            },
+            Err(error) => Self {
+                result: Value::Null,
+                error: Some(error),
+                id,
+            },
+        }
+    }
+
+    /// HTTP status bitcoind answers a single request with
+    fn status(&self) -> u16 {
+        match &self.error {
+            None => 200,
+            Some(error) if error.code == RpcError::METHOD_NOT_FOUND => 404,
+            Some(error) if error.code == RpcError::INVALID_REQUEST => 400,
+            Some(_) => 500,
+        }
+    }
+}
+
+/// Handle a request body posted to `path`, a single call or a batch
+pub async fn handle(state: &DaemonState, path: &str, body: &[u8]) -> HttpResponse {
+    let Some(wallet) = wallet_from_path(path) else {
+        return HttpResponse::error(404, "Not found");
+    };
+
+    let (status, response) = match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(batch)) => {
+            let mut responses = Vec::with_capacity(batch.len());
+            for request in batch {
+                responses.push(call(state, wallet.as_deref(), request).await);
+            }
+            (200, serde_json::to_vec(&responses))
+        }
+        Ok(request) => {
+            let response = call(state, wallet.as_deref(), request).await;
+            (response.status(), serde_json::to_vec(&response))
+        }
+        Err(e) => {
+            let error = RpcError::new(RpcError::PARSE_ERROR, format!("Parse error: {e}"));
+            (
+                500,
+                serde_json::to_vec(&LegacyResponse::new(Value::Null, Err(error))),
+            )
+        }
+    };
+
+    match response {
+        Ok(body) => HttpResponse { status, body },
+        Err(e) => HttpResponse::error(500, &e.to_string()),
+    }
+}
+
+async fn call(state: &DaemonState, wallet: Option<&str>, request: Value) -> LegacyResponse {
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(RpcError::INVALID_REQUEST, e.to_string());
+            return LegacyResponse::new(Value::Null, Err(error));
+        }
+    };
+
+    let result = dispatch(state, wallet, &request.method, request.params).await;
+    if let Err(e) = &result {
+        tracing::debug!("{} failed: {e}", request.method);
+    }
+    LegacyResponse::new(request.id, result)
+}
+
+/// Run a bitcoind wallet method against `wallet`, a wallet name or id from the URL
+pub async fn dispatch(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    method: &str,
+    params: Value,
+) -> MethodResult {
+    match method {
+        "listwallets" => list_wallets(state).await,
+        "getwalletinfo" => get_wallet_info(state, wallet).await,
+        "getnewaddress" => {
+            let params = Params::new(params, &["label", "address_type"])?;
+            get_new_address(state, wallet, params).await
+        }
+        "getbalance" => {
+            let params = Params::new(
+                params,
+                &["dummy", "minconf", "include_watchonly", "avoid_reuse"],
+            )?;
+            get_balance(state, wallet, params).await
+        }
+        "listtransactions" => {
+            let params = Params::new(params, &["label", "count", "skip", "include_watchonly"])?;
+            list_transactions(state, wallet, params).await
+        }
+        "listunspent" => {
+            let params = Params::new(
+                params,
+                &[
+                    "minconf",
+                    "maxconf",
+                    "addresses",
+                    "include_unsafe",
+                    "query_options",
+                ],
+            )?;
+            list_unspent(state, wallet, params).await
+        }
+        "sendtoaddress" => {
+            let params = Params::new(
+                params,
+                &[
+                    "address",
+                    "amount",
+                    "comment",
+                    "comment_to",
+                    "subtractfeefromamount",
+                    "replaceable",
+                    "conf_target",
+                    "estimate_mode",
+                    "avoid_reuse",
+                    "fee_rate",
+                    "verbose",
+                ],
+            )?;
+            send_to_address(state, wallet, params).await
+        }
+        "walletcreatefundedpsbt" => {
+            let params = Params::new(
+                params,
+                &["inputs", "outputs", "locktime", "options", "bip32derivs"],
+            )?;
+            wallet_create_funded_psbt(state, wallet, params).await
+        }
+        "walletprocesspsbt" => {
+            let params = Params::new(
+                params,
+                &["psbt", "sign", "sighashtype", "bip32derivs", "finalize"],
+            )?;
+            wallet_process_psbt(state, wallet, params).await
+        }
+        _ => Err(RpcError::method_not_found(method)),
+    }
+}
+
+/// Wallet named by a request path, `Some(None)` for `/` and `None` for anything else
+fn wallet_from_path(path: &str) -> Option<Option<String>> {
+    if path == "/" {
+        return Some(None);
+    }
+    let name = path.strip_prefix("/wallet/")?;
+    percent_decode(name).map(Some)
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut rest = input.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2)?;
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// A method's params given by position or by name, looked up by name
+struct Params {
+    names: &'static [&'static str],
+    values: Vec<Value>,
+}
+
+impl Params {
+    fn new(params: Value, names: &'static [&'static str]) -> Result<Self, RpcError> {
+        let values = match params {
+            Value::Null => Vec::new(),
+            Value::Array(values) if values.len() > names.len() => {
+                return Err(RpcError::new(
+                    RpcError::MISC_ERROR,
+                    format!("Expected at most {} params", names.len()),
+                ))
+            }
+            Value::Array(values) => values,
+            Value::Object(named) => {
+                let mut values = vec![Value::Null; names.len()];
+                for (name, value) in named {
+                    let Some(index) = names.iter().position(|known| *known == name) else {
+                        return Err(RpcError::new(
+                            RpcError::INVALID_PARAMETER,
+                            format!("Unknown named parameter {name}"),
+                        ));
+                    };
+                    values[index] = value;
+                }
+                values
+            }
+            _ => {
+                return Err(RpcError::new(
+                    RpcError::INVALID_REQUEST,
+                    "Params must be an array or object",
+                ))
+            }
+        };
+
+        Ok(Self { names, values })
+    }
+
+    /// A param's raw value, `None` when it's missing or null
+    fn value(&self, name: &str) -> Option<&Value> {
+        let index = self.names.iter().position(|known| *known == name)?;
+        self.values.get(index).filter(|value| !value.is_null())
+    }
+
+    fn get<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>, RpcError> {
+        self.value(name)
+            .map(|value| {
+                serde_json::from_value(value.clone()).map_err(|e| {
+                    RpcError::new(RpcError::TYPE_ERROR, format!("Invalid {name}: {e}"))
+                })
+            })
+            .transpose()
+    }
+
+    fn required<T: DeserializeOwned>(&self, name: &str) -> Result<T, RpcError> {
+        self.get(name)?.ok_or_else(|| {
+            RpcError::new(
+                RpcError::MISC_ERROR,
+                format!("Missing required parameter {name}"),
+            )
+        })
+    }
+}
+
+async fn list_wallets(state: &DaemonState) -> MethodResult {
+    let loaded = state.manager.list_wallet_ids();
+    let mut names: Vec<String> = Database::global()?
+        .wallets
+        .get_all(None)?
+        .into_iter()
+        .filter(|metadata| loaded.contains(&metadata.id))
+        .map(|metadata| metadata.name)
+        .collect();
+    names.sort();
+    Ok(json!(names))
+}
+
+async fn get_wallet_info(state: &DaemonState, wallet: Option<&str>) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let wallet = wallet.lock().await;
+    let balance = wallet.balance().0;
+
+    Ok(json!({
+        "walletname": wallet.name(),
+        "format": "lumo",
+        "balance": balance.confirmed.to_btc(),
+        "unconfirmed_balance": (balance.trusted_pending + balance.untrusted_pending).to_btc(),
+        "immature_balance": balance.immature.to_btc(),
+        "txcount": wallet.bdk.transactions().count(),
+        "descriptors": true,
+        "scanning": false,
+    }))
+}
+
+async fn get_new_address(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    params: Params,
+) -> MethodResult {
+    if let Some(address_type) = params.get::<String>("address_type")? {
+        if address_type != "bech32" {
+            return Err(RpcError::new(
+                RpcError::INVALID_ADDRESS,
+                format!("Unknown address type '{address_type}', lumo wallets only hand out bech32 addresses"),
+            ));
+        }
+    }
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let mut wallet = wallet.lock().await;
+
+    let address = wallet.get_new_address()?;
+    wallet.persist()?;
+    if let Some(label) = params.get::<String>("label")? {
+        wallet.set_label(LabelType::Addr, &address.to_string(), &label)?;
+    }
+    Ok(json!(address.to_string()))
+}
+
+async fn get_balance(state: &DaemonState, wallet: Option<&str>, params: Params) -> MethodResult {
+    if params
+        .get::<String>("dummy")?
+        .is_some_and(|dummy| dummy != "*")
+    {
+        return Err(RpcError::new(
+            RpcError::METHOD_DEPRECATED,
+            "dummy first argument must be excluded or set to \"*\".",
+        ));
+    }
+    let minconf: u32 = params.get("minconf")?.unwrap_or(0);
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let balance = state.manager.balance(&wallet_id).await?;
+    let amount = if minconf == 0 {
+        balance.spendable()
+    } else {
+        balance.confirmed()
+    };
+    Ok(json!(amount.as_btc()))
+}
+
+async fn list_transactions(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    params: Params,
+) -> MethodResult {
+    let label = params.get::<String>("label")?.filter(|label| label != "*");
+    let count: usize = params.get("count")?.unwrap_or(10);
+    let skip: usize = params.get("skip")?.unwrap_or(0);
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let wallet = wallet.lock().await;
+    let labels = address_labels(&wallet)?;
+    let tip = wallet.bdk.latest_checkpoint().height();
+
+    let mut entries = Vec::new();
+    for canonical_tx in wallet.bdk.transactions() {
+        let tx = &canonical_tx.tx_node.tx;
+        let txid = canonical_tx.tx_node.txid;
+        let (sent, _) = wallet.bdk.sent_and_received(tx);
+        let fee = wallet.bdk.calculate_fee(tx).ok();
+
+        let mut chain = json!({
+            "confirmations": confirmations(&canonical_tx.chain_position, tip),
+        });
+        let time = match &canonical_tx.chain_position {
+            ChainPosition::Confirmed { anchor, .. } => {
+                chain["blockhash"] = json!(anchor.block_id.hash.to_string());
+                chain["blockheight"] = json!(anchor.block_id.height);
+                chain["blocktime"] = json!(anchor.confirmation_time);
+                anchor.confirmation_time
+            }
+            ChainPosition::Unconfirmed { first_seen, .. } => first_seen.unwrap_or_default(),
+        };
+        chain["txid"] = json!(txid.to_string());
+        chain["time"] = json!(time);
+        chain["timereceived"] = json!(time);
+
+        for (vout, output) in tx.output.iter().enumerate() {
+            let keychain = wallet
+                .bdk
+                .derivation_of_spk(output.script_pubkey.clone())
+                .map(|(keychain, _)| keychain);
+            let address = address_of(&output.script_pubkey, wallet.network());
+            let address_label = address.as_ref().and_then(|address| labels.get(address));
+
+            // Like bitcoind, change isn't listed and a payment to ourselves is both
+            let mut categories = Vec::new();
+            if sent.to_sat() > 0 && keychain != Some(KeychainKind::Internal) {
+                categories.push("send");
+            }
+            if keychain == Some(KeychainKind::External) {
+                categories.push("receive");
+            }
+
+            for category in categories {
+                if label.is_some() && label.as_ref() != address_label {
+                    continue;
+                }
+
+                let amount = output.value.to_btc();
+                let mut entry = json!({
+                    "address": address,
+                    "category": category,
+                    "amount": if category == "send" { -amount } else { amount },
+                    "vout": vout,
+                });
+                if let Some(address_label) = address_label {
+                    entry["label"] = json!(address_label);
+                }
+                if let (Some(fee), "send") = (fee, category) {
+                    entry["fee"] = json!(-fee.to_btc());
+                }
+                merge(&mut entry, &chain);
+                entries.push((time, entry));
+            }
+        }
+    }
+
+    // The most recent `count` after skipping `skip`, returned oldest first
+    entries.sort_by_key(|(time, _)| *time);
+    let end = entries.len().saturating_sub(skip);
+    let start = end.saturating_sub(count);
+    let entries: Vec<Value> = entries.drain(start..end).map(|(_, entry)| entry).collect();
+    Ok(json!(entries))
+}
+
+async fn list_unspent(state: &DaemonState, wallet: Option<&str>, params: Params) -> MethodResult {
+    let minconf: u32 = params.get("minconf")?.unwrap_or(1);
+    let maxconf: u32 = params.get("maxconf")?.unwrap_or(9_999_999);
+    let include_unsafe: bool = params.get("include_unsafe")?.unwrap_or(true);
+    let addresses: Option<HashSet<String>> = params.get("addresses")?;
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let wallet = wallet.lock().await;
+    let labels = address_labels(&wallet)?;
+    let tip = wallet.bdk.latest_checkpoint().height();
+
+    let mut unspent = Vec::new();
+    for utxo in wallet.bdk.list_unspent() {
+        let confirmations = confirmations(&utxo.chain_position, tip);
+        // Our own unconfirmed change is safe to spend, other unconfirmed coins aren't
+        let safe = confirmations > 0 || utxo.keychain == KeychainKind::Internal;
+        if confirmations < minconf || confirmations > maxconf || (!safe && !include_unsafe) {
+            continue;
+        }
+
+        let address = address_of(&utxo.txout.script_pubkey, wallet.network());
+        if let Some(addresses) = &addresses {
+            if !address
+                .as_ref()
+                .is_some_and(|address| addresses.contains(address))
+            {
+                continue;
+            }
+        }
+
+        let mut entry = json!({
+            "txid": utxo.outpoint.txid.to_string(),
+            "vout": utxo.outpoint.vout,
+            "address": address,
+            "scriptPubKey": utxo.txout.script_pubkey.to_hex_string(),
+            "amount": utxo.txout.value.to_btc(),
+            "confirmations": confirmations,
+            "spendable": true,
+            "solvable": true,
+            "safe": safe,
+        });
+        if let Some(label) = address.as_ref().and_then(|address| labels.get(address)) {
+            entry["label"] = json!(label);
+        }
+        unspent.push(entry);
+    }
+
+    Ok(json!(unspent))
+}
+
+async fn send_to_address(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    params: Params,
+) -> MethodResult {
+    if params.get("subtractfeefromamount")?.unwrap_or(false) {
+        return Err(RpcError::new(
+            RpcError::INVALID_PARAMETER,
+            "subtractfeefromamount is not supported",
+        ));
+    }
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    // Spend from the latest UTXOs, not the last background sync
+    state.manager.sync(&wallet_id, &state.cancel).await?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let mut wallet = wallet.lock().await;
+
+    let address: String = params.required("address")?;
+    let amount = parse_btc(params.value("amount"))?;
+    if amount == bitcoin::Amount::ZERO {
+        return Err(RpcError::new(
+            RpcError::TYPE_ERROR,
+            "Invalid amount for send",
+        ));
+    }
+
+    let funding = Funding {
+        outputs: vec![Output::Pay(script_for(&address, wallet.network())?, amount)],
+        add_inputs: true,
+        fee_rate: params.value("fee_rate").map(parse_sat_per_vb).transpose()?,
+        replaceable: params.get("replaceable")?,
+        ..Funding::default()
+    };
+
+    let mut psbt = fund(&mut wallet, funding)?;
+
+    if !wallet.sign_psbt(&mut psbt)? {
+        return Err(RpcError::new(
+            RpcError::WALLET_ERROR,
+            "Transaction needs cosigner signatures, it was saved as a pending PSBT",
+        ));
+    }
+
+    let transaction = wallet.extract_transaction(psbt)?;
+    let txid = transaction.compute_txid();
+    wallet.broadcast_transaction(transaction).await?;
+
+    // bitcoind keeps the comment on the transaction, a tx label is the closest thing
+    if let Some(comment) = params.get::<String>("comment")? {
+        wallet.set_label(LabelType::Tx, &txid.to_string(), &comment)?;
+    }
+
+    Ok(json!(txid.to_string()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FundingOptions {
+    add_inputs: Option<bool>,
+    #[serde(rename = "changeAddress", alias = "change_address")]
+    change_address: Option<String>,
+    /// sat/vB
+    fee_rate: Option<Value>,
+    /// BTC/kvB
+    #[serde(rename = "feeRate")]
+    fee_rate_btc_kvb: Option<Value>,
+    replaceable: Option<bool>,
+    #[serde(rename = "subtractFeeFromOutputs", alias = "subtract_fee_from_outputs")]
+    subtract_fee_from_outputs: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Input {
+    txid: Txid,
+    vout: u32,
+}
+
+async fn wallet_create_funded_psbt(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    params: Params,
+) -> MethodResult {
+    let inputs: Vec<Input> = params.get("inputs")?.unwrap_or_default();
+    let locktime: Option<u32> = params.get("locktime")?;
+    let options: FundingOptions = params.get("options")?.unwrap_or_default();
+
+    if !options.subtract_fee_from_outputs.is_empty() {
+        return Err(RpcError::new(
+            RpcError::INVALID_PARAMETER,
+            "subtractFeeFromOutputs is not supported",
+        ));
+    }
+    let fee_rate = match (&options.fee_rate, &options.fee_rate_btc_kvb) {
+        (Some(_), Some(_)) => {
+            return Err(RpcError::new(
+                RpcError::INVALID_PARAMETER,
+                "Cannot specify both fee_rate (sat/vB) and feeRate (BTC/kvB)",
+            ))
+        }
+        (Some(sat_per_vb), None) => Some(parse_sat_per_vb(sat_per_vb)?),
+        (None, Some(btc_per_kvb)) => Some(FeeRate::from_sat_per_vb(
+            parse_btc(Some(btc_per_kvb))?.to_sat() as f32 / 1000.0,
+        )),
+        (None, None) => None,
+    };
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    // Spend from the latest UTXOs, not the last background sync
+    state.manager.sync(&wallet_id, &state.cancel).await?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let mut wallet = wallet.lock().await;
+    let network = wallet.network();
+
+    let change = options
+        .change_address
+        .as_deref()
+        .map(|address| script_for(address, network))
+        .transpose()?;
+    let funding = Funding {
+        add_inputs: options.add_inputs.unwrap_or(inputs.is_empty()),
+        inputs: inputs
+            .iter()
+            .map(|input| OutPoint::new(input.txid, input.vout))
+            .collect(),
+        outputs: parse_outputs(params.required("outputs")?, network)?,
+        change: change.clone(),
+        locktime,
+        fee_rate,
+        replaceable: options.replaceable,
+    };
+
+    let psbt = fund(&mut wallet, funding)?;
+
+    let changepos = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .position(|output| match &change {
+            Some(change) => output.script_pubkey == *change,
+            None => wallet
+                .bdk
+                .derivation_of_spk(output.script_pubkey.clone())
+                .is_some_and(|(keychain, _)| keychain == KeychainKind::Internal),
+        })
+        .map_or(-1, |position| position as i64);
+
+    Ok(json!({
+        "psbt": psbt.to_string(),
+        "fee": psbt.fee().map(|fee| fee.to_btc()).unwrap_or_default(),
+        "changepos": changepos,
+    }))
+}
+
+async fn wallet_process_psbt(
+    state: &DaemonState,
+    wallet: Option<&str>,
+    params: Params,
+) -> MethodResult {
+    let psbt: String = params.required("psbt")?;
+    let mut psbt = crate::wallet::psbt::decode_psbt(psbt.as_bytes()).map_err(|e| {
+        RpcError::new(
+            RpcError::DESERIALIZATION_ERROR,
+            format!("TX decode failed {e}"),
+        )
+    })?;
+    let sign = params.get("sign")?.unwrap_or(true);
+    if let Some(sighash) = params.get::<String>("sighashtype")? {
+        if !matches!(sighash.as_str(), "DEFAULT" | "ALL") {
+            return Err(RpcError::new(
+                RpcError::INVALID_PARAMETER,
+                format!("Sighash type {sighash} is not supported"),
+            ));
+        }
+    }
+
+    let wallet_id = wallet_id(&state.manager, wallet)?;
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let mut wallet = wallet.lock().await;
+
+    let complete = if sign {
+        wallet.sign_psbt(&mut psbt)?
+    } else {
+        wallet.finalize_psbt(&mut psbt)?
+    };
+
+    let mut result = json!({
+        "psbt": psbt.to_string(),
+        "complete": complete,
+    });
+    if complete {
+        let transaction = wallet.extract_transaction(psbt)?;
+        result["hex"] = json!(bitcoin::consensus::encode::serialize_hex(&transaction));
+    }
+    Ok(result)
+}
+
+/// Wallet an RPC is for, bitcoind requires a wallet path once several are loaded
+fn wallet_id(manager: &WalletManager, wallet: Option<&str>) -> Result<WalletId, RpcError> {
+    if let Some(wallet) = wallet {
+        return manager.find_wallet(wallet).map_err(|_| {
+            RpcError::new(
+                RpcError::WALLET_NOT_FOUND,
+                "Requested wallet does not exist or is not loaded",
+            )
+        });
+    }
+
+    match manager.list_wallet_ids().as_slice() {
+        [wallet_id] => Ok(wallet_id.clone()),
+        [] => Err(RpcError::new(
+            RpcError::WALLET_NOT_FOUND,
+            "No wallet is loaded. Create one with lumo first",
+        )),
+        _ => Err(RpcError::new(
+            RpcError::WALLET_NOT_SPECIFIED,
+            "Wallet file not specified (must request wallet RPC through /wallet/<filename> uri-path).",
+        )),
+    }
+}
+
+/// What to fund, the parts of bitcoind's funding options lumo supports
+#[derive(Debug, Default)]
+struct Funding {
+    inputs: Vec<OutPoint>,
+    /// Add wallet coins beyond `inputs` when they aren't enough
+    add_inputs: bool,
+    outputs: Vec<Output>,
+    change: Option<ScriptBuf>,
+    locktime: Option<u32>,
+    fee_rate: Option<FeeRate>,
+    replaceable: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Output {
+    Pay(ScriptBuf, bitcoin::Amount),
+    Data(Vec<u8>),
+}
+
+fn fund(wallet: &mut Wallet, funding: Funding) -> Result<Psbt, RpcError> {
+    let fee_rate = funding
+        .fee_rate
+        .unwrap_or_else(|| FeeRate::from_sat_per_vb(DEFAULT_FEE_RATE));
+
+    let mut builder = wallet.bdk.build_tx();
+    builder.fee_rate(fee_rate.into());
+
+    if !funding.inputs.is_empty() {
+        builder.add_utxos(&funding.inputs).map_err(|e| {
+            RpcError::new(
+                RpcError::INVALID_PARAMETER,
+                format!("Invalid parameter, {e}"),
+            )
+        })?;
+        if !funding.add_inputs {
+            builder.manually_selected_only();
+        }
+    }
+
+    for output in funding.outputs {
+        match output {
+            Output::Pay(script, amount) => {
+                builder.add_recipient(script, amount);
+            }
+            Output::Data(data) => {
+                let data = bitcoin::script::PushBytesBuf::try_from(data).map_err(|_| {
+                    RpcError::new(RpcError::INVALID_PARAMETER, "Data output is too large")
+                })?;
+                builder.add_data(&data);
+            }
+        }
+    }
+
+    if let Some(change) = funding.change {
+        builder.drain_to(change);
+    }
+    if let Some(locktime) = funding.locktime {
+        builder.nlocktime(bitcoin::absolute::LockTime::from_consensus(locktime));
+    }
+    if funding.replaceable == Some(false) {
+        builder.set_exact_sequence(bitcoin::Sequence::ENABLE_LOCKTIME_NO_RBF);
+    }
+
+    builder.finish().map_err(|e| match e {
+        CreateTxError::CoinSelection(e) => RpcError::new(
+            RpcError::INSUFFICIENT_FUNDS,
+            format!("Insufficient funds: {e}"),
+        ),
+        e => RpcError::new(
+            RpcError::WALLET_ERROR,
+            format!("Error building transaction: {e}"),
+        ),
+    })
+}
+
+/// `outputs` as bitcoind takes it: `[{"address": amount}, {"data": "hex"}]` or one object
+fn parse_outputs(outputs: Value, network: Network) -> Result<Vec<Output>, RpcError> {
+    let pairs: Vec<(String, Value)> = match outputs {
+        Value::Array(outputs) => outputs
+            .into_iter()
+            .map(|output| match output {
+                Value::Object(output) => Ok(output.into_iter().collect::<Vec<_>>()),
+                _ => Err(RpcError::new(
+                    RpcError::INVALID_PARAMETER,
+                    "Invalid parameter, key-value pair must be an object",
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect(),
+        Value::Object(outputs) => outputs.into_iter().collect(),
+        _ => {
+            return Err(RpcError::new(
+                RpcError::TYPE_ERROR,
+                "outputs must be an array or object",
+            ))
+        }
+    };
+
+    if pairs.is_empty() {
+        return Err(RpcError::new(
+            RpcError::INVALID_PARAMETER,
+            "Invalid parameter, outputs are empty",
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    pairs
+        .into_iter()
+        .map(|(key, value)| {
+            if !seen.insert(key.clone()) {
+                return Err(RpcError::new(
+                    RpcError::INVALID_PARAMETER,
+                    format!("Invalid parameter, duplicated address: {key}"),
+                ));
+            }
+
+            if key == "data" {
+                let data = value.as_str().and_then(|data| hex::decode(data).ok());
+                return data.map(Output::Data).ok_or_else(|| {
+                    RpcError::new(RpcError::INVALID_PARAMETER, "Data must be hexadecimal")
+                });
+            }
+
+            Ok(Output::Pay(
+                script_for(&key, network)?,
+                parse_btc(Some(&value))?,
+            ))
+        })
+        .collect()
+}
+
+/// A BTC amount given as a JSON number or string, like bitcoind accepts
+fn parse_btc(value: Option<&Value>) -> Result<bitcoin::Amount, RpcError> {
+    let invalid = || RpcError::new(RpcError::TYPE_ERROR, "Invalid amount");
+    match value {
+        Some(Value::Number(number)) => {
+            bitcoin::Amount::from_btc(number.as_f64().ok_or_else(invalid)?).map_err(|_| invalid())
+        }
+        Some(Value::String(amount)) => {
+            bitcoin::Amount::from_str_in(amount, Denomination::Bitcoin).map_err(|_| invalid())
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_sat_per_vb(value: &Value) -> Result<FeeRate, RpcError> {
+    let sat_per_vb = match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    };
+
+    match sat_per_vb {
+        Some(sat_per_vb) if sat_per_vb > 0.0 => Ok(FeeRate::from_sat_per_vb(sat_per_vb as f32)),
+        _ => Err(RpcError::new(RpcError::TYPE_ERROR, "Invalid fee_rate")),
+    }
+}
+
+fn script_for(address: &str, network: Network) -> Result<ScriptBuf, RpcError> {
+    Address::from_string(address, network)
+        .map(|address| address.to_bdk_address().script_pubkey())
+        .map_err(|e| {
+            RpcError::new(
+                RpcError::INVALID_ADDRESS,
+                format!("Invalid Bitcoin address: {address} ({e})"),
+            )
+        })
+}
+
+fn address_of(script: &bitcoin::Script, network: Network) -> Option<String> {
+    bitcoin::Address::from_script(script, network.to_bitcoin_network())
+        .ok()
+        .map(|address| address.to_string())
+}
+
+/// Address labels by address, what bitcoind calls an address's label
+fn address_labels(wallet: &Wallet) -> Result<HashMap<String, String>, RpcError> {
+    Ok(wallet
+        .labels()?
+        .into_iter()
+        .filter(|label| label.label_type == LabelType::Addr)
+        .map(|label| (label.reference, label.label))
+        .collect())
+}
+
+fn confirmations(position: &ChainPosition<ConfirmationBlockTime>, tip: u32) -> u32 {
+    match position {
+        ChainPosition::Confirmed { anchor, .. } => tip.saturating_sub(anchor.block_id.height) + 1,
+        ChainPosition::Unconfirmed { .. } => 0,
+    }
+}
+
+fn merge(entry: &mut Value, fields: &Value) {
+    if let (Value::Object(entry), Value::Object(fields)) = (entry, fields) {
+        entry.extend(fields.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::auth::Cookie;
+    use crate::daemon::http;
+    use crate::daemon::server::{Daemon, DaemonConfig};
+    use std::net::SocketAddr;
+    use tokio::io::BufReader;
+    use tokio::net::TcpStream;
+
+    const ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+    #[test]
+    fn test_params_by_position_and_name() {
+        let names = &["label", "count", "skip"];
+
+        let params = Params::new(json!(["*", 5]), names).unwrap();
+        assert_eq!(params.get::<String>("label").unwrap().as_deref(), Some("*"));
+        assert_eq!(params.get::<usize>("count").unwrap(), Some(5));
+        assert_eq!(params.get::<usize>("skip").unwrap(), None);
+
+        let params = Params::new(json!({"skip": 2}), names).unwrap();
+        assert_eq!(params.get::<usize>("skip").unwrap(), Some(2));
+        assert_eq!(params.get::<usize>("count").unwrap(), None);
+
+        let error = Params::new(json!({"verbose": true}), names).err().unwrap();
+        assert_eq!(error.code, RpcError::INVALID_PARAMETER);
+        let error = Params::new(json!([1, 2, 3, 4]), names).err().unwrap();
+        assert_eq!(error.code, RpcError::MISC_ERROR);
+
+        let params = Params::new(json!(["*", "ten"]), names).unwrap();
+        assert_eq!(
+            params.get::<usize>("count").unwrap_err().code,
+            RpcError::TYPE_ERROR
+        );
+        assert_eq!(
+            params.required::<String>("skip").unwrap_err().code,
+            RpcError::MISC_ERROR
+        );
+    }
+
+    #[test]
+    fn test_parse_btc() {
+        let sats = |value: Value| parse_btc(Some(&value)).map(|amount| amount.to_sat());
+        assert_eq!(sats(json!(0.1)).unwrap(), 10_000_000);
+        assert_eq!(sats(json!(21)).unwrap(), 2_100_000_000);
+        assert_eq!(sats(json!("0.00000001")).unwrap(), 1);
+        assert!(sats(json!(-1)).is_err());
+        assert!(sats(json!(0.000000001)).is_err());
+        assert!(sats(json!(true)).is_err());
+        assert!(parse_btc(None).is_err());
+    }
+
+    #[test]
+    fn test_wallet_from_path() {
+        assert_eq!(wallet_from_path("/"), Some(None));
+        assert_eq!(
+            wallet_from_path("/wallet/My%20Savings"),
+            Some(Some("My Savings".to_string()))
+        );
+        assert_eq!(wallet_from_path("/wallet/bad%2"), None);
+        assert_eq!(wallet_from_path("/rest/chaininfo.json"), None);
+    }
+
+    #[test]
+    fn test_parse_outputs() {
+        let outputs = parse_outputs(
+            json!([{ADDRESS: 0.5}, {"data": "6c756d6f"}]),
+            Network::Testnet,
+        )
+        .unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert!(matches!(&outputs[0], Output::Pay(_, amount) if amount.to_sat() == 50_000_000));
+        assert_eq!(outputs[1], Output::Data(b"lumo".to_vec()));
+
+        let error =
+            parse_outputs(json!([{ADDRESS: 1}, {ADDRESS: 2}]), Network::Testnet).unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_PARAMETER);
+
+        let error = parse_outputs(json!({ADDRESS: 1}), Network::Mainnet).unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_ADDRESS);
+
+        let error = parse_outputs(json!([]), Network::Testnet).unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_PARAMETER);
+    }
+
+    async fn post(addr: SocketAddr, cookie: &Cookie, path: &str, body: Value) -> (u16, Value) {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (reader, mut writer) = tokio::io::split(stream);
+        let body = serde_json::to_vec(&body).unwrap();
+        http::write_request(&mut writer, path, &cookie.authorization(), &body)
+            .await
+            .unwrap();
+
+        let response = http::read_response(&mut BufReader::new(reader))
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&response.body).unwrap_or(Value::Null);
+        (response.status, body)
+    }
+
+    #[tokio::test]
+    async fn test_bitcoind_listener() {
+        let daemon = Daemon::start(DaemonConfig {
+            socket: None,
+            listen: None,
+            sync_interval: None,
+            bitcoind_listen: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            payjoin_listen: None,
+        })
+        .await
+        .unwrap();
+
+        let addr = daemon.bitcoind_addr().unwrap();
+        let cookie = daemon.cookie().clone();
+        let server = tokio::spawn(daemon.serve(std::future::pending()));
+
+        let (status, response) = post(
+            addr,
+            &cookie,
+            "/",
+            json!({"jsonrpc": "1.0", "id": "t", "method": "listwallets", "params": []}),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(response["id"], "t");
+        assert!(response["result"].is_array());
+        assert!(response["error"].is_null());
+
+        let (status, response) = post(
+            addr,
+            &cookie,
+            "/wallet/no%20such%20wallet",
+            json!({"id": 1, "method": "getbalance"}),
+        )
+        .await;
+        assert_eq!(status, 500);
+        assert_eq!(response["error"]["code"], RpcError::WALLET_NOT_FOUND);
+        assert!(response["result"].is_null());
+
+        let (status, response) = post(
+            addr,
+            &cookie,
+            "/",
+            json!([
+                {"id": 1, "method": "getblockchaininfo"},
+                {"id": 2, "method": "listwallets"},
+            ]),
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(response[0]["error"]["code"], RpcError::METHOD_NOT_FOUND);
+        assert!(response[1]["result"].is_array());
+
+        let (status, _) = post(
+            addr,
+            &cookie,
+            "/",
+            json!({"id": 1, "method": "getblockchaininfo"}),
+        )
+        .await;
+        assert_eq!(status, 404);
+
+        let (status, _) = post(
+            addr,
+            &Cookie::generate(),
+            "/",
+            json!({"method": "listwallets"}),
+        )
+        .await;
+        assert_eq!(status, 401);
+
+        server.abort();
+    }
+}
@@ -0,0 +1,257 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader, Lines};
+use tokio::net::TcpStream;
+
+use crate::daemon::auth::{cookie_path, Cookie};
+use crate::daemon::error::DaemonError;
+use crate::daemon::http;
+use crate::daemon::protocol::{Notification, Request, Response};
+use crate::daemon::DEFAULT_RPC_PORT;
+use crate::wallet::events::WalletEvent;
+
+/// Where a daemon can be reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "{}", path.display()),
+            Endpoint::Tcp(addr) => write!(f, "http://{addr}"),
+        }
+    }
+}
+
+/// JSON-RPC client for a running `lumod`
+#[derive(Debug)]
+pub struct DaemonClient {
+    endpoint: Endpoint,
+    cookie: Cookie,
+    next_id: AtomicU64,
+}
+
+impl DaemonClient {
+    pub fn new(endpoint: Endpoint, cookie: Cookie) -> Self {
+        Self {
+            endpoint,
+            cookie,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// The daemon serving the current data directory, if one is running
+    ///
+    /// Tries the Unix socket first, then the default TCP port.
+    pub async fn discover() -> Option<Self> {
+        let cookie = Cookie::read(&cookie_path()).ok()?;
+
+        #[cfg(unix)]
+        {
+            let socket = crate::daemon::socket_path();
+            if tokio::net::UnixStream::connect(&socket).await.is_ok() {
+                return Some(Self::new(Endpoint::Unix(socket), cookie));
+            }
+        }
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], DEFAULT_RPC_PORT));
+        TcpStream::connect(addr).await.ok()?;
+        Some(Self::new(Endpoint::Tcp(addr), cookie))
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Call `method` with named `params` and decode the result
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, DaemonError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = serde_json::to_vec(&Request::new(id, method, params))?;
+
+        let response = match &self.endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                self.exchange(stream, &body).await?
+            }
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                self.exchange(stream, &body).await?
+            }
+        };
+
+        if response.status == 401 {
+            return Err(DaemonError::Unauthorized(
+                "lumod rejected the cookie, is it from an older run?".to_string(),
+            ));
+        }
+
+        let response: Response = serde_json::from_slice(&response.body).map_err(|_| {
+            DaemonError::Http(format!("lumod answered with HTTP {}", response.status))
+        })?;
+        Ok(serde_json::from_value(response.into_result()?)?)
+    }
+
+    /// Stream events for a wallet's syncs, or every wallet's without one
+    pub async fn subscribe(&self, wallet: Option<&str>) -> Result<EventStream, DaemonError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = serde_json::to_vec(&Request::new(id, "subscribe", json!({ "wallet": wallet })))?;
+
+        let reader: Box<dyn AsyncRead + Send + Unpin> = match &self.endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                self.open_stream(stream, &body).await?
+            }
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                self.open_stream(stream, &body).await?
+            }
+        };
+
+        let mut reader = BufReader::new(reader);
+        if http::read_stream_head(&mut reader).await? == 401 {
+            return Err(DaemonError::Unauthorized(
+                "lumod rejected the cookie, is it from an older run?".to_string(),
+            ));
+        }
+
+        // The first line confirms the subscription, or is the whole error response
+        let mut lines = reader.lines();
+        let first = lines
+            .next_line()
+            .await?
+            .ok_or_else(|| DaemonError::Http("lumod closed the subscription".to_string()))?;
+        serde_json::from_str::<Response>(&first)?.into_result()?;
+
+        Ok(EventStream { lines })
+    }
+
+    async fn open_stream<S: AsyncRead + AsyncWrite + Send + 'static>(
+        &self,
+        stream: S,
+        body: &[u8],
+    ) -> Result<Box<dyn AsyncRead + Send + Unpin>, DaemonError> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        http::write_request(&mut writer, "/", &self.cookie.authorization(), body).await?;
+        Ok(Box::new(reader))
+    }
+
+    async fn exchange<S: AsyncRead + AsyncWrite>(
+        &self,
+        stream: S,
+        body: &[u8],
+    ) -> Result<http::HttpResponse, DaemonError> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        http::write_request(&mut writer, "/", &self.cookie.authorization(), body).await?;
+        http::read_response(&mut BufReader::new(reader)).await
+    }
+}
+
+/// Events from [`DaemonClient::subscribe`]
+pub struct EventStream {
+    lines: Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>,
+}
+
+impl EventStream {
+    /// The next event, `None` once the daemon stops
+    pub async fn next(&mut self) -> Option<Result<WalletEvent, DaemonError>> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            let notification = match serde_json::from_str::<Notification>(&line) {
+                Ok(notification) => notification,
+                Err(e) => return Some(Err(e.into())),
+            };
+
+            return Some(match notification.method.as_str() {
+                "event" => serde_json::from_value(notification.params).map_err(Into::into),
+                "lagged" => Err(DaemonError::Lagged(
+                    notification.params["missed"].as_u64().unwrap_or_default(),
+                )),
+                method => Err(DaemonError::Http(format!(
+                    "Unexpected notification {method}"
+                ))),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::methods::DaemonInfo;
+    use crate::daemon::protocol::RpcError;
+    use crate::daemon::server::{Daemon, DaemonConfig};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_client_talks_to_daemon() {
+        let daemon = Daemon::start(DaemonConfig {
+            socket: None,
+            listen: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            sync_interval: None,
+            bitcoind_listen: None,
+            payjoin_listen: None,
+        })
+        .await
+        .unwrap();
+
+        let endpoint = Endpoint::Tcp(daemon.local_addr().unwrap());
+        let client = DaemonClient::new(endpoint.clone(), daemon.cookie().clone());
+        let server = tokio::spawn(daemon.serve(std::future::pending()));
+
+        let info: DaemonInfo = client.call("getinfo", Value::Null).await.unwrap();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+        let error = client
+            .call::<Value>("nosuchmethod", Value::Null)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            DaemonError::Rpc(RpcError {
+                code: RpcError::METHOD_NOT_FOUND,
+                ..
+            })
+        ));
+
+        let error = client
+            .call::<Value>("getbalance", json!({"wallet": "no such wallet"}))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            DaemonError::Rpc(RpcError {
+                code: RpcError::WALLET_NOT_FOUND,
+                ..
+            })
+        ));
+
+        let stranger = DaemonClient::new(endpoint, Cookie::generate());
+        assert!(matches!(
+            stranger.call::<Value>("getinfo", Value::Null).await,
+            Err(DaemonError::Unauthorized(_))
+        ));
+
+        let _: Value = client.call("stop", Value::Null).await.unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+use crate::daemon::protocol::RpcError;
+use thiserror::Error;
+
+/// Daemon and daemon client errors
+#[derive(Error, Debug)]
+pub enum DaemonError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+
+    #[error("Wallet error: {0}")]
+    Wallet(#[from] crate::wallet::error::WalletError),
+
+    #[error("lumod is already running: {0}")]
+    AlreadyRunning(String),
+
+    #[error("Invalid daemon configuration: {0}")]
+    Config(String),
+
+    #[error("Fell behind and missed {0} events")]
+    Lagged(u64),
+}
+
+impl From<crate::lock::LockError> for DaemonError {
+    fn from(err: crate::lock::LockError) -> Self {
+        match err {
+            crate::lock::LockError::Busy(path) => DaemonError::AlreadyRunning(path),
+            crate::lock::LockError::Io { path, error } => DaemonError::Io(std::io::Error::new(
+                error.kind(),
+                format!("{path}: {error}"),
+            )),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Just enough HTTP/1.1 to carry JSON-RPC: one request per connection, bodies
+//! sized by `Content-Length`
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+
+use crate::daemon::error::DaemonError;
+
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+
+/// Largest request or response body accepted, enough for big PSBTs
+pub const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Largest body accepted from unauthenticated clients, e.g. payjoin senders
+pub const MAX_PUBLIC_BODY_BYTES: usize = 100 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn json(body: Vec<u8>) -> Self {
+        Self { status: 200, body }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        let body = serde_json::json!({ "error": message })
+            .to_string()
+            .into_bytes();
+        Self { status, body }
+    }
+}
+
+/// Start line and headers of a request, so it can be checked before the body is read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub authorization: Option<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Read the body, refusing one over `max_bytes`
+    pub async fn read_body<R: AsyncRead + Unpin>(
+        self,
+        reader: &mut R,
+        max_bytes: usize,
+    ) -> Result<HttpRequest, DaemonError> {
+        let body = read_body(reader, &self.headers, max_bytes).await?;
+        Ok(HttpRequest {
+            method: self.method,
+            path: self.path,
+            authorization: self.authorization,
+            body,
+        })
+    }
+}
+
+pub async fn read_request_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<RequestHead, DaemonError> {
+    let (start_line, headers) = read_head(reader).await?;
+
+    let mut parts = start_line.split_whitespace();
+    let (Some(method), Some(path), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(DaemonError::Http(format!(
+            "Invalid request line: {start_line}"
+        )));
+    };
+
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization: header(&headers, "authorization").map(str::to_string),
+        headers,
+    })
+}
+
+pub async fn write_request<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    path: &str,
+    authorization: &str,
+    body: &[u8],
+) -> Result<(), DaemonError> {
+    let head = format!(
+        "POST {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: {authorization}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn read_response<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<HttpResponse, DaemonError> {
+    let (status, headers) = read_status(reader).await?;
+    Ok(HttpResponse {
+        status,
+        body: read_body(reader, &headers, MAX_BODY_BYTES).await?,
+    })
+}
+
+/// Status of a response whose body is read by the caller, e.g. a stream
+pub async fn read_stream_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<u16, DaemonError> {
+    let (status, _) = read_status(reader).await?;
+    Ok(status)
+}
+
+/// Head of a response whose body is JSON lines that run until the connection closes
+pub async fn write_stream_head<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<(), DaemonError> {
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// One message of a stream, flushed so the client sees it right away
+pub async fn write_line<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &impl serde::Serialize,
+) -> Result<(), DaemonError> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: &HttpResponse,
+) -> Result<(), DaemonError> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.body.len()
+    );
+    if response.status == 401 {
+        head.push_str("WWW-Authenticate: Basic realm=\"lumod\"\r\n");
+    }
+    head.push_str("\r\n");
+
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_status<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(u16, Vec<(String, String)>), DaemonError> {
+    let (status_line, headers) = read_head(reader).await?;
+
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| DaemonError::Http(format!("Invalid status line: {status_line}")))?;
+    Ok((status, headers))
+}
+
+/// Start line and headers, header names lowercased
+async fn read_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<(String, Vec<(String, String)>), DaemonError> {
+    let mut limited = reader.take(MAX_HEAD_BYTES);
+
+    let mut start_line = String::new();
+    if limited.read_line(&mut start_line).await? == 0 {
+        return Err(DaemonError::Http(
+            "Connection closed without a message".to_string(),
+        ));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if limited.read_line(&mut line).await? == 0 {
+            return Err(DaemonError::Http(
+                "Headers too large or cut short".to_string(),
+            ));
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| DaemonError::Http(format!("Invalid header: {line}")))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    Ok((start_line.trim_end().to_string(), headers))
+}
+
+async fn read_body<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    headers: &[(String, String)],
+    max_bytes: usize,
+) -> Result<Vec<u8>, DaemonError> {
+    let length = match header(headers, "content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| DaemonError::Http(format!("Invalid Content-Length: {length}")))?,
+        None => 0,
+    };
+
+    if length > max_bytes {
+        return Err(DaemonError::Http(format!(
+            "Body of {length} bytes is over the {max_bytes} byte limit"
+        )));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        500 => "Internal Server Error",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn test_request_and_response_round_trip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (client_read, mut client_write) = tokio::io::split(client);
+        let (server_read, mut server_write) = tokio::io::split(server);
+
+        let body = br#"{"method":"getinfo"}"#;
+        write_request(&mut client_write, "/", "Basic abc", body)
+            .await
+            .unwrap();
+
+        let mut server_read = BufReader::new(server_read);
+        let head = read_request_head(&mut server_read).await.unwrap();
+        let request = head
+            .read_body(&mut server_read, MAX_BODY_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/");
+        assert_eq!(request.authorization.as_deref(), Some("Basic abc"));
+        assert_eq!(request.body, body);
+
+        let response = HttpResponse::error(401, "Invalid cookie");
+        write_response(&mut server_write, &response).await.unwrap();
+        let read = read_response(&mut BufReader::new(client_read))
+            .await
+            .unwrap();
+        assert_eq!(read, response);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected() {
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_PUBLIC_BODY_BYTES + 1
+        );
+        let mut reader = BufReader::new(head.as_bytes());
+        let head = read_request_head(&mut reader).await.unwrap();
+        assert!(matches!(
+            head.read_body(&mut reader, MAX_PUBLIC_BODY_BYTES).await,
+            Err(DaemonError::Http(_))
+        ));
+    }
+}
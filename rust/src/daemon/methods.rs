@@ -0,0 +1,645 @@
+//! The daemon's JSON-RPC methods
+//!
+//! Params are named. Methods that work on a wallet take an optional `wallet`,
+//! its id or name, and default to the selected wallet.
+//!
+//! `subscribe` is handled by the server, it turns the connection into a stream
+//! of `event` notifications, see [`subscription`].
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::psbt::Psbt;
+use bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::daemon::protocol::{parse_params, RpcError};
+use crate::daemon::server::DaemonState;
+use crate::wallet::balance::Balance;
+use crate::wallet::invoice::{Invoice, InvoiceRequest};
+use crate::wallet::multisig::{PendingPsbt, SignatureStatus};
+use crate::wallet::payjoin::PayjoinOutcome;
+use crate::wallet::{Birthday, Wallet, WalletId, WalletMetadata, WalletType};
+use crate::wallet_manager::{SendOutcome, WalletHandle, WalletManager};
+use lumo_types::{Address, Amount, FeeRate, Label, LabelType, Network, Transaction};
+
+type MethodResult = Result<Value, RpcError>;
+
+/// Fee rate used when a send doesn't give one, in sat/vB
+pub(crate) const DEFAULT_FEE_RATE: f32 = 10.0;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonInfo {
+    pub version: String,
+    pub data_dir: String,
+    pub loaded_wallets: usize,
+    pub selected_wallet: Option<WalletId>,
+}
+
+/// A wallet's public details, secrets never leave the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletInfo {
+    pub id: WalletId,
+    pub name: String,
+    pub network: Network,
+    pub wallet_type: WalletType,
+    pub master_fingerprint: Option<String>,
+    pub birthday: Option<Birthday>,
+    pub archived: bool,
+}
+
+impl From<&WalletMetadata> for WalletInfo {
+    fn from(metadata: &WalletMetadata) -> Self {
+        Self {
+            id: metadata.id.clone(),
+            name: metadata.name.clone(),
+            network: metadata.network,
+            wallet_type: metadata.wallet_type,
+            master_fingerprint: metadata.master_fingerprint.clone(),
+            birthday: metadata.birthday,
+            archived: metadata.archived,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedWallet {
+    pub wallet: WalletInfo,
+    /// Only set for a newly generated seed, shown once so it can be written down
+    pub mnemonic: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressResult {
+    pub address: String,
+    pub index: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceResult {
+    pub confirmed: Amount,
+    pub spendable: Amount,
+    pub total: Amount,
+}
+
+/// A PSBT after a daemon operation, base64 encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PsbtResult {
+    pub psbt: String,
+    pub finalized: bool,
+    pub fee: Option<Amount>,
+    /// Signatures collected so far, for multisig wallets
+    pub signatures: Option<SignatureStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendResult {
+    /// Set once the transaction is broadcast
+    pub txid: Option<String>,
+    /// Set when a multisig transaction is waiting on cosigners
+    pub pending: Option<PsbtResult>,
+    /// What came of a payjoin, when the send asked for one
+    #[serde(default)]
+    pub payjoin: Option<PayjoinResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayjoinResult {
+    /// Whether the receiver's proposal was broadcast, rather than the original
+    pub payjoined: bool,
+    /// Why the payjoin fell back to the original
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxidResult {
+    pub txid: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureResult {
+    /// Base64 BIP322 simple or legacy signature
+    pub signature: String,
+}
+
+#[derive(Deserialize)]
+struct WalletParams {
+    #[serde(default)]
+    wallet: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListWalletsParams {
+    #[serde(default)]
+    network: Option<String>,
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Deserialize)]
+struct CreateWalletParams {
+    name: String,
+    network: String,
+    #[serde(default)]
+    mnemonic: Option<String>,
+    #[serde(default)]
+    birthday: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SelectWalletParams {
+    wallet: String,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    #[serde(default)]
+    index: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    address: String,
+    /// Amount in satoshis
+    amount: u64,
+    /// Fee rate in sat/vB
+    #[serde(default)]
+    fee_rate: Option<f32>,
+    /// BIP78 endpoint from the recipient's URI, `send` only
+    #[serde(default)]
+    payjoin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PsbtParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    psbt: String,
+}
+
+#[derive(Deserialize)]
+struct CombinePsbtParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    psbts: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateInvoiceParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    /// Amount in satoshis
+    amount: u64,
+    #[serde(default)]
+    memo: Option<String>,
+    /// Seconds until the invoice expires, 0 for never
+    #[serde(default)]
+    expiry: Option<u64>,
+    /// Confirmations a payment needs to count
+    #[serde(default)]
+    confirmations: Option<u32>,
+    /// Payjoin endpoint to offer in the URI
+    #[serde(default)]
+    payjoin: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InvoiceParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    /// Id, start of an id, or address
+    invoice: String,
+}
+
+#[derive(Deserialize)]
+struct SetLabelParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    label_type: String,
+    reference: String,
+    label: String,
+}
+
+#[derive(Deserialize)]
+struct SignMessageParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    address: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct ProveReservesParams {
+    #[serde(default)]
+    wallet: Option<String>,
+    message: String,
+    /// Outpoints (txid:vout) to cover, every unspent output when empty
+    #[serde(default)]
+    coins: Vec<String>,
+}
+
+pub async fn dispatch(state: &DaemonState, method: &str, params: Value) -> MethodResult {
+    match method {
+        "getinfo" => get_info(state).await,
+        "stop" => {
+            state.shutdown.notify_one();
+            to_value("lumod stopping")
+        }
+        "listwallets" => list_wallets(parse_params(params)?),
+        "createwallet" => create_wallet(state, parse_params(params)?).await,
+        "selectwallet" => select_wallet(state, parse_params(params)?).await,
+        "sync" => sync(state, parse_params(params)?).await,
+        "getaddress" => get_address(state, parse_params(params)?).await,
+        "getnewaddress" => get_new_address(state, parse_params(params)?).await,
+        "getbalance" => get_balance(state, parse_params(params)?).await,
+        "listtransactions" => list_transactions(state, parse_params(params)?).await,
+        "send" => send(state, parse_params(params)?).await,
+        "createpsbt" => create_psbt(state, parse_params(params)?).await,
+        "signpsbt" => sign_psbt(state, parse_params(params)?).await,
+        "combinepsbt" => combine_psbt(state, parse_params(params)?).await,
+        "finalizepsbt" => finalize_psbt(state, parse_params(params)?).await,
+        "broadcastpsbt" => broadcast_psbt(state, parse_params(params)?).await,
+        "listpendingpsbts" => list_pending_psbts(state, parse_params(params)?).await,
+        "setlabel" => set_label(state, parse_params(params)?).await,
+        "listlabels" => list_labels(state, parse_params(params)?).await,
+        "createinvoice" => create_invoice(state, parse_params(params)?).await,
+        "listinvoices" => list_invoices(state, parse_params(params)?).await,
+        "getinvoice" => get_invoice(state, parse_params(params)?).await,
+        "signmessage" => sign_message(state, parse_params(params)?).await,
+        "provereserves" => prove_reserves(state, parse_params(params)?).await,
+        _ => Err(RpcError::method_not_found(method)),
+    }
+}
+
+async fn get_info(state: &DaemonState) -> MethodResult {
+    to_value(DaemonInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        data_dir: lumo_common::ROOT_DATA_DIR.display().to_string(),
+        loaded_wallets: state.manager.list_wallet_ids().len(),
+        selected_wallet: state.manager.active_wallet_id()?,
+    })
+}
+
+/// Wallet a `subscribe` call is for, `None` when it didn't name one and wants them all
+pub(crate) fn subscription(
+    state: &DaemonState,
+    params: Value,
+) -> Result<Option<WalletId>, RpcError> {
+    let params: WalletParams = parse_params(params)?;
+    params
+        .wallet
+        .map(|wallet| state.manager.find_wallet(&wallet))
+        .transpose()
+        .map_err(RpcError::from)
+}
+
+fn list_wallets(params: ListWalletsParams) -> MethodResult {
+    let network = params.network.as_deref().map(parse_network).transpose()?;
+    let wallets = if params.archived {
+        Wallet::list_archived(network)?
+    } else {
+        Wallet::list_all(network)?
+    };
+
+    to_value(wallets.iter().map(WalletInfo::from).collect::<Vec<_>>())
+}
+
+async fn create_wallet(state: &DaemonState, params: CreateWalletParams) -> MethodResult {
+    let network = parse_network(&params.network)?;
+    let birthday = params
+        .birthday
+        .as_deref()
+        .map(Birthday::from_str)
+        .transpose()?;
+
+    let (wallet, mnemonic) = match params.mnemonic {
+        Some(mnemonic) => {
+            let mut wallet = Wallet::new_from_mnemonic(params.name, &mnemonic, network)?;
+            if let Some(birthday) = birthday {
+                wallet.set_birthday(birthday)?;
+            }
+            (wallet, None)
+        }
+        None if birthday.is_some() => {
+            return Err(RpcError::invalid_params(
+                "a birthday can only be given with a mnemonic",
+            ))
+        }
+        None => {
+            let (wallet, mnemonic) = Wallet::new_random(params.name, network)?;
+            (wallet, Some(mnemonic.to_string()))
+        }
+    };
+
+    let info = WalletInfo::from(&wallet.metadata);
+    state.manager.add_wallet(wallet)?;
+    to_value(CreatedWallet {
+        wallet: info,
+        mnemonic,
+    })
+}
+
+async fn select_wallet(state: &DaemonState, params: SelectWalletParams) -> MethodResult {
+    let wallet_id = state.manager.find_wallet(&params.wallet)?;
+    state.manager.set_active_wallet(wallet_id.clone())?;
+
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let info = WalletInfo::from(&wallet.lock().await.metadata);
+    to_value(info)
+}
+
+async fn sync(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    state.manager.sync(&wallet_id, &state.cancel).await?;
+    let balance = state.manager.balance(&wallet_id).await?;
+    to_value(BalanceResult::from(balance))
+}
+
+async fn get_address(state: &DaemonState, params: AddressParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let wallet = wallet.lock().await;
+
+    let address = match params.index {
+        Some(index) => wallet.address_at(index)?,
+        None => wallet.get_current_address()?,
+    };
+    // The index of the address returned, whichever way it was picked
+    let index = wallet
+        .bdk
+        .derivation_of_spk(address.script_pubkey())
+        .map(|(_, index)| index);
+    to_value(AddressResult {
+        address: address.to_string(),
+        index,
+    })
+}
+
+async fn get_new_address(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    let address = state.manager.new_address(&wallet_id).await?;
+    to_value(AddressResult {
+        address: address.to_string(),
+        index: None,
+    })
+}
+
+async fn get_balance(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    let balance = state.manager.balance(&wallet_id).await?;
+    to_value(BalanceResult::from(balance))
+}
+
+async fn list_transactions(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    let transactions: Vec<Transaction> = state.manager.get_transactions(&wallet_id).await?;
+    to_value(transactions)
+}
+
+async fn send(state: &DaemonState, params: SendParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    let network = state.manager.wallet(&wallet_id)?.lock().await.network();
+    let (recipient, amount, fee_rate) = payment(&params, network)?;
+
+    if let Some(endpoint) = &params.payjoin {
+        let outcome = state
+            .manager
+            .send_payjoin(
+                &wallet_id,
+                recipient,
+                amount,
+                fee_rate,
+                endpoint,
+                &state.cancel,
+            )
+            .await?;
+        let (payjoined, error) = match &outcome {
+            PayjoinOutcome::Payjoin(_) => (true, None),
+            PayjoinOutcome::Fallback { reason, .. } => (false, Some(reason.clone())),
+        };
+        return to_value(SendResult {
+            txid: Some(outcome.txid().to_string()),
+            pending: None,
+            payjoin: Some(PayjoinResult { payjoined, error }),
+        });
+    }
+
+    let outcome = state
+        .manager
+        .send(&wallet_id, recipient, amount, fee_rate, &state.cancel)
+        .await?;
+
+    match outcome {
+        SendOutcome::Broadcast(txid) => to_value(SendResult {
+            txid: Some(txid.to_string()),
+            pending: None,
+            payjoin: None,
+        }),
+        SendOutcome::Pending(psbt) => {
+            let wallet = state.manager.wallet(&wallet_id)?;
+            let wallet = wallet.lock().await;
+            let pending = psbt_result(&wallet, &psbt, false);
+            to_value(SendResult {
+                txid: None,
+                pending: Some(pending),
+                payjoin: None,
+            })
+        }
+    }
+}
+
+async fn create_psbt(state: &DaemonState, params: SendParams) -> MethodResult {
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    state.manager.sync(&wallet_id, &state.cancel).await?;
+
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let mut wallet = wallet.lock().await;
+    let (recipient, amount, fee_rate) = payment(&params, wallet.network())?;
+    let psbt = wallet.build_transaction(recipient, amount, fee_rate)?;
+    to_value(psbt_result(&wallet, &psbt, false))
+}
+
+async fn sign_psbt(state: &DaemonState, params: PsbtParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let mut wallet = wallet.lock().await;
+
+    let mut psbt = decode_psbt(&params.psbt)?;
+    let finalized = wallet.sign_psbt(&mut psbt)?;
+    to_value(psbt_result(&wallet, &psbt, finalized))
+}
+
+async fn combine_psbt(state: &DaemonState, params: CombinePsbtParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let wallet = wallet.lock().await;
+
+    let psbts = params
+        .psbts
+        .iter()
+        .map(|psbt| decode_psbt(psbt))
+        .collect::<Result<Vec<_>, _>>()?;
+    let combined = wallet.combine_psbts(psbts)?;
+    to_value(psbt_result(&wallet, &combined, false))
+}
+
+async fn finalize_psbt(state: &DaemonState, params: PsbtParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let wallet = wallet.lock().await;
+
+    let mut psbt = decode_psbt(&params.psbt)?;
+    let finalized = wallet.finalize_psbt(&mut psbt)?;
+    to_value(psbt_result(&wallet, &psbt, finalized))
+}
+
+async fn broadcast_psbt(state: &DaemonState, params: PsbtParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let mut wallet = wallet.lock().await;
+
+    let transaction = wallet.extract_transaction(decode_psbt(&params.psbt)?)?;
+    let txid = transaction.compute_txid();
+    wallet.broadcast_transaction(transaction).await?;
+    to_value(TxidResult {
+        txid: txid.to_string(),
+    })
+}
+
+async fn list_pending_psbts(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let pending: Vec<PendingPsbt> = wallet.lock().await.pending_psbts()?;
+    to_value(pending)
+}
+
+async fn set_label(state: &DaemonState, params: SetLabelParams) -> MethodResult {
+    let label_type = LabelType::from_str(&params.label_type).map_err(RpcError::invalid_params)?;
+
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    wallet
+        .lock()
+        .await
+        .set_label(label_type, &params.reference, &params.label)?;
+    to_value(Value::Null)
+}
+
+async fn list_labels(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let labels: Vec<Label> = wallet.lock().await.labels()?;
+    to_value(labels)
+}
+
+async fn create_invoice(state: &DaemonState, params: CreateInvoiceParams) -> MethodResult {
+    let mut request = InvoiceRequest::new(Amount::from_sat(params.amount));
+    request.memo = params.memo;
+    request.payjoin = params.payjoin;
+    if let Some(expiry) = params.expiry {
+        request.expiry = (expiry > 0).then(|| Duration::from_secs(expiry));
+    }
+    if let Some(confirmations) = params.confirmations {
+        request.required_confirmations = confirmations;
+    }
+
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let invoice: Invoice = wallet.lock().await.create_invoice(request)?;
+    to_value(invoice)
+}
+
+async fn list_invoices(state: &DaemonState, params: WalletParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let invoices: Vec<Invoice> = wallet.lock().await.update_invoices()?;
+    to_value(invoices)
+}
+
+async fn get_invoice(state: &DaemonState, params: InvoiceParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let wallet = wallet.lock().await;
+    wallet.update_invoices()?;
+    to_value(wallet.invoice(&params.invoice)?)
+}
+
+async fn sign_message(state: &DaemonState, params: SignMessageParams) -> MethodResult {
+    let wallet = wallet(&state.manager, params.wallet.as_deref())?;
+    let wallet = wallet.lock().await;
+    let address = Address::from_string(&params.address, wallet.network())
+        .map_err(RpcError::invalid_params)?;
+    let signature = wallet.sign_message(&address, &params.message)?;
+    to_value(SignatureResult { signature })
+}
+
+async fn prove_reserves(state: &DaemonState, params: ProveReservesParams) -> MethodResult {
+    let coins = params
+        .coins
+        .iter()
+        .map(|coin| OutPoint::from_str(coin).map_err(RpcError::invalid_params))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let wallet_id = wallet_id(&state.manager, params.wallet.as_deref())?;
+    state.manager.sync(&wallet_id, &state.cancel).await?;
+
+    let wallet = state.manager.wallet(&wallet_id)?;
+    let wallet = wallet.lock().await;
+    let coins = (!coins.is_empty()).then_some(coins.as_slice());
+    let psbt = wallet.create_reserves_proof(&params.message, coins)?;
+    to_value(psbt_result(&wallet, &psbt, true))
+}
+
+impl From<Balance> for BalanceResult {
+    fn from(balance: Balance) -> Self {
+        Self {
+            confirmed: balance.confirmed(),
+            spendable: balance.spendable(),
+            total: balance.total(),
+        }
+    }
+}
+
+fn wallet(manager: &WalletManager, id_or_name: Option<&str>) -> Result<WalletHandle, RpcError> {
+    let wallet_id = wallet_id(manager, id_or_name)?;
+    Ok(manager.wallet(&wallet_id)?)
+}
+
+fn wallet_id(manager: &WalletManager, id_or_name: Option<&str>) -> Result<WalletId, RpcError> {
+    match id_or_name {
+        Some(id_or_name) => Ok(manager.find_wallet(id_or_name)?),
+        None => manager.active_wallet_id()?.ok_or_else(|| {
+            RpcError::new(
+                RpcError::WALLET_NOT_FOUND,
+                "No wallet selected, pass `wallet` or call selectwallet first",
+            )
+        }),
+    }
+}
+
+/// Recipient, amount and fee rate of a send
+fn payment(params: &SendParams, network: Network) -> Result<(Address, Amount, FeeRate), RpcError> {
+    let recipient =
+        Address::from_string(&params.address, network).map_err(RpcError::invalid_params)?;
+    let fee_rate = FeeRate::from_sat_per_vb(params.fee_rate.unwrap_or(DEFAULT_FEE_RATE));
+    Ok((recipient, Amount::from_sat(params.amount), fee_rate))
+}
+
+fn psbt_result(wallet: &Wallet, psbt: &Psbt, finalized: bool) -> PsbtResult {
+    PsbtResult {
+        psbt: psbt.to_string(),
+        finalized,
+        fee: psbt.fee().ok().map(Amount::from),
+        signatures: wallet
+            .metadata
+            .multisig
+            .as_ref()
+            .map(|multisig| SignatureStatus::from_psbt(psbt, multisig.threshold)),
+    }
+}
+
+fn decode_psbt(psbt: &str) -> Result<Psbt, RpcError> {
+    crate::wallet::psbt::decode_psbt(psbt.as_bytes()).map_err(RpcError::invalid_params)
+}
+
+fn parse_network(network: &str) -> Result<Network, RpcError> {
+    Network::from_str(network).map_err(RpcError::invalid_params)
+}
+
+fn to_value(value: impl Serialize) -> MethodResult {
+    serde_json::to_value(value).map_err(|e| RpcError::new(RpcError::INTERNAL_ERROR, e.to_string()))
+}
@@ -0,0 +1,209 @@
+//! BIP78 payjoin endpoint
+//!
+//! Senders post their original PSBT here, following the `pj=` of an invoice
+//! URI, and get back a proposal that adds one of our coins. Only payments to
+//! invoices created with a payjoin endpoint are answered, the payment's
+//! outputs pick the wallet. Payers can't have the cookie, so this listener
+//! doesn't ask for it. Like the other listeners it only binds to loopback:
+//! reach it from outside through an https reverse proxy or an onion service.
+//!
+//! Anyone can post an original, so the endpoint guards against probing for our
+//! coins: the original's inputs must be unspent on chain, the same inputs
+//! always get the same coin back, and if the payjoin isn't broadcast in time
+//! the original is, so a sender can't learn a coin without paying.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bitcoin::psbt::Psbt;
+use bitcoin::OutPoint;
+
+use crate::cancel::CancelToken;
+use crate::daemon::http::HttpResponse;
+use crate::daemon::server::DaemonState;
+use crate::node::client::esplora::EsploraClient;
+use crate::node::Node;
+use crate::wallet::payjoin::{self, PayjoinError, PayjoinParams};
+use lumo_types::Network;
+
+/// How long the sender has to broadcast the payjoin before we broadcast the original
+const BROADCAST_ORIGINAL_AFTER: Duration = Duration::from_secs(2 * 60);
+
+/// Proposals sent and not yet settled, by the original's inputs
+#[derive(Debug, Clone, Default)]
+pub struct PayjoinSessions {
+    contributed: Arc<Mutex<HashMap<Vec<OutPoint>, OutPoint>>>,
+}
+
+impl PayjoinSessions {
+    /// Coin already offered to an original spending `inputs`
+    fn contributed(&self, inputs: &[OutPoint]) -> Option<OutPoint> {
+        self.lock().get(inputs).copied()
+    }
+
+    /// Returns false if a proposal for `inputs` is already waiting
+    fn start(&self, inputs: Vec<OutPoint>, coin: OutPoint) -> bool {
+        self.lock().insert(inputs, coin).is_none()
+    }
+
+    fn finish(&self, inputs: &[OutPoint]) {
+        self.lock().remove(inputs);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Vec<OutPoint>, OutPoint>> {
+        self.contributed
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Handle an original PSBT posted to `path`, answering with a proposal or a BIP78 error
+pub async fn handle(state: &DaemonState, path: &str, body: &[u8]) -> HttpResponse {
+    let query = path.split_once('?').map_or("", |(_, query)| query);
+
+    match propose(state, query, body).await {
+        Ok(proposal) => HttpResponse {
+            status: 200,
+            body: proposal.to_string().into_bytes(),
+        },
+        Err(e) => {
+            tracing::info!("Refused a payjoin: {e}");
+            HttpResponse {
+                status: 400,
+                body: payjoin::error_body(&e),
+            }
+        }
+    }
+}
+
+async fn propose(state: &DaemonState, query: &str, body: &[u8]) -> Result<Psbt, PayjoinError> {
+    let params = PayjoinParams::from_query(query)?;
+    let original = std::str::from_utf8(body)
+        .ok()
+        .and_then(|body| body.trim().parse::<Psbt>().ok())
+        .ok_or_else(|| PayjoinError::OriginalRejected("Body isn't a base64 PSBT".to_string()))?;
+
+    let mut paid = None;
+    for wallet_id in state.manager.list_wallet_ids() {
+        let Ok(wallet) = state.manager.wallet(&wallet_id) else {
+            continue;
+        };
+        let (pays_wallet, network) = {
+            let wallet = wallet.lock().await;
+            let pays_wallet = original
+                .unsigned_tx
+                .output
+                .iter()
+                .any(|output| wallet.bdk.is_mine(output.script_pubkey.clone()));
+            (pays_wallet, wallet.network())
+        };
+        if pays_wallet {
+            paid = Some((wallet, network));
+            break;
+        }
+    }
+    let Some((wallet, network)) = paid else {
+        return Err(PayjoinError::OriginalRejected(
+            "Doesn't pay any loaded wallet".to_string(),
+        ));
+    };
+
+    let mut inputs: Vec<OutPoint> = original
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .collect();
+    inputs.sort();
+    check_inputs_unspent(&original, network).await?;
+
+    let reuse = state.payjoins.contributed(&inputs);
+    let (proposal, coin) = wallet
+        .lock()
+        .await
+        .payjoin_proposal(&original, &params, reuse)?;
+
+    if state.payjoins.start(inputs.clone(), coin) {
+        let original = original
+            .extract_tx()
+            .map_err(|e| PayjoinError::OriginalRejected(e.to_string()))?;
+        tokio::spawn(broadcast_original_later(
+            state.payjoins.clone(),
+            inputs,
+            original,
+            network,
+            state.cancel.clone(),
+        ));
+    }
+    Ok(proposal)
+}
+
+/// Every input of the original must be an unspent output that matches its PSBT input
+async fn check_inputs_unspent(original: &Psbt, network: Network) -> Result<(), PayjoinError> {
+    let node = Node::for_network(network);
+    let esplora_client = EsploraClient::new(&node.url)
+        .await
+        .map_err(|e| PayjoinError::Unavailable(e.to_string()))?;
+
+    for (txin, input) in original.unsigned_tx.input.iter().zip(&original.inputs) {
+        let output = esplora_client
+            .unspent_output(txin.previous_output)
+            .await
+            .map_err(|e| PayjoinError::Unavailable(e.to_string()))?;
+        let Some(output) = output else {
+            return Err(PayjoinError::OriginalRejected(format!(
+                "Input {} is spent or doesn't exist",
+                txin.previous_output
+            )));
+        };
+        if input
+            .witness_utxo
+            .as_ref()
+            .is_some_and(|utxo| *utxo != output)
+        {
+            return Err(PayjoinError::OriginalRejected(format!(
+                "Input {} doesn't match the chain",
+                txin.previous_output
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Broadcast the original if its inputs are still unspent once the sender had
+/// time to broadcast the payjoin
+async fn broadcast_original_later(
+    sessions: PayjoinSessions,
+    inputs: Vec<OutPoint>,
+    original: bitcoin::Transaction,
+    network: Network,
+    cancel: CancelToken,
+) {
+    tokio::select! {
+        _ = cancel.cancelled() => return,
+        _ = tokio::time::sleep(BROADCAST_ORIGINAL_AFTER) => {}
+    }
+
+    let txid = original.compute_txid();
+    let node = Node::for_network(network);
+    let result = async {
+        let esplora_client = EsploraClient::new(&node.url).await?;
+        for input in &inputs {
+            if esplora_client.unspent_output(*input).await?.is_none() {
+                // Spent by the payjoin, the original or something else: settled either way
+                return Ok(false);
+            }
+        }
+        esplora_client.broadcast_transaction(&original).await?;
+        Ok::<_, eyre::Report>(true)
+    }
+    .await;
+
+    match result {
+        Ok(true) => tracing::info!("Payjoin wasn't broadcast in time, broadcast original {txid}"),
+        Ok(false) => tracing::debug!("Payjoin for original {txid} settled"),
+        Err(e) => tracing::warn!("Failed to check or broadcast payjoin original {txid}: {e}"),
+    }
+    sessions.finish(&inputs);
+}
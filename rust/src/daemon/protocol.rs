@@ -0,0 +1,196 @@
+//! JSON-RPC 2.0 request and response envelopes
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::database::error::DatabaseError;
+use crate::wallet::error::WalletError;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    #[serde(default)]
+    pub jsonrpc: Option<String>,
+    #[serde(default)]
+    pub id: Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: Some(JSONRPC_VERSION.to_string()),
+            id: id.into(),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            id,
+            result,
+            error,
+        }
+    }
+
+    pub fn into_result(self) -> Result<Value, RpcError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result.unwrap_or(Value::Null)),
+        }
+    }
+}
+
+/// A message that expects no reply, what `subscribe` streams carry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+impl Notification {
+    pub fn new(method: &str, params: Value) -> Self {
+        Self {
+            jsonrpc: JSONRPC_VERSION.to_string(),
+            method: method.to_string(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[error("{message} (code {code})")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    /// Same codes as bitcoind's wallet RPCs
+    pub const MISC_ERROR: i64 = -1;
+    pub const TYPE_ERROR: i64 = -3;
+    pub const WALLET_ERROR: i64 = -4;
+    pub const INVALID_ADDRESS: i64 = -5;
+    pub const INSUFFICIENT_FUNDS: i64 = -6;
+    pub const INVALID_PARAMETER: i64 = -8;
+    pub const WALLET_NOT_FOUND: i64 = -18;
+    pub const WALLET_NOT_SPECIFIED: i64 = -19;
+    pub const DESERIALIZATION_ERROR: i64 = -22;
+    pub const METHOD_DEPRECATED: i64 = -32;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn method_not_found(method: &str) -> Self {
+        Self::new(
+            Self::METHOD_NOT_FOUND,
+            format!("Method not found: {method}"),
+        )
+    }
+
+    pub fn invalid_params(message: impl std::fmt::Display) -> Self {
+        Self::new(Self::INVALID_PARAMS, format!("Invalid params: {message}"))
+    }
+}
+
+impl From<WalletError> for RpcError {
+    fn from(err: WalletError) -> Self {
+        let code = match err {
+            WalletError::WalletNotFound(_) => Self::WALLET_NOT_FOUND,
+            _ => Self::WALLET_ERROR,
+        };
+        Self::new(code, err.to_string())
+    }
+}
+
+impl From<DatabaseError> for RpcError {
+    fn from(err: DatabaseError) -> Self {
+        Self::new(Self::INTERNAL_ERROR, err.to_string())
+    }
+}
+
+/// Decode named params, a missing `params` counts as an empty object
+pub fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    let params = match params {
+        Value::Null => Value::Object(Default::default()),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_response_round_trip() {
+        let ok = Response::new(json!(1), Ok(json!({"txid": "00"})));
+        let encoded = serde_json::to_value(&ok).unwrap();
+        assert_eq!(
+            encoded,
+            json!({"jsonrpc": "2.0", "id": 1, "result": {"txid": "00"}})
+        );
+
+        let failed = Response::new(json!(2), Err(RpcError::method_not_found("nope")));
+        let decoded: Response =
+            serde_json::from_str(&serde_json::to_string(&failed).unwrap()).unwrap();
+        assert_eq!(
+            decoded.into_result().unwrap_err().code,
+            RpcError::METHOD_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_parse_params() {
+        #[derive(Deserialize)]
+        struct Params {
+            #[serde(default)]
+            wallet: Option<String>,
+        }
+
+        let params: Params = parse_params(Value::Null).unwrap();
+        assert_eq!(params.wallet, None);
+
+        let params: Params = parse_params(json!({"wallet": "Spending"})).unwrap();
+        assert_eq!(params.wallet.as_deref(), Some("Spending"));
+
+        let error = parse_params::<Params>(json!({"wallet": 5})).err().unwrap();
+        assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    }
+}
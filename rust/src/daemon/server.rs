@@ -0,0 +1,440 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+
+use crate::cancel::CancelToken;
+use crate::daemon::auth::{cookie_path, Cookie};
+use crate::daemon::error::DaemonError;
+use crate::daemon::http::{self, HttpRequest, HttpResponse};
+use crate::daemon::payjoin::PayjoinSessions;
+use crate::daemon::protocol::{Notification, Request, Response, RpcError};
+use crate::daemon::{bitcoind, lock_path, methods, payjoin, DEFAULT_RPC_PORT};
+use crate::lock::FileLock;
+use crate::sync_scheduler::{SyncSchedule, SyncScheduler};
+use crate::wallet::WalletId;
+use crate::wallet_manager::WalletManager;
+
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    /// Unix socket to listen on, `None` to only listen on TCP
+    pub socket: Option<PathBuf>,
+    /// Loopback address to listen on, `None` to only listen on the socket
+    pub listen: Option<SocketAddr>,
+    /// How often loaded wallets are synced in the background, `None` to never
+    pub sync_interval: Option<Duration>,
+    /// Loopback address to serve the bitcoind compatible wallet RPC on, `None` to not serve it
+    pub bitcoind_listen: Option<SocketAddr>,
+    /// Loopback address to serve the BIP78 payjoin endpoint on, `None` to not serve it
+    pub payjoin_listen: Option<SocketAddr>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(unix)]
+            socket: Some(crate::daemon::socket_path()),
+            #[cfg(not(unix))]
+            socket: None,
+            listen: Some(SocketAddr::from(([127, 0, 0, 1], DEFAULT_RPC_PORT))),
+            sync_interval: Some(Duration::from_secs(60)),
+            bitcoind_listen: None,
+            payjoin_listen: None,
+        }
+    }
+}
+
+/// Longest a client may take to send its whole request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Which RPC a listener serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Api {
+    Lumo,
+    Bitcoind,
+    /// Open to payers, no cookie
+    Payjoin,
+}
+
+/// What to send back on a connection
+enum Reply {
+    Response(HttpResponse),
+    /// Stream events for one wallet, or all of them, until the daemon stops
+    Events {
+        id: Value,
+        wallet: Option<WalletId>,
+    },
+}
+
+/// What request handlers share
+pub struct DaemonState {
+    pub manager: WalletManager,
+    /// Notified by the `stop` method
+    pub shutdown: Notify,
+    /// Cancelled on shutdown, so syncs in flight don't hold it up
+    pub cancel: CancelToken,
+    /// Payjoin proposals waiting for the sender to broadcast
+    pub payjoins: PayjoinSessions,
+    cookie: Cookie,
+}
+
+pub struct Daemon {
+    state: Arc<DaemonState>,
+    config: DaemonConfig,
+    tcp: Option<TcpListener>,
+    bitcoind: Option<TcpListener>,
+    payjoin: Option<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<tokio::net::UnixListener>,
+    _lock: FileLock,
+}
+
+impl Daemon {
+    /// Take the daemon lock, load wallets, write a fresh cookie and bind the listeners
+    pub async fn start(config: DaemonConfig) -> Result<Self, DaemonError> {
+        if config.socket.is_none() && config.listen.is_none() && config.bitcoind_listen.is_none() {
+            return Err(DaemonError::Config(
+                "Nothing to listen on, enable the socket or TCP".to_string(),
+            ));
+        }
+        let mut addrs = config
+            .listen
+            .iter()
+            .chain(&config.bitcoind_listen)
+            .chain(&config.payjoin_listen);
+        if let Some(addr) = addrs.find(|addr| !addr.ip().is_loopback()) {
+            return Err(DaemonError::Config(format!(
+                "{addr} is not a loopback address, lumod only serves local clients"
+            )));
+        }
+        #[cfg(not(unix))]
+        if config.socket.is_some() {
+            return Err(DaemonError::Config(
+                "Unix sockets aren't supported on this platform".to_string(),
+            ));
+        }
+
+        let lock = FileLock::try_acquire(&lock_path())?
+            .ok_or_else(|| DaemonError::AlreadyRunning(lock_path().display().to_string()))?;
+
+        let manager = WalletManager::new();
+        manager.load_all()?;
+
+        let tcp = match config.listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        let bitcoind = match config.bitcoind_listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        let payjoin = match config.payjoin_listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+
+        // We hold the daemon lock, so a socket file left behind is stale
+        #[cfg(unix)]
+        let unix = match &config.socket {
+            Some(path) => {
+                let _ = std::fs::remove_file(path);
+                Some(tokio::net::UnixListener::bind(path)?)
+            }
+            None => None,
+        };
+
+        let cookie = Cookie::generate();
+        cookie.write(&cookie_path())?;
+
+        Ok(Self {
+            state: Arc::new(DaemonState {
+                manager,
+                shutdown: Notify::new(),
+                cancel: CancelToken::new(),
+                payjoins: PayjoinSessions::default(),
+                cookie,
+            }),
+            config,
+            tcp,
+            bitcoind,
+            payjoin,
+            #[cfg(unix)]
+            unix,
+            _lock: lock,
+        })
+    }
+
+    /// Address of the TCP listener, useful when binding port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.tcp.as_ref().and_then(|tcp| tcp.local_addr().ok())
+    }
+
+    /// Address of the bitcoind compatible listener
+    pub fn bitcoind_addr(&self) -> Option<SocketAddr> {
+        self.bitcoind.as_ref().and_then(|tcp| tcp.local_addr().ok())
+    }
+
+    /// Address of the payjoin listener
+    pub fn payjoin_addr(&self) -> Option<SocketAddr> {
+        self.payjoin.as_ref().and_then(|tcp| tcp.local_addr().ok())
+    }
+
+    pub fn cookie(&self) -> &Cookie {
+        &self.state.cookie
+    }
+
+    /// Serve requests until `shutdown` completes or a client calls `stop`
+    pub async fn serve(self, shutdown: impl Future<Output = ()>) -> Result<(), DaemonError> {
+        let mut tasks = tokio::task::JoinSet::new();
+
+        let listeners = [
+            (self.tcp, Api::Lumo),
+            (self.bitcoind, Api::Bitcoind),
+            (self.payjoin, Api::Payjoin),
+        ];
+        for (tcp, api) in listeners {
+            let Some(tcp) = tcp else { continue };
+            let state = self.state.clone();
+            tasks.spawn(async move {
+                loop {
+                    match tcp.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_connection(state.clone(), api, stream));
+                        }
+                        Err(e) => tracing::warn!("Failed to accept a TCP connection: {e}"),
+                    }
+                }
+            });
+        }
+
+        #[cfg(unix)]
+        if let Some(unix) = self.unix {
+            let state = self.state.clone();
+            tasks.spawn(async move {
+                loop {
+                    match unix.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_connection(state.clone(), Api::Lumo, stream));
+                        }
+                        Err(e) => tracing::warn!("Failed to accept a socket connection: {e}"),
+                    }
+                }
+            });
+        }
+
+        if let Some(interval) = self.config.sync_interval {
+            let scheduler = SyncScheduler::new(
+                self.state.manager.clone(),
+                SyncSchedule::with_interval(interval),
+            );
+            tasks.spawn(scheduler.run(self.state.cancel.clone()));
+        }
+
+        tokio::select! {
+            _ = shutdown => {}
+            _ = self.state.shutdown.notified() => {}
+        }
+        self.state.cancel.cancel();
+        tasks.shutdown().await;
+
+        if let Some(socket) = &self.config.socket {
+            let _ = std::fs::remove_file(socket);
+        }
+        let _ = std::fs::remove_file(cookie_path());
+        Ok(())
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Send + 'static>(
+    state: Arc<DaemonState>,
+    api: Api,
+    stream: S,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&state, api, &mut reader))
+        .await
+        .unwrap_or_else(|_| Err(HttpResponse::error(408, "Timed out reading the request")));
+    let reply = match request {
+        Ok(request) => handle_request(&state, api, request).await,
+        Err(response) => Reply::Response(response),
+    };
+
+    let written = match reply {
+        Reply::Response(response) => http::write_response(&mut writer, &response).await,
+        Reply::Events { id, wallet } => stream_events(&state, &mut writer, id, wallet).await,
+    };
+    if let Err(e) = written {
+        tracing::debug!("Failed to write response: {e}");
+    }
+}
+
+/// Confirm the subscription, then send each event as a notification on its own line
+async fn stream_events<W: AsyncWrite + Unpin>(
+    state: &DaemonState,
+    writer: &mut W,
+    id: Value,
+    wallet: Option<WalletId>,
+) -> Result<(), DaemonError> {
+    let mut events = state.manager.subscribe();
+    http::write_stream_head(writer).await?;
+    http::write_line(writer, &Response::new(id, Ok(Value::from("subscribed")))).await?;
+
+    loop {
+        let event = tokio::select! {
+            _ = state.cancel.cancelled() => return Ok(()),
+            event = events.recv() => event,
+        };
+
+        let notification = match event {
+            Ok(event)
+                if wallet
+                    .as_ref()
+                    .is_some_and(|wallet| *wallet != event.wallet_id) =>
+            {
+                continue
+            }
+            Ok(event) => Notification::new("event", serde_json::to_value(event)?),
+            Err(RecvError::Lagged(missed)) => {
+                Notification::new("lagged", serde_json::json!({ "missed": missed }))
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        http::write_line(writer, &notification).await?;
+    }
+}
+
+/// Read a request, checking its method, cookie and path before the body
+///
+/// Only authenticated clients may send bodies up to `MAX_BODY_BYTES`.
+async fn read_request<R: AsyncBufRead + Unpin>(
+    state: &DaemonState,
+    api: Api,
+    reader: &mut R,
+) -> Result<HttpRequest, HttpResponse> {
+    let bad_request = |e: DaemonError| {
+        tracing::debug!("Bad request: {e}");
+        HttpResponse::error(400, &e.to_string())
+    };
+
+    let head = http::read_request_head(reader).await.map_err(bad_request)?;
+    if head.method != "POST" {
+        return Err(HttpResponse::error(405, "Only POST is supported"));
+    }
+    if api == Api::Payjoin {
+        return head
+            .read_body(reader, http::MAX_PUBLIC_BODY_BYTES)
+            .await
+            .map_err(bad_request);
+    }
+    if !state.cookie.check(head.authorization.as_deref()) {
+        return Err(HttpResponse::error(401, "Missing or wrong cookie"));
+    }
+    if api == Api::Lumo && head.path != "/" {
+        return Err(HttpResponse::error(404, "Not found"));
+    }
+
+    head.read_body(reader, http::MAX_BODY_BYTES)
+        .await
+        .map_err(bad_request)
+}
+
+async fn handle_request(state: &DaemonState, api: Api, request: HttpRequest) -> Reply {
+    if api == Api::Payjoin {
+        return Reply::Response(payjoin::handle(state, &request.path, &request.body).await);
+    }
+    if api == Api::Bitcoind {
+        return Reply::Response(bitcoind::handle(state, &request.path, &request.body).await);
+    }
+
+    let response = match serde_json::from_slice::<Request>(&request.body) {
+        Ok(rpc) if rpc.method == "subscribe" => match methods::subscription(state, rpc.params) {
+            Ok(wallet) => return Reply::Events { id: rpc.id, wallet },
+            Err(e) => Response::new(rpc.id, Err(e)),
+        },
+        Ok(rpc) => {
+            let result = methods::dispatch(state, &rpc.method, rpc.params).await;
+            if let Err(e) = &result {
+                tracing::debug!("{} failed: {e}", rpc.method);
+            }
+            Response::new(rpc.id, result)
+        }
+        Err(e) => Response::new(
+            Value::Null,
+            Err(RpcError::new(RpcError::PARSE_ERROR, e.to_string())),
+        ),
+    };
+
+    Reply::Response(match serde_json::to_vec(&response) {
+        Ok(body) => HttpResponse::json(body),
+        Err(e) => HttpResponse::error(500, &e.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancelToken;
+    use crate::daemon::client::{DaemonClient, Endpoint};
+    use crate::wallet::events::WalletEventKind;
+    use crate::wallet::Wallet;
+    use lumo_types::Network;
+
+    #[tokio::test]
+    async fn test_subscribe_streams_events() {
+        let daemon = Daemon::start(DaemonConfig {
+            socket: None,
+            listen: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            sync_interval: None,
+            bitcoind_listen: None,
+            payjoin_listen: None,
+        })
+        .await
+        .unwrap();
+
+        let state = daemon.state.clone();
+        let endpoint = Endpoint::Tcp(daemon.local_addr().unwrap());
+        let client = DaemonClient::new(endpoint, daemon.cookie().clone());
+        let server = tokio::spawn(daemon.serve(std::future::pending()));
+
+        let (wallet, _) = Wallet::new_random("Subscribed".to_string(), Network::Regtest).unwrap();
+        let wallet_id = state.manager.add_wallet(wallet).unwrap();
+        let mut events = client
+            .subscribe(Some(&wallet_id.to_string()))
+            .await
+            .unwrap();
+
+        // Cancelled up front, so the sync fails without touching the network
+        let cancelled = CancelToken::new();
+        cancelled.cancel();
+        assert!(state.manager.sync(&wallet_id, &cancelled).await.is_err());
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.wallet_id, wallet_id);
+        assert_eq!(event.kind, WalletEventKind::SyncStarted);
+        assert!(matches!(
+            events.next().await.unwrap().unwrap().kind,
+            WalletEventKind::SyncFailed { .. }
+        ));
+
+        assert!(matches!(
+            client.subscribe(Some("no such wallet")).await,
+            Err(DaemonError::Rpc(RpcError {
+                code: RpcError::WALLET_NOT_FOUND,
+                ..
+            }))
+        ));
+
+        let _: Value = client.call("stop", Value::Null).await.unwrap();
+        server.await.unwrap().unwrap();
+        assert!(events.next().await.is_none());
+    }
+}
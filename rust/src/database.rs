@@ -1,12 +1,23 @@
 pub mod error;
 pub mod global_config;
+pub mod invoices;
+pub mod labels;
+pub mod migrations;
+pub mod psbt;
 pub mod wallet;
 
+use crate::lock::LOCK_WAIT;
 use arc_swap::ArcSwap;
+use error::DatabaseError;
 use global_config::GlobalConfigTable;
+use invoices::InvoicesTable;
+use labels::LabelsTable;
 use lumo_common::ROOT_DATA_DIR;
 use once_cell::sync::OnceCell;
-use std::{path::PathBuf, sync::Arc};
+use psbt::PendingPsbtsTable;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use wallet::WalletsTable;
 
 pub static DATABASE: OnceCell<ArcSwap<Database>> = OnceCell::new();
@@ -15,53 +26,58 @@ pub static DATABASE: OnceCell<ArcSwap<Database>> = OnceCell::new();
 pub struct Database {
     pub wallets: WalletsTable,
     pub global_config: GlobalConfigTable,
+    pub pending_psbts: PendingPsbtsTable,
+    pub labels: LabelsTable,
+    pub invoices: InvoicesTable,
 }
 
-#[cfg(not(test))]
+// Tests get a temporary ROOT_DATA_DIR per run, see lumo-common's `test-dirs` feature
 fn database_location() -> PathBuf {
     ROOT_DATA_DIR.join("lumo.db") // ~/.lumo/lumo.db
 }
 
-#[cfg(test)]
-fn database_location() -> PathBuf {
-    // Use a single test database for all tests
-    let test_dir = ROOT_DATA_DIR.join("test");
-    std::fs::create_dir_all(&test_dir).expect("failed to create test dir");
-    test_dir.join("lumo_test.db")
-}
-
 impl Database {
-    pub fn global() -> Arc<Self> {
+    /// The process wide database, opened on first use
+    ///
+    /// redb holds an exclusive lock on the file while it's open, if another lumo
+    /// process has it this waits up to `LOCK_WAIT` before returning `Locked`.
+    pub fn global() -> Result<Arc<Self>, DatabaseError> {
         let db = DATABASE
-            .get_or_init(|| ArcSwap::new(Arc::new(Self::init())))
+            .get_or_try_init(|| Self::init().map(|db| ArcSwap::new(Arc::new(db))))?
             .load();
-        Arc::clone(&db)
+        Ok(Arc::clone(&db))
     }
 
-    fn init() -> Database {
+    fn init() -> Result<Database, DatabaseError> {
         let location = database_location();
-        let db = if location.exists() {
-            redb::Database::open(&location).expect("failed to open database")
-        } else {
-            redb::Database::create(&location).expect("failed to create database")
-        };
+        let db = open_with_retry(&location)?;
 
-        let db = Arc::new(db);
-        let write_txn = db.begin_write().expect("failed to begin write transaction");
-        let wallets =
-            WalletsTable::new(db.clone(), &write_txn).expect("failed to create wallets table");
-
-        let global_config = GlobalConfigTable::new(db.clone(), &write_txn)
-            .expect("failed to create global config table");
+        let report = migrations::run(&db, &location)?;
+        if let Some(backup) = &report.backup {
+            tracing::info!(
+                "Database migrated from version {} to {}, previous copy kept at {}",
+                report.from_version,
+                report.to_version,
+                backup.display()
+            );
+        }
 
-        write_txn
-            .commit()
-            .expect("failed to commit write transaction");
+        let db = Arc::new(db);
+        let write_txn = db.begin_write()?;
+        let wallets = WalletsTable::new(db.clone(), &write_txn)?;
+        let global_config = GlobalConfigTable::new(db.clone(), &write_txn)?;
+        let pending_psbts = PendingPsbtsTable::new(db.clone(), &write_txn)?;
+        let labels = LabelsTable::new(db.clone(), &write_txn)?;
+        let invoices = InvoicesTable::new(db.clone(), &write_txn)?;
+        write_txn.commit()?;
 
-        Database {
+        Ok(Database {
             wallets,
             global_config,
-        }
+            pending_psbts,
+            labels,
+            invoices,
+        })
     }
 
     #[cfg(test)]
@@ -72,7 +88,33 @@ impl Database {
             let _ = db.wallets.clear_all();
         }
 
-        // Also remove the test directory for cleanup
-        let _ = std::fs::remove_dir_all(ROOT_DATA_DIR.join("test"));
+        // Also remove the test run's data directory for cleanup
+        let _ = std::fs::remove_dir_all(&*ROOT_DATA_DIR);
+    }
+}
+
+fn open_with_retry(location: &Path) -> Result<redb::Database, DatabaseError> {
+    let deadline = Instant::now() + LOCK_WAIT;
+
+    loop {
+        let result = if location.exists() {
+            redb::Database::open(location)
+        } else {
+            redb::Database::create(location)
+        };
+
+        match result {
+            Ok(db) => return Ok(db),
+            Err(redb::DatabaseError::DatabaseAlreadyOpen) if Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(redb::DatabaseError::DatabaseAlreadyOpen) => {
+                return Err(DatabaseError::Locked(format!(
+                    "{} is in use by another lumo process",
+                    location.display()
+                )))
+            }
+            Err(e) => return Err(DatabaseError::OpenError(e.to_string())),
+        }
     }
 }
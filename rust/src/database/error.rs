@@ -16,6 +16,9 @@ pub enum DatabaseError {
 
     #[error("JSON Serialization error: {0}")]
     SerializationError(String),
+
+    #[error("Database is locked: {0}")]
+    Locked(String),
 }
 
 impl From<redb::DatabaseError> for DatabaseError {
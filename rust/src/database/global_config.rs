@@ -1,8 +1,21 @@
 use crate::database::error::DatabaseError;
 use crate::wallet::WalletId;
+use lumo_types::Network;
 use redb::{ReadableDatabase, TableDefinition};
 use std::sync::Arc;
 
+const NETWORKS: [Network; 5] = [
+    Network::Mainnet,
+    Network::Testnet,
+    Network::Testnet4,
+    Network::Signet,
+    Network::Regtest,
+];
+
+fn node_url_key(network: Network) -> String {
+    format!("node_url/{network}")
+}
+
 const TABLE: TableDefinition<&'static str, &'static str> = TableDefinition::new("global_config");
 
 #[derive(Debug, Clone)]
@@ -53,4 +66,45 @@ impl GlobalConfigTable {
         write_txn.commit()?;
         Ok(())
     }
+
+    // Use a custom esplora server for a network instead of the default one
+    pub fn set_node_url(&self, network: Network, url: &str) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.insert(node_url_key(network).as_str(), url)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn node_url(&self, network: Network) -> Result<Option<String>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+
+        Ok(table
+            .get(node_url_key(network).as_str())?
+            .map(|url| url.value().to_string()))
+    }
+
+    // Custom node urls for every network that has one
+    pub fn node_urls(&self) -> Result<Vec<(Network, String)>, DatabaseError> {
+        let mut urls = Vec::new();
+        for network in NETWORKS {
+            if let Some(url) = self.node_url(network)? {
+                urls.push((network, url));
+            }
+        }
+        Ok(urls)
+    }
+
+    pub fn clear_node_url(&self, network: Network) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.remove(node_url_key(network).as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
 }
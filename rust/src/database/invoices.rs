@@ -0,0 +1,84 @@
+use crate::database::error::DatabaseError;
+use crate::wallet::invoice::Invoice;
+use crate::wallet::WalletId;
+use redb::{ReadableDatabase, ReadableTable, TableDefinition};
+use std::sync::Arc;
+
+// Keyed by invoice id
+const TABLE: TableDefinition<&'static str, &'static str> = TableDefinition::new("invoices");
+
+#[derive(Debug, Clone)]
+pub struct InvoicesTable {
+    db: Arc<redb::Database>,
+}
+
+impl InvoicesTable {
+    pub fn new(
+        db: Arc<redb::Database>,
+        write_txn: &redb::WriteTransaction,
+    ) -> Result<Self, DatabaseError> {
+        let _table = write_txn.open_table(TABLE)?;
+        Ok(Self { db })
+    }
+
+    // Insert or replace invoices in one transaction
+    pub fn save(&self, invoices: &[Invoice]) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for invoice in invoices {
+                let json = serde_json::to_string(invoice)?;
+                table.insert(invoice.id.as_str(), json.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Invoice>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+
+        match table.get(id)? {
+            Some(json_data) => Ok(Some(serde_json::from_str(json_data.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    // All invoices of a wallet, oldest first
+    pub fn get_all(&self, wallet_id: &WalletId) -> Result<Vec<Invoice>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let mut invoices = Vec::new();
+
+        for item in table.iter()? {
+            let (_id, json_data) = item?;
+            let invoice: Invoice = serde_json::from_str(json_data.value())?;
+            if &invoice.wallet_id == wallet_id {
+                invoices.push(invoice);
+            }
+        }
+
+        invoices.sort_by_key(|invoice| invoice.created_at);
+        Ok(invoices)
+    }
+
+    // Remove every invoice of a wallet
+    pub fn remove_all(&self, wallet_id: &WalletId) -> Result<(), DatabaseError> {
+        let ids: Vec<String> = self
+            .get_all(wallet_id)?
+            .into_iter()
+            .map(|invoice| invoice.id)
+            .collect();
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for id in ids {
+                table.remove(id.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use crate::database::error::DatabaseError;
+use crate::wallet::WalletId;
+use lumo_types::{Label, LabelType};
+use redb::{ReadableDatabase, ReadableTable, TableDefinition};
+use std::sync::Arc;
+
+// Keyed by `<wallet id>/<label type>/<ref>`
+const TABLE: TableDefinition<&'static str, &'static str> = TableDefinition::new("labels");
+
+fn label_key(wallet_id: &WalletId, label_type: LabelType, reference: &str) -> String {
+    format!("{wallet_id}/{label_type}/{reference}")
+}
+
+#[derive(Debug, Clone)]
+pub struct LabelsTable {
+    db: Arc<redb::Database>,
+}
+
+impl LabelsTable {
+    pub fn new(
+        db: Arc<redb::Database>,
+        write_txn: &redb::WriteTransaction,
+    ) -> Result<Self, DatabaseError> {
+        let _table = write_txn.open_table(TABLE)?;
+        Ok(Self { db })
+    }
+
+    // Insert or replace labels, all in one transaction
+    pub fn save(&self, wallet_id: &WalletId, labels: &[Label]) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for label in labels {
+                let key = label_key(wallet_id, label.label_type, &label.reference);
+                let json = serde_json::to_string(label)?;
+                table.insert(key.as_str(), json.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(
+        &self,
+        wallet_id: &WalletId,
+        label_type: LabelType,
+        reference: &str,
+    ) -> Result<Option<Label>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let key = label_key(wallet_id, label_type, reference);
+
+        match table.get(key.as_str())? {
+            Some(json_data) => Ok(Some(serde_json::from_str(json_data.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    // All labels for a wallet
+    pub fn get_all(&self, wallet_id: &WalletId) -> Result<Vec<Label>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let prefix = format!("{wallet_id}/");
+        let mut labels = Vec::new();
+
+        for item in table.range(prefix.as_str()..)? {
+            let (key, json_data) = item?;
+            if !key.value().starts_with(&prefix) {
+                break;
+            }
+            labels.push(serde_json::from_str(json_data.value())?);
+        }
+
+        Ok(labels)
+    }
+
+    pub fn remove(
+        &self,
+        wallet_id: &WalletId,
+        label_type: LabelType,
+        reference: &str,
+    ) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.remove(label_key(wallet_id, label_type, reference).as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    // Remove every label of a wallet
+    pub fn remove_all(&self, wallet_id: &WalletId) -> Result<(), DatabaseError> {
+        let prefix = format!("{wallet_id}/");
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            let keys = table
+                .range(prefix.as_str()..)?
+                .map(|item| item.map(|(key, _)| key.value().to_string()))
+                .take_while(|key| key.as_ref().map_or(true, |key| key.starts_with(&prefix)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for key in keys {
+                table.remove(key.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Schema versions and migrations for the redb database
+//!
+//! Databases created before versioning have no `schema_version` table and count as
+//! version 0. Migrations work on the raw JSON values so they keep working as the
+//! current structs change. A copy of the database is taken before migrating.
+
+use std::path::{Path, PathBuf};
+
+use redb::{ReadableDatabase, ReadableTable, TableDefinition, TableError, WriteTransaction};
+
+use crate::database::error::DatabaseError;
+
+const SCHEMA_TABLE: TableDefinition<&'static str, u32> = TableDefinition::new("schema_version");
+const VERSION_KEY: &str = "version";
+
+// Same definition as `WalletsTable` uses
+const WALLETS_TABLE: TableDefinition<&'static str, &'static str> =
+    TableDefinition::new("wallet_metadata");
+
+/// Schema version written by this version of lumo
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    run: fn(&WriteTransaction) -> Result<(), DatabaseError>,
+}
+
+/// Ordered by version, each one is committed together with its version number
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "record the script type of every wallet",
+    run: add_script_types,
+}];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Copy of the database taken before migrating
+    pub backup: Option<PathBuf>,
+}
+
+/// Schema version of a database, `None` for a brand new one
+pub fn schema_version(db: &redb::Database) -> Result<Option<u32>, DatabaseError> {
+    let read_txn = db.begin_read()?;
+
+    match read_txn.open_table(SCHEMA_TABLE) {
+        Ok(table) => return Ok(Some(table.get(VERSION_KEY)?.map_or(0, |v| v.value()))),
+        Err(TableError::TableDoesNotExist(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // Tables without a version record were written before versioning
+    let has_tables = read_txn.list_tables()?.next().is_some();
+    Ok(has_tables.then_some(0))
+}
+
+/// Bring the database at `location` up to `CURRENT_SCHEMA_VERSION`
+pub fn run(db: &redb::Database, location: &Path) -> Result<MigrationReport, DatabaseError> {
+    let Some(from_version) = schema_version(db)? else {
+        set_version(db, CURRENT_SCHEMA_VERSION)?;
+        return Ok(MigrationReport {
+            from_version: CURRENT_SCHEMA_VERSION,
+            to_version: CURRENT_SCHEMA_VERSION,
+            backup: None,
+        });
+    };
+
+    if from_version > CURRENT_SCHEMA_VERSION {
+        return Err(DatabaseError::OpenError(format!(
+            "Database schema version {from_version} is newer than this version of lumo supports ({CURRENT_SCHEMA_VERSION})"
+        )));
+    }
+
+    if from_version == CURRENT_SCHEMA_VERSION {
+        return Ok(MigrationReport {
+            from_version,
+            to_version: from_version,
+            backup: None,
+        });
+    }
+
+    let backup = backup_path(location, from_version);
+    std::fs::copy(location, &backup).map_err(|e| {
+        DatabaseError::WriteError(format!("Unable to back up database before migrating: {e}"))
+    })?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from_version) {
+        tracing::info!(
+            "Migrating database to version {}: {}",
+            migration.version,
+            migration.description
+        );
+
+        let write_txn = db.begin_write()?;
+        (migration.run)(&write_txn)?;
+        {
+            let mut table = write_txn.open_table(SCHEMA_TABLE)?;
+            table.insert(VERSION_KEY, migration.version)?;
+        }
+        write_txn.commit()?;
+    }
+
+    Ok(MigrationReport {
+        from_version,
+        to_version: CURRENT_SCHEMA_VERSION,
+        backup: Some(backup),
+    })
+}
+
+fn set_version(db: &redb::Database, version: u32) -> Result<(), DatabaseError> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut table = write_txn.open_table(SCHEMA_TABLE)?;
+        table.insert(VERSION_KEY, version)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+/// `lumo.db` -> `lumo.db.v0.bak`
+fn backup_path(location: &Path, version: u32) -> PathBuf {
+    let mut path = location.as_os_str().to_owned();
+    path.push(format!(".v{version}.bak"));
+    PathBuf::from(path)
+}
+
+// Version 1: wallets from before script types were tracked got P2WPKH through
+// `#[serde(default)]`, which is wrong for multisig wallets
+fn add_script_types(write_txn: &WriteTransaction) -> Result<(), DatabaseError> {
+    let mut table = write_txn.open_table(WALLETS_TABLE)?;
+    let mut updated = Vec::new();
+
+    for item in table.iter()? {
+        let (id, json_data) = item?;
+        let mut wallet: serde_json::Value = serde_json::from_str(json_data.value())?;
+        let Some(fields) = wallet.as_object_mut() else {
+            continue;
+        };
+        if fields.contains_key("script_type") {
+            continue;
+        }
+
+        let is_multisig = fields.get("multisig").is_some_and(|m| !m.is_null());
+        let script_type = if is_multisig { "P2wsh" } else { "P2wpkh" };
+        fields.insert("script_type".to_string(), script_type.into());
+        updated.push((id.value().to_string(), wallet.to_string()));
+    }
+
+    for (id, json) in updated {
+        table.insert(id.as_str(), json.as_str())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::{ScriptType, WalletMetadata};
+    use lumo_common::data_dir::TestDir;
+
+    // Wallet records as written before schema versioning
+    const V0_WALLET: &str = r#"{"id":"3f2504e0-4f89-41d3-9a0c-0305e82c3301","name":"Spending","network":"Testnet","created_at":"2025-01-04T10:00:00+00:00","wallet_type":"Hot","master_fingerprint":"73C5DA0A","mnemonic":"b64:YWJhbmRvbg==","multisig":null}"#;
+    const V0_MULTISIG: &str = r#"{"id":"3f2504e0-4f89-41d3-9a0c-0305e82c3302","name":"Vault","network":"Testnet","created_at":"2025-01-04T10:00:00+00:00","wallet_type":"Multisig","master_fingerprint":null,"mnemonic":null,"multisig":{"threshold":2}}"#;
+
+    /// Where to put a snapshot database, in a directory removed when the test ends
+    fn snapshot_location(name: &str) -> (TestDir, PathBuf) {
+        let dir = TestDir::new("migrations");
+        let location = dir.join(format!("{name}.db"));
+        (dir, location)
+    }
+
+    /// A database laid out the way version 0 wrote it
+    fn v0_snapshot(location: &Path) -> redb::Database {
+        let db = redb::Database::create(location).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut wallets = write_txn.open_table(WALLETS_TABLE).unwrap();
+            for json in [V0_WALLET, V0_MULTISIG] {
+                let id = serde_json::from_str::<serde_json::Value>(json).unwrap()["id"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                wallets.insert(id.as_str(), json).unwrap();
+            }
+
+            let config: TableDefinition<&str, &str> = TableDefinition::new("global_config");
+            let mut config = write_txn.open_table(config).unwrap();
+            config
+                .insert("selected_wallet_id", "3f2504e0-4f89-41d3-9a0c-0305e82c3301")
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+        db
+    }
+
+    fn raw_wallet(db: &redb::Database, id: &str) -> serde_json::Value {
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(WALLETS_TABLE).unwrap();
+        let json = table.get(id).unwrap().unwrap();
+        serde_json::from_str(json.value()).unwrap()
+    }
+
+    #[test]
+    fn test_fresh_database_starts_at_current_version() {
+        let (_dir, location) = snapshot_location("fresh");
+        let db = redb::Database::create(&location).unwrap();
+        assert_eq!(schema_version(&db).unwrap(), None);
+
+        let report = run(&db, &location).unwrap();
+        assert_eq!(report.to_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(report.backup, None);
+        assert_eq!(schema_version(&db).unwrap(), Some(CURRENT_SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_migrate_v0_snapshot() {
+        let (_dir, location) = snapshot_location("v0");
+        let db = v0_snapshot(&location);
+        assert_eq!(schema_version(&db).unwrap(), Some(0));
+
+        let report = run(&db, &location).unwrap();
+        assert_eq!(report.from_version, 0);
+        assert_eq!(report.to_version, CURRENT_SCHEMA_VERSION);
+        assert!(report.backup.as_ref().is_some_and(|backup| backup.exists()));
+
+        let multisig = raw_wallet(&db, "3f2504e0-4f89-41d3-9a0c-0305e82c3302");
+        assert_eq!(multisig["script_type"], "P2wsh");
+
+        // Old records still load into the current metadata
+        let wallet: WalletMetadata =
+            serde_json::from_value(raw_wallet(&db, "3f2504e0-4f89-41d3-9a0c-0305e82c3301"))
+                .unwrap();
+        assert_eq!(wallet.name, "Spending");
+        assert_eq!(wallet.script_type, ScriptType::P2wpkh);
+        assert!(!wallet.archived);
+
+        // Already migrated, nothing to do
+        let report = run(&db, &location).unwrap();
+        assert_eq!(report.from_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(report.backup, None);
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let (_dir, location) = snapshot_location("newer");
+        let db = redb::Database::create(&location).unwrap();
+        set_version(&db, CURRENT_SCHEMA_VERSION + 1).unwrap();
+
+        assert!(run(&db, &location).is_err());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::database::error::DatabaseError;
+use crate::wallet::multisig::PendingPsbt;
+use crate::wallet::WalletId;
+use redb::{ReadableDatabase, ReadableTable, TableDefinition};
+use std::sync::Arc;
+
+// Keyed by the txid of the unsigned transaction
+const TABLE: TableDefinition<&'static str, &'static str> = TableDefinition::new("pending_psbts");
+
+#[derive(Debug, Clone)]
+pub struct PendingPsbtsTable {
+    db: Arc<redb::Database>,
+}
+
+impl PendingPsbtsTable {
+    pub fn new(
+        db: Arc<redb::Database>,
+        write_txn: &redb::WriteTransaction,
+    ) -> Result<Self, DatabaseError> {
+        let _table = write_txn.open_table(TABLE)?;
+        Ok(Self { db })
+    }
+
+    // Insert or replace a pending PSBT
+    pub fn save(&self, pending: &PendingPsbt) -> Result<(), DatabaseError> {
+        let json = serde_json::to_string(pending)?;
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.insert(pending.txid.as_str(), json.as_str())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get(&self, txid: &str) -> Result<Option<PendingPsbt>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+
+        match table.get(txid)? {
+            Some(json_data) => Ok(Some(serde_json::from_str(json_data.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    // All pending PSBTs for a wallet
+    pub fn get_all(&self, wallet_id: &WalletId) -> Result<Vec<PendingPsbt>, DatabaseError> {
+        let read_txn = self.db.begin_read()?;
+        let table = read_txn.open_table(TABLE)?;
+        let mut pending = Vec::new();
+
+        for item in table.iter()? {
+            let (_txid, json_data) = item?;
+            let psbt: PendingPsbt = serde_json::from_str(json_data.value())?;
+            if &psbt.wallet_id == wallet_id {
+                pending.push(psbt);
+            }
+        }
+
+        Ok(pending)
+    }
+
+    pub fn remove(&self, txid: &str) -> Result<(), DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            table.remove(txid)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    // Remove every pending PSBT of a wallet
+    pub fn remove_all(&self, wallet_id: &WalletId) -> Result<(), DatabaseError> {
+        let txids: Vec<String> = self
+            .get_all(wallet_id)?
+            .into_iter()
+            .map(|pending| pending.txid)
+            .collect();
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            for txid in txids {
+                table.remove(txid.as_str())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+}
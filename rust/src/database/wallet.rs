@@ -33,6 +33,39 @@ impl WalletsTable {
 
         Ok(())
     }
+    // Replace the metadata of an existing wallet
+    pub fn update_wallet_metadata(&self, wallet: &WalletMetadata) -> Result<(), DatabaseError> {
+        let wallet_json = serde_json::to_string(wallet)?;
+
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            if table.get(wallet.id.to_string().as_str())?.is_none() {
+                return Err(DatabaseError::WriteError(format!(
+                    "Wallet {} not found",
+                    wallet.id
+                )));
+            }
+            table.insert(wallet.id.to_string().as_str(), wallet_json.as_str())?;
+        }
+        write_txn.commit()?;
+
+        Ok(())
+    }
+
+    // Remove a wallet's metadata, returns false if there was none
+    pub fn delete(&self, id: &WalletId) -> Result<bool, DatabaseError> {
+        let write_txn = self.db.begin_write()?;
+        let removed;
+        {
+            let mut table = write_txn.open_table(TABLE)?;
+            removed = table.remove(id.to_string().as_str())?.is_some();
+        }
+        write_txn.commit()?;
+
+        Ok(removed)
+    }
+
     pub fn get(&self, id: &WalletId) -> Result<Option<WalletMetadata>, DatabaseError> {
         let read_txn = self.db.begin_read()?;
         let table = read_txn.open_table(TABLE)?;
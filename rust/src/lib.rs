@@ -1,13 +1,20 @@
 pub mod bdk_store;
+pub mod cancel;
+pub mod daemon;
 pub mod database;
+pub mod lock;
 pub mod node;
 pub mod node_urls;
+pub mod sync_scheduler;
+pub mod ur;
 pub mod wallet;
 pub mod wallet_manager;
 
 // Re-export types from our crates
+pub use cancel::CancelToken;
 pub use lumo_common::{setup_logging, LumoError, GAP_LIMIT, MIN_SEND_SATS, ROOT_DATA_DIR};
 pub use lumo_types::*;
+pub use sync_scheduler::{SyncSchedule, SyncScheduler};
 pub use wallet_manager::WalletManager;
 
 // Re-export wallet types
@@ -16,10 +23,23 @@ pub use wallet::{
     Wallet, WalletId, WalletMetadata,
 };
 
+pub use lumo_common::data_dir::{list_profiles, DataDirOptions};
+
 /// Initialize the Lumo wallet library
 pub fn init() -> lumo_common::Result<()> {
+    init_with_options(DataDirOptions::default())
+}
+
+/// Initialize the Lumo wallet library with a data directory and profile
+///
+/// Must be called before any wallet or database is opened.
+pub fn init_with_options(options: DataDirOptions) -> lumo_common::Result<()> {
     lumo_common::setup_logging()?;
-    tracing::info!("Lumo wallet library initialized");
+    let data_dir = lumo_common::configure_data_dir(options)?;
+    tracing::info!(
+        "Lumo wallet library initialized, data in {}",
+        data_dir.display()
+    );
     Ok(())
 }
 
@@ -29,6 +49,11 @@ mod tests {
 
     #[test]
     fn test_init() {
-        assert!(init().is_ok());
+        // Tests keep their data in a temporary directory, never the platform one
+        let options = DataDirOptions {
+            data_dir: Some(ROOT_DATA_DIR.clone()),
+            profile: Some("default".to_string()),
+        };
+        assert!(init_with_options(options).is_ok());
     }
 }
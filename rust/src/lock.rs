@@ -0,0 +1,119 @@
+//! Advisory file locks shared between lumo processes
+//!
+//! A lock is held for as long as any `FileLock` for its path is alive. Taking the
+//! same lock twice in one process shares it instead of blocking on ourselves.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+/// How long to wait for another process to let go of a lock before giving up
+pub const LOCK_WAIT: Duration = Duration::from_secs(5);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// Locks held by this process, dropped entries are cleaned up on the next insert
+static HELD_LOCKS: Lazy<Mutex<HashMap<PathBuf, Weak<File>>>> = Lazy::new(Default::default);
+
+#[derive(Debug, thiserror::Error)]
+pub enum LockError {
+    #[error("{0} is in use by another lumo process")]
+    Busy(String),
+
+    #[error("Unable to lock {path}: {error}")]
+    Io { path: String, error: std::io::Error },
+}
+
+#[derive(Debug, Clone)]
+pub struct FileLock {
+    path: PathBuf,
+    _file: Arc<File>,
+}
+
+impl FileLock {
+    /// Take the lock, waiting up to `wait` for another process to release it
+    pub fn acquire(path: &Path, wait: Duration) -> Result<Self, LockError> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            match Self::try_acquire(path)? {
+                Some(lock) => return Ok(lock),
+                None if Instant::now() >= deadline => {
+                    return Err(LockError::Busy(path.display().to_string()))
+                }
+                None => std::thread::sleep(RETRY_INTERVAL),
+            }
+        }
+    }
+
+    /// Take the lock if no other process holds it
+    pub fn try_acquire(path: &Path) -> Result<Option<Self>, LockError> {
+        let mut held = HELD_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(file) = held.get(path).and_then(Weak::upgrade) {
+            return Ok(Some(Self {
+                path: path.to_path_buf(),
+                _file: file,
+            }));
+        }
+
+        let io_error = |error| LockError::Io {
+            path: path.display().to_string(),
+            error,
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(io_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(error)) => return Err(io_error(error)),
+        }
+
+        let file = Arc::new(file);
+        held.retain(|_, file| file.strong_count() > 0);
+        held.insert(path.to_path_buf(), Arc::downgrade(&file));
+
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            _file: file,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lumo_common::data_dir::TestDir;
+
+    #[test]
+    fn test_lock_is_shared_within_a_process() {
+        let dir = TestDir::new("locks");
+        let path = dir.join("wallet.lock");
+
+        let first = FileLock::try_acquire(&path).unwrap().unwrap();
+        let second = FileLock::acquire(&path, Duration::ZERO).unwrap();
+        assert_eq!(first.path(), second.path());
+
+        // Another open file description of the same file is what a second
+        // process would see, and it can't take the lock
+        let other = File::open(&path).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+
+        drop(first);
+        drop(second);
+        assert!(other.try_lock().is_ok());
+    }
+}
@@ -1,13 +1,42 @@
+use bdk_wallet::KeychainKind;
 use clap::{Parser, Subcommand};
+use lumo::daemon::methods::{
+    AddressResult, BalanceResult, CreatedWallet, PsbtResult, SendResult, SignatureResult,
+    TxidResult, WalletInfo,
+};
+use lumo::daemon::DaemonClient;
 use lumo::database::Database;
 use lumo::transaction::{ConfirmationStatus, TransactionDirection};
-use lumo::{init, Amount, FeeRate, Network, Wallet};
+use lumo::ur::{render_qr, UrDecoder, UrEncoder, UrPayload, DEFAULT_FRAGMENT_LEN};
+use lumo::wallet::analysis::TransactionAnalysis;
+use lumo::wallet::backup::Backup;
+use lumo::wallet::bsms::{CoordinatorSession, DescriptorRecord, EncryptionLevel, Token};
+use lumo::wallet::export::ExportFormat;
+use lumo::wallet::import::ImportedWallet;
+use lumo::wallet::invoice::{Invoice, InvoiceRequest};
+use lumo::wallet::multisig::PendingPsbt;
+use lumo::wallet::payjoin::PayjoinOutcome;
+use lumo::wallet::progress::{ProgressReporter, SyncProgress};
+use lumo::wallet::psbt::{decode_psbt, read_psbt_file, write_psbt_file, PsbtFormat};
+use lumo::wallet::reserves;
+use lumo::{init_with_options, Amount, CancelToken, DataDirOptions, FeeRate, Network, Wallet};
+use serde_json::json;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
 
 #[derive(Parser)]
 #[command(name = "lumo")]
 #[command(about = "A Bitcoin wallet CLI for testing")]
 #[command(version)]
 struct Cli {
+    /// Data directory (defaults to $LUMO_DATA_DIR or the platform data directory)
+    #[arg(long, global = true)]
+    data_dir: Option<PathBuf>,
+    /// Profile with its own wallets and settings (defaults to $LUMO_PROFILE)
+    #[arg(long, global = true)]
+    profile: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,12 +53,58 @@ enum Commands {
         /// Create wallet from existing mnemonic
         #[arg(long)]
         from_mnemonic: Option<String>,
+        /// Block height or date (YYYY-MM-DD) the restored wallet was first used
+        #[arg(long, requires = "from_mnemonic")]
+        birthday: Option<String>,
+    },
+    /// Create a new m-of-n multisig wallet
+    CreateMultisig {
+        /// Name of the wallet
+        name: String,
+        /// Bitcoin network (testnet or mainnet)
+        #[arg(long, default_value = "testnet")]
+        network: String,
+        /// Number of signatures required (m)
+        #[arg(long)]
+        threshold: usize,
+        /// Cosigner key with origin, e.g. [fingerprint/48h/1h/0h/2h]tpub... (repeatable)
+        #[arg(long = "cosigner")]
+        cosigners: Vec<String>,
+        /// Mnemonic of our own cosigner key, enables signing
+        #[arg(long)]
+        local_mnemonic: Option<String>,
     },
     /// List all wallets
     ListWallets {
         /// Filter by network
         #[arg(long)]
         network: Option<String>,
+        /// List archived wallets instead
+        #[arg(long)]
+        archived: bool,
+    },
+    /// Rename a wallet
+    RenameWallet {
+        /// Current name of the wallet
+        name: String,
+        /// New name of the wallet
+        new_name: String,
+    },
+    /// Hide a wallet from the wallet list without deleting it
+    ArchiveWallet {
+        /// Name of the wallet
+        name: String,
+        /// Bring an archived wallet back instead
+        #[arg(long)]
+        unarchive: bool,
+    },
+    /// Delete a wallet, its secret, labels and chain data
+    DeleteWallet {
+        /// Name of the wallet
+        name: String,
+        /// Confirmation token, required when the wallet holds funds
+        #[arg(long)]
+        confirm: Option<String>,
     },
     /// Select a wallet for operations
     SelectWallet {
@@ -53,16 +128,316 @@ enum Commands {
         #[arg(long, default_value = "sats")]
         unit: String,
     },
+    /// Create an invoice with its own address for the selected wallet
+    CreateInvoice {
+        /// Amount in satoshis
+        amount: u64,
+        /// What the payment is for, shown to the payer
+        #[arg(long)]
+        memo: Option<String>,
+        /// Seconds until the invoice expires, 0 for never
+        #[arg(long, default_value = "86400")]
+        expiry: u64,
+        /// Confirmations a payment needs before it counts
+        #[arg(long, default_value = "1")]
+        confirmations: u32,
+        /// Payjoin endpoint to offer in the URI, where lumod's payjoin listener is reachable
+        #[arg(long)]
+        payjoin: Option<String>,
+    },
+    /// List the selected wallet's invoices
+    ListInvoices {
+        #[arg(long, default_value = "sats")]
+        unit: String,
+    },
+    /// Show an invoice and the payments made to it
+    ShowInvoice {
+        /// Invoice id, the start of it, or its address
+        invoice: String,
+        #[arg(long, default_value = "sats")]
+        unit: String,
+    },
     /// Send a transaction
     SendTransaction {
-        /// Recipient address
+        /// Recipient address or BIP21 URI
         address: String,
-        /// Amount in satoshis
-        amount: u64,
+        /// Amount in satoshis, optional when the URI has one
+        amount: Option<u64>,
         /// Fee rate in sat/vB
         #[arg(long, default_value = "10")]
         fee_rate: f32,
+        /// Send a plain transaction even if the URI offers payjoin
+        #[arg(long)]
+        no_payjoin: bool,
+    },
+    /// Create an unsigned PSBT and write it to a file
+    CreatePsbt {
+        /// Recipient address or BIP21 URI
+        address: String,
+        /// Amount in satoshis, optional when the URI has one
+        amount: Option<u64>,
+        /// Fee rate in sat/vB
+        #[arg(long, default_value = "10")]
+        fee_rate: f32,
+        /// PSBT file to write
+        #[arg(long)]
+        out: PathBuf,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// Sign a PSBT file with the selected wallet
+    SignPsbt {
+        /// PSBT file to sign (base64, hex or binary)
+        input: PathBuf,
+        /// Signed PSBT file to write (defaults to overwriting the input)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// Combine partially signed PSBT files into one
+    CombinePsbt {
+        /// PSBT files to combine
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+        /// Combined PSBT file to write
+        #[arg(long)]
+        out: PathBuf,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// Finalize a fully signed PSBT file
+    FinalizePsbt {
+        /// PSBT file to finalize
+        input: PathBuf,
+        /// Finalized PSBT file to write (defaults to overwriting the input)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// Extract the transaction from a signed PSBT file and broadcast it
+    BroadcastPsbt {
+        /// Signed PSBT file
+        input: PathBuf,
+    },
+    /// Decode a PSBT or raw transaction and show what it does
+    Inspect {
+        /// PSBT or raw transaction hex, either inline or as a file path
+        input: String,
+        #[arg(long, default_value = "sats")]
+        unit: String,
+    },
+    /// List multisig PSBTs waiting for cosigner signatures
+    ListPendingPsbts,
+    /// Show a PSBT, descriptor or account as an animated BC-UR QR code
+    ShowQr {
+        /// What to show (psbt, output, account)
+        #[arg(default_value = "psbt")]
+        kind: String,
+        /// PSBT file to show
+        #[arg(long)]
+        input: Option<PathBuf>,
+        /// Maximum bytes per QR frame
+        #[arg(long, default_value_t = DEFAULT_FRAGMENT_LEN)]
+        fragment_len: usize,
+        /// Milliseconds between frames
+        #[arg(long, default_value = "300")]
+        interval: u64,
+    },
+    /// Decode scanned BC-UR parts, one per line, from a file or stdin
+    ReadQr {
+        /// File with the scanned parts (reads stdin if omitted)
+        #[arg(long)]
+        input: Option<PathBuf>,
+        /// PSBT file to write when the parts hold a PSBT
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// BSMS coordinator: start a multisig setup session and generate signer tokens
+    BsmsStart {
+        /// Number of signatures required (m)
+        #[arg(long)]
+        threshold: usize,
+        /// Number of signers (n)
+        #[arg(long)]
+        signers: usize,
+        /// Bitcoin network (testnet or mainnet)
+        #[arg(long, default_value = "testnet")]
+        network: String,
+        /// Encryption level (none, standard, extended)
+        #[arg(long, default_value = "standard")]
+        encryption: String,
+        /// Session file to write
+        #[arg(long, default_value = "bsms_session.json")]
+        session: PathBuf,
+    },
+    /// BSMS signer: write a signed key record for the selected wallet
+    BsmsKeyRecord {
+        /// Token received from the coordinator
+        #[arg(long)]
+        token: String,
+        /// Description of this signer
+        #[arg(long, default_value = "lumo")]
+        description: String,
+        /// Key record file to write
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// BSMS coordinator: verify key records and write descriptor records for the signers
+    BsmsFinalize {
+        /// Session file from bsms-start
+        #[arg(long, default_value = "bsms_session.json")]
+        session: PathBuf,
+        /// Key record file from a signer (repeatable)
+        #[arg(long = "record")]
+        records: Vec<PathBuf>,
+        /// Directory for the descriptor record files
+        #[arg(long, default_value = ".")]
+        out_dir: PathBuf,
+        /// Also create a watch-only multisig wallet with this name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// BSMS signer: verify a descriptor record and create the multisig wallet
+    BsmsImport {
+        /// Name of the new multisig wallet
+        name: String,
+        /// Token received from the coordinator
+        #[arg(long)]
+        token: String,
+        /// Descriptor record file from the coordinator
+        #[arg(long)]
+        record: PathBuf,
+    },
+    /// Import a wallet from Bitcoin Core `listdescriptors` output or an Electrum wallet file
+    ImportWallet {
+        /// Name of the wallet
+        name: String,
+        /// Wallet file to import
+        file: PathBuf,
+        /// Bitcoin network (testnet or mainnet)
+        #[arg(long, default_value = "testnet")]
+        network: String,
+        /// Script type to import if the file has several (p2pkh, p2sh-p2wpkh, p2wpkh, p2tr)
+        #[arg(long)]
+        script_type: Option<String>,
+        /// BIP329 labels file to import along with the wallet
+        #[arg(long)]
+        labels: Option<PathBuf>,
+    },
+    /// Export the selected wallet's public descriptors, birthday and labels
+    Export {
+        /// Export format (descriptors, labels, core, coldcard)
+        #[arg(long, default_value = "descriptors")]
+        format: String,
+        /// File to write (prints to the terminal if omitted)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Label a transaction, address or output of the selected wallet (BIP329)
+    SetLabel {
+        /// What to label (tx, addr, pubkey, input, output, xpub)
+        label_type: String,
+        /// Txid, address or outpoint (txid:vout) to label
+        reference: String,
+        /// The label, an empty label removes it
+        label: String,
+    },
+    /// Sign a message with the key of one of the selected wallet's addresses
+    SignMessage {
+        /// Address to prove control of
+        address: String,
+        message: String,
+    },
+    /// Check a signed message, BIP322 simple or legacy, against an address
+    VerifyMessage {
+        address: String,
+        message: String,
+        /// Base64 signature
+        signature: String,
+    },
+    /// Write a signed proof of reserves (BIP127) for the selected wallet's coins
+    ProveReserves {
+        /// Message the proof commits to, e.g. the auditor's challenge
+        #[arg(long)]
+        message: String,
+        /// Coin (txid:vout) to cover, repeat for more (defaults to every confirmed unspent output)
+        #[arg(long = "coin")]
+        coins: Vec<String>,
+        /// PSBT file to write
+        #[arg(long)]
+        out: PathBuf,
+        /// File format (base64 or binary)
+        #[arg(long, default_value = "base64")]
+        format: String,
+    },
+    /// Check a proof of reserves against the current UTXO set, counting only confirmed coins
+    VerifyReserves {
+        /// Proof PSBT file
+        input: PathBuf,
+        /// Message the proof must commit to
+        #[arg(long)]
+        message: String,
+        /// Bitcoin network (testnet or mainnet)
+        #[arg(long, default_value = "testnet")]
+        network: String,
+    },
+    /// Write an encrypted backup of the selected wallet, or of all wallets
+    Backup {
+        /// Backup file to write
+        #[arg(long)]
+        out: PathBuf,
+        /// Passphrase to encrypt the backup with
+        #[arg(long)]
+        passphrase: String,
+        /// Back up every wallet instead of just the selected one
+        #[arg(long)]
+        all: bool,
+        /// Leave out synced chain data, restored wallets will need a full rescan
+        #[arg(long)]
+        no_chain_data: bool,
+    },
+    /// Restore wallets from an encrypted backup
+    Restore {
+        /// Backup file to restore
+        file: PathBuf,
+        /// Passphrase the backup was encrypted with
+        #[arg(long)]
+        passphrase: String,
+    },
+    /// Reset the selected wallet's chain data and scan again
+    Rescan {
+        /// Skip history before this block height (defaults to the wallet's birthday)
+        #[arg(long)]
+        from_height: Option<u32>,
+    },
+    /// Show or change how the selected wallet is scanned
+    ScanSettings {
+        /// Unused addresses in a row before a scan stops
+        #[arg(long)]
+        stop_gap: Option<u32>,
+        /// Requests in flight at once, public servers are capped lower
+        #[arg(long)]
+        parallel_requests: Option<u32>,
     },
+    /// Use a custom esplora server for a network
+    SetNode {
+        /// Esplora url, omit to go back to the default server
+        url: Option<String>,
+        /// Bitcoin network (testnet or mainnet)
+        #[arg(long, default_value = "testnet")]
+        network: String,
+    },
+    /// List profiles in the data directory
+    ListProfiles,
     /// Generate a new mnemonic
     GenerateMnemonic,
 }
@@ -76,37 +451,469 @@ fn format_amount(amount: lumo::Amount, unit: &str) -> String {
 }
 
 fn parse_network(network_str: &str) -> Result<Network, String> {
-    match network_str.to_lowercase().as_str() {
-        "mainnet" => Ok(Network::Mainnet),
-        "testnet" => Ok(Network::Testnet),
-        "testnet4" => Ok(Network::Testnet4),
-        "signet" => Ok(Network::Signet),
-        "regtest" => Ok(Network::Regtest),
-        _ => Err(format!(
-            "Invalid network: {}. Valid options: mainnet, testnet, testnet4, signet, regtest",
-            network_str
-        )),
+    network_str.parse().map_err(|e: eyre::Error| e.to_string())
+}
+
+/// Load the selected wallet, printing why if there is none
+fn load_selected_wallet() -> Result<Option<Wallet>, Box<dyn std::error::Error>> {
+    let database = Database::global()?;
+    let Some(wallet_id) = database.global_config.selected_wallet()? else {
+        println!("❌ No wallet selected. Use 'select-wallet' command first.");
+        return Ok(None);
+    };
+
+    let wallets = Wallet::list_all(None)?;
+    let Some(meta) = wallets.iter().find(|w| w.id == wallet_id) else {
+        println!("❌ Selected wallet not found: {}", wallet_id);
+        return Ok(None);
+    };
+
+    Ok(Some(Wallet::try_load_persisted(&wallet_id, meta.network)?))
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1_023 => format!("{} B", bytes),
+        1_024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1_024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Sync that the next Ctrl-C stops, without one Ctrl-C exits as usual
+static CTRL_C_CANCEL: Mutex<Option<CancelToken>> = Mutex::new(None);
+
+/// Route Ctrl-C to `cancel`, or back to exiting the process with `None`
+///
+/// Tokio's handler stays installed for the rest of the process once registered,
+/// so a single handler decides what each Ctrl-C does.
+fn on_ctrl_c(cancel: Option<CancelToken>) {
+    static HANDLER: Once = Once::new();
+    HANDLER.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                let cancel = CTRL_C_CANCEL
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .take();
+                match cancel {
+                    Some(cancel) => cancel.cancel(),
+                    None => std::process::exit(130),
+                }
+            }
+        });
+    });
+
+    *CTRL_C_CANCEL
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = cancel;
+}
+
+/// Live sync status on stderr, Ctrl-C stops the sync and a second one exits
+struct SyncDisplay {
+    progress: ProgressReporter,
+    cancel: CancelToken,
+    shown: Arc<AtomicBool>,
+}
+
+impl SyncDisplay {
+    fn new() -> Self {
+        let shown = Arc::new(AtomicBool::new(false));
+        let interactive = std::io::stderr().is_terminal();
+
+        let line_shown = shown.clone();
+        let progress = ProgressReporter::new(move |progress: &SyncProgress| {
+            if !interactive {
+                return;
+            }
+            line_shown.store(true, Ordering::Relaxed);
+
+            let keychain = match progress.keychain {
+                KeychainKind::External => "receive",
+                KeychainKind::Internal => "change",
+            };
+            let last_used = progress
+                .last_active_index
+                .map_or("none used".to_string(), |index| {
+                    format!("last used #{}", index)
+                });
+            eprint!(
+                "\r   {} scripts checked, {} addresses {}, {} fetched\x1b[K",
+                progress.scripts_checked,
+                keychain,
+                last_used,
+                format_bytes(progress.bytes_fetched)
+            );
+        });
+
+        let cancel = CancelToken::new();
+        on_ctrl_c(Some(cancel.clone()));
+
+        Self {
+            progress,
+            cancel,
+            shown,
+        }
+    }
+}
+
+impl Drop for SyncDisplay {
+    fn drop(&mut self) {
+        on_ctrl_c(None);
+        if self.shown.load(Ordering::Relaxed) {
+            eprintln!();
+        }
+    }
+}
+
+/// Sync with a progress line, the wallet is left as it was if stopped early
+async fn sync_wallet(wallet: &mut Wallet) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔄 Syncing with blockchain... (Ctrl-C to stop)");
+    let display = SyncDisplay::new();
+    wallet
+        .sync_with(display.progress.clone(), &display.cancel)
+        .await?;
+    Ok(())
+}
+
+fn print_signature_status(wallet: &Wallet, psbt: &bitcoin::psbt::Psbt) {
+    if let Some(multisig) = &wallet.metadata.multisig {
+        let status = lumo::wallet::multisig::SignatureStatus::from_psbt(psbt, multisig.threshold);
+        println!(
+            "🖊️  Collected {}/{} signatures",
+            status.collected, status.required
+        );
+    }
+}
+
+fn print_transactions(transactions: &[lumo::Transaction], unit: &str) {
+    if transactions.is_empty() {
+        println!("📝 No transactions found");
+        return;
+    }
+
+    println!(
+        "📝 Transaction History ({} transactions):",
+        transactions.len()
+    );
+    println!();
+
+    for (i, tx) in transactions.iter().enumerate() {
+        let direction = match tx.direction {
+            TransactionDirection::Incoming => "📥 Received",
+            TransactionDirection::Outgoing => "📤 Sent",
+            TransactionDirection::SelfTransfer => "🔄 Self Transfer",
+        };
+
+        println!(
+            "{}. {} {}",
+            i + 1,
+            direction,
+            format_amount(tx.amount, unit)
+        );
+        match tx.direction {
+            TransactionDirection::Outgoing => {
+                if let Some(fee) = &tx.fee {
+                    let recipient_amount = tx.amount.as_sat().saturating_sub(fee.as_sat());
+                    println!(
+                        "   ├── To recipient: {} {}",
+                        recipient_amount,
+                        if unit == "sats" { "sats" } else { "BTC" }
+                    );
+                    println!("   ├── Network fee: {}", format_amount(*fee, unit));
+                }
+            }
+            TransactionDirection::SelfTransfer => {
+                println!("   ├── Transfer fee: {}", format_amount(tx.amount, unit));
+                println!("   ├── (Sent to your own address)");
+            }
+            TransactionDirection::Incoming => {
+                // No additional details needed for received transactions
+            }
+        }
+
+        // Truncated TXID
+        let txid_str = tx.id.to_string();
+        let short_txid = if txid_str.len() > 16 {
+            format!("{}...{}", &txid_str[0..8], &txid_str[txid_str.len() - 8..])
+        } else {
+            txid_str
+        };
+        println!("   └── TXID: {}", short_txid);
+
+        // Better status display
+        let status = match &tx.confirmation_status {
+            ConfirmationStatus::Unconfirmed => "Pending".to_string(),
+            ConfirmationStatus::Confirmed { block_height } => {
+                format!("Confirmed (Block {})", block_height)
+            }
+        };
+        println!("   Status: {}", status);
+        println!();
+    }
+}
+
+fn invoice_request(
+    amount: u64,
+    memo: Option<String>,
+    expiry: u64,
+    confirmations: u32,
+    payjoin: Option<String>,
+) -> InvoiceRequest {
+    InvoiceRequest {
+        memo,
+        payjoin,
+        expiry: (expiry > 0).then(|| std::time::Duration::from_secs(expiry)),
+        required_confirmations: confirmations,
+        ..InvoiceRequest::new(Amount::from_sat(amount))
+    }
+}
+
+/// Recipient address and amount to send, from a bare address or a BIP21 URI
+///
+/// An amount given on the command line has to match the one in the URI.
+fn send_target(
+    recipient: &str,
+    amount: Option<u64>,
+) -> Result<(String, u64, Option<lumo::Bip21Uri>), Box<dyn std::error::Error>> {
+    if !lumo::Bip21Uri::is_uri(recipient) {
+        let amount = amount.ok_or("An amount is required when sending to an address")?;
+        return Ok((recipient.trim().to_string(), amount, None));
+    }
+
+    let uri: lumo::Bip21Uri = recipient.parse()?;
+    let amount = match (uri.amount.map(|requested| requested.as_sat()), amount) {
+        (Some(requested), Some(given)) if requested != given => {
+            return Err(
+                format!("The URI asks for {requested} sats but {given} sats were given").into(),
+            )
+        }
+        (Some(requested), _) => requested,
+        (None, Some(given)) => given,
+        (None, None) => return Err("The URI has no amount, give one after it".into()),
+    };
+    Ok((uri.address.to_string(), amount, Some(uri)))
+}
+
+fn print_payment_request(uri: &lumo::Bip21Uri) {
+    if let Some(label) = &uri.label {
+        println!("   Recipient: {}", label);
+    }
+    if let Some(message) = &uri.message {
+        println!("   Message: {}", message);
+    }
+}
+
+/// `error` is why the payjoin fell back to the plain transaction
+fn print_payjoin_sent(txid: &str, error: Option<&str>) {
+    match error {
+        None => println!("✅ Payjoin sent successfully!"),
+        Some(error) => println!("⚠️ Payjoin failed, sent the original instead: {}", error),
+    }
+    println!("   TXID: {}", txid);
+}
+
+fn print_reserves_proof(out: &std::path::Path, psbt: &bitcoin::psbt::Psbt) {
+    let amount: u64 = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|o| o.value.to_sat())
+        .sum();
+    println!("✅ Proof of reserves written: {}", out.display());
+    println!("   Reserves: {} sats", amount);
+    // The first input is the commitment to the message, not a coin
+    println!("   Coins: {}", psbt.inputs.len() - 1);
+}
+
+fn print_signature(address: &str, signature: &str) {
+    println!("🖊️  Signed with {}", address);
+    println!("   Signature: {}", signature);
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn print_invoices(invoices: &[Invoice], unit: &str) {
+    if invoices.is_empty() {
+        println!("📭 No invoices");
+        return;
+    }
+
+    println!("🧾 Invoices ({}):", invoices.len());
+    for invoice in invoices {
+        println!(
+            "   {}  {}  {}  {}",
+            invoice.short_id(),
+            format_amount(invoice.amount, unit),
+            invoice.status,
+            invoice.memo.as_deref().unwrap_or("")
+        );
+    }
+}
+
+fn print_invoice(invoice: &Invoice, unit: &str) {
+    println!("🧾 Invoice {}", invoice.id);
+    println!("   Status: {}", invoice.status);
+    println!("   Amount: {}", format_amount(invoice.amount, unit));
+    println!("   Received: {}", format_amount(invoice.received, unit));
+    if !invoice.pending.is_zero() {
+        println!(
+            "   Waiting for {} confirmation(s): {}",
+            invoice.required_confirmations,
+            format_amount(invoice.pending, unit)
+        );
+    }
+    if let Some(memo) = &invoice.memo {
+        println!("   Memo: {}", memo);
+    }
+    println!("   Address: {}", invoice.address);
+    println!("   URI: {}", invoice.uri());
+    println!("   Created: {}", format_timestamp(invoice.created_at));
+    match invoice.expires_at {
+        Some(expires_at) => println!("   Expires: {}", format_timestamp(expires_at)),
+        None => println!("   Expires: never"),
+    }
+    for payment in &invoice.payments {
+        println!(
+            "   Payment: {} {} ({} confirmation(s))",
+            payment.txid,
+            format_amount(payment.amount, unit),
+            payment.confirmations
+        );
+    }
+}
+
+fn print_pending_psbts(pending: &[PendingPsbt]) {
+    if pending.is_empty() {
+        println!("📝 No pending PSBTs");
+        return;
+    }
+
+    println!("📝 Pending PSBTs ({}):", pending.len());
+    println!();
+    for (i, item) in pending.iter().enumerate() {
+        println!("{}. TXID: {}", i + 1, item.txid);
+        println!(
+            "   Signatures: {}/{}",
+            item.signatures.collected, item.signatures.required
+        );
+        println!("   Updated: {}", item.updated_at);
+        println!();
+    }
+}
+
+fn print_analysis(analysis: &TransactionAnalysis, unit: &str) {
+    println!("🔎 Transaction {}", analysis.txid);
+    println!();
+
+    println!("📥 Inputs ({}):", analysis.inputs.len());
+    for (i, input) in analysis.inputs.iter().enumerate() {
+        let amount = input
+            .amount
+            .map(|amount| format_amount(amount, unit))
+            .unwrap_or_else(|| "unknown amount".to_string());
+        let owner = if input.is_mine { "mine" } else { "external" };
+        println!("{}. {} ({}, {})", i, input.previous_output, amount, owner);
+        if let Some(address) = &input.address {
+            println!("   ├── Address: {}", address);
+        }
+        for path in &input.derivation_paths {
+            println!("   ├── Path: {}", path);
+        }
+        println!("   └── Signatures: {}", input.signatures);
+    }
+    println!();
+
+    println!("📤 Outputs ({}):", analysis.outputs.len());
+    for output in &analysis.outputs {
+        let owner = match (output.is_mine, output.is_change) {
+            (true, true) => "change",
+            (true, false) => "mine",
+            _ => "external",
+        };
+        let address = output.address.as_deref().unwrap_or("non-standard script");
+        println!(
+            "{}. {} ({}, {})",
+            output.index,
+            address,
+            format_amount(output.amount, unit),
+            owner
+        );
+        for path in &output.derivation_paths {
+            println!("   └── Path: {}", path);
+        }
+    }
+    println!();
+
+    match (&analysis.fee, &analysis.fee_rate) {
+        (Some(fee), Some(fee_rate)) => {
+            let estimate = if analysis.vsize_is_estimate { "~" } else { "" };
+            println!("   Fee: {}", format_amount(*fee, unit));
+            println!(
+                "   Fee Rate: {}{} ({}{} vB)",
+                estimate, fee_rate, estimate, analysis.vsize
+            );
+        }
+        _ => println!("   Fee: unknown"),
+    }
+    println!("   RBF: {}", if analysis.is_rbf { "yes" } else { "no" });
+    println!(
+        "   Locktime: {}",
+        analysis.lock_time.as_deref().unwrap_or("none")
+    );
+
+    if !analysis.warnings.is_empty() {
+        println!();
+        println!("⚠️  Warnings:");
+        for warning in &analysis.warnings {
+            println!("   - {}", warning);
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
     // Initialize the library
-    init()?;
+    init_with_options(DataDirOptions {
+        data_dir: cli.data_dir.clone(),
+        profile: cli.profile.clone(),
+    })?;
 
-    let cli = Cli::parse();
+    // A running daemon holds the database, so it answers for us
+    if let Some(client) = DaemonClient::discover().await {
+        match cli.command {
+            Commands::ListProfiles
+            | Commands::GenerateMnemonic
+            | Commands::VerifyMessage { .. }
+            | Commands::VerifyReserves { .. } => {}
+            command => return run_with_daemon(&client, command).await,
+        }
+    }
 
     match cli.command {
         Commands::CreateWallet {
             name,
             network,
             from_mnemonic,
+            birthday,
         } => {
             let network = parse_network(&network)?;
+            let birthday = birthday
+                .map(|birthday| birthday.parse::<lumo::wallet::Birthday>())
+                .transpose()?;
 
             let wallet = if let Some(mnemonic) = from_mnemonic {
                 println!("Creating wallet: {}", name);
-                Wallet::new_from_mnemonic(name, &mnemonic, network)?
+                let mut wallet = Wallet::new_from_mnemonic(name, &mnemonic, network)?;
+                if let Some(birthday) = birthday {
+                    wallet.set_birthday(birthday)?;
+                }
+                wallet
             } else {
                 println!("Creating wallet: {}", name);
                 let (wallet, mnemonic) = Wallet::new_random(name, network)?;
@@ -127,8 +934,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             if let Some(fingerprint) = &wallet.metadata.master_fingerprint {
                 println!("   Fingerprint: {}", fingerprint);
             }
+            if let Some(birthday) = &wallet.metadata.birthday {
+                println!("   Birthday: {}", birthday);
+            }
+        }
+        Commands::CreateMultisig {
+            name,
+            network,
+            threshold,
+            cosigners,
+            local_mnemonic,
+        } => {
+            let network = parse_network(&network)?;
+
+            println!("Creating multisig wallet: {}", name);
+            let wallet = Wallet::new_multisig(
+                name,
+                network,
+                threshold,
+                &cosigners,
+                local_mnemonic.as_deref(),
+            )?;
+
+            println!("✅ Multisig wallet created successfully: {}", wallet.id);
+            println!("   Name: {}", wallet.name());
+            println!("   Network: {}", wallet.network());
+            if let Some(multisig) = &wallet.metadata.multisig {
+                println!("   Policy: {} of {}", multisig.threshold, multisig.total());
+                for cosigner in &multisig.cosigners {
+                    let local = multisig.local_fingerprint.as_ref() == Some(&cosigner.fingerprint);
+                    println!(
+                        "   Cosigner: {}{}",
+                        cosigner.key,
+                        if local { " (local)" } else { "" }
+                    );
+                }
+            }
         }
-        Commands::ListWallets { network } => {
+        Commands::ListWallets { network, archived } => {
             println!("Listing wallets");
             let filter_network = if let Some(net_str) = network {
                 Some(parse_network(&net_str)?)
@@ -136,7 +979,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 None
             };
 
-            let wallets = Wallet::list_all(filter_network)?;
+            let wallets = if archived {
+                Wallet::list_archived(filter_network)?
+            } else {
+                Wallet::list_all(filter_network)?
+            };
 
             if wallets.is_empty() {
                 println!("No wallets found");
@@ -153,13 +1000,81 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+        Commands::RenameWallet { name, new_name } => {
+            let wallets = Database::global()?.wallets.get_all(None)?;
+            match wallets.iter().find(|w| w.name == name) {
+                Some(meta) => {
+                    let mut wallet = Wallet::try_load_persisted(&meta.id, meta.network)?;
+                    wallet.rename(&new_name)?;
+                    println!("✅ Renamed wallet {} to {}", name, wallet.name());
+                }
+                None => println!("❌ Wallet not found: {}", name),
+            }
+        }
+        Commands::ArchiveWallet { name, unarchive } => {
+            let wallets = if unarchive {
+                Wallet::list_archived(None)?
+            } else {
+                Wallet::list_all(None)?
+            };
+
+            match wallets.iter().find(|w| w.name == name) {
+                Some(meta) => {
+                    let mut wallet = Wallet::try_load_persisted(&meta.id, meta.network)?;
+                    wallet.set_archived(!unarchive)?;
+                    if unarchive {
+                        println!("✅ Wallet restored from the archive: {}", name);
+                    } else {
+                        println!("✅ Wallet archived: {}", name);
+                        println!("   Use 'list-wallets --archived' to see archived wallets");
+                    }
+                }
+                None => println!("❌ Wallet not found: {}", name),
+            }
+        }
+        Commands::DeleteWallet { name, confirm } => {
+            let wallets = Database::global()?.wallets.get_all(None)?;
+            let Some(meta) = wallets.iter().find(|w| w.name == name) else {
+                println!("❌ Wallet not found: {}", name);
+                return Ok(());
+            };
+
+            let mut wallet = Wallet::try_load_persisted(&meta.id, meta.network)?;
+            if let Err(e) = wallet.sync().await {
+                println!("⚠️  Unable to sync wallet {}: {}", name, e);
+            }
+            if let Some(token) = wallet.delete_confirmation_token() {
+                if confirm.as_deref() != Some(token.as_str()) {
+                    if wallet.recently_synced() {
+                        println!(
+                            "⚠️  Wallet {} holds {}",
+                            name,
+                            format_amount(wallet.balance().total(), "btc")
+                        );
+                    } else {
+                        println!(
+                            "⚠️  Wallet {} hasn't synced recently and may hold funds",
+                            name
+                        );
+                    }
+                    println!("   Make sure its recovery phrase is backed up, then run:");
+                    println!("   lumo delete-wallet \"{}\" --confirm {}", name, token);
+                    return Ok(());
+                }
+            }
+
+            let wallet_id = wallet.id.clone();
+            wallet.delete(confirm.as_deref())?;
+            println!("✅ Wallet deleted: {}", name);
+            println!("   ID: {}", wallet_id);
+        }
         Commands::SelectWallet { name } => {
             let wallets = Wallet::list_all(None)?;
             let wallet = wallets.iter().find(|w| w.name == name);
 
             match wallet {
                 Some(wallet_meta) => {
-                    let database = Database::global();
+                    let database = Database::global()?;
                     database.global_config.select_wallet(&wallet_meta.id)?;
 
                     println!("✅ Selected wallet: {}", wallet_meta.name);
@@ -179,7 +1094,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         Commands::GetAddress { index } => {
-            let database = Database::global();
+            let database = Database::global()?;
             let selected_id = database.global_config.selected_wallet()?;
 
             match selected_id {
@@ -215,7 +1130,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         Commands::GetBalance { unit } => {
-            let database = Database::global();
+            let database = Database::global()?;
             let selected_id = database.global_config.selected_wallet()?;
 
             match selected_id {
@@ -227,8 +1142,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let mut wallet = Wallet::try_load_persisted(&wallet_id, meta.network)?;
 
                         // Auto sync wallet
-                        println!("🔄 Syncing with blockchain...");
-                        wallet.sync().await?;
+                        sync_wallet(&mut wallet).await?;
 
                         let balance = wallet.balance();
 
@@ -256,7 +1170,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
         Commands::ShowHistory { unit } => {
-            let database = Database::global();
+            let database = Database::global()?;
             let selected_id = database.global_config.selected_wallet()?;
 
             match selected_id {
@@ -268,63 +1182,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let mut wallet = Wallet::try_load_persisted(&wallet_id, meta.network)?;
 
                         // Auto-sync for latest transactions
-                        println!("🔄 Syncing with blockchain...");
-                        wallet.sync().await?;
+                        sync_wallet(&mut wallet).await?;
 
                         let transactions = wallet.transactions()?;
-
-                        if transactions.is_empty() {
-                            println!("📝 No transactions found");
-                        } else {
-                            println!(
-                                "📝 Transaction History ({} transactions):",
-                                transactions.len()
-                            );
-                            println!();
-
-                            for (i, tx) in transactions.iter().enumerate() {
-                                let direction = match tx.direction {
-                                    TransactionDirection::Incoming => "📥 Received",
-                                    TransactionDirection::Outgoing => "📤 Sent",
-                                    TransactionDirection::SelfTransfer => "🔄 Self Transfer",
-                                };
-
-                                println!("{}. {} {}", i + 1, direction, format_amount(tx.amount, &unit));
-                                match tx.direction {
-                                    TransactionDirection::Outgoing => {
-                                        if let Some(fee) = &tx.fee {
-                                            let recipient_amount = tx.amount.as_sat().saturating_sub(fee.as_sat());
-                                            println!("   ├── To recipient: {} {}", recipient_amount, if unit == "sats" { "sats" } else { "BTC" });
-                                            println!("   ├── Network fee: {}", format_amount(*fee, &unit));
-                                        }
-                                    }
-                                    TransactionDirection::SelfTransfer => {
-                                        println!("   ├── Transfer fee: {}", format_amount(tx.amount, &unit));
-                                        println!("   ├── (Sent to your own address)");
-                                    }
-                                    TransactionDirection::Incoming => {
-                                        // No additional details needed for received transactions
-                                    }
-                                }
-
-                                // Truncated TXID
-                                let txid_str = tx.id.to_string();
-                                let short_txid = if txid_str.len() > 16 {
-                                    format!("{}...{}", &txid_str[0..8], &txid_str[txid_str.len()-8..])
-                                } else {
-                                    txid_str
-                                };
-                                println!("   └── TXID: {}", short_txid);
-
-                                // Better status display
-                                let status = match &tx.confirmation_status {
-                                    ConfirmationStatus::Unconfirmed => "Pending".to_string(),
-                                    ConfirmationStatus::Confirmed { block_height } => format!("Confirmed (Block {})", block_height),
-                                };
-                                println!("   Status: {}", status);
-                                println!();
-                            }
-                        }
+                        print_transactions(&transactions, &unit);
 
                         println!("   Wallet: {}", meta.name);
                         println!("   Network: {}", meta.network);
@@ -337,12 +1198,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+        Commands::CreateInvoice {
+            amount,
+            memo,
+            expiry,
+            confirmations,
+            payjoin,
+        } => {
+            if let Some(mut wallet) = load_selected_wallet()? {
+                let request = invoice_request(amount, memo, expiry, confirmations, payjoin);
+                let invoice = wallet.create_invoice(request)?;
+                println!("✅ Invoice created");
+                print_invoice(&invoice, "sats");
+            }
+        }
+        Commands::ListInvoices { unit } => {
+            if let Some(wallet) = load_selected_wallet()? {
+                print_invoices(&wallet.update_invoices()?, &unit);
+            }
+        }
+        Commands::ShowInvoice { invoice, unit } => {
+            if let Some(wallet) = load_selected_wallet()? {
+                wallet.update_invoices()?;
+                print_invoice(&wallet.invoice(&invoice)?, &unit);
+            }
+        }
         Commands::SendTransaction {
             address,
             amount,
             fee_rate,
+            no_payjoin,
         } => {
-            let database = Database::global();
+            let database = Database::global()?;
             let selected_id = database.global_config.selected_wallet()?;
 
             match selected_id {
@@ -354,10 +1241,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         let mut wallet = Wallet::try_load_persisted(&wallet_id, meta.network)?;
 
                         // Auto-sync for latest UTXOs
-                        println!("🔄 Syncing with blockchain...");
-                        wallet.sync().await?;
+                        sync_wallet(&mut wallet).await?;
 
-                        // Parse recipient address
+                        // Parse recipient address, or the address in a payment URI
+                        let (address, amount, uri) = send_target(&address, amount)?;
                         let recipient = lumo::Address::from_string(&address, meta.network)?;
                         let send_amount = Amount::from_sat(amount);
                         let fee_rate = FeeRate::from_sat_per_vb(fee_rate);
@@ -367,6 +1254,23 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         println!("   Amount: {} sats", amount);
                         println!("   Fee Rate: {}", fee_rate);
                         println!("   From: {}", meta.name);
+                        if let Some(uri) = &uri {
+                            print_payment_request(uri);
+                        }
+
+                        let payjoin = uri.and_then(|uri| uri.payjoin).filter(|_| !no_payjoin);
+                        if let Some(endpoint) = payjoin {
+                            println!("🤝 Requesting a payjoin from {}...", endpoint);
+                            let outcome = wallet
+                                .send_payjoin(recipient, send_amount, fee_rate, &endpoint)
+                                .await?;
+                            let error = match &outcome {
+                                PayjoinOutcome::Payjoin(_) => None,
+                                PayjoinOutcome::Fallback { reason, .. } => Some(reason.as_str()),
+                            };
+                            print_payjoin_sent(&outcome.txid().to_string(), error);
+                            return Ok(());
+                        }
 
                         // Build transaction
                         println!("🔨 Building transaction...");
@@ -382,7 +1286,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                         // Sign transaction
                         println!("✍️ Signing transaction...");
-                        let signed_tx = wallet.sign_transaction(psbt)?;
+                        let mut psbt = psbt;
+                        let finalized = wallet.sign_psbt(&mut psbt)?;
+
+                        if !finalized {
+                            // Multisig: more cosigners need to sign before broadcasting
+                            if wallet.metadata.multisig.is_some() {
+                                print_signature_status(&wallet, &psbt);
+                                println!("   Share this PSBT with your cosigners:");
+                                println!();
+                                println!("{}", psbt);
+                                return Ok(());
+                            }
+                            return Err("Transaction could not be finalized".into());
+                        }
+
+                        let signed_tx = psbt.extract_tx()?;
 
                         // Get TXID before broadcasting
                         let txid = signed_tx.compute_txid();
@@ -406,6 +1325,646 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
         }
+        Commands::CreatePsbt {
+            address,
+            amount,
+            fee_rate,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            if let Some(mut wallet) = load_selected_wallet()? {
+                // Auto-sync for latest UTXOs
+                sync_wallet(&mut wallet).await?;
+
+                let (address, amount, _) = send_target(&address, amount)?;
+                let recipient = lumo::Address::from_string(&address, wallet.network())?;
+                let psbt = wallet.build_transaction(
+                    recipient,
+                    Amount::from_sat(amount),
+                    FeeRate::from_sat_per_vb(fee_rate),
+                )?;
+                write_psbt_file(&out, &psbt, format)?;
+
+                println!("✅ PSBT written: {}", out.display());
+                println!("   Inputs: {}", psbt.inputs.len());
+                println!("   Outputs: {}", psbt.outputs.len());
+                if let Ok(fee) = psbt.fee() {
+                    println!("   Fee: {} sats", fee.to_sat());
+                }
+                println!("   Wallet: {}", wallet.name());
+            }
+        }
+        Commands::SignPsbt { input, out, format } => {
+            let format = format.parse::<PsbtFormat>()?;
+            if let Some(mut wallet) = load_selected_wallet()? {
+                let mut psbt = read_psbt_file(&input)?;
+
+                println!("✍️ Signing PSBT...");
+                let finalized = wallet.sign_psbt(&mut psbt)?;
+
+                let out = out.unwrap_or(input);
+                write_psbt_file(&out, &psbt, format)?;
+
+                if finalized {
+                    println!("✅ PSBT signed and finalized: {}", out.display());
+                } else {
+                    println!("✅ PSBT signed: {}", out.display());
+                }
+                print_signature_status(&wallet, &psbt);
+            }
+        }
+        Commands::CombinePsbt {
+            inputs,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            if let Some(wallet) = load_selected_wallet()? {
+                let psbts = inputs
+                    .iter()
+                    .map(|path| read_psbt_file(path))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let combined = wallet.combine_psbts(psbts)?;
+                write_psbt_file(&out, &combined, format)?;
+
+                println!("✅ Combined {} PSBTs: {}", inputs.len(), out.display());
+                print_signature_status(&wallet, &combined);
+            }
+        }
+        Commands::FinalizePsbt { input, out, format } => {
+            let format = format.parse::<PsbtFormat>()?;
+            if let Some(wallet) = load_selected_wallet()? {
+                let mut psbt = read_psbt_file(&input)?;
+
+                if wallet.finalize_psbt(&mut psbt)? {
+                    let out = out.unwrap_or(input);
+                    write_psbt_file(&out, &psbt, format)?;
+                    println!("✅ PSBT finalized: {}", out.display());
+                } else {
+                    println!("❌ PSBT could not be finalized, it is missing signatures");
+                    print_signature_status(&wallet, &psbt);
+                }
+            }
+        }
+        Commands::BroadcastPsbt { input } => {
+            if let Some(mut wallet) = load_selected_wallet()? {
+                let psbt = read_psbt_file(&input)?;
+                let transaction = wallet.extract_transaction(psbt)?;
+                let txid = transaction.compute_txid();
+
+                println!("📡 Broadcasting to network...");
+                wallet.broadcast_transaction(transaction).await?;
+
+                println!("✅ Transaction sent successfully!");
+                println!("   TXID: {}", txid);
+            }
+        }
+        Commands::Inspect { input, unit } => {
+            if let Some(wallet) = load_selected_wallet()? {
+                let path = PathBuf::from(&input);
+                let data = if path.is_file() {
+                    std::fs::read(&path)?
+                } else {
+                    input.clone().into_bytes()
+                };
+
+                let analysis = match decode_psbt(&data) {
+                    Ok(psbt) => wallet.analyze_psbt(&psbt)?,
+                    Err(_) => wallet.analyze_raw_tx(String::from_utf8_lossy(&data).as_ref())?,
+                };
+
+                print_analysis(&analysis, &unit);
+            }
+        }
+        Commands::ListPendingPsbts => {
+            let database = Database::global()?;
+            let selected_id = database.global_config.selected_wallet()?;
+
+            match selected_id {
+                Some(wallet_id) => {
+                    let wallets = Wallet::list_all(None)?;
+                    let wallet_meta = wallets.iter().find(|w| w.id == wallet_id);
+
+                    if let Some(meta) = wallet_meta {
+                        let wallet = Wallet::try_load_persisted(&wallet_id, meta.network)?;
+                        let pending = wallet.pending_psbts()?;
+                        print_pending_psbts(&pending);
+
+                        println!("   Wallet: {}", meta.name);
+                        println!("   Network: {}", meta.network);
+                    } else {
+                        println!("❌ Selected wallet not found: {}", wallet_id);
+                    }
+                }
+                None => {
+                    println!("❌ No wallet selected. Use 'select-wallet' command first.");
+                }
+            }
+        }
+        Commands::ShowQr {
+            kind,
+            input,
+            fragment_len,
+            interval,
+        } => {
+            let payload = match kind.to_lowercase().as_str() {
+                "psbt" => {
+                    let Some(input) = input else {
+                        return Err("A PSBT file is required, use --input".into());
+                    };
+                    UrPayload::Psbt(read_psbt_file(&input)?)
+                }
+                "output" => match load_selected_wallet()? {
+                    Some(wallet) => wallet.ur_output(),
+                    None => return Ok(()),
+                },
+                "account" => match load_selected_wallet()? {
+                    Some(wallet) => wallet.ur_account()?,
+                    None => return Ok(()),
+                },
+                _ => {
+                    return Err(format!(
+                        "Invalid kind: {}. Valid options: psbt, output, account",
+                        kind
+                    )
+                    .into())
+                }
+            };
+
+            let mut encoder = UrEncoder::new(&payload, fragment_len)?;
+            let fragments = encoder.fragment_count();
+
+            if fragments == 1 {
+                let part = encoder.next_part()?;
+                println!("{}", render_qr(&part)?);
+                println!("{}", part.to_uppercase());
+                return Ok(());
+            }
+
+            // Fountain coded parts keep coming after the first n, so a scanner that
+            // missed some frames catches up on the next loop
+            let mut frame: usize = 0;
+            loop {
+                let part = encoder.next_part()?;
+                frame += 1;
+
+                print!("\x1B[2J\x1B[H");
+                println!("{}", render_qr(&part)?);
+                println!(
+                    "📷 {} frame {} ({} parts) - press Ctrl+C to stop",
+                    payload.ur_type(),
+                    frame,
+                    fragments
+                );
+
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = tokio::time::sleep(std::time::Duration::from_millis(interval)) => {}
+                }
+            }
+        }
+        Commands::ReadQr { input, out, format } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let text = match &input {
+                Some(path) => std::fs::read_to_string(path)?,
+                None => std::io::read_to_string(std::io::stdin())?,
+            };
+
+            let mut decoder = UrDecoder::new();
+            for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                decoder.receive(line)?;
+                if decoder.is_complete() {
+                    break;
+                }
+            }
+
+            match decoder.payload()? {
+                Some(UrPayload::Psbt(psbt)) => match out {
+                    Some(out) => {
+                        write_psbt_file(&out, &psbt, format)?;
+                        println!("✅ PSBT written: {}", out.display());
+                    }
+                    None => println!("{}", psbt),
+                },
+                Some(UrPayload::Output(descriptor)) => {
+                    println!("📜 Descriptor:");
+                    println!("{}", descriptor);
+                }
+                Some(UrPayload::Account {
+                    master_fingerprint,
+                    outputs,
+                }) => {
+                    println!("🔑 Account {}:", master_fingerprint);
+                    for descriptor in outputs {
+                        println!("   {}", descriptor);
+                    }
+                }
+                None => {
+                    println!("❌ Not enough parts to decode, keep scanning and try again");
+                }
+            }
+        }
+        Commands::BsmsStart {
+            threshold,
+            signers,
+            network,
+            encryption,
+            session,
+        } => {
+            let network = parse_network(&network)?;
+            let level = encryption.parse::<EncryptionLevel>()?;
+            let bsms_session = CoordinatorSession::new(threshold, signers, network, level)?;
+            bsms_session.save(&session)?;
+
+            println!("✅ BSMS session started: {} of {}", threshold, signers);
+            println!("   Session file: {}", session.display());
+            println!();
+            println!("🔑 Give each signer its token:");
+            for (i, token) in bsms_session.tokens.iter().enumerate() {
+                println!("   Signer {}: {}", i + 1, token);
+            }
+        }
+        Commands::BsmsKeyRecord {
+            token,
+            description,
+            out,
+        } => {
+            let database = Database::global()?;
+            let selected_id = database.global_config.selected_wallet()?;
+
+            match selected_id {
+                Some(wallet_id) => {
+                    let wallets = Wallet::list_all(None)?;
+                    let wallet_meta = wallets.iter().find(|w| w.id == wallet_id);
+
+                    if let Some(meta) = wallet_meta {
+                        let wallet = Wallet::try_load_persisted(&wallet_id, meta.network)?;
+                        let record =
+                            wallet.bsms_key_record(Token::from_hex(&token)?, &description)?;
+                        std::fs::write(&out, record.encode())?;
+
+                        println!("✅ Key record written: {}", out.display());
+                        println!("   Key: {}", record.key);
+                        println!("   Wallet: {}", meta.name);
+                    } else {
+                        println!("❌ Selected wallet not found: {}", wallet_id);
+                    }
+                }
+                None => {
+                    println!("❌ No wallet selected. Use 'select-wallet' command first.");
+                }
+            }
+        }
+        Commands::BsmsFinalize {
+            session,
+            records,
+            out_dir,
+            name,
+        } => {
+            let bsms_session = CoordinatorSession::load(&session)?;
+
+            let payloads = records
+                .iter()
+                .map(std::fs::read_to_string)
+                .collect::<Result<Vec<_>, _>>()?;
+            let key_records = bsms_session.collect_key_records(&payloads)?;
+
+            println!("✅ Verified {} key records:", key_records.len());
+            for record in &key_records {
+                println!("   {} ({})", record.key, record.description);
+            }
+
+            let descriptor_record = bsms_session.descriptor_record(&key_records)?;
+            println!();
+            println!("📍 First address: {}", descriptor_record.first_address);
+
+            std::fs::create_dir_all(&out_dir)?;
+            for (i, token) in bsms_session.tokens.iter().enumerate() {
+                let path = out_dir.join(format!("bsms_descriptor_{}.txt", i + 1));
+                std::fs::write(&path, descriptor_record.encode(token))?;
+                println!("   Signer {}: {}", i + 1, path.display());
+            }
+
+            if let Some(name) = name {
+                let wallet = Wallet::new_multisig_from_bsms(
+                    name,
+                    bsms_session.network,
+                    &descriptor_record,
+                    None,
+                )?;
+                println!();
+                println!("✅ Watch-only multisig wallet created: {}", wallet.id);
+            }
+        }
+        Commands::BsmsImport {
+            name,
+            token,
+            record,
+        } => {
+            let database = Database::global()?;
+            let selected_id = database.global_config.selected_wallet()?;
+
+            match selected_id {
+                Some(wallet_id) => {
+                    let wallets = Wallet::list_all(None)?;
+                    let wallet_meta = wallets.iter().find(|w| w.id == wallet_id);
+
+                    if let Some(meta) = wallet_meta {
+                        let signer = Wallet::try_load_persisted(&wallet_id, meta.network)?;
+                        let payload = std::fs::read_to_string(&record)?;
+                        let descriptor_record =
+                            DescriptorRecord::decode(&payload, &Token::from_hex(&token)?)?;
+
+                        let wallet = Wallet::new_multisig_from_bsms(
+                            name,
+                            meta.network,
+                            &descriptor_record,
+                            Some(&signer),
+                        )?;
+
+                        println!(
+                            "✅ First address verified: {}",
+                            descriptor_record.first_address
+                        );
+                        println!("✅ Multisig wallet created successfully: {}", wallet.id);
+                        println!("   Name: {}", wallet.name());
+                        println!("   Signer: {}", meta.name);
+                    } else {
+                        println!("❌ Selected wallet not found: {}", wallet_id);
+                    }
+                }
+                None => {
+                    println!("❌ No wallet selected. Use 'select-wallet' command first.");
+                }
+            }
+        }
+        Commands::ImportWallet {
+            name,
+            file,
+            network,
+            script_type,
+            labels,
+        } => {
+            let network = parse_network(&network)?;
+            let script_type = script_type
+                .map(|script_type| script_type.parse::<lumo::wallet::ScriptType>())
+                .transpose()?;
+
+            let data = std::fs::read_to_string(&file)?;
+            let mut imported = ImportedWallet::parse(&data, script_type)?;
+            if let Some(labels) = labels {
+                let jsonl = std::fs::read_to_string(&labels)?;
+                imported.labels.extend(lumo::Label::from_jsonl(&jsonl)?);
+            }
+
+            let wallet = Wallet::new_from_import(name, network, &imported)?;
+
+            println!("✅ Wallet imported successfully: {}", wallet.id);
+            println!("   Name: {}", wallet.name());
+            println!("   Script type: {}", imported.script_type.description());
+            println!("   Descriptor: {}", imported.external);
+            if imported.has_private_keys() {
+                println!("   Private keys: imported, this wallet can sign");
+            } else {
+                println!("   Private keys: none, this wallet is watch-only");
+            }
+            if let Some(birthday) = &wallet.metadata.birthday {
+                println!("   Birthday: {}", birthday);
+            }
+            println!("   Labels: {}", imported.labels.len());
+        }
+        Commands::Export { format, out } => {
+            let format = format.parse::<ExportFormat>()?;
+            if let Some(wallet) = load_selected_wallet()? {
+                let export = wallet.export().await?.export(format)?;
+
+                match out {
+                    Some(out) => {
+                        std::fs::write(&out, export)?;
+                        println!("✅ Wallet exported: {}", out.display());
+                        println!("   Wallet: {}", wallet.name());
+                    }
+                    None => println!("{}", export),
+                }
+            }
+        }
+        Commands::SetLabel {
+            label_type,
+            reference,
+            label,
+        } => {
+            let label_type = label_type.parse::<lumo::LabelType>()?;
+            if let Some(wallet) = load_selected_wallet()? {
+                wallet.set_label(label_type, &reference, &label)?;
+
+                if label.trim().is_empty() {
+                    println!("✅ Label removed from {} {}", label_type, reference);
+                } else {
+                    println!("✅ Labeled {} {}: {}", label_type, reference, label.trim());
+                }
+            }
+        }
+        Commands::SignMessage { address, message } => {
+            if let Some(wallet) = load_selected_wallet()? {
+                let address = lumo::Address::from_string(&address, wallet.network())?;
+                let signature = wallet.sign_message(&address, &message)?;
+                print_signature(&address.to_string(), &signature);
+            }
+        }
+        Commands::VerifyMessage {
+            address,
+            message,
+            signature,
+        } => {
+            let address = lumo::AddressWithNetwork::from_string(&address)?.address;
+            if lumo::verify_message(&address, &message, &signature)? {
+                println!("✅ Signature is valid for {}", address);
+            } else {
+                return Err(format!("Signature is not valid for {}", address).into());
+            }
+        }
+        Commands::ProveReserves {
+            message,
+            coins,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let coins = coins
+                .iter()
+                .map(|coin| coin.parse::<bitcoin::OutPoint>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(mut wallet) = load_selected_wallet()? {
+                // Auto-sync so the proof covers the latest UTXOs
+                sync_wallet(&mut wallet).await?;
+
+                let coins = (!coins.is_empty()).then_some(coins.as_slice());
+                let psbt = wallet.create_reserves_proof(&message, coins)?;
+                write_psbt_file(&out, &psbt, format)?;
+                print_reserves_proof(&out, &psbt);
+            }
+        }
+        Commands::VerifyReserves {
+            input,
+            message,
+            network,
+        } => {
+            let network = parse_network(&network)?;
+            let psbt = read_psbt_file(&input)?;
+            let node = lumo::node::Node::for_network(network);
+            let client = lumo::node::client::esplora::EsploraClient::new(&node.url).await?;
+
+            println!("🔍 Checking the proof against {}...", node.url);
+            let proven = reserves::verify_proof(&psbt, &message, &client).await?;
+            println!("✅ Proof of reserves is valid");
+            println!("   Reserves: {} sats", proven.amount.as_sat());
+            println!("   Coins: {}", proven.coins.len());
+        }
+        Commands::Backup {
+            out,
+            passphrase,
+            all,
+            no_chain_data,
+        } => {
+            let database = Database::global()?;
+            let wallets = if all {
+                // Archived wallets are backed up too
+                database
+                    .wallets
+                    .get_all(None)?
+                    .iter()
+                    .map(|meta| Wallet::try_load_persisted(&meta.id, meta.network))
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                match load_selected_wallet()? {
+                    Some(wallet) => vec![wallet],
+                    None => return Ok(()),
+                }
+            };
+
+            let wallet_backups = wallets
+                .iter()
+                .map(|wallet| wallet.backup(!no_chain_data))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let backup = Backup::new(
+                wallet_backups,
+                database.global_config.selected_wallet()?,
+                database.global_config.node_urls()?,
+            );
+            std::fs::write(&out, backup.encrypt(&passphrase)?)?;
+
+            println!("✅ Backup written: {}", out.display());
+            for wallet in &wallets {
+                println!("   {} ({})", wallet.name(), wallet.id);
+            }
+            if no_chain_data {
+                println!("   Chain data left out, restored wallets will need a full rescan");
+            }
+        }
+        Commands::Restore { file, passphrase } => {
+            let data = std::fs::read(&file)?;
+            let backup = Backup::decrypt(&data, &passphrase)?;
+            let restored = backup.restore()?;
+
+            println!(
+                "✅ Restored {} wallet(s) from {}",
+                restored.len(),
+                file.display()
+            );
+            for wallet in &restored {
+                println!("   {} ({})", wallet.metadata.name, wallet.metadata.id);
+                if wallet.needs_rescan {
+                    println!("      Run 'get-balance' to scan the chain for this wallet");
+                }
+            }
+            if !backup.node_urls.is_empty() {
+                println!(
+                    "   Node settings restored for {} network(s)",
+                    backup.node_urls.len()
+                );
+            }
+        }
+        Commands::Rescan { from_height } => {
+            if let Some(mut wallet) = load_selected_wallet()? {
+                match (from_height, &wallet.metadata.birthday) {
+                    (Some(height), _) => println!("🔄 Rescanning from block {}...", height),
+                    (None, Some(birthday)) => println!("🔄 Rescanning from {}...", birthday),
+                    (None, None) => println!("🔄 Rescanning the whole chain..."),
+                }
+                let display = SyncDisplay::new();
+                wallet
+                    .rescan(from_height, display.progress.clone(), &display.cancel)
+                    .await?;
+                drop(display);
+
+                println!("✅ Rescan complete");
+                println!("   Wallet: {}", wallet.name());
+                println!("   Transactions: {}", wallet.transactions()?.len());
+            }
+        }
+        Commands::ScanSettings {
+            stop_gap,
+            parallel_requests,
+        } => {
+            if let Some(mut wallet) = load_selected_wallet()? {
+                let mut settings = wallet.metadata.scan;
+                if stop_gap.is_some() || parallel_requests.is_some() {
+                    settings.stop_gap = stop_gap.unwrap_or(settings.stop_gap);
+                    settings.parallel_requests =
+                        parallel_requests.unwrap_or(settings.parallel_requests);
+                    wallet.set_scan_settings(settings)?;
+                    println!("✅ Scan settings updated");
+                } else {
+                    println!("🔍 Scan settings:");
+                }
+
+                println!("   Wallet: {}", wallet.name());
+                println!("   Stop gap: {}", settings.stop_gap);
+                println!("   Parallel requests: {}", settings.parallel_requests);
+                let node = lumo::node::Node::for_network(wallet.network());
+                if lumo::node_urls::is_public_esplora(&node.url) {
+                    println!(
+                        "   {} is a public server, at most {} requests run at once",
+                        node.name,
+                        lumo::node::client::throttle::PUBLIC_MAX_PARALLEL
+                    );
+                }
+            }
+        }
+        Commands::SetNode { url, network } => {
+            let network = parse_network(&network)?;
+            let database = Database::global()?;
+
+            match url {
+                Some(url) => {
+                    database.global_config.set_node_url(network, &url)?;
+                    println!("✅ Using {} for {}", url, network);
+                }
+                None => {
+                    database.global_config.clear_node_url(network)?;
+                    let node = lumo::node::Node::default(network);
+                    println!("✅ Using the default node for {}: {}", network, node.url);
+                }
+            }
+        }
+        Commands::ListProfiles => {
+            let base = DataDirOptions {
+                data_dir: cli.data_dir.clone(),
+                profile: Some("default".to_string()),
+            }
+            .resolve()?;
+            let profiles = lumo::list_profiles(&base)?;
+
+            println!("📁 Data directory: {}", base.display());
+            println!("   default");
+            for profile in profiles {
+                println!("   {}", profile);
+            }
+            println!("   Active: {}", lumo::ROOT_DATA_DIR.display());
+        }
         Commands::GenerateMnemonic => {
             println!("Generating new mnemonic");
             // TODO: Implement mnemonic generation
@@ -414,3 +1973,363 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Run a command through a running `lumod` instead of opening the database
+async fn run_with_daemon(
+    client: &DaemonClient,
+    command: Commands,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        Commands::CreateWallet {
+            name,
+            network,
+            from_mnemonic,
+            birthday,
+        } => {
+            println!("Creating wallet: {}", name);
+            let created: CreatedWallet = client
+                .call(
+                    "createwallet",
+                    json!({
+                        "name": name,
+                        "network": network,
+                        "mnemonic": from_mnemonic,
+                        "birthday": birthday,
+                    }),
+                )
+                .await?;
+
+            if let Some(mnemonic) = &created.mnemonic {
+                println!();
+                println!("🔑 RECOVERY PHRASE (WRITE THIS DOWN!):");
+                println!();
+                println!("{}", mnemonic);
+                println!();
+                println!("⚠️  IMPORTANT: Save these words in a secure location!");
+                println!();
+            }
+
+            let wallet = created.wallet;
+            println!("✅ Wallet created successfully: {}", wallet.id);
+            println!("   Name: {}", wallet.name);
+            println!("   ID: {}", wallet.id);
+            println!("   Network: {}", wallet.network);
+            if let Some(fingerprint) = &wallet.master_fingerprint {
+                println!("   Fingerprint: {}", fingerprint);
+            }
+            if let Some(birthday) = &wallet.birthday {
+                println!("   Birthday: {}", birthday);
+            }
+        }
+        Commands::ListWallets { network, archived } => {
+            println!("Listing wallets");
+            let wallets: Vec<WalletInfo> = client
+                .call(
+                    "listwallets",
+                    json!({ "network": network, "archived": archived }),
+                )
+                .await?;
+
+            if wallets.is_empty() {
+                println!("No wallets found");
+            } else {
+                println!("Found {} wallets:", wallets.len());
+                for (i, wallet) in wallets.iter().enumerate() {
+                    println!("{}. {}", i + 1, wallet.name);
+                    println!("    Network: {}", wallet.network);
+                    println!("    ID: {}", wallet.id);
+                    if let Some(fingerprint) = &wallet.master_fingerprint {
+                        println!("    Fingerprint: {}", fingerprint);
+                    }
+                    println!();
+                }
+            }
+        }
+        Commands::SelectWallet { name } => {
+            let wallet: WalletInfo = client
+                .call("selectwallet", json!({ "wallet": name }))
+                .await?;
+
+            println!("✅ Selected wallet: {}", wallet.name);
+            println!("   ID: {}", wallet.id);
+            println!("   Network: {}", wallet.network);
+            if let Some(fp) = &wallet.master_fingerprint {
+                println!("   Fingerprint: {}", fp);
+            }
+        }
+        Commands::GetAddress { index } => {
+            let address: AddressResult =
+                client.call("getaddress", json!({ "index": index })).await?;
+
+            match index {
+                Some(idx) => println!("📍 Address at index {}: {}", idx, address.address),
+                None => println!("📍 Current receiving address: {}", address.address),
+            }
+        }
+        Commands::GetBalance { unit } => {
+            // The daemon keeps wallets synced, no need to wait for a sync here
+            let balance: BalanceResult = client.call("getbalance", json!({})).await?;
+
+            println!(
+                "💰 Wallet balance: {}",
+                format_amount(balance.spendable, &unit)
+            );
+            println!("   Spendable: {}", format_amount(balance.spendable, &unit));
+            println!("   Confirmed: {}", format_amount(balance.confirmed, &unit));
+        }
+        Commands::ShowHistory { unit } => {
+            let transactions: Vec<lumo::Transaction> =
+                client.call("listtransactions", json!({})).await?;
+            print_transactions(&transactions, &unit);
+        }
+        Commands::SendTransaction {
+            address,
+            amount,
+            fee_rate,
+            no_payjoin,
+        } => {
+            let (address, amount, uri) = send_target(&address, amount)?;
+
+            println!("💸 Sending Transaction:");
+            println!("   To: {}", address);
+            println!("   Amount: {} sats", amount);
+            println!("   Fee Rate: {}", FeeRate::from_sat_per_vb(fee_rate));
+            if let Some(uri) = &uri {
+                print_payment_request(uri);
+            }
+
+            let payjoin = uri.and_then(|uri| uri.payjoin).filter(|_| !no_payjoin);
+            if let Some(endpoint) = &payjoin {
+                println!("🤝 Requesting a payjoin from {}...", endpoint);
+            }
+
+            let sent: SendResult = client
+                .call(
+                    "send",
+                    json!({
+                        "address": address,
+                        "amount": amount,
+                        "fee_rate": fee_rate,
+                        "payjoin": payjoin,
+                    }),
+                )
+                .await?;
+
+            if let (Some(txid), Some(payjoin)) = (&sent.txid, &sent.payjoin) {
+                print_payjoin_sent(txid, payjoin.error.as_deref());
+                return Ok(());
+            }
+            match (sent.txid, sent.pending) {
+                (Some(txid), _) => {
+                    println!("✅ Transaction sent successfully!");
+                    println!("   TXID: {}", txid);
+                }
+                (None, Some(pending)) => {
+                    print_psbt_signatures(&pending);
+                    println!("   Share this PSBT with your cosigners:");
+                    println!();
+                    println!("{}", pending.psbt);
+                }
+                (None, None) => {
+                    return Err("lumod neither sent nor returned the transaction".into())
+                }
+            }
+        }
+        Commands::CreatePsbt {
+            address,
+            amount,
+            fee_rate,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let (address, amount, _) = send_target(&address, amount)?;
+            let created: PsbtResult = client
+                .call(
+                    "createpsbt",
+                    json!({ "address": address, "amount": amount, "fee_rate": fee_rate }),
+                )
+                .await?;
+
+            let psbt = decode_psbt(created.psbt.as_bytes())?;
+            write_psbt_file(&out, &psbt, format)?;
+
+            println!("✅ PSBT written: {}", out.display());
+            println!("   Inputs: {}", psbt.inputs.len());
+            println!("   Outputs: {}", psbt.outputs.len());
+            if let Some(fee) = created.fee {
+                println!("   Fee: {} sats", fee.as_sat());
+            }
+        }
+        Commands::SignPsbt { input, out, format } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let psbt = read_psbt_file(&input)?;
+
+            println!("✍️ Signing PSBT...");
+            let signed: PsbtResult = client
+                .call("signpsbt", json!({ "psbt": psbt.to_string() }))
+                .await?;
+
+            let out = out.unwrap_or(input);
+            write_psbt_file(&out, &decode_psbt(signed.psbt.as_bytes())?, format)?;
+
+            if signed.finalized {
+                println!("✅ PSBT signed and finalized: {}", out.display());
+            } else {
+                println!("✅ PSBT signed: {}", out.display());
+            }
+            print_psbt_signatures(&signed);
+        }
+        Commands::CombinePsbt {
+            inputs,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let psbts = inputs
+                .iter()
+                .map(|path| read_psbt_file(path).map(|psbt| psbt.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let combined: PsbtResult = client
+                .call("combinepsbt", json!({ "psbts": psbts }))
+                .await?;
+            write_psbt_file(&out, &decode_psbt(combined.psbt.as_bytes())?, format)?;
+
+            println!("✅ Combined {} PSBTs: {}", inputs.len(), out.display());
+            print_psbt_signatures(&combined);
+        }
+        Commands::FinalizePsbt { input, out, format } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let psbt = read_psbt_file(&input)?;
+
+            let finalized: PsbtResult = client
+                .call("finalizepsbt", json!({ "psbt": psbt.to_string() }))
+                .await?;
+
+            if finalized.finalized {
+                let out = out.unwrap_or(input);
+                write_psbt_file(&out, &decode_psbt(finalized.psbt.as_bytes())?, format)?;
+                println!("✅ PSBT finalized: {}", out.display());
+            } else {
+                println!("❌ PSBT could not be finalized, it is missing signatures");
+                print_psbt_signatures(&finalized);
+            }
+        }
+        Commands::BroadcastPsbt { input } => {
+            let psbt = read_psbt_file(&input)?;
+
+            println!("📡 Broadcasting to network...");
+            let sent: TxidResult = client
+                .call("broadcastpsbt", json!({ "psbt": psbt.to_string() }))
+                .await?;
+
+            println!("✅ Transaction sent successfully!");
+            println!("   TXID: {}", sent.txid);
+        }
+        Commands::ListPendingPsbts => {
+            let pending: Vec<PendingPsbt> = client.call("listpendingpsbts", json!({})).await?;
+            print_pending_psbts(&pending);
+        }
+        Commands::SetLabel {
+            label_type,
+            reference,
+            label,
+        } => {
+            let label_type = label_type.parse::<lumo::LabelType>()?;
+            let _: serde_json::Value = client
+                .call(
+                    "setlabel",
+                    json!({
+                        "label_type": label_type.as_str(),
+                        "reference": reference,
+                        "label": label,
+                    }),
+                )
+                .await?;
+
+            if label.trim().is_empty() {
+                println!("✅ Label removed from {} {}", label_type, reference);
+            } else {
+                println!("✅ Labeled {} {}: {}", label_type, reference, label.trim());
+            }
+        }
+        Commands::ProveReserves {
+            message,
+            coins,
+            out,
+            format,
+        } => {
+            let format = format.parse::<PsbtFormat>()?;
+            let proof: PsbtResult = client
+                .call(
+                    "provereserves",
+                    json!({ "message": message, "coins": coins }),
+                )
+                .await?;
+
+            let psbt = decode_psbt(proof.psbt.as_bytes())?;
+            write_psbt_file(&out, &psbt, format)?;
+            print_reserves_proof(&out, &psbt);
+        }
+        Commands::SignMessage { address, message } => {
+            let signed: SignatureResult = client
+                .call(
+                    "signmessage",
+                    json!({ "address": address, "message": message }),
+                )
+                .await?;
+            print_signature(&address, &signed.signature);
+        }
+        Commands::CreateInvoice {
+            amount,
+            memo,
+            expiry,
+            confirmations,
+            payjoin,
+        } => {
+            let invoice: Invoice = client
+                .call(
+                    "createinvoice",
+                    json!({
+                        "amount": amount,
+                        "memo": memo,
+                        "expiry": expiry,
+                        "confirmations": confirmations,
+                        "payjoin": payjoin,
+                    }),
+                )
+                .await?;
+            println!("✅ Invoice created");
+            print_invoice(&invoice, "sats");
+        }
+        Commands::ListInvoices { unit } => {
+            let invoices: Vec<Invoice> = client.call("listinvoices", json!({})).await?;
+            print_invoices(&invoices, &unit);
+        }
+        Commands::ShowInvoice { invoice, unit } => {
+            let invoice: Invoice = client
+                .call("getinvoice", json!({ "invoice": invoice }))
+                .await?;
+            print_invoice(&invoice, &unit);
+        }
+        _ => {
+            let endpoint = client.endpoint();
+            return Err(format!(
+                "lumod is running at {endpoint} and has the wallet database open, stop it to run this command"
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+fn print_psbt_signatures(psbt: &PsbtResult) {
+    if let Some(status) = &psbt.signatures {
+        println!(
+            "🖊️  Collected {}/{} signatures",
+            status.collected, status.required
+        );
+    }
+}
@@ -1,4 +1,5 @@
 pub mod client;
+use crate::database::Database;
 use crate::node_urls::*;
 use lumo_types::Network;
 
@@ -9,6 +10,21 @@ pub struct Node {
 }
 
 impl Node {
+    /// The node configured for a network, falling back to the default one
+    pub fn for_network(network: Network) -> Self {
+        let custom_url =
+            Database::global().and_then(|database| database.global_config.node_url(network));
+
+        match custom_url {
+            Ok(Some(url)) => Self {
+                name: "custom".to_string(),
+                network,
+                url,
+            },
+            _ => Self::default(network),
+        }
+    }
+
     pub fn default(network: Network) -> Self {
         match network {
             Network::Mainnet => {
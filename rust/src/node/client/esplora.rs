@@ -1,30 +1,431 @@
-use bdk_esplora::{esplora_client, EsploraAsyncExt};
-use bdk_wallet::chain::spk_client::{FullScanRequest, FullScanResponse};
-use bdk_wallet::KeychainKind;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use bdk_esplora::esplora_client;
+use bdk_wallet::chain::spk_client::FullScanRequest;
+use bdk_wallet::chain::Indexed;
+use bdk_wallet::chain::{BlockId, CheckPoint, ConfirmationBlockTime, TxUpdate};
+use bdk_wallet::{KeychainKind, Update};
+use bitcoin::{BlockHash, OutPoint, Script, ScriptBuf, TxOut};
+use once_cell::sync::Lazy;
+use tokio::task::JoinSet;
+
+use crate::node::client::throttle::{Throttle, PUBLIC_MAX_PARALLEL, PUBLIC_REQUESTS_PER_SECOND};
+use crate::node_urls::is_public_esplora;
+use crate::wallet::metadata::ScanSettings;
+use crate::wallet::progress::ProgressReporter;
+
+/// Esplora returns at most this many confirmed transactions per page
+const TXS_PER_PAGE: usize = 25;
+
+/// Times a request is sent again after the server answers 429, by the
+/// underlying client and then again by [`Throttle`]
+const MAX_RATE_LIMITED_RETRIES: usize = 6;
+
+/// Block timestamps can be up to two hours ahead of real time
+const MAX_BLOCK_TIME_DRIFT: u64 = 2 * 60 * 60;
+
+/// One throttle per server URL, shared by all clients of that server
+static THROTTLES: Lazy<Mutex<HashMap<String, Arc<Throttle>>>> = Lazy::new(Default::default);
 
 pub struct EsploraClient {
     client: esplora_client::AsyncClient,
+    throttle: Arc<Throttle>,
 }
 
 impl EsploraClient {
+    /// Client for `url`, paced if it's one of the public servers
+    ///
+    /// Every client for the same server shares one [`Throttle`], so wallets
+    /// syncing at the same time stay within its limits together.
     pub async fn new(url: &str) -> eyre::Result<Self> {
-        let client = esplora_client::Builder::new(url).build_async()?;
-        Ok(Self { client })
+        let client = esplora_client::Builder::new(url)
+            .max_retries(MAX_RATE_LIMITED_RETRIES)
+            .build_async()?;
+        Ok(Self {
+            client,
+            throttle: throttle_for(url),
+        })
     }
 
+    /// Full scan of every script until `stop_gap` unused ones in a row
+    ///
+    /// Scripts up to the `revealed` index of each keychain are always looked up,
+    /// even past the stop gap, so addresses handed out (e.g. for invoices) that
+    /// are paid out of order are still found.
     pub async fn full_scan(
         &self,
         request: FullScanRequest<KeychainKind>,
-        stop_gap: usize,
-    ) -> eyre::Result<FullScanResponse<KeychainKind>> {
-        Ok(self.client.full_scan(request, stop_gap, 1).await?)
+        settings: &ScanSettings,
+        revealed: &BTreeMap<KeychainKind, u32>,
+        progress: &ProgressReporter,
+    ) -> eyre::Result<Update> {
+        self.full_scan_from(request, settings, revealed, 0, progress)
+            .await
+    }
+
+    /// Full scan that stops paging through an address's history at `from_height`
+    ///
+    /// Esplora lists history newest first, so older pages are never requested.
+    /// Scripts are looked up in batches of `parallel_requests`, so used indices and
+    /// downloads are reported as each batch comes back. The chain update is built
+    /// on top of the wallet's own checkpoints, from the newest one still in the
+    /// server's best chain.
+    pub async fn full_scan_from(
+        &self,
+        mut request: FullScanRequest<KeychainKind>,
+        settings: &ScanSettings,
+        revealed: &BTreeMap<KeychainKind, u32>,
+        from_height: u32,
+        progress: &ProgressReporter,
+    ) -> eyre::Result<Update> {
+        let tip_height = self.throttled(|| self.client.get_height()).await?;
+        let tip_hash = self.throttled(|| self.client.get_tip_hash()).await?;
+        let local_tip = request.chain_tip();
+        let seen_at = request.start_time();
+
+        let stop_gap = settings.stop_gap as usize;
+        let parallel_requests = self
+            .throttle
+            .parallelism(settings.parallel_requests as usize);
+
+        let mut tx_update = TxUpdate::<ConfirmationBlockTime>::default();
+        let mut last_active_indices = BTreeMap::new();
+        let mut seen_txids = HashSet::new();
+        let mut blocks = BTreeMap::new();
+
+        for keychain in request.keychains() {
+            let mut spks = request.iter_spks(keychain);
+            let revealed_end = revealed.get(&keychain).map_or(0, |index| index + 1);
+            let mut unused_in_a_row = 0;
+            let mut next_index = 0;
+
+            while !scan_done(unused_in_a_row, stop_gap, next_index, revealed_end) {
+                let batch: Vec<_> = spks.by_ref().take(parallel_requests).collect();
+                if batch.is_empty() {
+                    break;
+                }
+
+                for (index, history) in self.script_histories(batch, from_height).await? {
+                    if scan_done(unused_in_a_row, stop_gap, index, revealed_end) {
+                        break;
+                    }
+                    next_index = index + 1;
+                    if history.is_empty() {
+                        unused_in_a_row += 1;
+                        continue;
+                    }
+
+                    unused_in_a_row = 0;
+                    last_active_indices.insert(keychain, index);
+                    progress.script_used(keychain, index);
+
+                    for tx in history {
+                        if !seen_txids.insert(tx.txid) {
+                            continue;
+                        }
+
+                        let full_tx = tx.to_tx();
+                        progress.fetched(full_tx.total_size() as u64);
+
+                        match (
+                            tx.status.block_height,
+                            tx.status.block_hash,
+                            tx.status.block_time,
+                        ) {
+                            (Some(height), Some(hash), Some(time)) => {
+                                let block_id = BlockId { height, hash };
+                                blocks.insert(height, hash);
+                                tx_update.anchors.insert((
+                                    ConfirmationBlockTime {
+                                        block_id,
+                                        confirmation_time: time,
+                                    },
+                                    tx.txid,
+                                ));
+                            }
+                            _ => {
+                                tx_update.seen_ats.insert((tx.txid, seen_at));
+                            }
+                        }
+
+                        for (vin, prevout) in tx.vin.iter().zip(tx.previous_outputs()) {
+                            if let Some(prevout) = prevout {
+                                tx_update
+                                    .txouts
+                                    .insert(bitcoin::OutPoint::new(vin.txid, vin.vout), prevout);
+                            }
+                        }
+                        tx_update.txs.push(Arc::new(full_tx));
+                    }
+                }
+            }
+        }
+
+        blocks.insert(tip_height, tip_hash);
+        let chain = match local_tip {
+            Some(local_tip) => Some(self.chain_update(local_tip, tip_height, blocks).await?),
+            None => CheckPoint::from_block_ids(
+                blocks
+                    .into_iter()
+                    .map(|(height, hash)| BlockId { height, hash }),
+            )
+            .ok(),
+        };
+
+        Ok(Update {
+            last_active_indices,
+            tx_update,
+            chain,
+        })
+    }
+
+    /// `blocks` on top of the newest local checkpoint the server agrees with
+    ///
+    /// Local checkpoints above it were reorged out, the server's blocks at their
+    /// heights are added so applying the update replaces them.
+    async fn chain_update(
+        &self,
+        local_tip: CheckPoint,
+        tip_height: u32,
+        mut blocks: BTreeMap<u32, BlockHash>,
+    ) -> eyre::Result<CheckPoint> {
+        let mut agreement = None;
+        for checkpoint in local_tip.iter() {
+            if checkpoint.height() > tip_height {
+                continue;
+            }
+            let hash = self
+                .throttled(|| self.client.get_block_hash(checkpoint.height()))
+                .await?;
+            if hash == checkpoint.hash() {
+                agreement = Some(checkpoint);
+                break;
+            }
+            blocks.insert(checkpoint.height(), hash);
+        }
+
+        let agreement = agreement
+            .ok_or_else(|| eyre::eyre!("Esplora server is on a different chain than the wallet"))?;
+        Ok(blocks
+            .into_iter()
+            .fold(agreement, |checkpoint, (height, hash)| {
+                checkpoint.insert(BlockId { height, hash })
+            }))
+    }
+
+    /// Height of the first block mined at or after `timestamp`, allowing for clock drift
+    pub async fn height_at_time(&self, timestamp: i64) -> eyre::Result<u32> {
+        let target = (timestamp.max(0) as u64).saturating_sub(MAX_BLOCK_TIME_DRIFT);
+        let (mut low, mut high) = (0, self.throttled(|| self.client.get_height()).await?);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let hash = self
+                .throttled(|| self.client.get_block_hash(middle))
+                .await?;
+            let header = self
+                .throttled(|| self.client.get_header_by_hash(&hash))
+                .await?;
+
+            if (header.time as u64) < target {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Timestamp of the block at `height`
+    pub async fn block_time(&self, height: u32) -> eyre::Result<i64> {
+        let hash = self
+            .throttled(|| self.client.get_block_hash(height))
+            .await?;
+        let header = self
+            .throttled(|| self.client.get_header_by_hash(&hash))
+            .await?;
+        Ok(header.time as i64)
     }
 
     pub async fn broadcast_transaction(
         &self,
         transaction: &bitcoin::Transaction,
     ) -> eyre::Result<bitcoin::Txid> {
-        self.client.broadcast(transaction).await?;
+        self.throttled(|| self.client.broadcast(transaction))
+            .await?;
         Ok(transaction.compute_txid())
     }
+
+    /// Whether `txid` is in a block, false while it's only in the mempool
+    pub async fn is_confirmed(&self, txid: bitcoin::Txid) -> eyre::Result<bool> {
+        let status = self.throttled(|| self.client.get_tx_status(&txid)).await?;
+        Ok(status.confirmed)
+    }
+
+    /// `outpoint`'s output if it's unspent, a spend still in the mempool counts
+    pub async fn unspent_output(&self, outpoint: OutPoint) -> eyre::Result<Option<TxOut>> {
+        let status = self
+            .throttled(|| {
+                self.client
+                    .get_output_status(&outpoint.txid, outpoint.vout as u64)
+            })
+            .await?;
+        match status {
+            Some(status) if !status.spent => {}
+            _ => return Ok(None),
+        }
+
+        let transaction = self
+            .throttled(|| self.client.get_tx(&outpoint.txid))
+            .await?;
+        Ok(transaction.and_then(|tx| tx.output.get(outpoint.vout as usize).cloned()))
+    }
+
+    /// History of each script from `from_height` on, looked up all at once
+    async fn script_histories(
+        &self,
+        scripts: Vec<Indexed<ScriptBuf>>,
+        from_height: u32,
+    ) -> eyre::Result<Vec<Indexed<Vec<esplora_client::Tx>>>> {
+        let mut lookups = JoinSet::new();
+        for (index, spk) in scripts {
+            let client = self.client.clone();
+            let throttle = self.throttle.clone();
+            lookups.spawn(async move {
+                let history = script_history(&client, &throttle, &spk, from_height).await;
+                (index, history)
+            });
+        }
+
+        let mut histories = Vec::new();
+        while let Some(lookup) = lookups.join_next().await {
+            let (index, history) = lookup?;
+            histories.push((index, history?));
+        }
+        histories.sort_by_key(|(index, _)| *index);
+        Ok(histories)
+    }
+
+    async fn throttled<T, F, Fut>(&self, request: F) -> Result<T, esplora_client::Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, esplora_client::Error>>,
+    {
+        throttled(&self.throttle, request).await
+    }
+}
+
+/// A keychain's scan is done after `stop_gap` unused scripts in a row, once every
+/// script below `revealed_end` has been looked up
+pub(crate) fn scan_done(
+    unused_in_a_row: usize,
+    stop_gap: usize,
+    next_index: u32,
+    revealed_end: u32,
+) -> bool {
+    unused_in_a_row >= stop_gap && next_index >= revealed_end
+}
+
+/// The throttle shared by every client of `url`
+fn throttle_for(url: &str) -> Arc<Throttle> {
+    let mut throttles = THROTTLES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    throttles
+        .entry(url.trim_end_matches('/').to_string())
+        .or_insert_with(|| {
+            let throttle = if is_public_esplora(url) {
+                Throttle::new(PUBLIC_REQUESTS_PER_SECOND, PUBLIC_MAX_PARALLEL)
+            } else {
+                Throttle::unlimited()
+            };
+            Arc::new(throttle)
+        })
+        .clone()
+}
+
+/// A script's transactions, newest first, down to `from_height`
+async fn script_history(
+    client: &esplora_client::AsyncClient,
+    throttle: &Throttle,
+    spk: &Script,
+    from_height: u32,
+) -> Result<Vec<esplora_client::Tx>, esplora_client::Error> {
+    let mut history = Vec::new();
+    let mut last_seen = None;
+
+    loop {
+        let page = throttled(throttle, || client.scripthash_txs(spk, last_seen)).await?;
+        let page_len = page.len();
+
+        for tx in page {
+            last_seen = Some(tx.txid);
+            if tx.status.block_height.is_some_and(|h| h < from_height) {
+                return Ok(history);
+            }
+            history.push(tx);
+        }
+
+        if page_len < TXS_PER_PAGE {
+            return Ok(history);
+        }
+    }
+}
+
+/// Send `request` in its turn, trying again while the server rate limits
+async fn throttled<T, F, Fut>(
+    throttle: &Throttle,
+    mut request: F,
+) -> Result<T, esplora_client::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, esplora_client::Error>>,
+{
+    let mut retries = 0;
+    loop {
+        throttle.wait().await;
+        match request().await {
+            Err(esplora_client::Error::HttpResponse { status: 429, .. })
+                if retries < MAX_RATE_LIMITED_RETRIES =>
+            {
+                retries += 1;
+                let pause = throttle.rate_limited();
+                tracing::warn!("Esplora server is rate limiting, pausing requests for {pause:?}");
+            }
+            result => {
+                if result.is_ok() {
+                    throttle.succeeded();
+                }
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients_of_a_server_share_a_throttle() {
+        let first = throttle_for("http://127.0.0.1:3002");
+        let second = throttle_for("http://127.0.0.1:3002/");
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &throttle_for("http://127.0.0.1:3003")));
+    }
+
+    #[test]
+    fn test_scan_covers_revealed_scripts() {
+        // Five unpaid invoices with a stop gap of three
+        assert!(!scan_done(3, 3, 3, 5));
+        assert!(!scan_done(4, 3, 4, 5));
+        assert!(scan_done(5, 3, 5, 5));
+
+        // Nothing revealed past the gap
+        assert!(scan_done(3, 3, 3, 0));
+        assert!(!scan_done(2, 3, 2, 0));
+    }
 }
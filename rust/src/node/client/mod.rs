@@ -1 +1,2 @@
 pub mod esplora;
+pub mod throttle;
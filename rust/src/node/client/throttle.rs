@@ -0,0 +1,131 @@
+//! Request pacing for Esplora servers
+//!
+//! Public servers ban clients that hit them too hard, so requests to them are
+//! spaced out, and any server answering 429 gets an increasing pause before
+//! the next request.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Requests per second sent to a public server
+pub const PUBLIC_REQUESTS_PER_SECOND: u32 = 10;
+
+/// Requests in flight at once on a public server, whatever the wallet asks for
+pub const PUBLIC_MAX_PARALLEL: usize = 4;
+
+/// Pause after the first 429, doubled for each one after it
+const MIN_PENALTY: Duration = Duration::from_millis(500);
+const MAX_PENALTY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct Throttle {
+    min_interval: Duration,
+    max_parallel: Option<usize>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    next_slot: Instant,
+    penalty: Duration,
+}
+
+impl Throttle {
+    /// At most `requests_per_second`, spread evenly
+    pub fn new(requests_per_second: u32, max_parallel: usize) -> Self {
+        Self {
+            min_interval: Duration::from_secs(1) / requests_per_second.max(1),
+            max_parallel: Some(max_parallel.max(1)),
+            state: Mutex::new(State {
+                next_slot: Instant::now(),
+                penalty: Duration::ZERO,
+            }),
+        }
+    }
+
+    /// Only slows down once the server rate limits
+    pub fn unlimited() -> Self {
+        Self {
+            min_interval: Duration::ZERO,
+            max_parallel: None,
+            ..Self::new(1, 1)
+        }
+    }
+
+    /// Parallel requests to actually use when `requested` are wanted
+    pub fn parallelism(&self, requested: usize) -> usize {
+        let requested = requested.max(1);
+        self.max_parallel
+            .map_or(requested, |max_parallel| requested.min(max_parallel))
+    }
+
+    /// Wait for the next request's turn
+    pub async fn wait(&self) {
+        let slot = {
+            let mut state = self.lock();
+            let slot = state.next_slot.max(Instant::now());
+            state.next_slot = slot + self.min_interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// The server answered 429, hold every request back for a while
+    ///
+    /// Returns how long requests are held back.
+    pub fn rate_limited(&self) -> Duration {
+        let mut state = self.lock();
+        state.penalty = (state.penalty * 2).clamp(MIN_PENALTY, MAX_PENALTY);
+        state.next_slot = state.next_slot.max(Instant::now() + state.penalty);
+        state.penalty
+    }
+
+    /// A request went through, ease off the penalty
+    pub fn succeeded(&self) {
+        let mut state = self.lock();
+        state.penalty /= 2;
+        if state.penalty < MIN_PENALTY {
+            state.penalty = Duration::ZERO;
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_requests_are_spaced_out() {
+        let throttle = Throttle::new(PUBLIC_REQUESTS_PER_SECOND, PUBLIC_MAX_PARALLEL);
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.wait().await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(400));
+        assert_eq!(throttle.parallelism(16), PUBLIC_MAX_PARALLEL);
+        assert_eq!(Throttle::unlimited().parallelism(16), 16);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit_penalty_grows_and_recovers() {
+        let throttle = Throttle::unlimited();
+        assert_eq!(throttle.rate_limited(), MIN_PENALTY);
+        assert_eq!(throttle.rate_limited(), MIN_PENALTY * 2);
+
+        let start = Instant::now();
+        throttle.wait().await;
+        assert!(start.elapsed() >= MIN_PENALTY * 2);
+
+        throttle.succeeded();
+        throttle.succeeded();
+        assert_eq!(throttle.rate_limited(), MIN_PENALTY);
+    }
+}
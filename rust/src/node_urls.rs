@@ -44,3 +44,14 @@ pub fn default_esplora_urls(network: Network) -> &'static str {
         Network::Signet => SIGNET_ESPLORA[0].1,
     }
 }
+
+/// Whether `url` is one of the public Esplora servers above, which rate limit
+pub fn is_public_esplora(url: &str) -> bool {
+    let url = url.trim_end_matches('/');
+    MAINNET_ESPLORA
+        .iter()
+        .chain(&TESTNET_ESPLORA)
+        .chain(&TESTNET4_ESPLORA)
+        .chain(&SIGNET_ESPLORA)
+        .any(|(_, public)| public.trim_end_matches('/') == url)
+}
@@ -0,0 +1,224 @@
+//! Keeps loaded wallets synced in the background
+//!
+//! Each wallet is synced on its own task and clock: every `interval` normally,
+//! every `pending_interval` while it has unconfirmed transactions, and with an
+//! exponential backoff after failures so an unreachable node isn't hammered. A
+//! slow wallet never holds up the others.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::task::{AbortHandle, JoinSet};
+use tokio::time::Instant;
+
+use crate::cancel::CancelToken;
+use crate::wallet::error::WalletError;
+use crate::wallet::WalletId;
+use crate::wallet_manager::WalletManager;
+
+/// Longest the scheduler sleeps before looking for newly loaded wallets
+const WALLET_POLL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncSchedule {
+    /// Between syncs of a wallet with nothing pending
+    pub interval: Duration,
+    /// Between syncs while a wallet has unconfirmed transactions
+    pub pending_interval: Duration,
+    /// Wait after the first failure, doubled for each failure in a row
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            pending_interval: Duration::from_secs(15),
+            min_backoff: Duration::from_secs(5),
+            max_backoff: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl SyncSchedule {
+    /// The default schedule with a different interval, never slower while pending
+    pub fn with_interval(interval: Duration) -> Self {
+        let default = Self::default();
+        Self {
+            interval,
+            pending_interval: default.pending_interval.min(interval),
+            ..default
+        }
+    }
+
+    /// Wait before the next attempt after `failures` failed syncs in a row
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31);
+        self.min_backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff)
+    }
+}
+
+/// Syncs every wallet in a [`WalletManager`] on a [`SyncSchedule`]
+///
+/// Syncs go through the manager, so they publish the usual wallet events and
+/// share its per-wallet locks with every other caller.
+pub struct SyncScheduler {
+    manager: WalletManager,
+    schedule: SyncSchedule,
+    tasks: HashMap<WalletId, AbortHandle>,
+}
+
+impl SyncScheduler {
+    pub fn new(manager: WalletManager, schedule: SyncSchedule) -> Self {
+        Self {
+            manager,
+            schedule,
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Run on a background task until `cancel` fires
+    pub fn spawn(self, cancel: CancelToken) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.run(cancel))
+    }
+
+    /// Sync wallets as they come due until `cancel` fires
+    ///
+    /// Wallets loaded after the scheduler starts are picked up within a few
+    /// seconds and synced right away.
+    pub async fn run(mut self, cancel: CancelToken) {
+        tracing::info!(
+            "Background sync every {:?}, every {:?} while transactions are unconfirmed",
+            self.schedule.interval,
+            self.schedule.pending_interval
+        );
+
+        let mut syncs = JoinSet::new();
+        while !cancel.is_cancelled() {
+            self.track_loaded_wallets(&mut syncs, &cancel);
+            while syncs.try_join_next().is_some() {}
+
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(WALLET_POLL) => {}
+            }
+        }
+
+        syncs.shutdown().await;
+        tracing::debug!("Background sync stopped");
+    }
+
+    /// Start a sync task for each new wallet, stop the tasks of unloaded ones
+    fn track_loaded_wallets(&mut self, syncs: &mut JoinSet<()>, cancel: &CancelToken) {
+        let loaded = self.manager.list_wallet_ids();
+        self.tasks.retain(|wallet_id, task| {
+            let keep = loaded.contains(wallet_id) && !task.is_finished();
+            if !keep {
+                task.abort();
+            }
+            keep
+        });
+
+        for wallet_id in loaded {
+            if self.tasks.contains_key(&wallet_id) {
+                continue;
+            }
+            let task = syncs.spawn(sync_wallet(
+                self.manager.clone(),
+                self.schedule,
+                wallet_id.clone(),
+                cancel.clone(),
+            ));
+            self.tasks.insert(wallet_id, task);
+        }
+    }
+}
+
+/// Sync one wallet on its own clock until it's unloaded or `cancel` fires
+async fn sync_wallet(
+    manager: WalletManager,
+    schedule: SyncSchedule,
+    wallet_id: WalletId,
+    cancel: CancelToken,
+) {
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        let delay = match manager.sync(&wallet_id, &cancel).await {
+            Ok(()) => {
+                if failures > 0 {
+                    tracing::info!(
+                        "Wallet {wallet_id} synced again after {failures} failed attempts"
+                    );
+                }
+                failures = 0;
+
+                let pending = manager.has_unconfirmed(&wallet_id).await.unwrap_or(false);
+                let delay = if pending {
+                    schedule.pending_interval
+                } else {
+                    schedule.interval
+                };
+                tracing::debug!(
+                    "Synced wallet {wallet_id} in {:?}, next sync in {delay:?}",
+                    started.elapsed()
+                );
+                delay
+            }
+            Err(WalletError::Cancelled) | Err(WalletError::WalletNotFound(_)) => return,
+            Err(e) => {
+                failures += 1;
+                let delay = schedule.backoff(failures);
+                tracing::warn!(
+                    "Sync of wallet {wallet_id} failed ({failures} in a row), retrying in {delay:?}: {e}"
+                );
+                delay
+            }
+        };
+
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let schedule = SyncSchedule::default();
+        assert_eq!(schedule.backoff(1), Duration::from_secs(5));
+        assert_eq!(schedule.backoff(2), Duration::from_secs(10));
+        assert_eq!(schedule.backoff(4), Duration::from_secs(40));
+        assert_eq!(schedule.backoff(20), schedule.max_backoff);
+        assert_eq!(schedule.backoff(u32::MAX), schedule.max_backoff);
+    }
+
+    #[test]
+    fn test_with_interval_keeps_pending_faster() {
+        let schedule = SyncSchedule::with_interval(Duration::from_secs(5));
+        assert_eq!(schedule.pending_interval, Duration::from_secs(5));
+
+        let schedule = SyncSchedule::with_interval(Duration::from_secs(600));
+        assert_eq!(schedule.pending_interval, Duration::from_secs(15));
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_when_cancelled() {
+        let cancel = CancelToken::new();
+        let scheduler = SyncScheduler::new(WalletManager::new(), SyncSchedule::default());
+        let handle = scheduler.spawn(cancel.clone());
+
+        cancel.cancel();
+        tokio::time::timeout(Duration::from_secs(5), handle)
+            .await
+            .expect("scheduler kept running after cancel")
+            .unwrap();
+    }
+}
@@ -0,0 +1,284 @@
+//! Uniform Resources (BC-UR) for air-gapped signers
+//!
+//! Payloads are CBOR encoded, split into fountain coded parts by the `ur` crate and
+//! shown as an animated QR code. Any large enough subset of the parts, in any order,
+//! is enough to rebuild the payload, so the scanner can join in mid-animation.
+
+pub mod cbor;
+pub mod registry;
+
+use bdk_wallet::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use bitcoin::bip32::Fingerprint;
+use bitcoin::psbt::Psbt;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+
+use crate::ur::cbor::Value;
+
+/// Fragment length that keeps each QR frame small enough for phone cameras
+pub const DEFAULT_FRAGMENT_LEN: usize = 200;
+
+const UR_TYPE_PSBT: &str = "crypto-psbt";
+const UR_TYPE_OUTPUT: &str = "crypto-output";
+const UR_TYPE_ACCOUNT: &str = "crypto-account";
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum UrError {
+    #[error("CBOR error: {0}")]
+    Cbor(String),
+
+    #[error("UR error: {0}")]
+    Ur(String),
+
+    #[error("Unsupported UR type: {0}")]
+    UnsupportedType(String),
+
+    #[error("Unsupported descriptor: {0}")]
+    UnsupportedDescriptor(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("QR code error: {0}")]
+    QrCode(String),
+}
+
+/// Data that can be exchanged with an air-gapped signer
+#[derive(Debug, Clone, PartialEq)]
+pub enum UrPayload {
+    Psbt(Psbt),
+    Output(Descriptor<DescriptorPublicKey>),
+    Account {
+        master_fingerprint: Fingerprint,
+        outputs: Vec<Descriptor<DescriptorPublicKey>>,
+    },
+}
+
+impl UrPayload {
+    pub fn ur_type(&self) -> &'static str {
+        match self {
+            UrPayload::Psbt(_) => UR_TYPE_PSBT,
+            UrPayload::Output(_) => UR_TYPE_OUTPUT,
+            UrPayload::Account { .. } => UR_TYPE_ACCOUNT,
+        }
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, UrError> {
+        let value = match self {
+            UrPayload::Psbt(psbt) => Value::Bytes(psbt.serialize()),
+            UrPayload::Output(descriptor) => registry::output_to_cbor(descriptor)?,
+            UrPayload::Account {
+                master_fingerprint,
+                outputs,
+            } => registry::account_to_cbor(*master_fingerprint, outputs)?,
+        };
+
+        Ok(value.encode())
+    }
+
+    pub fn from_cbor(ur_type: &str, data: &[u8]) -> Result<Self, UrError> {
+        let value = Value::decode(data)?;
+
+        match ur_type {
+            // `psbt` is the newer name for the same registry type
+            UR_TYPE_PSBT | "psbt" => {
+                let psbt = Psbt::deserialize(value.as_bytes()?)
+                    .map_err(|e| UrError::Ur(format!("Invalid PSBT: {e}")))?;
+                Ok(UrPayload::Psbt(psbt))
+            }
+            UR_TYPE_OUTPUT => Ok(UrPayload::Output(registry::output_from_cbor(&value)?)),
+            UR_TYPE_ACCOUNT => {
+                let (master_fingerprint, outputs) = registry::account_from_cbor(&value)?;
+                Ok(UrPayload::Account {
+                    master_fingerprint,
+                    outputs,
+                })
+            }
+            _ => Err(UrError::UnsupportedType(ur_type.to_string())),
+        }
+    }
+}
+
+/// Produces the parts of a payload, looping forever once every fragment was sent
+pub struct UrEncoder {
+    encoder: ::ur::ur::Encoder<'static>,
+}
+
+impl UrEncoder {
+    pub fn new(payload: &UrPayload, max_fragment_len: usize) -> Result<Self, UrError> {
+        let encoder =
+            ::ur::ur::Encoder::new(&payload.to_cbor()?, max_fragment_len, payload.ur_type())
+                .map_err(|e| UrError::Ur(e.to_string()))?;
+        Ok(Self { encoder })
+    }
+
+    /// Number of parts needed to send the payload once
+    pub fn fragment_count(&self) -> usize {
+        self.encoder.fragment_count()
+    }
+
+    pub fn next_part(&mut self) -> Result<String, UrError> {
+        self.encoder
+            .next_part()
+            .map_err(|e| UrError::Ur(e.to_string()))
+    }
+}
+
+/// Collects scanned parts until the payload can be rebuilt
+#[derive(Default)]
+pub struct UrDecoder {
+    ur_type: Option<String>,
+    decoder: ::ur::ur::Decoder,
+    // Set straight away for single part URs
+    message: Option<Vec<u8>>,
+}
+
+impl UrDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a scanned part, parts can arrive in any order and repeat
+    pub fn receive(&mut self, part: &str) -> Result<(), UrError> {
+        let part = part.trim().to_lowercase();
+        let ur_type = parse_ur_type(&part)?;
+
+        match &self.ur_type {
+            Some(expected) if expected != ur_type => {
+                return Err(UrError::Ur(format!(
+                    "Part of type {ur_type} doesn't belong to a {expected}"
+                )))
+            }
+            Some(_) => {}
+            None => self.ur_type = Some(ur_type.to_string()),
+        }
+
+        if self.is_complete() {
+            return Ok(());
+        }
+
+        let (kind, data) = ::ur::ur::decode(&part).map_err(|e| UrError::Ur(e.to_string()))?;
+        match kind {
+            ::ur::ur::Kind::SinglePart => self.message = Some(data),
+            ::ur::ur::Kind::MultiPart => self
+                .decoder
+                .receive(&part)
+                .map_err(|e| UrError::Ur(e.to_string()))?,
+        }
+
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.message.is_some() || self.decoder.complete()
+    }
+
+    /// The decoded payload, once enough parts were received
+    pub fn payload(&self) -> Result<Option<UrPayload>, UrError> {
+        let message = match &self.message {
+            Some(message) => Some(message.clone()),
+            None if self.decoder.complete() => self
+                .decoder
+                .message()
+                .map_err(|e| UrError::Ur(e.to_string()))?,
+            None => None,
+        };
+
+        match (message, &self.ur_type) {
+            (Some(message), Some(ur_type)) => Ok(Some(UrPayload::from_cbor(ur_type, &message)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The type of a `ur:<type>/...` string
+fn parse_ur_type(part: &str) -> Result<&str, UrError> {
+    part.strip_prefix("ur:")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|ur_type| !ur_type.is_empty())
+        .ok_or_else(|| UrError::Ur(format!("Not a UR: {part}")))
+}
+
+/// Render a part as a QR code made of unicode half blocks for the terminal
+pub fn render_qr(part: &str) -> Result<String, UrError> {
+    // Uppercase URs fit the denser alphanumeric QR mode
+    let code = QrCode::with_error_correction_level(part.to_uppercase(), EcLevel::L)
+        .map_err(|e| UrError::QrCode(e.to_string()))?;
+
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{absolute, transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut};
+
+    fn test_psbt(outputs: usize) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: (0..outputs)
+                .map(|i| TxOut {
+                    value: Amount::from_sat(1000 + i as u64),
+                    script_pubkey: ScriptBuf::from_bytes(vec![0x51; 34]),
+                })
+                .collect(),
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn test_psbt_multi_part_roundtrip() {
+        let payload = UrPayload::Psbt(test_psbt(20));
+        let mut encoder = UrEncoder::new(&payload, 100).unwrap();
+        assert!(encoder.fragment_count() > 1);
+
+        // Skip the first parts as if the scanner joined late; fountain parts make up for it
+        let mut decoder = UrDecoder::new();
+        for part in (0..encoder.fragment_count() * 4).map(|_| encoder.next_part().unwrap()) {
+            if part.contains("/1-") || part.contains("/2-") {
+                continue;
+            }
+            decoder.receive(&part.to_uppercase()).unwrap();
+            if decoder.is_complete() {
+                break;
+            }
+        }
+
+        assert!(decoder.is_complete());
+        assert_eq!(decoder.payload().unwrap(), Some(payload));
+    }
+
+    #[test]
+    fn test_decoder_rejects_mixed_types() {
+        let mut psbt_encoder = UrEncoder::new(&UrPayload::Psbt(test_psbt(20)), 100).unwrap();
+        let mut decoder = UrDecoder::new();
+        decoder.receive(&psbt_encoder.next_part().unwrap()).unwrap();
+        assert!(!decoder.is_complete());
+        assert_eq!(decoder.payload().unwrap(), None);
+
+        let other = psbt_encoder
+            .next_part()
+            .unwrap()
+            .replace("crypto-psbt", "crypto-output");
+        assert!(decoder.receive(&other).is_err());
+        assert!(decoder.receive("not a ur").is_err());
+    }
+
+    #[test]
+    fn test_render_qr() {
+        let mut encoder =
+            UrEncoder::new(&UrPayload::Psbt(test_psbt(1)), DEFAULT_FRAGMENT_LEN).unwrap();
+        let qr = render_qr(&encoder.next_part().unwrap()).unwrap();
+        assert!(qr.lines().count() > 10);
+    }
+}
@@ -0,0 +1,295 @@
+//! Minimal CBOR (RFC 8949) encoder and decoder, enough for the BC-UR registry types
+
+use crate::ur::UrError;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+const SIMPLE_FALSE: u8 = 20;
+const SIMPLE_TRUE: u8 = 21;
+const SIMPLE_NULL: u8 = 22;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Unsigned(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    /// Map entries in the order they are encoded
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    pub fn tag(tag: u64, value: Value) -> Self {
+        Self::Tag(tag, Box::new(value))
+    }
+
+    /// Map with unsigned integer keys, the shape used by the UR registry
+    pub fn int_map(entries: Vec<(u64, Value)>) -> Self {
+        Self::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::Unsigned(key), value))
+                .collect(),
+        )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Unsigned(n) => write_header(out, MAJOR_UNSIGNED, *n),
+            Value::Bytes(bytes) => {
+                write_header(out, MAJOR_BYTES, bytes.len() as u64);
+                out.extend_from_slice(bytes);
+            }
+            Value::Text(text) => {
+                write_header(out, MAJOR_TEXT, text.len() as u64);
+                out.extend_from_slice(text.as_bytes());
+            }
+            Value::Array(items) => {
+                write_header(out, MAJOR_ARRAY, items.len() as u64);
+                items.iter().for_each(|item| item.encode_into(out));
+            }
+            Value::Map(entries) => {
+                write_header(out, MAJOR_MAP, entries.len() as u64);
+                for (key, value) in entries {
+                    key.encode_into(out);
+                    value.encode_into(out);
+                }
+            }
+            Value::Tag(tag, value) => {
+                write_header(out, MAJOR_TAG, *tag);
+                value.encode_into(out);
+            }
+            Value::Bool(false) => out.push(MAJOR_SIMPLE << 5 | SIMPLE_FALSE),
+            Value::Bool(true) => out.push(MAJOR_SIMPLE << 5 | SIMPLE_TRUE),
+            Value::Null => out.push(MAJOR_SIMPLE << 5 | SIMPLE_NULL),
+        }
+    }
+
+    /// Decode a single CBOR item, rejecting trailing bytes
+    pub fn decode(bytes: &[u8]) -> Result<Self, UrError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let value = reader.read_value(0)?;
+        if reader.pos != bytes.len() {
+            return Err(UrError::Cbor("Trailing bytes after CBOR item".to_string()));
+        }
+        Ok(value)
+    }
+
+    /// Look up an integer key in a map
+    pub fn get(&self, key: u64) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| *k == Value::Unsigned(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Strip a tag, if present, checking it's the expected one
+    pub fn untag(&self, expected: u64) -> Result<&Value, UrError> {
+        match self {
+            Value::Tag(tag, value) if *tag == expected => Ok(value),
+            Value::Tag(tag, _) => Err(UrError::Cbor(format!(
+                "Expected tag {expected}, found tag {tag}"
+            ))),
+            value => Ok(value),
+        }
+    }
+
+    pub fn as_unsigned(&self) -> Result<u64, UrError> {
+        match self {
+            Value::Unsigned(n) => Ok(*n),
+            _ => Err(UrError::Cbor("Expected an unsigned integer".to_string())),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8], UrError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(UrError::Cbor("Expected a byte string".to_string())),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Value], UrError> {
+        match self {
+            Value::Array(items) => Ok(items),
+            _ => Err(UrError::Cbor("Expected an array".to_string())),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, UrError> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(UrError::Cbor("Expected a boolean".to_string())),
+        }
+    }
+}
+
+fn write_header(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+/// Nesting deeper than this is rejected, registry types never get close
+const MAX_DEPTH: usize = 32;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], UrError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| UrError::Cbor("Unexpected end of CBOR data".to_string()))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_header(&mut self) -> Result<(u8, u8, u64), UrError> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let additional = initial & 0x1f;
+
+        let value = match additional {
+            0..=23 => additional as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().expect("2 bytes")) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().expect("4 bytes")) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().expect("8 bytes")),
+            _ => {
+                return Err(UrError::Cbor(
+                    "Indefinite length CBOR items are not supported".to_string(),
+                ))
+            }
+        };
+
+        Ok((major, additional, value))
+    }
+
+    fn read_value(&mut self, depth: usize) -> Result<Value, UrError> {
+        if depth > MAX_DEPTH {
+            return Err(UrError::Cbor("CBOR nesting is too deep".to_string()));
+        }
+
+        let (major, additional, value) = self.read_header()?;
+        let len = usize::try_from(value)
+            .map_err(|_| UrError::Cbor("CBOR length is too large".to_string()))?;
+
+        match major {
+            MAJOR_UNSIGNED => Ok(Value::Unsigned(value)),
+            MAJOR_BYTES => Ok(Value::Bytes(self.take(len)?.to_vec())),
+            MAJOR_TEXT => {
+                let text = std::str::from_utf8(self.take(len)?)
+                    .map_err(|_| UrError::Cbor("Invalid UTF-8 in CBOR text".to_string()))?;
+                Ok(Value::Text(text.to_string()))
+            }
+            MAJOR_ARRAY => {
+                let items = (0..len)
+                    .map(|_| self.read_value(depth + 1))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Array(items))
+            }
+            MAJOR_MAP => {
+                let entries = (0..len)
+                    .map(|_| Ok((self.read_value(depth + 1)?, self.read_value(depth + 1)?)))
+                    .collect::<Result<Vec<_>, UrError>>()?;
+                Ok(Value::Map(entries))
+            }
+            MAJOR_TAG => Ok(Value::tag(value, self.read_value(depth + 1)?)),
+            MAJOR_SIMPLE => match additional {
+                SIMPLE_FALSE => Ok(Value::Bool(false)),
+                SIMPLE_TRUE => Ok(Value::Bool(true)),
+                SIMPLE_NULL => Ok(Value::Null),
+                _ => Err(UrError::Cbor(format!(
+                    "Unsupported CBOR simple value {additional}"
+                ))),
+            },
+            _ => Err(UrError::Cbor(format!(
+                "Unsupported CBOR major type {major}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cbor_roundtrip() {
+        let value = Value::tag(
+            303,
+            Value::int_map(vec![
+                (3, Value::Bytes(vec![2; 33])),
+                (
+                    6,
+                    Value::tag(
+                        304,
+                        Value::int_map(vec![
+                            (
+                                1,
+                                Value::Array(vec![Value::Unsigned(84), Value::Bool(true)]),
+                            ),
+                            (2, Value::Unsigned(0x7371_2ef3)),
+                        ]),
+                    ),
+                ),
+            ]),
+        );
+
+        let encoded = value.encode();
+        assert_eq!(Value::decode(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_cbor_known_encodings() {
+        // Examples from RFC 8949 appendix A
+        assert_eq!(Value::Unsigned(23).encode(), vec![0x17]);
+        assert_eq!(Value::Unsigned(24).encode(), vec![0x18, 0x18]);
+        assert_eq!(Value::Unsigned(1000).encode(), vec![0x19, 0x03, 0xe8]);
+        assert_eq!(Value::Bool(true).encode(), vec![0xf5]);
+        assert_eq!(
+            Value::Bytes(vec![1, 2, 3, 4]).encode(),
+            vec![0x44, 1, 2, 3, 4]
+        );
+
+        assert!(Value::decode(&[0x44, 1, 2]).is_err());
+        assert!(Value::decode(&[0x17, 0x17]).is_err());
+    }
+}
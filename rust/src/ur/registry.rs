@@ -0,0 +1,513 @@
+//! BC-UR registry types (BCR-2020-006, BCR-2020-007, BCR-2020-010, BCR-2020-015)
+//!
+//! Output descriptors are mapped to and from the tagged CBOR structures used by
+//! `crypto-output` and `crypto-account`. Only extended public keys are supported,
+//! which covers every descriptor lumo creates.
+
+use std::str::FromStr;
+
+use bdk_wallet::miniscript::descriptor::{
+    Descriptor, DescriptorPublicKey, ShInner, Wildcard, Wsh, WshInner,
+};
+use bitcoin::bip32::{ChainCode, ChildNumber, Fingerprint, Xpub};
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::NetworkKind;
+
+use crate::ur::cbor::Value;
+use crate::ur::UrError;
+
+const TAG_HDKEY: u64 = 303;
+const TAG_KEYPATH: u64 = 304;
+const TAG_COIN_INFO: u64 = 305;
+const TAG_OUTPUT: u64 = 308;
+const TAG_ACCOUNT: u64 = 311;
+
+const TAG_SH: u64 = 400;
+const TAG_WSH: u64 = 401;
+const TAG_PKH: u64 = 403;
+const TAG_WPKH: u64 = 404;
+const TAG_MULTI: u64 = 406;
+const TAG_SORTED_MULTI: u64 = 407;
+const TAG_TAPROOT: u64 = 409;
+
+/// Encode a descriptor as an untagged `crypto-output`
+pub fn output_to_cbor(descriptor: &Descriptor<DescriptorPublicKey>) -> Result<Value, UrError> {
+    let unsupported = || UrError::UnsupportedDescriptor(descriptor.to_string());
+
+    let value = match descriptor {
+        Descriptor::Pkh(pkh) => Value::tag(TAG_PKH, hdkey_to_cbor(pkh.as_inner())?),
+        Descriptor::Wpkh(wpkh) => Value::tag(TAG_WPKH, hdkey_to_cbor(wpkh.as_inner())?),
+        Descriptor::Wsh(wsh) => wsh_to_cbor(wsh)?,
+        Descriptor::Sh(sh) => {
+            let inner = match sh.as_inner() {
+                ShInner::Wpkh(wpkh) => Value::tag(TAG_WPKH, hdkey_to_cbor(wpkh.as_inner())?),
+                ShInner::Wsh(wsh) => wsh_to_cbor(wsh)?,
+                ShInner::SortedMulti(multi) => {
+                    multikey_to_cbor(TAG_SORTED_MULTI, multi.k(), multi.pks())?
+                }
+                ShInner::Ms(_) => return Err(unsupported()),
+            };
+            Value::tag(TAG_SH, inner)
+        }
+        Descriptor::Tr(tr) if tr.tap_tree().is_none() => {
+            Value::tag(TAG_TAPROOT, hdkey_to_cbor(tr.internal_key())?)
+        }
+        _ => return Err(unsupported()),
+    };
+
+    Ok(value)
+}
+
+/// Decode a `crypto-output`, tagged or not, into a descriptor
+pub fn output_from_cbor(value: &Value) -> Result<Descriptor<DescriptorPublicKey>, UrError> {
+    // Untagged outputs start right at their script expression's tag
+    let script = match value {
+        Value::Tag(TAG_OUTPUT, script) => script,
+        script => script,
+    };
+    let expression = script_expression(script)?;
+    Descriptor::from_str(&expression)
+        .map_err(|e| UrError::UnsupportedDescriptor(format!("{expression}: {e}")))
+}
+
+/// Encode a `crypto-account`: the master fingerprint and the account's descriptors
+pub fn account_to_cbor(
+    master_fingerprint: Fingerprint,
+    outputs: &[Descriptor<DescriptorPublicKey>],
+) -> Result<Value, UrError> {
+    let outputs = outputs
+        .iter()
+        .map(|output| Ok(Value::tag(TAG_OUTPUT, output_to_cbor(output)?)))
+        .collect::<Result<Vec<_>, UrError>>()?;
+
+    Ok(Value::int_map(vec![
+        (
+            1,
+            Value::Unsigned(fingerprint_to_u32(master_fingerprint) as u64),
+        ),
+        (2, Value::Array(outputs)),
+    ]))
+}
+
+pub fn account_from_cbor(
+    value: &Value,
+) -> Result<(Fingerprint, Vec<Descriptor<DescriptorPublicKey>>), UrError> {
+    let account = value.untag(TAG_ACCOUNT)?;
+
+    let master_fingerprint = account
+        .get(1)
+        .ok_or_else(|| UrError::Cbor("Account is missing the master fingerprint".to_string()))?
+        .as_unsigned()?;
+
+    let outputs = account
+        .get(2)
+        .ok_or_else(|| UrError::Cbor("Account is missing its outputs".to_string()))?
+        .as_array()?
+        .iter()
+        .map(output_from_cbor)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((fingerprint_from_u64(master_fingerprint)?, outputs))
+}
+
+fn wsh_to_cbor(wsh: &Wsh<DescriptorPublicKey>) -> Result<Value, UrError> {
+    match wsh.as_inner() {
+        WshInner::SortedMulti(multi) => Ok(Value::tag(
+            TAG_WSH,
+            multikey_to_cbor(TAG_SORTED_MULTI, multi.k(), multi.pks())?,
+        )),
+        WshInner::Ms(_) => Err(UrError::UnsupportedDescriptor(wsh.to_string())),
+    }
+}
+
+fn multikey_to_cbor(
+    tag: u64,
+    threshold: usize,
+    keys: &[DescriptorPublicKey],
+) -> Result<Value, UrError> {
+    let keys = keys
+        .iter()
+        .map(hdkey_to_cbor)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Value::tag(
+        tag,
+        Value::int_map(vec![
+            (1, Value::Unsigned(threshold as u64)),
+            (2, Value::Array(keys)),
+        ]),
+    ))
+}
+
+/// Rebuild the descriptor string for a tagged script expression
+fn script_expression(value: &Value) -> Result<String, UrError> {
+    let Value::Tag(tag, inner) = value else {
+        return Err(UrError::Cbor(
+            "Expected a tagged script expression".to_string(),
+        ));
+    };
+
+    let expression = match *tag {
+        TAG_SH => format!("sh({})", script_expression(inner)?),
+        TAG_WSH => format!("wsh({})", script_expression(inner)?),
+        TAG_PKH => format!("pkh({})", hdkey_from_cbor(inner)?),
+        TAG_WPKH => format!("wpkh({})", hdkey_from_cbor(inner)?),
+        TAG_TAPROOT => format!("tr({})", hdkey_from_cbor(inner)?),
+        TAG_MULTI => format!("multi({})", multikey_from_cbor(inner)?),
+        TAG_SORTED_MULTI => format!("sortedmulti({})", multikey_from_cbor(inner)?),
+        tag => {
+            return Err(UrError::UnsupportedDescriptor(format!(
+                "Unsupported script expression tag {tag}"
+            )))
+        }
+    };
+
+    Ok(expression)
+}
+
+fn multikey_from_cbor(value: &Value) -> Result<String, UrError> {
+    let threshold = value
+        .get(1)
+        .ok_or_else(|| UrError::Cbor("Multisig is missing the threshold".to_string()))?
+        .as_unsigned()?;
+
+    let keys = value
+        .get(2)
+        .ok_or_else(|| UrError::Cbor("Multisig is missing its keys".to_string()))?
+        .as_array()?
+        .iter()
+        .map(hdkey_from_cbor)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(format!("{threshold},{}", keys.join(",")))
+}
+
+/// Encode an extended public key as a `crypto-hdkey`
+fn hdkey_to_cbor(key: &DescriptorPublicKey) -> Result<Value, UrError> {
+    let DescriptorPublicKey::XPub(xkey) = key else {
+        return Err(UrError::InvalidKey(format!(
+            "{key}: only extended public keys can be encoded"
+        )));
+    };
+
+    let xpub = &xkey.xkey;
+    let network = match xpub.network {
+        NetworkKind::Main => 0,
+        NetworkKind::Test => 1,
+    };
+
+    let mut entries = vec![
+        (3, Value::Bytes(xpub.public_key.serialize().to_vec())),
+        (4, Value::Bytes(xpub.chain_code.to_bytes().to_vec())),
+        (
+            5,
+            Value::tag(
+                TAG_COIN_INFO,
+                Value::int_map(vec![(1, Value::Unsigned(0)), (2, Value::Unsigned(network))]),
+            ),
+        ),
+    ];
+
+    if let Some((fingerprint, path)) = &xkey.origin {
+        let components = path.into_iter().copied().collect::<Vec<_>>();
+        entries.push((
+            6,
+            Value::tag(
+                TAG_KEYPATH,
+                Value::int_map(vec![
+                    (1, keypath_components(&components, Wildcard::None)),
+                    (2, Value::Unsigned(fingerprint_to_u32(*fingerprint) as u64)),
+                    (3, Value::Unsigned(xpub.depth as u64)),
+                ]),
+            ),
+        ));
+    }
+
+    let children = (&xkey.derivation_path)
+        .into_iter()
+        .copied()
+        .collect::<Vec<_>>();
+    if !children.is_empty() || xkey.wildcard != Wildcard::None {
+        entries.push((
+            7,
+            Value::tag(
+                TAG_KEYPATH,
+                Value::int_map(vec![(1, keypath_components(&children, xkey.wildcard))]),
+            ),
+        ));
+    }
+
+    entries.push((
+        8,
+        Value::Unsigned(fingerprint_to_u32(xpub.parent_fingerprint) as u64),
+    ));
+
+    Ok(Value::tag(TAG_HDKEY, Value::int_map(entries)))
+}
+
+/// Decode a `crypto-hdkey` into a descriptor key expression
+fn hdkey_from_cbor(value: &Value) -> Result<String, UrError> {
+    let hdkey = value.untag(TAG_HDKEY)?;
+
+    if let Some(is_private) = hdkey.get(2) {
+        if is_private.as_bool()? {
+            return Err(UrError::InvalidKey(
+                "Private keys are not accepted over UR".to_string(),
+            ));
+        }
+    }
+
+    let key_data = hdkey
+        .get(3)
+        .ok_or_else(|| UrError::InvalidKey("Key is missing its key data".to_string()))?
+        .as_bytes()?;
+    let public_key =
+        PublicKey::from_slice(key_data).map_err(|e| UrError::InvalidKey(e.to_string()))?;
+
+    let chain_code: [u8; 32] = hdkey
+        .get(4)
+        .ok_or_else(|| UrError::InvalidKey("Master keys are not supported".to_string()))?
+        .as_bytes()?
+        .try_into()
+        .map_err(|_| UrError::InvalidKey("Chain code must be 32 bytes".to_string()))?;
+
+    let network = match hdkey.get(5) {
+        Some(coin_info) => match coin_info.untag(TAG_COIN_INFO)?.get(2) {
+            Some(network) if network.as_unsigned()? != 0 => NetworkKind::Test,
+            _ => NetworkKind::Main,
+        },
+        None => NetworkKind::Main,
+    };
+
+    let (origin, source_fingerprint, depth) = match hdkey.get(6) {
+        Some(keypath) => {
+            let keypath = keypath.untag(TAG_KEYPATH)?;
+            let (components, wildcard) = parse_keypath_components(keypath)?;
+            if wildcard != Wildcard::None {
+                return Err(UrError::InvalidKey(
+                    "Key origin can't contain a wildcard".to_string(),
+                ));
+            }
+            let fingerprint = keypath
+                .get(2)
+                .map(|fp| fp.as_unsigned().and_then(fingerprint_from_u64))
+                .transpose()?;
+            let depth = keypath.get(3).map(Value::as_unsigned).transpose()?;
+            (components, fingerprint, depth)
+        }
+        None => (Vec::new(), None, None),
+    };
+
+    let parent_fingerprint = hdkey
+        .get(8)
+        .map(|fp| fp.as_unsigned().and_then(fingerprint_from_u64))
+        .transpose()?
+        .unwrap_or_default();
+
+    let depth = depth.unwrap_or(origin.len() as u64);
+    let xpub = Xpub {
+        network,
+        depth: u8::try_from(depth)
+            .map_err(|_| UrError::InvalidKey("Key depth is too large".to_string()))?,
+        parent_fingerprint,
+        child_number: origin
+            .last()
+            .copied()
+            .unwrap_or(ChildNumber::Normal { index: 0 }),
+        public_key,
+        chain_code: ChainCode::from(chain_code),
+    };
+
+    let mut expression = String::new();
+    if let Some(fingerprint) = source_fingerprint {
+        expression.push_str(&format!("[{fingerprint}{}]", format_path(&origin)));
+    }
+    expression.push_str(&xpub.to_string());
+
+    if let Some(children) = hdkey.get(7) {
+        let (components, wildcard) = parse_keypath_components(children.untag(TAG_KEYPATH)?)?;
+        expression.push_str(&format_path(&components));
+        match wildcard {
+            Wildcard::None => {}
+            Wildcard::Unhardened => expression.push_str("/*"),
+            Wildcard::Hardened => expression.push_str("/*h"),
+        }
+    }
+
+    Ok(expression)
+}
+
+/// Keypath components are pairs of `index | []` (wildcard) and a hardened flag
+fn keypath_components(path: &[ChildNumber], wildcard: Wildcard) -> Value {
+    let mut components = Vec::with_capacity(path.len() * 2 + 2);
+
+    for child in path {
+        let (index, hardened) = match child {
+            ChildNumber::Normal { index } => (*index, false),
+            ChildNumber::Hardened { index } => (*index, true),
+        };
+        components.push(Value::Unsigned(index as u64));
+        components.push(Value::Bool(hardened));
+    }
+
+    match wildcard {
+        Wildcard::None => {}
+        Wildcard::Unhardened | Wildcard::Hardened => {
+            components.push(Value::Array(Vec::new()));
+            components.push(Value::Bool(wildcard == Wildcard::Hardened));
+        }
+    }
+
+    Value::Array(components)
+}
+
+fn parse_keypath_components(keypath: &Value) -> Result<(Vec<ChildNumber>, Wildcard), UrError> {
+    let components = match keypath.get(1) {
+        Some(components) => components.as_array()?,
+        None => return Ok((Vec::new(), Wildcard::None)),
+    };
+
+    if components.len() % 2 != 0 {
+        return Err(UrError::Cbor(
+            "Keypath components must be pairs".to_string(),
+        ));
+    }
+
+    let mut path = Vec::new();
+    let mut wildcard = Wildcard::None;
+
+    for pair in components.chunks(2) {
+        if wildcard != Wildcard::None {
+            return Err(UrError::InvalidKey(
+                "Wildcard must be the last keypath component".to_string(),
+            ));
+        }
+
+        let hardened = pair[1].as_bool()?;
+        match &pair[0] {
+            Value::Unsigned(index) => {
+                let index = u32::try_from(*index)
+                    .map_err(|_| UrError::InvalidKey("Keypath index is too large".to_string()))?;
+                let child = if hardened {
+                    ChildNumber::from_hardened_idx(index)
+                } else {
+                    ChildNumber::from_normal_idx(index)
+                }
+                .map_err(|e| UrError::InvalidKey(e.to_string()))?;
+                path.push(child);
+            }
+            Value::Array(range) if range.is_empty() => {
+                wildcard = if hardened {
+                    Wildcard::Hardened
+                } else {
+                    Wildcard::Unhardened
+                };
+            }
+            _ => {
+                return Err(UrError::InvalidKey(
+                    "Keypath ranges are not supported".to_string(),
+                ))
+            }
+        }
+    }
+
+    Ok((path, wildcard))
+}
+
+fn format_path(path: &[ChildNumber]) -> String {
+    path.iter()
+        .map(|child| match child {
+            ChildNumber::Normal { index } => format!("/{index}"),
+            ChildNumber::Hardened { index } => format!("/{index}h"),
+        })
+        .collect()
+}
+
+fn fingerprint_to_u32(fingerprint: Fingerprint) -> u32 {
+    u32::from_be_bytes(fingerprint.to_bytes())
+}
+
+fn fingerprint_from_u64(value: u64) -> Result<Fingerprint, UrError> {
+    let value = u32::try_from(value)
+        .map_err(|_| UrError::InvalidKey("Fingerprint must fit in 32 bits".to_string()))?;
+    Ok(Fingerprint::from(value.to_be_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::multisig::{LocalCosigner, MultisigConfig};
+    use bdk_wallet::KeychainKind;
+    use bip39::Mnemonic;
+    use lumo_types::Network;
+
+    const MNEMONICS: [&str; 2] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank yellow",
+    ];
+
+    fn cosigners() -> Vec<LocalCosigner> {
+        MNEMONICS
+            .iter()
+            .map(|m| {
+                let mnemonic = Mnemonic::from_str(m).unwrap();
+                LocalCosigner::from_mnemonic(&mnemonic, Network::Testnet).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_output_roundtrip() {
+        let locals = cosigners();
+        let key = &locals[0].cosigner.key;
+        let multisig =
+            MultisigConfig::new(2, locals.iter().map(|l| l.cosigner.clone()).collect(), None)
+                .unwrap();
+
+        let descriptors = [
+            format!("wpkh({key}/0/*)"),
+            format!("pkh({key}/0/*)"),
+            format!("sh(wpkh({key}/1/*))"),
+            format!("tr({key}/0/*)"),
+            multisig.descriptor(KeychainKind::External),
+        ];
+
+        for descriptor in descriptors {
+            let descriptor = Descriptor::<DescriptorPublicKey>::from_str(&descriptor).unwrap();
+            let cbor = output_to_cbor(&descriptor).unwrap();
+            let decoded = output_from_cbor(&Value::decode(&cbor.encode()).unwrap()).unwrap();
+            assert_eq!(decoded, descriptor);
+        }
+    }
+
+    #[test]
+    fn test_account_roundtrip() {
+        let local = &cosigners()[0];
+        let outputs =
+            vec![Descriptor::from_str(&format!("wpkh({}/0/*)", local.cosigner.key)).unwrap()];
+        let fingerprint = Fingerprint::from_str(&local.cosigner.fingerprint).unwrap();
+
+        let cbor = account_to_cbor(fingerprint, &outputs).unwrap();
+        let (decoded_fingerprint, decoded_outputs) =
+            account_from_cbor(&Value::decode(&cbor.encode()).unwrap()).unwrap();
+
+        assert_eq!(decoded_fingerprint, fingerprint);
+        assert_eq!(decoded_outputs, outputs);
+    }
+
+    #[test]
+    fn test_unsupported_descriptors() {
+        let single_key = Descriptor::<DescriptorPublicKey>::from_str(
+            "wpkh(02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443)",
+        )
+        .unwrap();
+        assert!(output_to_cbor(&single_key).is_err());
+
+        // Private keys are rejected even if the rest of the key is fine
+        let private = Value::tag(
+            TAG_WPKH,
+            Value::tag(TAG_HDKEY, Value::int_map(vec![(2, Value::Bool(true))])),
+        );
+        assert!(output_from_cbor(&private).is_err());
+    }
+}
@@ -1,34 +1,73 @@
+pub mod analysis;
+pub mod backup;
 pub mod balance;
+pub mod bsms;
 pub mod encryption;
 pub mod error;
+pub mod events;
+pub mod export;
+pub mod import;
+pub mod invoice;
 pub mod metadata;
-pub use metadata::{WalletId, WalletMetadata, WalletType};
+pub mod multisig;
+pub mod payjoin;
+pub mod progress;
+pub mod psbt;
+pub mod reserves;
+pub use metadata::{Birthday, ScanSettings, ScriptType, WalletId, WalletMetadata, WalletType};
 
-use crate::GAP_LIMIT;
 use bdk_wallet::{
     chain::ChainPosition as BdkChainPosition,
+    descriptor::ExtendedDescriptor,
+    miniscript::descriptor::{DescriptorSecretKey, Wildcard},
     template::{Bip84, DescriptorTemplate},
     KeychainKind, Wallet as BdkWallet,
 };
 use bip39::Mnemonic;
+use bitcoin::bip32::ChildNumber;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::Psbt;
 use bitcoin::secp256k1;
 use rand::Rng;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
-use crate::bdk_store::BDKStore;
+use crate::bdk_store::{lock_path, rescan_data_path, sqlite_data_path, BDKStore};
+use crate::cancel::CancelToken;
 use crate::database::Database;
+use crate::lock::{FileLock, LOCK_WAIT};
 use crate::node::client::esplora::EsploraClient;
 use crate::node::Node;
+use crate::ur::UrPayload;
+use crate::wallet::analysis::TransactionAnalysis;
+use crate::wallet::backup::WalletBackup;
 use crate::wallet::balance::Balance;
+use crate::wallet::bsms::{DescriptorRecord, KeyRecord, Token};
 use crate::wallet::encryption::MnemonicEncryption;
 use crate::wallet::error::{Result, WalletError};
+use crate::wallet::export::WalletExport;
+use crate::wallet::import::ImportedWallet;
+use crate::wallet::invoice::{payments_to, Invoice, InvoiceRequest};
+use crate::wallet::multisig::{Cosigner, LocalCosigner, MultisigConfig, PendingPsbt};
+use crate::wallet::payjoin::{
+    Contribution, PayjoinError, PayjoinOutcome, PayjoinParams, PayjoinSender,
+};
+use crate::wallet::progress::ProgressReporter;
 use lumo_types::address::AddressInfo;
+use lumo_types::message::MessageError;
 use lumo_types::{
     transaction::{ConfirmationStatus, TransactionDirection, TransactionId},
-    Address, Amount as LumoAmount, FeeRate, Network, Transaction,
+    Address, Amount as LumoAmount, FeeRate, Label, LabelType, Network, Transaction,
 };
 
 type PersistedBdkWallet = bdk_wallet::PersistedWallet<bdk_wallet::rusqlite::Connection>;
+type DescriptorWithKeys = (
+    bdk_wallet::descriptor::ExtendedDescriptor,
+    bdk_wallet::miniscript::descriptor::KeyMap,
+);
+
+/// A balance from a sync older than this isn't trusted when deleting a wallet
+const RECENT_SYNC_SECS: i64 = 10 * 60;
 
 /// Lumo Bitcoin wallet
 #[derive(Debug)]
@@ -36,6 +75,8 @@ pub struct Wallet {
     pub id: WalletId,
     pub metadata: WalletMetadata,
     pub bdk: bdk_wallet::PersistedWallet<bdk_wallet::rusqlite::Connection>,
+    // Held while this wallet is open so no other process writes to its store
+    store_lock: FileLock,
 }
 
 impl Wallet {
@@ -52,15 +93,16 @@ impl Wallet {
         let mut metadata = WalletMetadata::new(name, network);
 
         // Create BDK wallet with Native SegWit (bech32)
+        let store_lock = Self::lock_store(&metadata.id)?;
         let (bdk_wallet, fingerprint) =
             Self::create_bdk_wallet(&mnemonic, network, &metadata.id, None)?;
 
         metadata.master_fingerprint = Some(fingerprint.to_string().to_uppercase());
         metadata.mnemonic = Some(MnemonicEncryption::encrypt(mnemonic_phrase)?); // Store encrypted mnemonic
-        Self::check_for_duplicate_wallet(network, fingerprint)?;
+        Self::check_for_duplicate_wallet(network, fingerprint, ScriptType::P2wpkh)?;
 
         // Save metadata to database
-        let database = Database::global();
+        let database = Database::global()?;
         database
             .wallets
             .save_new_wallet_metadata(metadata.clone())?;
@@ -69,6 +111,7 @@ impl Wallet {
             id: metadata.id.clone(),
             metadata,
             bdk: bdk_wallet,
+            store_lock,
         })
     }
 
@@ -79,19 +122,21 @@ impl Wallet {
         let mnemonic =
             Mnemonic::from_entropy(&random_bytes).map_err(WalletError::InvalidMnemonic)?;
 
-        // Create metadata
+        // Create metadata, a brand new seed has no history before now
         let mut metadata = WalletMetadata::new(name, network);
+        metadata.birthday = Some(Birthday::now());
 
         // Create BDK wallet
+        let store_lock = Self::lock_store(&metadata.id)?;
         let (bdk_wallet, fingerprint) =
             Self::create_bdk_wallet(&mnemonic, network, &metadata.id, None)?;
 
         metadata.master_fingerprint = Some(fingerprint.to_string().to_uppercase());
         metadata.mnemonic = Some(MnemonicEncryption::encrypt(&mnemonic.to_string())?); // Store encrypted mnemonic
-        Self::check_for_duplicate_wallet(network, fingerprint)?;
+        Self::check_for_duplicate_wallet(network, fingerprint, ScriptType::P2wpkh)?;
 
         // Save metadata to database
-        let database = Database::global();
+        let database = Database::global()?;
         database
             .wallets
             .save_new_wallet_metadata(metadata.clone())?;
@@ -100,11 +145,165 @@ impl Wallet {
             id: metadata.id.clone(),
             metadata,
             bdk: bdk_wallet,
+            store_lock,
         };
 
         Ok((wallet, mnemonic))
     }
 
+    /// Create a new m-of-n multisig wallet (`wsh(sortedmulti(...))`) from cosigner xpubs
+    ///
+    /// If a mnemonic is given, its BIP48 key is added as a cosigner (unless already
+    /// listed) and stored so this wallet can add its own signature to PSBTs.
+    pub fn new_multisig(
+        name: String,
+        network: Network,
+        threshold: usize,
+        cosigner_keys: &[String],
+        local_mnemonic: Option<&str>,
+    ) -> Result<Self> {
+        let mut cosigners = cosigner_keys
+            .iter()
+            .map(|key| Cosigner::from_key_expression(key))
+            .collect::<Result<Vec<_>>>()?;
+
+        let local_fingerprint = match local_mnemonic {
+            Some(phrase) => {
+                let mnemonic = Mnemonic::from_str(phrase)?;
+                let local = LocalCosigner::from_mnemonic(&mnemonic, network)?;
+                if !cosigners.contains(&local.cosigner) {
+                    cosigners.push(local.cosigner.clone());
+                }
+                Some(local.cosigner.fingerprint)
+            }
+            None => None,
+        };
+
+        let config = MultisigConfig::new(threshold, cosigners, local_fingerprint)?;
+        Self::check_for_duplicate_multisig(network, &config)?;
+
+        let mut metadata = WalletMetadata::new_multisig(name, network, config.clone());
+        if let Some(phrase) = local_mnemonic {
+            metadata.mnemonic = Some(MnemonicEncryption::encrypt(phrase)?); // Store encrypted mnemonic
+        }
+
+        let store_lock = Self::lock_store(&metadata.id)?;
+        let bdk_wallet = Self::create_bdk_wallet_from_descriptors(
+            config.descriptor(KeychainKind::External),
+            config.descriptor(KeychainKind::Internal),
+            network,
+            &metadata.id,
+        )?;
+
+        // Save metadata to database
+        let database = Database::global()?;
+        database
+            .wallets
+            .save_new_wallet_metadata(metadata.clone())?;
+
+        Ok(Self {
+            id: metadata.id.clone(),
+            metadata,
+            bdk: bdk_wallet,
+            store_lock,
+        })
+    }
+
+    /// Create a multisig wallet from a verified BSMS (BIP129) descriptor record
+    ///
+    /// When `signer` is given, the record must include that wallet's BIP48 key and
+    /// the new multisig can sign with it. Otherwise the multisig is watch-only.
+    pub fn new_multisig_from_bsms(
+        name: String,
+        network: Network,
+        record: &DescriptorRecord,
+        signer: Option<&Wallet>,
+    ) -> Result<Self> {
+        let (config, local_mnemonic) = match signer {
+            Some(signer) => {
+                let local = signer.local_cosigner()?;
+                let config = record.verify_for_signer(network, &local)?;
+                (config, signer.decrypted_mnemonic()?)
+            }
+            None => (record.verify(network)?, None),
+        };
+
+        let keys: Vec<String> = config.cosigners.iter().map(|c| c.key.clone()).collect();
+        Self::new_multisig(
+            name,
+            network,
+            config.threshold,
+            &keys,
+            local_mnemonic.as_deref(),
+        )
+    }
+
+    /// Create a wallet from descriptors imported from Bitcoin Core or Electrum
+    ///
+    /// Private descriptors, if the source had them, are stored so the wallet can
+    /// sign. The wallet's creation date is set to the source's birthday.
+    pub fn new_from_import(
+        name: String,
+        network: Network,
+        imported: &ImportedWallet,
+    ) -> Result<Self> {
+        if let Some(fingerprint) = imported.master_fingerprint {
+            Self::check_for_duplicate_wallet(network, fingerprint, imported.script_type)?;
+        }
+
+        let mut metadata = WalletMetadata::new_from_descriptors(
+            name,
+            network,
+            imported.script_type,
+            imported
+                .master_fingerprint
+                .map(|fingerprint| fingerprint.to_string().to_uppercase()),
+            imported.has_private_keys(),
+        );
+
+        if let Some(birthday) = imported
+            .birthday
+            .and_then(|birthday| chrono::DateTime::from_timestamp(birthday, 0))
+        {
+            metadata.created_at = birthday.to_rfc3339();
+        }
+        metadata.birthday = imported.birthday.map(Birthday::Date);
+
+        if let Some((external, internal)) = &imported.private_descriptors {
+            metadata.private_descriptors = Some((
+                MnemonicEncryption::encrypt(external)?,
+                MnemonicEncryption::encrypt(internal)?,
+            ));
+        }
+
+        let store_lock = Self::lock_store(&metadata.id)?;
+        let bdk_wallet = Self::create_bdk_wallet_from_descriptors(
+            imported.external.clone(),
+            imported.internal.clone(),
+            network,
+            &metadata.id,
+        )?;
+
+        // Save metadata to database
+        let database = Database::global()?;
+        database
+            .wallets
+            .save_new_wallet_metadata(metadata.clone())?;
+        database.labels.save(&metadata.id, &imported.labels)?;
+
+        Ok(Self {
+            id: metadata.id.clone(),
+            metadata,
+            bdk: bdk_wallet,
+            store_lock,
+        })
+    }
+
+    /// Lock the wallet's BDK store, waiting briefly if another process has it open
+    fn lock_store(wallet_id: &WalletId) -> Result<FileLock> {
+        Ok(FileLock::acquire(&lock_path(wallet_id), LOCK_WAIT)?)
+    }
+
     /// Create BDK wallet from mnemonic using BIP84 (Native SegWit)
     fn create_bdk_wallet(
         mnemonic: &Mnemonic,
@@ -112,6 +311,44 @@ impl Wallet {
         wallet_id: &WalletId,
         passphrase: Option<&str>,
     ) -> Result<(PersistedBdkWallet, bitcoin::bip32::Fingerprint)> {
+        let (external, internal, fingerprint) =
+            Self::bip84_descriptors(mnemonic, network, passphrase)?;
+
+        let mut store = BDKStore::try_new(wallet_id, network)?;
+
+        let bdk_wallet = BdkWallet::create(external, internal)
+            .network(network.to_bitcoin_network())
+            .create_wallet(&mut store.conn)?;
+
+        Ok((bdk_wallet, fingerprint))
+    }
+
+    /// Create BDK wallet from a pair of public descriptors
+    fn create_bdk_wallet_from_descriptors(
+        external_descriptor: String,
+        internal_descriptor: String,
+        network: Network,
+        wallet_id: &WalletId,
+    ) -> Result<PersistedBdkWallet> {
+        let mut store = BDKStore::try_new(wallet_id, network)?;
+
+        let bdk_wallet = BdkWallet::create(external_descriptor, internal_descriptor)
+            .network(network.to_bitcoin_network())
+            .create_wallet(&mut store.conn)?;
+
+        Ok(bdk_wallet)
+    }
+
+    /// BIP84 (Native SegWit) descriptors with private keys for a mnemonic
+    fn bip84_descriptors(
+        mnemonic: &Mnemonic,
+        network: Network,
+        passphrase: Option<&str>,
+    ) -> Result<(
+        DescriptorWithKeys,
+        DescriptorWithKeys,
+        bitcoin::bip32::Fingerprint,
+    )> {
         // Convert our Network to BDK's network
         let bdk_network = network.to_bitcoin_network();
 
@@ -131,27 +368,32 @@ impl Wallet {
         let (internal_descriptor, internal_keymap, _) =
             Bip84(xpriv, KeychainKind::Internal).build(bdk_network)?;
 
-        let mut store = BDKStore::try_new(wallet_id, network)?;
-
-        // Create BDK wallet (in-memory for now, no persistence)
-        let bdk_wallet = BdkWallet::create(
+        Ok((
             (external_descriptor, external_keymap),
             (internal_descriptor, internal_keymap),
-        )
-        .network(bdk_network)
-        .create_wallet(&mut store.conn)?;
-
-        Ok((bdk_wallet, fingerprint))
+            fingerprint,
+        ))
     }
 
     fn check_for_duplicate_wallet(
         network: Network,
         fingerprint: bitcoin::bip32::Fingerprint,
+        script_type: ScriptType,
     ) -> Result<()> {
-        let database = Database::global();
+        let database = Database::global()?;
         let all_metadata = database.wallets.get_all(Some(network))?;
 
         for metadata in all_metadata {
+            // A multisig shares its local fingerprint with the single-sig wallet of the same seed
+            if metadata.multisig.is_some() {
+                continue;
+            }
+
+            // The same seed can back one wallet per script type
+            if metadata.script_type != script_type {
+                continue;
+            }
+
             if let Some(existing_fingerprint) = &metadata.master_fingerprint {
                 if existing_fingerprint.to_uppercase() == fingerprint.to_string().to_uppercase() {
                     return Err(WalletError::WalletAlreadyExists(metadata.id.to_string()));
@@ -162,13 +404,44 @@ impl Wallet {
         Ok(())
     }
 
+    fn check_for_duplicate_multisig(network: Network, config: &MultisigConfig) -> Result<()> {
+        let database = Database::global()?;
+        let all_metadata = database.wallets.get_all(Some(network))?;
+
+        let sorted_keys = |config: &MultisigConfig| {
+            let mut keys: Vec<_> = config.cosigners.iter().map(|c| c.key.clone()).collect();
+            keys.sort();
+            keys
+        };
+
+        for metadata in all_metadata {
+            if let Some(existing) = &metadata.multisig {
+                if existing.threshold == config.threshold
+                    && sorted_keys(existing) == sorted_keys(config)
+                {
+                    return Err(WalletError::WalletAlreadyExists(metadata.id.to_string()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wallets that aren't archived
     pub fn list_all(network: Option<Network>) -> Result<Vec<WalletMetadata>> {
-        let database = Database::global();
-        Ok(database.wallets.get_all(network)?)
+        let database = Database::global()?;
+        let wallets = database.wallets.get_all(network)?;
+        Ok(wallets.into_iter().filter(|w| !w.archived).collect())
+    }
+
+    pub fn list_archived(network: Option<Network>) -> Result<Vec<WalletMetadata>> {
+        let database = Database::global()?;
+        let wallets = database.wallets.get_all(network)?;
+        Ok(wallets.into_iter().filter(|w| w.archived).collect())
     }
 
     pub fn try_load_persisted(wallet_id: &WalletId, network: Network) -> Result<Self> {
-        let database = Database::global();
+        let database = Database::global()?;
 
         let metadata = database
             .wallets
@@ -178,6 +451,7 @@ impl Wallet {
             ))?;
 
         // Load the persisted wallet (watch-only)
+        let store_lock = Self::lock_store(wallet_id)?;
         let mut store = BDKStore::try_new(wallet_id, network)?;
         let bdk_wallet = bdk_wallet::Wallet::load()
             .load_wallet(&mut store.conn)?
@@ -189,6 +463,7 @@ impl Wallet {
             id: wallet_id.clone(),
             metadata,
             bdk: bdk_wallet,
+            store_lock,
         })
     }
 
@@ -201,7 +476,10 @@ impl Wallet {
 
                 let direction = if sent.to_sat() > received.to_sat() {
                     // Check if all outputs belong to this wallet (self-transfer)
-                    let all_outputs_mine = canonical_tx.tx_node.tx.output
+                    let all_outputs_mine = canonical_tx
+                        .tx_node
+                        .tx
+                        .output
                         .iter()
                         .all(|output| self.bdk.is_mine(output.script_pubkey.clone()));
 
@@ -228,8 +506,12 @@ impl Wallet {
                 let txid = TransactionId::from(canonical_tx.tx_node.tx.compute_txid());
                 let amount = match direction {
                     TransactionDirection::Incoming => LumoAmount::from(received),
-                    TransactionDirection::Outgoing => LumoAmount::from(sent.checked_sub(received).unwrap_or(sent)),
-                    TransactionDirection::SelfTransfer => fee.map(LumoAmount::from).unwrap_or(LumoAmount::ZERO),
+                    TransactionDirection::Outgoing => {
+                        LumoAmount::from(sent.checked_sub(received).unwrap_or(sent))
+                    }
+                    TransactionDirection::SelfTransfer => {
+                        fee.map(LumoAmount::from).unwrap_or(LumoAmount::ZERO)
+                    }
                 };
 
                 Transaction::new(
@@ -246,43 +528,283 @@ impl Wallet {
         Ok(transactions)
     }
 
+    /// Whether any of the wallet's transactions are still waiting for a block
+    pub fn has_unconfirmed(&self) -> bool {
+        self.bdk.transactions().any(|canonical_tx| {
+            matches!(
+                canonical_tx.chain_position,
+                BdkChainPosition::Unconfirmed { .. }
+            )
+        })
+    }
+
     pub fn balance(&self) -> Balance {
         Balance(self.bdk.balance())
     }
 
     pub async fn sync(&mut self) -> Result<()> {
-        let node = Node::default(self.network());
-        let esplora_client = EsploraClient::new(&node.url).await?;
-        let scan_request = self.bdk.start_full_scan().build();
-        let scan_result = esplora_client
-            .full_scan(scan_request, GAP_LIMIT as usize)
-            .await?;
+        self.sync_with(ProgressReporter::silent(), &CancelToken::new())
+            .await
+    }
+
+    /// Sync, reporting to `progress` as the scan moves along
+    ///
+    /// Chain data is only applied once the scan completes, so a sync stopped
+    /// through `cancel` leaves the wallet as it was.
+    pub async fn sync_with(
+        &mut self,
+        progress: ProgressReporter,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let update = cancel
+            .run(self.scan(progress))
+            .await
+            .ok_or(WalletError::Cancelled)??;
+
         self.bdk
-            .apply_update(scan_result)
+            .apply_update(update)
             .map_err(|e| WalletError::Generic(e.to_string()))?;
+        self.persist()?;
+        self.update_invoices()?;
+        self.mark_synced()
+    }
+
+    async fn scan(&mut self, progress: ProgressReporter) -> Result<bdk_wallet::Update> {
+        let node = Node::for_network(self.network());
+        let esplora_client = EsploraClient::new(&node.url).await?;
+
+        let from_height = self.birthday_height(&esplora_client).await?;
+        Self::scan_wallet(
+            &self.bdk,
+            &esplora_client,
+            self.metadata.scan,
+            from_height,
+            progress,
+        )
+        .await
+    }
+
+    /// Scan `bdk`'s scripts, skipping history before `from_height` if given
+    async fn scan_wallet(
+        bdk: &BdkWallet,
+        esplora_client: &EsploraClient,
+        settings: ScanSettings,
+        from_height: Option<u32>,
+        progress: ProgressReporter,
+    ) -> Result<bdk_wallet::Update> {
+        let inspector = progress.clone();
+        let scan_request = bdk
+            .start_full_scan()
+            .inspect(move |keychain, _, _| inspector.script_checked(keychain))
+            .build();
+
+        let revealed = Self::revealed_indices(bdk);
+        let update = match from_height {
+            Some(from_height) => {
+                esplora_client
+                    .full_scan_from(scan_request, &settings, &revealed, from_height, &progress)
+                    .await?
+            }
+            None => {
+                esplora_client
+                    .full_scan(scan_request, &settings, &revealed, &progress)
+                    .await?
+            }
+        };
+        Ok(update)
+    }
+
+    /// Last revealed index of each keychain that has any
+    fn revealed_indices(bdk: &BdkWallet) -> BTreeMap<KeychainKind, u32> {
+        [KeychainKind::External, KeychainKind::Internal]
+            .into_iter()
+            .filter_map(|keychain| Some((keychain, bdk.derivation_index(keychain)?)))
+            .collect()
+    }
+
+    /// Birthday as a block height, a date birthday is looked up once and saved
+    async fn birthday_height(&mut self, esplora_client: &EsploraClient) -> Result<Option<u32>> {
+        match self.metadata.birthday {
+            Some(Birthday::Height(height)) => Ok(Some(height)),
+            Some(Birthday::Date(timestamp)) => {
+                let height = esplora_client.height_at_time(timestamp).await?;
+                self.set_birthday(Birthday::Height(height))?;
+                Ok(Some(height))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Birthday as a unix timestamp, `None` if it isn't known
+    ///
+    /// A height birthday needs its block time from the server. If that lookup
+    /// fails the birthday is treated as unknown, which only costs a longer rescan.
+    async fn birthday_timestamp(&self) -> Option<i64> {
+        match self.metadata.birthday? {
+            Birthday::Date(timestamp) => Some(timestamp),
+            Birthday::Height(height) => {
+                let node = Node::for_network(self.network());
+                let block_time = match EsploraClient::new(&node.url).await {
+                    Ok(esplora_client) => esplora_client.block_time(height).await,
+                    Err(e) => Err(e),
+                };
+                block_time
+                    .inspect_err(|e| tracing::warn!("Unable to look up the birthday block: {e}"))
+                    .ok()
+            }
+        }
+    }
+
+    fn mark_synced(&mut self) -> Result<()> {
+        self.metadata.last_synced = Some(chrono::Utc::now().timestamp());
+        let database = Database::global()?;
+        database.wallets.update_wallet_metadata(&self.metadata)?;
+        Ok(())
+    }
+
+    /// Whether a sync completed recently enough to trust the balance
+    pub fn recently_synced(&self) -> bool {
+        self.metadata.last_synced.is_some_and(|last_synced| {
+            chrono::Utc::now().timestamp() - last_synced < RECENT_SYNC_SECS
+        })
+    }
+
+    pub fn set_birthday(&mut self, birthday: Birthday) -> Result<()> {
+        self.metadata.birthday = Some(birthday);
+        let database = Database::global()?;
+        database.wallets.update_wallet_metadata(&self.metadata)?;
+        Ok(())
+    }
+
+    /// Change the stop gap and parallelism used from the next sync on
+    pub fn set_scan_settings(&mut self, settings: ScanSettings) -> Result<()> {
+        settings.validate()?;
+        self.metadata.scan = settings;
+        let database = Database::global()?;
+        database.wallets.update_wallet_metadata(&self.metadata)?;
         Ok(())
     }
 
+    /// Throw away all chain data and scan again
+    ///
+    /// History before `from_height` is skipped, without a height the wallet's
+    /// birthday (if any) is used. The scan runs against an empty in-memory
+    /// wallet and the stored chain data is only replaced once it succeeds.
+    pub async fn rescan(
+        &mut self,
+        from_height: Option<u32>,
+        progress: ProgressReporter,
+        cancel: &CancelToken,
+    ) -> Result<()> {
+        let external = self.public_descriptor(KeychainKind::External);
+        let internal = self.public_descriptor(KeychainKind::Internal);
+
+        let node = Node::for_network(self.network());
+        let esplora_client = EsploraClient::new(&node.url).await?;
+        let from_height = match from_height {
+            Some(from_height) => Some(from_height),
+            None => self.birthday_height(&esplora_client).await?,
+        };
+
+        // Addresses handed out (e.g. for invoices) are scanned and stay revealed
+        let revealed = Self::revealed_indices(&self.bdk);
+        let mut empty = BdkWallet::create(external.clone(), internal.clone())
+            .network(self.network().to_bitcoin_network())
+            .create_wallet_no_persist()?;
+        for (&keychain, &index) in &revealed {
+            let _ = empty.reveal_addresses_to(keychain, index);
+        }
+        let update = cancel
+            .run(Self::scan_wallet(
+                &empty,
+                &esplora_client,
+                self.metadata.scan,
+                from_height,
+                progress,
+            ))
+            .await
+            .ok_or(WalletError::Cancelled)??;
+
+        // The new store is built on the side and only replaces the old one once
+        // it's complete, a failure part-way leaves the old chain data in place
+        let rescan_path = rescan_data_path(&self.id);
+        let _ = std::fs::remove_file(&rescan_path);
+        let bdk = match Self::build_rescanned_store(
+            &rescan_path,
+            external,
+            internal,
+            self.network(),
+            revealed,
+            update,
+        ) {
+            Ok(bdk) => bdk,
+            Err(e) => {
+                let _ = std::fs::remove_file(&rescan_path);
+                return Err(e);
+            }
+        };
+        std::fs::rename(&rescan_path, sqlite_data_path(&self.id))
+            .map_err(|e| WalletError::Generic(format!("Unable to replace chain data: {e}")))?;
+
+        self.bdk = bdk;
+        self.update_invoices()?;
+        self.mark_synced()
+    }
+
+    /// A new sqlite store at `path` holding `update`, with the `revealed` addresses
+    fn build_rescanned_store(
+        path: &std::path::Path,
+        external: String,
+        internal: String,
+        network: Network,
+        revealed: BTreeMap<KeychainKind, u32>,
+        update: bdk_wallet::Update,
+    ) -> Result<PersistedBdkWallet> {
+        let mut conn = bdk_wallet::rusqlite::Connection::open(path)
+            .map_err(|e| WalletError::Generic(format!("Unable to create chain data: {e}")))?;
+        let mut bdk = BdkWallet::create(external, internal)
+            .network(network.to_bitcoin_network())
+            .create_wallet(&mut conn)?;
+        for (keychain, index) in revealed {
+            let _ = bdk.reveal_addresses_to(keychain, index);
+        }
+
+        bdk.apply_update(update)
+            .map_err(|e| WalletError::Generic(e.to_string()))?;
+        bdk.persist(&mut conn)
+            .map_err(|e| WalletError::Bdk(e.to_string()))?;
+        Ok(bdk)
+    }
+
+    /// Write staged BDK changes (chain data, revealed addresses) to the sqlite store
+    pub fn persist(&mut self) -> Result<()> {
+        let mut store = BDKStore::try_new(&self.id, self.network())?;
+        self.bdk
+            .persist(&mut store.conn)
+            .map_err(|e| WalletError::Bdk(e.to_string()))?;
+        Ok(())
+    }
 
     /// Get a new receiving address with gap limit protection
     pub fn get_new_address(&mut self) -> Result<Address> {
-        const MAX_ADDRESSES: usize = (GAP_LIMIT - 5) as usize; // 25 addresses max
+        // Stay short of the stop gap so a scan always finds the addresses handed out
+        let max_addresses = self.metadata.scan.stop_gap.saturating_sub(5).max(1) as usize;
 
         // Get unused addresses to check how many we have
         let unused_addresses: Vec<_> = self
             .bdk
             .list_unused_addresses(KeychainKind::External)
-            .take(MAX_ADDRESSES)
+            .take(max_addresses)
             .collect();
 
-        // If we have fewer than 25 revealed addresses, reveal a new one
-        if unused_addresses.len() < MAX_ADDRESSES {
+        // If we have fewer than the max revealed addresses, reveal a new one
+        if unused_addresses.len() < max_addresses {
             let address_info = self.bdk.reveal_next_address(KeychainKind::External);
             let address = Address::new(address_info.address);
             return Ok(address);
         }
 
-        // If we already have 25 addresses, cycle through unused ones
+        // If we already have the max, cycle through unused ones
         if let Some(first_unused) = unused_addresses.first() {
             let address = Address::new(first_unused.address.clone());
             return Ok(address);
@@ -334,38 +856,442 @@ impl Wallet {
         Ok(psbt)
     }
 
-    pub fn sign_transaction(
-        &mut self,
-        mut psbt: bitcoin::psbt::Psbt,
-    ) -> Result<bitcoin::Transaction> {
+    /// Sign a PSBT with the keys this wallet holds
+    ///
+    /// Returns true if the PSBT is finalized. Multisig wallets record the PSBT
+    /// as pending along with how many signatures it has collected.
+    pub fn sign_psbt(&mut self, psbt: &mut Psbt) -> Result<bool> {
         use bdk_wallet::SignOptions;
 
-        // Create temporary signing wallet from mnemonic
-        let finalized = if let Some(encrypted_mnemonic) = &self.metadata.mnemonic {
-            // Decrypt mnemonic and create temporary signing wallet
-            let mnemonic_phrase = MnemonicEncryption::decrypt(encrypted_mnemonic)?;
-            let mnemonic = Mnemonic::from_str(&mnemonic_phrase)?;
+        let finalized = match self.signing_wallet()? {
+            Some(signing_wallet) => signing_wallet.sign(psbt, SignOptions::default()),
+            // No keys stored - try to sign with loaded wallet (will likely fail)
+            None => self.bdk.sign(psbt, SignOptions::default()),
+        }
+        .map_err(|e| WalletError::Generic(format!("Error signing transaction: {e}")))?;
+
+        if let Some(multisig) = &self.metadata.multisig {
+            let pending = PendingPsbt::new(self.id.clone(), psbt, multisig.threshold);
+            Database::global()?.pending_psbts.save(&pending)?;
+        }
+
+        Ok(finalized)
+    }
+
+    /// Build a temporary in-memory wallet holding our private keys, used only for signing
+    fn signing_wallet(&self) -> Result<Option<BdkWallet>> {
+        if let Some((external, internal)) = &self.metadata.private_descriptors {
+            let signing_wallet = BdkWallet::create(
+                MnemonicEncryption::decrypt(external)?,
+                MnemonicEncryption::decrypt(internal)?,
+            )
+            .network(self.network().to_bitcoin_network())
+            .create_wallet_no_persist()?;
+            return Ok(Some(signing_wallet));
+        }
+
+        let Some(encrypted_mnemonic) = &self.metadata.mnemonic else {
+            return Ok(None);
+        };
+
+        // Decrypt mnemonic
+        let mnemonic_phrase = MnemonicEncryption::decrypt(encrypted_mnemonic)?;
+        let mnemonic = Mnemonic::from_str(&mnemonic_phrase)?;
+        let network = self.network();
+
+        let signing_wallet = match &self.metadata.multisig {
+            Some(multisig) => {
+                let local = LocalCosigner::from_mnemonic(&mnemonic, network)?;
+                BdkWallet::create(
+                    multisig.signing_descriptor(KeychainKind::External, &local),
+                    multisig.signing_descriptor(KeychainKind::Internal, &local),
+                )
+                .network(network.to_bitcoin_network())
+                .create_wallet_no_persist()?
+            }
+            None => {
+                let (external, internal, _) = Self::bip84_descriptors(&mnemonic, network, None)?;
+                BdkWallet::create(external, internal)
+                    .network(network.to_bitcoin_network())
+                    .create_wallet_no_persist()?
+            }
+        };
 
-            // Create temporary signing wallet (in-memory only)
-            let network = self.network();
-            let temp_wallet_id = WalletId::new(); // Temporary ID
-            let (temp_signing_wallet, _) =
-                Self::create_bdk_wallet(&mnemonic, network, &temp_wallet_id, None)?;
-
-            // Sign with the temporary signing wallet
-            temp_signing_wallet
-                .sign(&mut psbt, SignOptions::default())
-                .map_err(|e| {
-                    WalletError::Generic(format!("Error signing transaction: {}", e.to_string()))
-                })?
+        Ok(Some(signing_wallet))
+    }
+
+    /// Decrypt the stored mnemonic, if this wallet has one
+    fn decrypted_mnemonic(&self) -> Result<Option<String>> {
+        self.metadata
+            .mnemonic
+            .as_ref()
+            .map(|encrypted| MnemonicEncryption::decrypt(encrypted))
+            .transpose()
+    }
+
+    /// Sign `message` with the key behind one of our addresses, proving we control it
+    ///
+    /// Segwit and taproot addresses get a BIP322 simple signature, P2PKH ones a
+    /// legacy `signmessage` signature. Both are base64.
+    pub fn sign_message(&self, address: &Address, message: &str) -> Result<String> {
+        let (keychain, index) = self
+            .bdk
+            .derivation_of_spk(address.script_pubkey())
+            .ok_or_else(|| {
+                WalletError::Message(format!("{address} isn't an address of this wallet"))
+            })?;
+
+        let mut signed = Err(MessageError::WrongKey);
+        for key in self.private_keys_at(keychain, index)? {
+            signed = lumo_types::message::sign_message(address, message, &key);
+            if signed != Err(MessageError::WrongKey) {
+                break;
+            }
+        }
+        signed.map_err(|e| WalletError::Message(e.to_string()))
+    }
+
+    /// Private keys of a keychain's descriptor, derived at `index`
+    fn private_keys_at(
+        &self,
+        keychain: KeychainKind,
+        index: u32,
+    ) -> Result<Vec<bitcoin::PrivateKey>> {
+        if self.metadata.multisig.is_some() {
+            return Err(WalletError::Message(
+                "Multisig wallets can't sign messages".to_string(),
+            ));
+        }
+
+        let secp = secp256k1::Secp256k1::new();
+        let keymap = if let Some((external, internal)) = &self.metadata.private_descriptors {
+            let descriptor = match keychain {
+                KeychainKind::External => external,
+                KeychainKind::Internal => internal,
+            };
+            let descriptor = MnemonicEncryption::decrypt(descriptor)?;
+            let (_, keymap) = ExtendedDescriptor::parse_descriptor(&secp, &descriptor)
+                .map_err(|e| WalletError::Bdk(e.to_string()))?;
+            keymap
+        } else if let Some(mnemonic_phrase) = self.decrypted_mnemonic()? {
+            let mnemonic = Mnemonic::from_str(&mnemonic_phrase)?;
+            let (external, internal, _) = Self::bip84_descriptors(&mnemonic, self.network(), None)?;
+            match keychain {
+                KeychainKind::External => external.1,
+                KeychainKind::Internal => internal.1,
+            }
         } else {
-            // No mnemonic stored - try to sign with loaded wallet (will likely fail)
-            self.bdk
-                .sign(&mut psbt, SignOptions::default())
-                .map_err(|e| {
-                    WalletError::Generic(format!("Error signing transaction: {}", e.to_string()))
-                })?
+            return Err(WalletError::Message(
+                "Wallet has no private keys to sign with".to_string(),
+            ));
+        };
+
+        keymap
+            .values()
+            .filter_map(|secret| match secret {
+                DescriptorSecretKey::Single(single) => Some(Ok(single.key)),
+                DescriptorSecretKey::XPrv(xkey) => {
+                    let path = match xkey.wildcard {
+                        Wildcard::None => Ok(xkey.derivation_path.clone()),
+                        Wildcard::Unhardened => ChildNumber::from_normal_idx(index)
+                            .map(|child| xkey.derivation_path.child(child)),
+                        Wildcard::Hardened => ChildNumber::from_hardened_idx(index)
+                            .map(|child| xkey.derivation_path.child(child)),
+                    };
+                    let key = path
+                        .and_then(|path| xkey.xkey.derive_priv(&secp, &path))
+                        .map(|xpriv| xpriv.to_priv())
+                        .map_err(WalletError::from);
+                    Some(key)
+                }
+                DescriptorSecretKey::MultiXPrv(_) => None,
+            })
+            .collect()
+    }
+
+    /// Build a signed BIP127 proof of reserves committing to `message`
+    ///
+    /// Covers `coins` if given, otherwise every confirmed unspent output of the
+    /// wallet. Verifiers only count confirmed coins, so unconfirmed ones are refused.
+    pub fn create_reserves_proof(
+        &self,
+        message: &str,
+        coins: Option<&[bitcoin::OutPoint]>,
+    ) -> Result<Psbt> {
+        use bdk_wallet::SignOptions;
+
+        if self.metadata.multisig.is_some() {
+            return Err(WalletError::Reserves(
+                "Multisig wallets can't prove reserves".to_string(),
+            ));
+        }
+
+        let mut unspent: Vec<_> = self
+            .bdk
+            .list_unspent()
+            .filter(|utxo| matches!(utxo.chain_position, BdkChainPosition::Confirmed { .. }))
+            .collect();
+        if let Some(coins) = coins {
+            if let Some(missing) = coins
+                .iter()
+                .find(|coin| !unspent.iter().any(|utxo| utxo.outpoint == **coin))
+            {
+                return Err(WalletError::Reserves(format!(
+                    "{missing} isn't a confirmed unspent output of this wallet"
+                )));
+            }
+            unspent.retain(|utxo| coins.contains(&utxo.outpoint));
+        }
+
+        let inputs = unspent
+            .into_iter()
+            .map(|utxo| {
+                let outpoint = utxo.outpoint;
+                self.bdk
+                    .get_psbt_input(utxo, None, true)
+                    .map(|input| (outpoint, input))
+                    .map_err(|e| WalletError::Reserves(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut psbt = reserves::unsigned_proof(message, inputs)?;
+
+        let signing_wallet = self.signing_wallet()?.ok_or_else(|| {
+            WalletError::Reserves("Wallet has no private keys to sign with".to_string())
+        })?;
+        // The coins' inputs only carry their witness_utxo, and nothing is ever broadcast
+        let sign_options = SignOptions {
+            trust_witness_utxo: true,
+            ..SignOptions::default()
         };
+        let finalized = signing_wallet
+            .sign(&mut psbt, sign_options)
+            .map_err(|e| WalletError::Reserves(format!("Error signing proof: {e}")))?;
+        if !finalized {
+            return Err(WalletError::Reserves(
+                "Couldn't sign every coin of the proof".to_string(),
+            ));
+        }
+
+        Ok(psbt)
+    }
+
+    /// Our BIP48 multisig cosigner key, derived from the stored mnemonic
+    pub fn local_cosigner(&self) -> Result<LocalCosigner> {
+        let mnemonic_phrase = self.decrypted_mnemonic()?.ok_or(WalletError::Generic(
+            "Wallet has no mnemonic to derive a cosigner key from".to_string(),
+        ))?;
+        let mnemonic = Mnemonic::from_str(&mnemonic_phrase)?;
+        LocalCosigner::from_mnemonic(&mnemonic, self.network())
+    }
+
+    /// Create a signed BSMS key record for this wallet's multisig cosigner key
+    pub fn bsms_key_record(&self, token: Token, description: &str) -> Result<KeyRecord> {
+        let local = self.local_cosigner()?;
+        KeyRecord::create(token, &local, description)
+    }
+
+    /// PSBTs of this multisig wallet still waiting for cosigner signatures
+    pub fn pending_psbts(&self) -> Result<Vec<PendingPsbt>> {
+        let database = Database::global()?;
+        Ok(database.pending_psbts.get_all(&self.id)?)
+    }
+
+    /// Combine partially signed copies of a PSBT, e.g. from different cosigners
+    pub fn combine_psbts(&self, psbts: Vec<Psbt>) -> Result<Psbt> {
+        let combined = psbt::combine_psbts(psbts)?;
+
+        if let Some(multisig) = &self.metadata.multisig {
+            let pending = PendingPsbt::new(self.id.clone(), &combined, multisig.threshold);
+            Database::global()?.pending_psbts.save(&pending)?;
+        }
+
+        Ok(combined)
+    }
+
+    /// Finalize a fully signed PSBT, returns true if every input could be finalized
+    pub fn finalize_psbt(&self, psbt: &mut Psbt) -> Result<bool> {
+        use bdk_wallet::SignOptions;
+
+        self.bdk
+            .finalize_psbt(psbt, SignOptions::default())
+            .map_err(|e| WalletError::Psbt(format!("Error finalizing PSBT: {e}")))
+    }
+
+    /// Finalize (if needed) and extract the network transaction from a PSBT
+    pub fn extract_transaction(&self, mut psbt: Psbt) -> Result<bitcoin::Transaction> {
+        let already_final = psbt
+            .inputs
+            .iter()
+            .all(|input| input.final_script_witness.is_some() || input.final_script_sig.is_some());
+
+        if !already_final && !self.finalize_psbt(&mut psbt)? {
+            return Err(WalletError::Psbt(
+                "PSBT is missing signatures and cannot be finalized".to_string(),
+            ));
+        }
+
+        psbt.extract_tx()
+            .map_err(|e| WalletError::Psbt(format!("Error extracting transaction: {e}")))
+    }
+
+    /// Decode a PSBT and show what it does from this wallet's point of view
+    pub fn analyze_psbt(&self, psbt: &Psbt) -> Result<TransactionAnalysis> {
+        TransactionAnalysis::from_psbt(&self.bdk, psbt)
+    }
+
+    /// Decode a hex encoded raw transaction and show what it does from this wallet's point of view
+    pub fn analyze_raw_tx(&self, raw_tx: &str) -> Result<TransactionAnalysis> {
+        TransactionAnalysis::from_raw_tx(&self.bdk, raw_tx)
+    }
+
+    /// Public descriptor of a keychain, with checksum
+    pub fn public_descriptor(&self, keychain: KeychainKind) -> String {
+        self.bdk.public_descriptor(keychain).to_string()
+    }
+
+    /// Public descriptors, birthday and labels, ready to write in any export format
+    pub async fn export(&self) -> Result<WalletExport> {
+        Ok(WalletExport::new(
+            &self.metadata,
+            self.birthday_timestamp().await,
+            self.public_descriptor(KeychainKind::External),
+            self.public_descriptor(KeychainKind::Internal),
+            self.bdk.next_derivation_index(KeychainKind::External),
+            self.labels()?,
+        ))
+    }
+
+    /// Set a BIP329 label, an empty label removes it
+    pub fn set_label(&self, label_type: LabelType, reference: &str, label: &str) -> Result<()> {
+        let database = Database::global()?;
+        let reference = reference.trim();
+
+        if label.trim().is_empty() {
+            database.labels.remove(&self.id, label_type, reference)?;
+        } else {
+            let label = Label::new(label_type, reference, label.trim());
+            database.labels.save(&self.id, &[label])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn labels(&self) -> Result<Vec<Label>> {
+        let database = Database::global()?;
+        Ok(database.labels.get_all(&self.id)?)
+    }
+
+    /// New invoice paying to a receive address nothing else has been given
+    ///
+    /// The memo, if any, also becomes the address's label.
+    pub fn create_invoice(&mut self, request: InvoiceRequest) -> Result<Invoice> {
+        let address_info = self.bdk.reveal_next_address(KeychainKind::External);
+        let address = Address::new(address_info.address);
+        let invoice = Invoice::new(
+            self.id.clone(),
+            &address,
+            address_info.index,
+            request,
+            chrono::Utc::now().timestamp(),
+        )?;
+        self.persist()?;
+
+        if let Some(memo) = &invoice.memo {
+            self.set_label(LabelType::Addr, &invoice.address, memo)?;
+        }
+        Database::global()?
+            .invoices
+            .save(std::slice::from_ref(&invoice))?;
+        Ok(invoice)
+    }
+
+    /// The wallet's invoices, oldest first
+    pub fn invoices(&self) -> Result<Vec<Invoice>> {
+        let database = Database::global()?;
+        Ok(database.invoices.get_all(&self.id)?)
+    }
+
+    /// Invoice by id, start of an id, or address
+    pub fn invoice(&self, id_or_address: &str) -> Result<Invoice> {
+        let query = id_or_address.trim();
+        let mut matches: Vec<Invoice> = self
+            .invoices()?
+            .into_iter()
+            .filter(|invoice| {
+                invoice.address == query || (!query.is_empty() && invoice.id.starts_with(query))
+            })
+            .collect();
+
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(WalletError::Invoice(format!("No invoice matches {query}"))),
+            _ => Err(WalletError::Invoice(format!(
+                "{} invoices start with {query}, give more of the id",
+                matches.len()
+            ))),
+        }
+    }
+
+    /// Check every invoice against the wallet's transactions and the clock,
+    /// saving the ones whose status changed
+    ///
+    /// Returns all of the wallet's invoices, oldest first.
+    pub fn update_invoices(&self) -> Result<Vec<Invoice>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut invoices = self.invoices()?;
+        let mut changed = Vec::new();
+
+        for invoice in &mut invoices {
+            let script = self
+                .bdk
+                .peek_address(KeychainKind::External, invoice.index)
+                .script_pubkey();
+            let payments = payments_to(self, &script);
+            if invoice.update(payments, now) {
+                changed.push(invoice.clone());
+            }
+        }
+
+        if !changed.is_empty() {
+            Database::global()?.invoices.save(&changed)?;
+        }
+        Ok(invoices)
+    }
+
+    /// Everything needed to restore this wallet, optionally with its synced chain data
+    pub fn backup(&self, include_chain_data: bool) -> Result<WalletBackup> {
+        WalletBackup::new(self, self.labels()?, include_chain_data)
+    }
+
+    /// Receive descriptor as a BC-UR `crypto-output`
+    pub fn ur_output(&self) -> UrPayload {
+        UrPayload::Output(self.bdk.public_descriptor(KeychainKind::External).clone())
+    }
+
+    /// Receive and change descriptors as a BC-UR `crypto-account`
+    pub fn ur_account(&self) -> Result<UrPayload> {
+        let master_fingerprint =
+            self.metadata
+                .master_fingerprint
+                .as_deref()
+                .ok_or(WalletError::Ur(
+                    "Wallet has no master fingerprint".to_string(),
+                ))?;
+        let master_fingerprint =
+            bitcoin::bip32::Fingerprint::from_str(&master_fingerprint.to_lowercase())
+                .map_err(|e| WalletError::Ur(format!("Invalid master fingerprint: {e}")))?;
+
+        Ok(UrPayload::Account {
+            master_fingerprint,
+            outputs: vec![
+                self.bdk.public_descriptor(KeychainKind::External).clone(),
+                self.bdk.public_descriptor(KeychainKind::Internal).clone(),
+            ],
+        })
+    }
+
+    pub fn sign_transaction(&mut self, mut psbt: Psbt) -> Result<bitcoin::Transaction> {
+        let finalized = self.sign_psbt(&mut psbt)?;
 
         if !finalized {
             return Err(WalletError::Generic(
@@ -373,27 +1299,193 @@ impl Wallet {
             ));
         }
 
-        let tx = psbt.extract_tx().map_err(|e| {
-            WalletError::Generic(format!("Error extracting transaction: {}", e.to_string()))
-        })?;
+        let tx = psbt
+            .extract_tx()
+            .map_err(|e| WalletError::Generic(format!("Error extracting transaction: {e}")))?;
 
         Ok(tx)
     }
 
     pub async fn broadcast_transaction(&mut self, transaction: bitcoin::Transaction) -> Result<()> {
-        let node = Node::default(self.network());
+        let node = Node::for_network(self.network());
         let esplora_client = EsploraClient::new(&node.url).await?;
 
         esplora_client
             .broadcast_transaction(&transaction)
             .await
-            .map_err(|e| {
-                WalletError::Generic(format!("Error broadcasting transaction: {}", e.to_string()))
-            })?;
+            .map_err(|e| WalletError::Generic(format!("Error broadcasting transaction: {e}")))?;
+
+        // No longer waiting on cosigners once it's on the network
+        let database = Database::global()?;
+        database
+            .pending_psbts
+            .remove(&transaction.compute_txid().to_string())?;
 
         Ok(())
     }
 
+    /// Pay `recipient` through the payjoin endpoint of their BIP21 URI
+    ///
+    /// If the receiver can't be reached or its proposal doesn't pass our checks,
+    /// the plain transaction we offered it is broadcast instead.
+    pub async fn send_payjoin(
+        &mut self,
+        recipient: Address,
+        amount: LumoAmount,
+        fee_rate: FeeRate,
+        endpoint: &str,
+    ) -> Result<PayjoinOutcome> {
+        if self.metadata.multisig.is_some() {
+            return Err(WalletError::Payjoin(
+                "Multisig wallets can't payjoin".to_string(),
+            ));
+        }
+
+        let payee = recipient.to_bdk_address().script_pubkey();
+        let unsigned = self.build_transaction(recipient, amount, fee_rate)?;
+        let mut original = unsigned.clone();
+        if !self.sign_psbt(&mut original)? {
+            return Err(WalletError::Psbt(
+                "Transaction could not be finalized".to_string(),
+            ));
+        }
+
+        let change = original.unsigned_tx.output.iter().position(|output| {
+            output.script_pubkey != payee && self.bdk.is_mine(output.script_pubkey.clone())
+        });
+        let sender = PayjoinSender::new(endpoint, unsigned, original, payee, change, fee_rate)?;
+        let fallback = sender.original_transaction()?;
+
+        match self.payjoin(&sender).await {
+            Ok(txid) => Ok(PayjoinOutcome::Payjoin(txid)),
+            Err(e) => {
+                tracing::warn!("Payjoin failed, broadcasting the original: {e}");
+                let txid = fallback.compute_txid();
+                self.broadcast_transaction(fallback).await?;
+                Ok(PayjoinOutcome::Fallback {
+                    txid,
+                    reason: e.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Get a proposal from the receiver, sign it and broadcast it
+    async fn payjoin(&mut self, sender: &PayjoinSender) -> Result<bitcoin::Txid> {
+        let proposal = sender.post().await?;
+        let mut proposal =
+            sender.check_proposal(proposal, |script| self.bdk.is_mine(script.to_owned()))?;
+
+        if !self.sign_psbt(&mut proposal)? {
+            return Err(WalletError::Payjoin(
+                "Proposal could not be finalized".to_string(),
+            ));
+        }
+        let transaction = self.extract_transaction(proposal)?;
+        let txid = transaction.compute_txid();
+        self.broadcast_transaction(transaction).await?;
+        Ok(txid)
+    }
+
+    /// Answer a payjoin request paying one of this wallet's payjoin invoices with a
+    /// proposal spending one of our confirmed coins too
+    ///
+    /// Only outputs to the address of an open invoice created with a payjoin
+    /// endpoint are answered. The coin is picked at random among those of the
+    /// same script type as the sender's inputs, unless `reuse` names one that's
+    /// still unspent: a sender posting the same original again must get the same
+    /// coin back, or it could list our coins one request at a time. The original
+    /// is only checked to be fully signed and to pay us here, the caller checks
+    /// its inputs against the chain.
+    ///
+    /// Returns the proposal and the coin it adds.
+    pub fn payjoin_proposal(
+        &mut self,
+        original: &Psbt,
+        params: &PayjoinParams,
+        reuse: Option<bitcoin::OutPoint>,
+    ) -> std::result::Result<(Psbt, bitcoin::OutPoint), PayjoinError> {
+        if self.metadata.multisig.is_some() {
+            return Err(PayjoinError::Unavailable(
+                "Multisig wallets can't payjoin".to_string(),
+            ));
+        }
+
+        let spends_ours = original.inputs.iter().any(|input| {
+            input
+                .witness_utxo
+                .as_ref()
+                .is_some_and(|utxo| self.bdk.is_mine(utxo.script_pubkey.clone()))
+        });
+        if spends_ours {
+            return Err(PayjoinError::OriginalRejected(
+                "Original spends our coins".to_string(),
+            ));
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let invoice_scripts: Vec<bitcoin::ScriptBuf> = self
+            .invoices()
+            .map_err(|e| PayjoinError::Unavailable(e.to_string()))?
+            .into_iter()
+            .filter(|invoice| invoice.payjoin.is_some() && !invoice.is_expired(now))
+            .filter(|invoice| !invoice.status.is_settled())
+            .map(|invoice| {
+                self.bdk
+                    .peek_address(KeychainKind::External, invoice.index)
+                    .address
+                    .script_pubkey()
+            })
+            .collect();
+        let receiver_output = original
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|output| invoice_scripts.contains(&output.script_pubkey))
+            .ok_or_else(|| {
+                PayjoinError::OriginalRejected("Original doesn't pay a payjoin invoice".to_string())
+            })?;
+
+        let sender_type = original
+            .inputs
+            .first()
+            .and_then(|input| input.witness_utxo.as_ref())
+            .map(|utxo| payjoin::script_type(&utxo.script_pubkey));
+        let coins: Vec<_> = self
+            .bdk
+            .list_unspent()
+            .filter(|utxo| matches!(utxo.chain_position, BdkChainPosition::Confirmed { .. }))
+            .filter(|utxo| sender_type == Some(payjoin::script_type(&utxo.txout.script_pubkey)))
+            .collect();
+        if coins.is_empty() {
+            return Err(PayjoinError::Unavailable(
+                "No confirmed coin to contribute".to_string(),
+            ));
+        }
+
+        let mut rng = rand::rng();
+        let coin = match coins.iter().find(|coin| Some(coin.outpoint) == reuse) {
+            Some(coin) => coin.clone(),
+            None => coins[rng.random_range(0..coins.len())].clone(),
+        };
+        let outpoint = coin.outpoint;
+        let input = self
+            .bdk
+            .get_psbt_input(coin, None, false)
+            .map_err(|e| PayjoinError::Unavailable(e.to_string()))?;
+        let contribution = Contribution {
+            outpoint,
+            input,
+            position: rng.random_range(0..=original.inputs.len()),
+        };
+
+        let mut proposal = payjoin::contribute(original, params, receiver_output, contribution)?;
+        self.sign_psbt(&mut proposal)
+            .map_err(|e| PayjoinError::Unavailable(e.to_string()))?;
+        payjoin::finish_proposal(&mut proposal, original)?;
+        Ok((proposal, outpoint))
+    }
+
     pub fn get_all_addresses(&self) -> Result<Vec<AddressInfo>> {
         let mut addresses = Vec::new();
 
@@ -473,11 +1565,114 @@ impl Wallet {
     pub fn name(&self) -> &str {
         &self.metadata.name
     }
+
+    /// Rename the wallet, names must be unique since wallets are selected by name
+    pub fn rename(&mut self, new_name: &str) -> Result<()> {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return Err(WalletError::Generic(
+                "Wallet name can't be empty".to_string(),
+            ));
+        }
+
+        let database = Database::global()?;
+        let taken = database
+            .wallets
+            .get_all(None)?
+            .iter()
+            .any(|w| w.id != self.id && w.name == new_name);
+        if taken {
+            return Err(WalletError::Generic(format!(
+                "A wallet named {new_name} already exists"
+            )));
+        }
+
+        self.metadata.name = new_name.to_string();
+        database.wallets.update_wallet_metadata(&self.metadata)?;
+        Ok(())
+    }
+
+    /// Hide the wallet from the wallet list, or bring it back
+    pub fn set_archived(&mut self, archived: bool) -> Result<()> {
+        self.metadata.archived = archived;
+
+        let database = Database::global()?;
+        database.wallets.update_wallet_metadata(&self.metadata)?;
+        if archived && database.global_config.selected_wallet()?.as_ref() == Some(&self.id) {
+            database.global_config.clear_selected_wallet()?;
+        }
+        Ok(())
+    }
+
+    /// Token that must be passed to `delete` while the wallet holds funds, or might
+    /// because it hasn't synced recently. It changes with the balance, so a stale
+    /// token can't be reused.
+    pub fn delete_confirmation_token(&self) -> Option<String> {
+        let total = self.bdk.balance().total();
+        if total == bitcoin::Amount::ZERO && self.recently_synced() {
+            return None;
+        }
+
+        let commitment = format!("{}:{}", self.id, total.to_sat());
+        let hash = sha256::Hash::hash(commitment.as_bytes());
+        Some(hash.to_string()[..8].to_string())
+    }
+
+    /// Delete the wallet's metadata (including its secret), labels, pending PSBTs
+    /// and chain data
+    pub fn delete(self, confirmation: Option<&str>) -> Result<()> {
+        if let Some(token) = self.delete_confirmation_token() {
+            if confirmation != Some(token.as_str()) {
+                let holds = if self.recently_synced() {
+                    format!("holds {} sats", self.bdk.balance().total().to_sat())
+                } else {
+                    "hasn't synced recently and may hold funds".to_string()
+                };
+                return Err(WalletError::ConfirmationRequired(format!(
+                    "Wallet {} {holds}, confirm with token {token}",
+                    self.name(),
+                )));
+            }
+        }
+
+        let database = Database::global()?;
+        database.labels.remove_all(&self.id)?;
+        database.pending_psbts.remove_all(&self.id)?;
+        database.invoices.remove_all(&self.id)?;
+        if database.global_config.selected_wallet()?.as_ref() == Some(&self.id) {
+            database.global_config.clear_selected_wallet()?;
+        }
+        database.wallets.delete(&self.id)?;
+
+        match std::fs::remove_file(sqlite_data_path(&self.id)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(WalletError::Generic(format!(
+                    "Unable to remove wallet database: {e}"
+                )))
+            }
+        }
+
+        // The lock file goes last, once nothing is left for it to guard
+        let lock_file = self.store_lock.path().to_path_buf();
+        drop(self);
+        let _ = std::fs::remove_file(lock_file);
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::node::client::esplora::scan_done;
+    use crate::wallet::invoice::InvoiceStatus;
+    use bdk_wallet::chain::{BlockId, ConfirmationBlockTime, TxUpdate};
+    use bdk_wallet::Update;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{BlockHash, OutPoint, ScriptBuf, Transaction, TxIn, TxOut, Txid};
+    use std::sync::Arc;
 
     #[test]
     fn test_wallet_creation() {
@@ -528,6 +1723,43 @@ mod tests {
         assert!(!first_addr.as_str().is_empty());
     }
 
+    #[test]
+    fn test_sign_message() {
+        let (mut wallet, _) =
+            Wallet::new_random("Message Test".to_string(), Network::Regtest).unwrap();
+        wallet.get_new_address().unwrap();
+        let address = wallet.get_new_address().unwrap();
+
+        let signature = wallet.sign_message(&address, "Proof of control").unwrap();
+        assert_eq!(
+            lumo_types::verify_message(&address, "Proof of control", &signature),
+            Ok(true)
+        );
+
+        let (other, _) = Wallet::new_random("Other Wallet".to_string(), Network::Regtest).unwrap();
+        assert!(matches!(
+            other.sign_message(&address, "Proof of control"),
+            Err(WalletError::Message(_))
+        ));
+    }
+
+    #[test]
+    fn test_reserves_proof_needs_coins() {
+        let (wallet, _) =
+            Wallet::new_random("Reserves Test".to_string(), Network::Regtest).unwrap();
+
+        assert!(matches!(
+            wallet.create_reserves_proof("Audit", None),
+            Err(WalletError::Reserves(_))
+        ));
+
+        let unknown = bitcoin::OutPoint::new(bitcoin::Txid::all_zeros(), 0);
+        assert!(matches!(
+            wallet.create_reserves_proof("Audit", Some(&[unknown])),
+            Err(WalletError::Reserves(_))
+        ));
+    }
+
     #[test]
     fn test_wallet_basic_properties() {
         let (wallet, _) =
@@ -542,6 +1774,173 @@ mod tests {
         assert_eq!(transactions.len(), 0);
     }
 
+    #[test]
+    fn test_wallet_rename_archive_delete() {
+        let (mut wallet, _) =
+            Wallet::new_random("Lifecycle Test".to_string(), Network::Regtest).unwrap();
+        let wallet_id = wallet.id.clone();
+        wallet
+            .set_label(LabelType::Addr, "bcrt1qexample", "Donations")
+            .unwrap();
+
+        let new_name = format!("Renamed {}", wallet_id);
+        wallet.rename(&new_name).unwrap();
+        assert!(wallet.rename("  ").is_err());
+
+        wallet.set_archived(true).unwrap();
+        let listed = |wallets: Vec<WalletMetadata>| wallets.iter().any(|w| w.id == wallet_id);
+        assert!(!listed(Wallet::list_all(None).unwrap()));
+        assert!(listed(Wallet::list_archived(None).unwrap()));
+
+        // A wallet that never synced might hold funds, so it needs a token
+        let mut wallet = Wallet::try_load_persisted(&wallet_id, Network::Regtest).unwrap();
+        assert_eq!(wallet.name(), new_name);
+        assert!(wallet.metadata.archived);
+        assert!(!wallet.recently_synced());
+        assert!(wallet.delete_confirmation_token().is_some());
+
+        // Empty wallets that just synced don't
+        wallet.mark_synced().unwrap();
+        assert_eq!(wallet.delete_confirmation_token(), None);
+        wallet.delete(None).unwrap();
+
+        let database = Database::global().unwrap();
+        assert!(database.wallets.get(&wallet_id).unwrap().is_none());
+        assert!(database.labels.get_all(&wallet_id).unwrap().is_empty());
+        assert!(!sqlite_data_path(&wallet_id).exists());
+    }
+
+    #[test]
+    fn test_invoices_past_the_stop_gap_are_scanned() {
+        let (mut wallet, _) =
+            Wallet::new_random("Invoice Gap Test".to_string(), Network::Regtest).unwrap();
+        let settings = ScanSettings {
+            stop_gap: 3,
+            ..wallet.metadata.scan
+        };
+        wallet.set_scan_settings(settings).unwrap();
+
+        let invoices: Vec<Invoice> = (0..settings.stop_gap + 2)
+            .map(|_| {
+                wallet
+                    .create_invoice(InvoiceRequest::new(LumoAmount::from_sat(10_000)))
+                    .unwrap()
+            })
+            .collect();
+
+        // None are paid, so the last ones sit past the stop gap
+        let last = invoices.last().unwrap();
+        assert!(last.index >= settings.stop_gap);
+        let revealed = Wallet::revealed_indices(&wallet.bdk);
+        assert_eq!(revealed.get(&KeychainKind::External), Some(&last.index));
+
+        // With every script unused, the scan looks up all of the invoices' scripts
+        let revealed_end = last.index + 1;
+        let stop_gap = settings.stop_gap as usize;
+        let mut request = wallet.bdk.start_full_scan().build();
+        let scanned: Vec<ScriptBuf> = request
+            .iter_spks(KeychainKind::External)
+            .take_while(|(index, _)| !scan_done(*index as usize, stop_gap, *index, revealed_end))
+            .map(|(_, spk)| spk)
+            .collect();
+        let scripts: Vec<ScriptBuf> = invoices
+            .iter()
+            .map(|invoice| {
+                wallet
+                    .bdk
+                    .peek_address(KeychainKind::External, invoice.index)
+                    .script_pubkey()
+            })
+            .collect();
+        assert!(scripts.iter().all(|script| scanned.contains(script)));
+
+        // A scan that finds the last invoice paid in the next block settles it
+        let payment = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: bitcoin::Amount::from_sat(last.amount.as_sat()),
+                script_pubkey: scripts.last().unwrap().clone(),
+            }],
+        };
+        let tip = wallet.bdk.latest_checkpoint();
+        let block_id = BlockId {
+            height: tip.height() + 1,
+            hash: BlockHash::all_zeros(),
+        };
+        let mut tx_update = TxUpdate::default();
+        tx_update.anchors.insert((
+            ConfirmationBlockTime {
+                block_id,
+                confirmation_time: 1_700_000_000,
+            },
+            payment.compute_txid(),
+        ));
+        tx_update.txs.push(Arc::new(payment));
+        wallet
+            .bdk
+            .apply_update(Update {
+                last_active_indices: BTreeMap::from([(KeychainKind::External, last.index)]),
+                tx_update,
+                chain: Some(tip.push(block_id).unwrap()),
+            })
+            .unwrap();
+
+        let updated = wallet.update_invoices().unwrap();
+        let status = |id: &str| {
+            updated
+                .iter()
+                .find(|invoice| invoice.id == id)
+                .map(|invoice| invoice.status)
+        };
+        assert_eq!(status(&last.id), Some(InvoiceStatus::Paid));
+        assert_eq!(status(&invoices[0].id), Some(InvoiceStatus::Unpaid));
+    }
+
+    #[test]
+    fn test_multisig_wallet() {
+        // BIP48 account keys of "legal winner thank year ..." and "letter advice cage ..."
+        let cosigners = [
+            "[b8688df1/48'/1'/0'/2']tpubDEfobrrtptRTbKf4gysDhoabneABDTAcdj3Vbn4XwPsLE2pmqpizSPRG6zHsbAMuiSgWmWPsYCLHTKTPpyrGJ5rAoTpKoQNZcxodiPf2tSJ".to_string(),
+            "[28645006/48'/1'/0'/2']tpubDEwqCvJxKwKWX9xvRe48uofWJn1Y89Jn8UeH1Efrjb1UEVjUDy3URYTiqWaVCW7WdvHrL8XrSihHEhTwv5H3VDJoakjuCHiAnr6xcF2Xm4s".to_string(),
+        ];
+
+        let multisig = match Wallet::new_multisig(
+            "Multisig Test".to_string(),
+            Network::Regtest,
+            2,
+            &cosigners,
+            None,
+        ) {
+            Ok(wallet) => wallet,
+            Err(WalletError::WalletAlreadyExists(wallet_id)) => Wallet::try_load_persisted(
+                &WalletId::from_string(&wallet_id).unwrap(),
+                Network::Regtest,
+            )
+            .unwrap(),
+            Err(e) => panic!("Failed to create/load wallet: {}", e),
+        };
+
+        assert_eq!(multisig.metadata.wallet_type, WalletType::Multisig);
+        let config = multisig.metadata.multisig.clone().unwrap();
+        assert_eq!(config.threshold, 2);
+        assert_eq!(config.total(), 2);
+        assert!(config
+            .descriptor(KeychainKind::External)
+            .starts_with("wsh(sortedmulti(2,"));
+
+        // wsh(sortedmulti(2,...)) of both keys at /0/0
+        assert_eq!(
+            multisig.first_address().unwrap().as_str(),
+            "bcrt1qztt683a3smcpmy063gdl9g5ayelpxtxhqzarcn3c20np75ncz9dswehuxp"
+        );
+        assert!(multisig.pending_psbts().unwrap().is_empty());
+    }
+
     #[test]
     fn test_wallet_loading_errors() {
         let fake_id = WalletId::new();
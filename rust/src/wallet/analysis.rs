@@ -0,0 +1,431 @@
+use std::collections::HashSet;
+
+use bdk_wallet::miniscript::ForEachKey;
+use bdk_wallet::{KeychainKind, Wallet as BdkWallet};
+use bitcoin::psbt::Psbt;
+use bitcoin::{
+    Amount as BdkAmount, Network as BitcoinNetwork, ScriptBuf, Transaction, TxOut, Weight,
+};
+
+use crate::wallet::error::{Result, WalletError};
+use lumo_types::{Amount, FeeRate};
+
+/// Fee rates above this are flagged as high
+pub const HIGH_FEE_RATE_SAT_VB: f32 = 250.0;
+
+/// Fees above this share of the amount being sent are flagged as high
+pub const HIGH_FEE_PERCENT: f64 = 10.0;
+
+/// Witness weight assumed for inputs we can't estimate (P2WPKH)
+const UNKNOWN_INPUT_SATISFACTION_WEIGHT: Weight = Weight::from_wu(108);
+
+/// Something about a transaction worth a second look before signing
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnalysisWarning {
+    HighFee { fee: Amount, percent: f64 },
+    HighFeeRate(FeeRate),
+    UnknownInput { index: usize },
+    MissingInputValue { index: usize },
+    DustOutput { index: usize, amount: Amount },
+    AddressReuse { index: usize, address: String },
+}
+
+impl std::fmt::Display for AnalysisWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HighFee { fee, percent } => {
+                write!(
+                    f,
+                    "High fee: {} sats is {percent:.1}% of the amount sent",
+                    fee.as_sat()
+                )
+            }
+            Self::HighFeeRate(fee_rate) => write!(f, "High fee rate: {fee_rate}"),
+            Self::UnknownInput { index } => {
+                write!(f, "Input {index} does not belong to this wallet")
+            }
+            Self::MissingInputValue { index } => {
+                write!(f, "Input {index} has no known value, fee can't be verified")
+            }
+            Self::DustOutput { index, amount } => {
+                write!(f, "Output {index} is dust ({} sats)", amount.as_sat())
+            }
+            Self::AddressReuse { index, address } => {
+                write!(f, "Output {index} reuses address {address}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InputAnalysis {
+    pub previous_output: String,
+    pub amount: Option<Amount>,
+    pub address: Option<String>,
+    pub is_mine: bool,
+    pub derivation_paths: Vec<String>,
+    pub signatures: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputAnalysis {
+    pub index: usize,
+    pub amount: Amount,
+    pub address: Option<String>,
+    pub is_mine: bool,
+    pub is_change: bool,
+    pub derivation_paths: Vec<String>,
+}
+
+/// Decoded view of a PSBT or raw transaction from this wallet's perspective
+#[derive(Debug, Clone)]
+pub struct TransactionAnalysis {
+    pub txid: String,
+    pub inputs: Vec<InputAnalysis>,
+    pub outputs: Vec<OutputAnalysis>,
+    pub fee: Option<Amount>,
+    pub fee_rate: Option<FeeRate>,
+    /// Virtual size, estimated for PSBTs that aren't finalized yet
+    pub vsize: u64,
+    pub vsize_is_estimate: bool,
+    pub is_rbf: bool,
+    pub lock_time: Option<String>,
+    pub warnings: Vec<AnalysisWarning>,
+}
+
+impl TransactionAnalysis {
+    /// Analyze a PSBT, using its UTXO fields for input values
+    pub fn from_psbt(wallet: &BdkWallet, psbt: &Psbt) -> Result<Self> {
+        let tx = &psbt.unsigned_tx;
+
+        let prevouts: Vec<Option<TxOut>> = psbt
+            .inputs
+            .iter()
+            .zip(&tx.input)
+            .map(|(input, txin)| {
+                input
+                    .witness_utxo
+                    .clone()
+                    .or_else(|| {
+                        input.non_witness_utxo.as_ref().and_then(|prev_tx| {
+                            prev_tx
+                                .output
+                                .get(txin.previous_output.vout as usize)
+                                .cloned()
+                        })
+                    })
+                    .or_else(|| wallet.tx_graph().get_txout(txin.previous_output).cloned())
+            })
+            .collect();
+
+        let signatures: Vec<usize> = psbt
+            .inputs
+            .iter()
+            .map(|input| {
+                if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+                    1
+                } else {
+                    input.partial_sigs.len() + usize::from(input.tap_key_sig.is_some())
+                }
+            })
+            .collect();
+
+        let finalized = psbt
+            .inputs
+            .iter()
+            .all(|input| input.final_script_witness.is_some() || input.final_script_sig.is_some());
+
+        let weight = if finalized {
+            psbt.clone().extract_tx_unchecked_fee_rate().weight()
+        } else {
+            estimate_weight(wallet, tx, &prevouts)
+        };
+
+        Ok(Self::analyze(
+            wallet, tx, prevouts, signatures, weight, !finalized,
+        ))
+    }
+
+    /// Analyze a hex encoded network transaction, using wallet history for input values
+    pub fn from_raw_tx(wallet: &BdkWallet, raw_tx: &str) -> Result<Self> {
+        let bytes = hex::decode(raw_tx.trim())
+            .map_err(|e| WalletError::Generic(format!("Invalid transaction hex: {e}")))?;
+        let tx: Transaction = bitcoin::consensus::deserialize(&bytes)
+            .map_err(|e| WalletError::Generic(format!("Invalid transaction: {e}")))?;
+
+        let prevouts: Vec<Option<TxOut>> = tx
+            .input
+            .iter()
+            .map(|txin| wallet.tx_graph().get_txout(txin.previous_output).cloned())
+            .collect();
+
+        let signatures: Vec<usize> = tx
+            .input
+            .iter()
+            .map(|txin| usize::from(!txin.witness.is_empty() || !txin.script_sig.is_empty()))
+            .collect();
+
+        let weight = tx.weight();
+        Ok(Self::analyze(
+            wallet, &tx, prevouts, signatures, weight, false,
+        ))
+    }
+
+    fn analyze(
+        wallet: &BdkWallet,
+        tx: &Transaction,
+        prevouts: Vec<Option<TxOut>>,
+        signatures: Vec<usize>,
+        weight: Weight,
+        vsize_is_estimate: bool,
+    ) -> Self {
+        let network = wallet.network();
+        let txid = tx.compute_txid();
+        let mut warnings = Vec::new();
+
+        let inputs: Vec<InputAnalysis> = tx
+            .input
+            .iter()
+            .zip(prevouts.iter())
+            .zip(signatures)
+            .enumerate()
+            .map(|(index, ((txin, prevout), signatures))| {
+                let spk = prevout.as_ref().map(|txout| txout.script_pubkey.clone());
+                let is_mine = spk.as_ref().is_some_and(|spk| wallet.is_mine(spk.clone()));
+
+                if prevout.is_none() {
+                    warnings.push(AnalysisWarning::MissingInputValue { index });
+                }
+                if !is_mine {
+                    warnings.push(AnalysisWarning::UnknownInput { index });
+                }
+
+                InputAnalysis {
+                    previous_output: txin.previous_output.to_string(),
+                    amount: prevout.as_ref().map(|txout| Amount::from(txout.value)),
+                    address: spk.as_ref().and_then(|spk| script_address(spk, network)),
+                    is_mine,
+                    derivation_paths: spk
+                        .as_ref()
+                        .map(|spk| derivation_paths(wallet, spk))
+                        .unwrap_or_default(),
+                    signatures,
+                }
+            })
+            .collect();
+
+        // Scripts this wallet has already been paid to in other transactions
+        let used_scripts: HashSet<ScriptBuf> = wallet
+            .transactions()
+            .filter(|canonical_tx| canonical_tx.tx_node.txid != txid)
+            .flat_map(|canonical_tx| {
+                canonical_tx
+                    .tx_node
+                    .tx
+                    .output
+                    .iter()
+                    .map(|txout| txout.script_pubkey.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let outputs: Vec<OutputAnalysis> = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(index, txout)| {
+                let spk = &txout.script_pubkey;
+                let keychain = wallet.derivation_of_spk(spk.clone()).map(|(k, _)| k);
+                let address = script_address(spk, network);
+
+                if !spk.is_op_return() && txout.value < spk.minimal_non_dust() {
+                    warnings.push(AnalysisWarning::DustOutput {
+                        index,
+                        amount: txout.value.into(),
+                    });
+                }
+                if used_scripts.contains(spk) {
+                    if let Some(address) = &address {
+                        warnings.push(AnalysisWarning::AddressReuse {
+                            index,
+                            address: address.clone(),
+                        });
+                    }
+                }
+
+                OutputAnalysis {
+                    index,
+                    amount: txout.value.into(),
+                    address,
+                    is_mine: keychain.is_some(),
+                    is_change: keychain == Some(KeychainKind::Internal),
+                    derivation_paths: derivation_paths(wallet, spk),
+                }
+            })
+            .collect();
+
+        let input_total: Option<BdkAmount> = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().map(|txout| txout.value))
+            .sum();
+        let output_total: BdkAmount = tx.output.iter().map(|txout| txout.value).sum();
+
+        let fee = input_total.and_then(|total| total.checked_sub(output_total));
+        let vsize = weight.to_vbytes_ceil();
+        let fee_rate = fee.map(|fee| FeeRate::from_sat_per_vb(fee.to_sat() as f32 / vsize as f32));
+
+        if let Some(fee_rate) = fee_rate {
+            if fee_rate.as_sat_per_vb() > HIGH_FEE_RATE_SAT_VB {
+                warnings.push(AnalysisWarning::HighFeeRate(fee_rate));
+            }
+        }
+
+        // Compare the fee against what actually leaves the wallet
+        let sent: BdkAmount = outputs
+            .iter()
+            .filter(|output| !output.is_mine)
+            .map(|output| output.amount.to_bdk_amount())
+            .sum();
+        if let Some(fee) = fee {
+            let base = if sent > BdkAmount::ZERO {
+                sent
+            } else {
+                output_total
+            };
+            if base > BdkAmount::ZERO {
+                let percent = fee.to_sat() as f64 / base.to_sat() as f64 * 100.0;
+                if percent > HIGH_FEE_PERCENT {
+                    warnings.push(AnalysisWarning::HighFee {
+                        fee: fee.into(),
+                        percent,
+                    });
+                }
+            }
+        }
+
+        Self {
+            txid: txid.to_string(),
+            inputs,
+            outputs,
+            fee: fee.map(Amount::from),
+            fee_rate,
+            vsize,
+            vsize_is_estimate,
+            is_rbf: tx.is_explicitly_rbf(),
+            lock_time: tx.is_lock_time_enabled().then(|| tx.lock_time.to_string()),
+            warnings,
+        }
+    }
+}
+
+/// Unsigned weight plus the worst case witness for each input
+fn estimate_weight(wallet: &BdkWallet, tx: &Transaction, prevouts: &[Option<TxOut>]) -> Weight {
+    let satisfaction: Weight = prevouts
+        .iter()
+        .map(|prevout| {
+            prevout
+                .as_ref()
+                .and_then(|txout| wallet.derivation_of_spk(txout.script_pubkey.clone()))
+                .and_then(|(keychain, _)| {
+                    wallet
+                        .public_descriptor(keychain)
+                        .max_weight_to_satisfy()
+                        .ok()
+                })
+                .unwrap_or(UNKNOWN_INPUT_SATISFACTION_WEIGHT)
+        })
+        .sum();
+
+    tx.weight() + satisfaction
+}
+
+fn script_address(spk: &ScriptBuf, network: BitcoinNetwork) -> Option<String> {
+    bitcoin::Address::from_script(spk, network)
+        .ok()
+        .map(|address| address.to_string())
+}
+
+/// Full key origins (`[fingerprint]m/...`) of our keys for a script
+fn derivation_paths(wallet: &BdkWallet, spk: &ScriptBuf) -> Vec<String> {
+    let Some((keychain, index)) = wallet.derivation_of_spk(spk.clone()) else {
+        return Vec::new();
+    };
+    let Ok(descriptor) = wallet
+        .public_descriptor(keychain)
+        .at_derivation_index(index)
+    else {
+        return Vec::new();
+    };
+
+    let mut paths = Vec::new();
+    descriptor.for_each_key(|key| {
+        let key = key.as_descriptor_public_key();
+        if let Some(path) = key.full_derivation_path() {
+            let steps: Vec<String> = (&path).into_iter().map(ToString::to_string).collect();
+            paths.push(format!(
+                "[{}]m/{}",
+                key.master_fingerprint(),
+                steps.join("/")
+            ));
+        }
+        true
+    });
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Wallet;
+    use bitcoin::{absolute, transaction, OutPoint, TxIn};
+    use lumo_types::Network;
+
+    #[test]
+    fn test_analyze_raw_tx() {
+        let (mut wallet, _) =
+            Wallet::new_random("Analysis Test".to_string(), Network::Regtest).unwrap();
+        let address = wallet.get_new_address().unwrap();
+
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: BdkAmount::from_sat(50_000),
+                    script_pubkey: address.to_bdk_address().script_pubkey(),
+                },
+                TxOut {
+                    value: BdkAmount::from_sat(100),
+                    script_pubkey: address.to_bdk_address().script_pubkey(),
+                },
+            ],
+        };
+
+        let analysis = wallet
+            .analyze_raw_tx(&bitcoin::consensus::encode::serialize_hex(&tx))
+            .unwrap();
+
+        assert_eq!(analysis.outputs.len(), 2);
+        assert!(analysis.outputs[0].is_mine);
+        assert!(!analysis.outputs[0].is_change);
+        assert_eq!(analysis.outputs[0].derivation_paths.len(), 1);
+        assert!(analysis.outputs[0].derivation_paths[0].contains("m/84'/1'/0'/0/"));
+
+        // Input isn't ours and its value is unknown, so there is no fee
+        assert_eq!(analysis.fee, None);
+        assert!(analysis
+            .warnings
+            .contains(&AnalysisWarning::UnknownInput { index: 0 }));
+        assert!(analysis
+            .warnings
+            .contains(&AnalysisWarning::MissingInputValue { index: 0 }));
+        assert!(analysis
+            .warnings
+            .iter()
+            .any(|w| matches!(w, AnalysisWarning::DustOutput { index: 1, .. })));
+    }
+}
@@ -0,0 +1,366 @@
+//! Encrypted backups of one or all wallets
+//!
+//! A backup holds everything needed to bring a wallet back on another machine: its
+//! metadata (including the stored secret), descriptors, labels, the node
+//! configuration and, optionally, the BDK sqlite file so the restored wallet
+//! doesn't need a rescan.
+//!
+//! File layout: `magic | version | iterations | salt | iv | ciphertext | mac`. The
+//! AES-256-CTR and HMAC-SHA256 keys come from PBKDF2-HMAC-SHA512 of the passphrase,
+//! and the MAC covers the header and the ciphertext.
+
+use base64::{engine::general_purpose, Engine as _};
+use bdk_wallet::{KeychainKind, Wallet as BdkWallet};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::bdk_store::{sqlite_data_path, BDKStore};
+use crate::database::Database;
+use crate::wallet::encryption::{aes256_ctr, hmac_sha256, pbkdf2_hmac_sha512};
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::{Wallet, WalletId, WalletMetadata};
+use lumo_types::{Label, Network};
+
+/// Current backup format version, bumped whenever `Backup` changes incompatibly
+pub const BACKUP_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 8] = b"LUMOBAK\0";
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN + IV_LEN;
+
+/// Everything stored for a single wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletBackup {
+    /// Metadata as stored, the mnemonic or private descriptors stay in their stored form
+    pub metadata: WalletMetadata,
+    pub external_descriptor: String,
+    pub internal_descriptor: String,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    /// Base64 of the BDK sqlite file, restores chain data without a rescan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bdk_sqlite: Option<String>,
+}
+
+/// A wallet brought back from a backup
+#[derive(Debug, Clone)]
+pub struct RestoredWallet {
+    pub metadata: WalletMetadata,
+    /// The backup had no usable chain data, the wallet must be synced from scratch
+    pub needs_rescan: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u8,
+    pub created_at: String,
+    pub wallets: Vec<WalletBackup>,
+    #[serde(default)]
+    pub selected_wallet: Option<WalletId>,
+    /// Custom node urls, networks using the default node are left out
+    #[serde(default)]
+    pub node_urls: Vec<(Network, String)>,
+}
+
+impl WalletBackup {
+    pub fn new(wallet: &Wallet, labels: Vec<Label>, include_chain_data: bool) -> Result<Self> {
+        let bdk_sqlite = if include_chain_data {
+            let data = std::fs::read(sqlite_data_path(&wallet.id))
+                .map_err(|e| WalletError::Backup(format!("Unable to read wallet database: {e}")))?;
+            Some(general_purpose::STANDARD.encode(data))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            metadata: wallet.metadata.clone(),
+            external_descriptor: wallet.public_descriptor(KeychainKind::External),
+            internal_descriptor: wallet.public_descriptor(KeychainKind::Internal),
+            labels,
+            bdk_sqlite,
+        })
+    }
+
+    /// Recreate the wallet's BDK store, metadata and labels
+    fn restore(&self) -> Result<RestoredWallet> {
+        let id = &self.metadata.id;
+        let network = self.metadata.network;
+        let _store_lock = Wallet::lock_store(id)?;
+
+        let restored_chain_data = match &self.bdk_sqlite {
+            Some(data) => self.restore_sqlite(data)?,
+            None => false,
+        };
+
+        if !restored_chain_data {
+            Wallet::create_bdk_wallet_from_descriptors(
+                self.external_descriptor.clone(),
+                self.internal_descriptor.clone(),
+                network,
+                id,
+            )?;
+        }
+
+        let database = Database::global()?;
+        database
+            .wallets
+            .save_new_wallet_metadata(self.metadata.clone())?;
+        database.labels.save(id, &self.labels)?;
+
+        Ok(RestoredWallet {
+            metadata: self.metadata.clone(),
+            needs_rescan: !restored_chain_data,
+        })
+    }
+
+    /// Write the sqlite file back, keeping it only if it loads with our descriptors
+    fn restore_sqlite(&self, data: &str) -> Result<bool> {
+        let path = sqlite_data_path(&self.metadata.id);
+        let bytes = general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| WalletError::Backup(format!("Invalid wallet database encoding: {e}")))?;
+
+        std::fs::write(&path, bytes)
+            .map_err(|e| WalletError::Backup(format!("Unable to write wallet database: {e}")))?;
+
+        let loaded = {
+            let mut store = BDKStore::try_new(&self.metadata.id, self.metadata.network)?;
+            BdkWallet::load()
+                .descriptor(
+                    KeychainKind::External,
+                    Some(self.external_descriptor.clone()),
+                )
+                .descriptor(
+                    KeychainKind::Internal,
+                    Some(self.internal_descriptor.clone()),
+                )
+                .check_network(self.metadata.network.to_bitcoin_network())
+                .load_wallet(&mut store.conn)
+        };
+
+        match loaded {
+            Ok(Some(_)) => Ok(true),
+            _ => {
+                tracing::warn!(
+                    "Chain data for wallet {} could not be restored, a rescan is needed",
+                    self.metadata.id
+                );
+                let _ = std::fs::remove_file(&path);
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl Backup {
+    pub fn new(
+        wallets: Vec<WalletBackup>,
+        selected_wallet: Option<WalletId>,
+        node_urls: Vec<(Network, String)>,
+    ) -> Self {
+        Self {
+            version: BACKUP_VERSION,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            wallets,
+            selected_wallet,
+            node_urls,
+        }
+    }
+
+    /// Serialize and encrypt the backup with a passphrase
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>> {
+        self.encrypt_with_iterations(passphrase, PBKDF2_ITERATIONS)
+    }
+
+    fn encrypt_with_iterations(&self, passphrase: &str, iterations: u32) -> Result<Vec<u8>> {
+        if passphrase.is_empty() {
+            return Err(WalletError::Backup(
+                "Backup passphrase can't be empty".to_string(),
+            ));
+        }
+
+        let mut data = serde_json::to_vec(self)
+            .map_err(|e| WalletError::Backup(format!("Unable to serialize backup: {e}")))?;
+
+        let salt = rand::rng().random::<[u8; SALT_LEN]>();
+        let iv = rand::rng().random::<[u8; IV_LEN]>();
+        let (encryption_key, mac_key) = derive_keys(passphrase, &salt, iterations);
+
+        let mut output = Vec::with_capacity(HEADER_LEN + data.len() + MAC_LEN);
+        output.extend_from_slice(MAGIC);
+        output.push(BACKUP_VERSION);
+        output.extend_from_slice(&iterations.to_be_bytes());
+        output.extend_from_slice(&salt);
+        output.extend_from_slice(&iv);
+
+        aes256_ctr(&encryption_key, &iv, &mut data);
+        output.extend_from_slice(&data);
+
+        let mac = hmac_sha256(&mac_key, &output);
+        output.extend_from_slice(&mac);
+        Ok(output)
+    }
+
+    /// Check and decrypt a backup file
+    pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Self> {
+        if data.len() < HEADER_LEN + MAC_LEN || !data.starts_with(MAGIC) {
+            return Err(WalletError::Backup("Not a lumo backup file".to_string()));
+        }
+
+        let version = data[MAGIC.len()];
+        if version > BACKUP_VERSION {
+            return Err(WalletError::Backup(format!(
+                "Backup version {version} is newer than this version of lumo supports ({BACKUP_VERSION})"
+            )));
+        }
+
+        let mut offset = MAGIC.len() + 1;
+        let iterations = u32::from_be_bytes(
+            data[offset..offset + 4]
+                .try_into()
+                .expect("slice is 4 bytes"),
+        );
+        offset += 4;
+        let salt = &data[offset..offset + SALT_LEN];
+        offset += SALT_LEN;
+        let iv: [u8; IV_LEN] = data[offset..offset + IV_LEN]
+            .try_into()
+            .expect("slice is 16 bytes");
+
+        let (signed, mac) = data.split_at(data.len() - MAC_LEN);
+        let (encryption_key, mac_key) = derive_keys(passphrase, salt, iterations);
+        if hmac_sha256(&mac_key, signed) != mac {
+            return Err(WalletError::Backup(
+                "Wrong passphrase or corrupted backup".to_string(),
+            ));
+        }
+
+        let mut plaintext = signed[HEADER_LEN..].to_vec();
+        aes256_ctr(&encryption_key, &iv, &mut plaintext);
+
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| WalletError::Backup(format!("Invalid backup contents: {e}")))
+    }
+
+    /// Restore every wallet in the backup, along with node urls and the selected wallet
+    ///
+    /// Nothing is written if any of the wallets already exists.
+    pub fn restore(&self) -> Result<Vec<RestoredWallet>> {
+        let database = Database::global()?;
+        for wallet in &self.wallets {
+            if database.wallets.get(&wallet.metadata.id)?.is_some() {
+                return Err(WalletError::WalletAlreadyExists(
+                    wallet.metadata.id.to_string(),
+                ));
+            }
+        }
+
+        let restored = self
+            .wallets
+            .iter()
+            .map(WalletBackup::restore)
+            .collect::<Result<Vec<_>>>()?;
+
+        for (network, url) in &self.node_urls {
+            database.global_config.set_node_url(*network, url)?;
+        }
+
+        // Keep the current selection when restoring into a data dir that's in use
+        if let Some(selected) = &self.selected_wallet {
+            let in_backup = self.wallets.iter().any(|w| &w.metadata.id == selected);
+            if in_backup && database.global_config.selected_wallet()?.is_none() {
+                database.global_config.select_wallet(selected)?;
+            }
+        }
+
+        Ok(restored)
+    }
+}
+
+/// AES and HMAC keys for a passphrase
+fn derive_keys(passphrase: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; 32]) {
+    let mut key_material = [0u8; 64];
+    pbkdf2_hmac_sha512(passphrase.as_bytes(), salt, iterations, &mut key_material);
+
+    let (encryption_key, mac_key) = key_material.split_at(32);
+    (
+        encryption_key.try_into().expect("32 bytes"),
+        mac_key.try_into().expect("32 bytes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lumo_types::LabelType;
+
+    fn test_backup() -> Backup {
+        let metadata = WalletMetadata::new("Savings".to_string(), Network::Regtest);
+        let wallet = WalletBackup {
+            metadata: metadata.clone(),
+            external_descriptor: "wpkh(tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)".to_string(),
+            internal_descriptor: "wpkh(tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/1/*)".to_string(),
+            labels: vec![Label::new(
+                LabelType::Tx,
+                "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd",
+                "Rent",
+            )],
+            bdk_sqlite: Some(general_purpose::STANDARD.encode(b"SQLite format 3\0")),
+        };
+
+        Backup::new(
+            vec![wallet],
+            Some(metadata.id),
+            vec![(Network::Regtest, "http://localhost:3002".to_string())],
+        )
+    }
+
+    #[test]
+    fn test_backup_roundtrip() {
+        let backup = test_backup();
+        let encrypted = backup.encrypt_with_iterations("hunter2", 10).unwrap();
+        assert!(encrypted.starts_with(MAGIC));
+
+        let decrypted = Backup::decrypt(&encrypted, "hunter2").unwrap();
+        assert_eq!(decrypted.version, BACKUP_VERSION);
+        assert_eq!(decrypted.selected_wallet, backup.selected_wallet);
+        assert_eq!(decrypted.node_urls, backup.node_urls);
+        assert_eq!(
+            decrypted.wallets[0].metadata.id,
+            backup.wallets[0].metadata.id
+        );
+        assert_eq!(decrypted.wallets[0].labels, backup.wallets[0].labels);
+        assert_eq!(
+            decrypted.wallets[0].bdk_sqlite,
+            backup.wallets[0].bdk_sqlite
+        );
+    }
+
+    #[test]
+    fn test_backup_rejects_wrong_passphrase_and_tampering() {
+        let mut encrypted = test_backup()
+            .encrypt_with_iterations("hunter2", 10)
+            .unwrap();
+        assert!(Backup::decrypt(&encrypted, "hunter3").is_err());
+
+        encrypted[HEADER_LEN] ^= 1;
+        assert!(Backup::decrypt(&encrypted, "hunter2").is_err());
+
+        assert!(Backup::decrypt(b"not a backup", "hunter2").is_err());
+        assert!(test_backup().encrypt_with_iterations("", 10).is_err());
+    }
+
+    #[test]
+    fn test_backup_rejects_newer_version() {
+        let mut encrypted = test_backup()
+            .encrypt_with_iterations("hunter2", 10)
+            .unwrap();
+        encrypted[MAGIC.len()] = BACKUP_VERSION + 1;
+
+        let error = Backup::decrypt(&encrypted, "hunter2").unwrap_err();
+        assert!(error.to_string().contains("newer"));
+    }
+}
@@ -11,4 +11,8 @@ impl Balance {
     pub fn spendable(&self) -> Amount {
         self.0.trusted_spendable().into()
     }
+
+    pub fn total(&self) -> Amount {
+        self.0.total().into()
+    }
 }
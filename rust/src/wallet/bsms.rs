@@ -0,0 +1,575 @@
+//! Bitcoin Secure Multisig Setup (BIP129)
+//!
+//! Round 1: the coordinator creates a session with one token per signer, and each
+//! signer answers with a signed key record. Round 2: the coordinator verifies the
+//! key records and hands out a descriptor record that signers check against their
+//! own key and the first receive address. Records are plain files so the whole flow
+//! works offline.
+//!
+//! With a token other than `00`, records are encrypted as described in BIP129:
+//! `key = PBKDF2-HMAC-SHA512("No SPOF", token, 2048)[..32]`,
+//! `mac = HMAC-SHA256(SHA256(key), token_hex || data)`, and the payload is
+//! `hex(mac || AES-256-CTR(key, iv = mac[..16], data))`.
+
+use std::str::FromStr;
+
+use bdk_wallet::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::sign_message::{signed_msg_hash, MessageSignature};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::wallet::encryption::{aes256_ctr, hmac_sha256, pbkdf2_hmac_sha512};
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::multisig::{Cosigner, LocalCosigner, MultisigConfig};
+use lumo_types::Network;
+
+pub const BSMS_VERSION: &str = "BSMS 1.0";
+
+/// Path restrictions for receive and change descriptors
+const PATH_RESTRICTIONS: &str = "/0/*,/1/*";
+
+/// Encryption level of a BSMS session, which determines the token size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EncryptionLevel {
+    NoEncryption,
+    Standard, // 64-bit token
+    Extended, // 128-bit token
+}
+
+impl FromStr for EncryptionLevel {
+    type Err = WalletError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::NoEncryption),
+            "standard" => Ok(Self::Standard),
+            "extended" => Ok(Self::Extended),
+            _ => Err(WalletError::Bsms(format!(
+                "Invalid encryption level: {s}. Valid options: none, standard, extended"
+            ))),
+        }
+    }
+}
+
+/// Session token shared between the coordinator and one signer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token(String);
+
+impl Token {
+    pub fn generate(level: EncryptionLevel) -> Self {
+        match level {
+            EncryptionLevel::NoEncryption => Self("00".to_string()),
+            EncryptionLevel::Standard => Self(hex::encode(rand::rng().random::<[u8; 8]>())),
+            EncryptionLevel::Extended => Self(hex::encode(rand::rng().random::<[u8; 16]>())),
+        }
+    }
+
+    pub fn from_hex(token: &str) -> Result<Self> {
+        let token = token.trim().to_lowercase();
+        let valid_length = matches!(token.len(), 2 | 16 | 32);
+        if !valid_length || hex::decode(&token).is_err() {
+            return Err(WalletError::Bsms(format!("Invalid BSMS token: {token}")));
+        }
+        if token.len() == 2 && token != "00" {
+            return Err(WalletError::Bsms(format!("Invalid BSMS token: {token}")));
+        }
+        Ok(Self(token))
+    }
+
+    pub fn as_hex(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.0 != "00"
+    }
+
+    fn encryption_key(&self) -> [u8; 32] {
+        let salt = hex::decode(&self.0).expect("validated token");
+        let mut key = [0u8; 32];
+        pbkdf2_hmac_sha512(b"No SPOF", &salt, 2048, &mut key);
+        key
+    }
+
+    fn mac(&self, key: &[u8; 32], data: &[u8]) -> [u8; 32] {
+        let hmac_key = sha256::Hash::hash(key).to_byte_array();
+        let mut message = self.0.as_bytes().to_vec();
+        message.extend_from_slice(data);
+        hmac_sha256(&hmac_key, &message)
+    }
+
+    /// Encrypt a record for this token, records for `00` are left as plain text
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        if !self.is_encrypted() {
+            return plaintext.to_string();
+        }
+
+        let key = self.encryption_key();
+        let mac = self.mac(&key, plaintext.as_bytes());
+        let iv: [u8; 16] = mac[..16].try_into().expect("mac is 32 bytes");
+
+        let mut data = plaintext.as_bytes().to_vec();
+        aes256_ctr(&key, &iv, &mut data);
+
+        let mut payload = mac.to_vec();
+        payload.extend_from_slice(&data);
+        hex::encode(payload)
+    }
+
+    /// Decrypt a record for this token and check its MAC
+    pub fn decrypt(&self, payload: &str) -> Result<String> {
+        if !self.is_encrypted() {
+            return Ok(payload.to_string());
+        }
+
+        let payload = hex::decode(payload.trim())
+            .map_err(|e| WalletError::Bsms(format!("Encrypted record is not hex: {e}")))?;
+        if payload.len() < 32 {
+            return Err(WalletError::Bsms(
+                "Encrypted record is too short".to_string(),
+            ));
+        }
+
+        let (mac, ciphertext) = payload.split_at(32);
+        let key = self.encryption_key();
+        let iv: [u8; 16] = mac[..16].try_into().expect("mac is 32 bytes");
+
+        let mut data = ciphertext.to_vec();
+        aes256_ctr(&key, &iv, &mut data);
+
+        if self.mac(&key, &data) != mac {
+            return Err(WalletError::Bsms(
+                "Record could not be decrypted with this token".to_string(),
+            ));
+        }
+
+        String::from_utf8(data)
+            .map_err(|e| WalletError::Bsms(format!("Invalid record encoding: {e}")))
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Round 1 coordinator state, saved to a file between rounds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoordinatorSession {
+    pub threshold: usize,
+    pub total: usize,
+    pub network: Network,
+    pub tokens: Vec<Token>,
+    pub created_at: String, // ISO timestamp
+}
+
+impl CoordinatorSession {
+    pub fn new(
+        threshold: usize,
+        total: usize,
+        network: Network,
+        level: EncryptionLevel,
+    ) -> Result<Self> {
+        if threshold == 0 || threshold > total {
+            return Err(WalletError::Bsms(format!(
+                "Threshold must be between 1 and {total}, got {threshold}"
+            )));
+        }
+
+        // Without encryption every signer shares the same `00` token
+        let tokens = (0..total).map(|_| Token::generate(level)).collect();
+
+        Ok(Self {
+            threshold,
+            total,
+            network,
+            tokens,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        })
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| WalletError::Bsms(format!("Failed to serialize session: {e}")))?;
+        std::fs::write(path, json)
+            .map_err(|e| WalletError::Bsms(format!("Failed to write session file: {e}")))
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| WalletError::Bsms(format!("Failed to read session file: {e}")))?;
+        serde_json::from_str(&json)
+            .map_err(|e| WalletError::Bsms(format!("Invalid session file: {e}")))
+    }
+
+    /// Decrypt and verify the key records returned by the signers
+    pub fn collect_key_records(&self, payloads: &[String]) -> Result<Vec<KeyRecord>> {
+        if payloads.len() != self.total {
+            return Err(WalletError::Bsms(format!(
+                "Expected {} key records, got {}",
+                self.total,
+                payloads.len()
+            )));
+        }
+
+        let mut records: Vec<KeyRecord> = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let record = self
+                .tokens
+                .iter()
+                .find_map(|token| KeyRecord::decode(payload, token).ok())
+                .ok_or_else(|| {
+                    WalletError::Bsms(
+                        "Key record does not match any token of this session".to_string(),
+                    )
+                })?;
+
+            if records.iter().any(|r| r.key == record.key) {
+                return Err(WalletError::Bsms(format!(
+                    "Duplicate key record: {}",
+                    record.key
+                )));
+            }
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    /// Build the descriptor record from verified key records
+    pub fn descriptor_record(&self, records: &[KeyRecord]) -> Result<DescriptorRecord> {
+        let cosigners = records
+            .iter()
+            .map(|record| Cosigner::from_key_expression(&record.key))
+            .collect::<Result<Vec<_>>>()?;
+        let config = MultisigConfig::new(self.threshold, cosigners, None)?;
+        DescriptorRecord::from_config(&config, self.network)
+    }
+}
+
+/// Round 1 signer response: the signer's key, signed with that same key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRecord {
+    pub token: Token,
+    pub key: String,
+    pub description: String,
+    pub signature: String,
+}
+
+impl KeyRecord {
+    /// Create and sign a key record for our BIP48 cosigner key
+    pub fn create(token: Token, local: &LocalCosigner, description: &str) -> Result<Self> {
+        let key = local.cosigner.key.clone();
+        let description = description.replace('\n', " ");
+        let message = Self::signed_message(&token, &key, &description);
+
+        let secp = Secp256k1::new();
+        let digest = secp256k1::Message::from_digest(signed_msg_hash(&message).to_byte_array());
+        let signature = secp.sign_ecdsa_recoverable(&digest, &local.account_xprv.private_key);
+
+        Ok(Self {
+            token,
+            key,
+            description,
+            signature: MessageSignature::new(signature, true).to_base64(),
+        })
+    }
+
+    fn signed_message(token: &Token, key: &str, description: &str) -> String {
+        format!("{BSMS_VERSION}\n{token}\n{key}\n{description}")
+    }
+
+    /// Check the signature was made by the key in the record
+    pub fn verify(&self) -> Result<()> {
+        let parsed = DescriptorPublicKey::from_str(&self.key)
+            .map_err(|e| WalletError::Bsms(format!("Invalid key in record: {e}")))?;
+        let DescriptorPublicKey::XPub(xkey) = parsed else {
+            return Err(WalletError::Bsms(
+                "Key record must contain an extended public key".to_string(),
+            ));
+        };
+
+        let signature = MessageSignature::from_base64(&self.signature)
+            .map_err(|e| WalletError::Bsms(format!("Invalid key record signature: {e}")))?;
+
+        let secp = Secp256k1::verification_only();
+        let message = Self::signed_message(&self.token, &self.key, &self.description);
+        let recovered = signature
+            .recover_pubkey(&secp, signed_msg_hash(&message))
+            .map_err(|e| WalletError::Bsms(format!("Invalid key record signature: {e}")))?;
+
+        if recovered.inner != xkey.xkey.public_key {
+            return Err(WalletError::Bsms(format!(
+                "Key record signature does not match key {}",
+                self.key
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Serialize and encrypt for the record's token
+    pub fn encode(&self) -> String {
+        let text = format!(
+            "{}\n{}",
+            Self::signed_message(&self.token, &self.key, &self.description),
+            self.signature
+        );
+        self.token.encrypt(&text)
+    }
+
+    /// Decrypt, parse and verify a key record
+    pub fn decode(payload: &str, token: &Token) -> Result<Self> {
+        let text = token.decrypt(payload)?;
+        let lines: Vec<&str> = text.trim().lines().map(str::trim).collect();
+
+        let [version, record_token, key, description, signature] = lines[..] else {
+            return Err(WalletError::Bsms(
+                "Key record must have exactly 5 lines".to_string(),
+            ));
+        };
+
+        if version != BSMS_VERSION {
+            return Err(WalletError::Bsms(format!("Unsupported version: {version}")));
+        }
+
+        if record_token != token.as_hex() {
+            return Err(WalletError::Bsms(
+                "Key record token does not match session token".to_string(),
+            ));
+        }
+
+        let record = Self {
+            token: token.clone(),
+            key: key.to_string(),
+            description: description.to_string(),
+            signature: signature.to_string(),
+        };
+        record.verify()?;
+
+        Ok(record)
+    }
+}
+
+/// Round 2 coordinator output: descriptor template and first address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorRecord {
+    /// `wsh(sortedmulti(m,KEY/**,...))`
+    pub template: String,
+    pub path_restrictions: String,
+    pub first_address: String,
+}
+
+impl DescriptorRecord {
+    pub fn from_config(config: &MultisigConfig, network: Network) -> Result<Self> {
+        let keys: Vec<String> = config
+            .cosigners
+            .iter()
+            .map(|cosigner| format!("{}/**", cosigner.key))
+            .collect();
+        let template = format!("wsh(sortedmulti({},{}))", config.threshold, keys.join(","));
+
+        Ok(Self {
+            template,
+            path_restrictions: PATH_RESTRICTIONS.to_string(),
+            first_address: first_address(config, network)?,
+        })
+    }
+
+    /// Rebuild the multisig config and check it derives the advertised first address
+    pub fn verify(&self, network: Network) -> Result<MultisigConfig> {
+        if self.path_restrictions != PATH_RESTRICTIONS {
+            return Err(WalletError::Bsms(format!(
+                "Unsupported path restrictions: {}",
+                self.path_restrictions
+            )));
+        }
+
+        let inner = self
+            .template
+            .strip_prefix("wsh(sortedmulti(")
+            .and_then(|rest| rest.strip_suffix("))"))
+            .ok_or_else(|| {
+                WalletError::Bsms(format!(
+                    "Only wsh(sortedmulti(...)) templates are supported: {}",
+                    self.template
+                ))
+            })?;
+
+        let mut parts = inner.split(',');
+        let threshold = parts
+            .next()
+            .and_then(|m| m.parse::<usize>().ok())
+            .ok_or_else(|| WalletError::Bsms("Invalid threshold in template".to_string()))?;
+
+        let cosigners = parts
+            .map(|key| {
+                let key = key.strip_suffix("/**").ok_or_else(|| {
+                    WalletError::Bsms(format!("Template key must end with /**: {key}"))
+                })?;
+                Cosigner::from_key_expression(key)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let config = MultisigConfig::new(threshold, cosigners, None)?;
+        let expected_address = first_address(&config, network)?;
+        if expected_address != self.first_address {
+            return Err(WalletError::Bsms(format!(
+                "First address mismatch: record has {}, descriptor derives {expected_address}",
+                self.first_address
+            )));
+        }
+
+        Ok(config)
+    }
+
+    /// Verify the record and check our key is one of the cosigners
+    pub fn verify_for_signer(
+        &self,
+        network: Network,
+        local: &LocalCosigner,
+    ) -> Result<MultisigConfig> {
+        let mut config = self.verify(network)?;
+        if !config.cosigners.contains(&local.cosigner) {
+            return Err(WalletError::Bsms(
+                "Our key is not part of this multisig descriptor".to_string(),
+            ));
+        }
+        config.local_fingerprint = Some(local.cosigner.fingerprint.clone());
+        Ok(config)
+    }
+
+    pub fn encode(&self, token: &Token) -> String {
+        token.encrypt(&format!(
+            "{BSMS_VERSION}\n{}\n{}\n{}",
+            self.template, self.path_restrictions, self.first_address
+        ))
+    }
+
+    pub fn decode(payload: &str, token: &Token) -> Result<Self> {
+        let text = token.decrypt(payload)?;
+        let lines: Vec<&str> = text.trim().lines().map(str::trim).collect();
+
+        let [version, template, path_restrictions, first_address] = lines[..] else {
+            return Err(WalletError::Bsms(
+                "Descriptor record must have exactly 4 lines".to_string(),
+            ));
+        };
+
+        if version != BSMS_VERSION {
+            return Err(WalletError::Bsms(format!("Unsupported version: {version}")));
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            path_restrictions: path_restrictions.to_string(),
+            first_address: first_address.to_string(),
+        })
+    }
+}
+
+/// First receive address (index 0) of a multisig config
+fn first_address(config: &MultisigConfig, network: Network) -> Result<String> {
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(
+        &config.descriptor(bdk_wallet::KeychainKind::External),
+    )
+    .map_err(|e| WalletError::Bsms(format!("Invalid descriptor: {e}")))?;
+
+    let address = descriptor
+        .at_derivation_index(0)
+        .map_err(|e| WalletError::Bsms(format!("Invalid descriptor: {e}")))?
+        .address(network.to_bitcoin_network())
+        .map_err(|e| WalletError::Bsms(format!("Invalid descriptor: {e}")))?;
+
+    Ok(address.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bip39::Mnemonic;
+
+    const MNEMONICS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+    ];
+
+    fn signers() -> Vec<LocalCosigner> {
+        MNEMONICS
+            .iter()
+            .map(|m| {
+                LocalCosigner::from_mnemonic(&Mnemonic::from_str(m).unwrap(), Network::Testnet)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_token_encryption_roundtrip() {
+        let token = Token::generate(EncryptionLevel::Standard);
+        assert_eq!(token.as_hex().len(), 16);
+
+        let encrypted = token.encrypt("BSMS 1.0\nhello");
+        assert_ne!(encrypted, "BSMS 1.0\nhello");
+        assert_eq!(token.decrypt(&encrypted).unwrap(), "BSMS 1.0\nhello");
+
+        let other = Token::generate(EncryptionLevel::Standard);
+        assert!(other.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn test_bsms_full_flow() {
+        let signers = signers();
+        let session =
+            CoordinatorSession::new(2, 3, Network::Testnet, EncryptionLevel::Standard).unwrap();
+
+        // Round 1: every signer answers with a key record for its own token
+        let payloads: Vec<String> = signers
+            .iter()
+            .zip(&session.tokens)
+            .enumerate()
+            .map(|(i, (signer, token))| {
+                KeyRecord::create(token.clone(), signer, &format!("Signer {i}"))
+                    .unwrap()
+                    .encode()
+            })
+            .collect();
+
+        let records = session.collect_key_records(&payloads).unwrap();
+        let descriptor_record = session.descriptor_record(&records).unwrap();
+
+        // Round 2: each signer checks the descriptor record
+        for (signer, token) in signers.iter().zip(&session.tokens) {
+            let payload = descriptor_record.encode(token);
+            let decoded = DescriptorRecord::decode(&payload, token).unwrap();
+            let config = decoded.verify_for_signer(Network::Testnet, signer).unwrap();
+            assert_eq!(config.threshold, 2);
+            assert_eq!(config.total(), 3);
+        }
+    }
+
+    #[test]
+    fn test_tampered_records_are_rejected() {
+        let signers = signers();
+        let token = Token::generate(EncryptionLevel::NoEncryption);
+
+        let mut record = KeyRecord::create(token.clone(), &signers[0], "Signer").unwrap();
+        record.key = signers[1].cosigner.key.clone();
+        assert!(KeyRecord::decode(&record.encode(), &token).is_err());
+
+        let config = MultisigConfig::new(
+            2,
+            signers.iter().map(|s| s.cosigner.clone()).collect(),
+            None,
+        )
+        .unwrap();
+        let mut descriptor_record =
+            DescriptorRecord::from_config(&config, Network::Testnet).unwrap();
+        descriptor_record.first_address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx".to_string();
+        assert!(descriptor_record.verify(Network::Testnet).is_err());
+    }
+}
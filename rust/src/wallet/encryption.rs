@@ -1,5 +1,9 @@
 use crate::wallet::error::{Result, WalletError};
+use aes::cipher::{KeyIvInit, StreamCipher};
 use base64::{engine::general_purpose, Engine as _};
+use bitcoin::hashes::{hmac, sha256, sha512, Hash, HashEngine};
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
 
 /// Simple encryption utility for mnemonic storage
 /// TODO: Replace with platform keychain/keystore in production
@@ -40,6 +44,42 @@ impl MnemonicEncryption {
     }
 }
 
+/// PBKDF2 with HMAC-SHA512, filling `output` with derived key material
+pub fn pbkdf2_hmac_sha512(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    for (block_index, chunk) in output.chunks_mut(64).enumerate() {
+        let mut engine = hmac::HmacEngine::<sha512::Hash>::new(password);
+        engine.input(salt);
+        engine.input(&(block_index as u32 + 1).to_be_bytes());
+        let mut block = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+        let mut result = block;
+
+        for _ in 1..iterations {
+            let mut engine = hmac::HmacEngine::<sha512::Hash>::new(password);
+            engine.input(&block);
+            block = hmac::Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+            result
+                .iter_mut()
+                .zip(block.iter())
+                .for_each(|(r, b)| *r ^= b);
+        }
+
+        chunk.copy_from_slice(&result[..chunk.len()]);
+    }
+}
+
+/// HMAC-SHA256 of `data` under `key`
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+/// AES-256 in CTR mode, encryption and decryption are the same operation
+pub fn aes256_ctr(key: &[u8; 32], iv: &[u8; 16], data: &mut [u8]) {
+    let mut cipher = Aes256Ctr::new(key.into(), iv.into());
+    cipher.apply_keystream(data);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +94,29 @@ mod tests {
         let decrypted = MnemonicEncryption::decrypt(&encrypted).unwrap();
         assert_eq!(original, decrypted);
     }
+
+    #[test]
+    fn test_pbkdf2_matches_bip39_seed() {
+        // BIP39 seeds are PBKDF2-HMAC-SHA512("mnemonic" + passphrase, 2048 rounds)
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mnemonic = bip39::Mnemonic::parse(phrase).unwrap();
+
+        let mut seed = [0u8; 64];
+        pbkdf2_hmac_sha512(phrase.as_bytes(), b"mnemonicTREZOR", 2048, &mut seed);
+        assert_eq!(seed, mnemonic.to_seed("TREZOR"));
+    }
+
+    #[test]
+    fn test_aes256_ctr_roundtrip() {
+        let key = [7u8; 32];
+        let iv = [9u8; 16];
+        let original = b"wsh(sortedmulti(2,...))".to_vec();
+
+        let mut data = original.clone();
+        aes256_ctr(&key, &iv, &mut data);
+        assert_ne!(data, original);
+
+        aes256_ctr(&key, &iv, &mut data);
+        assert_eq!(data, original);
+    }
 }
@@ -31,6 +31,48 @@ pub enum WalletError {
 
     #[error("Wallet already exists with ID: {0}")]
     WalletAlreadyExists(String),
+
+    #[error("Multisig error: {0}")]
+    Multisig(String),
+
+    #[error("BSMS error: {0}")]
+    Bsms(String),
+
+    #[error("PSBT error: {0}")]
+    Psbt(String),
+
+    #[error("UR error: {0}")]
+    Ur(String),
+
+    #[error("Export error: {0}")]
+    Export(String),
+
+    #[error("Import error: {0}")]
+    Import(String),
+
+    #[error("Backup error: {0}")]
+    Backup(String),
+
+    #[error("Invoice error: {0}")]
+    Invoice(String),
+
+    #[error("Payjoin error: {0}")]
+    Payjoin(String),
+
+    #[error("Message signing error: {0}")]
+    Message(String),
+
+    #[error("Proof of reserves error: {0}")]
+    Reserves(String),
+
+    #[error("Confirmation required: {0}")]
+    ConfirmationRequired(String),
+
+    #[error("Wallet is busy: {0}")]
+    WalletBusy(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
 }
 
 impl From<eyre::Error> for WalletError {
@@ -45,6 +87,21 @@ impl From<DatabaseError> for WalletError {
     }
 }
 
+impl From<crate::lock::LockError> for WalletError {
+    fn from(err: crate::lock::LockError) -> Self {
+        match err {
+            crate::lock::LockError::Busy(_) => WalletError::WalletBusy(err.to_string()),
+            crate::lock::LockError::Io { .. } => WalletError::Generic(err.to_string()),
+        }
+    }
+}
+
+impl From<crate::ur::UrError> for WalletError {
+    fn from(err: crate::ur::UrError) -> Self {
+        WalletError::Ur(err.to_string())
+    }
+}
+
 impl From<bitcoin::bip32::Error> for WalletError {
     fn from(err: bitcoin::bip32::Error) -> Self {
         WalletError::Bitcoin(err.to_string())
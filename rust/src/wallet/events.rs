@@ -0,0 +1,317 @@
+//! Typed events describing what a sync changed
+//!
+//! A [`WalletSnapshot`] is taken before and after each sync, and the
+//! difference between the two becomes the events subscribers see.
+
+use std::collections::HashMap;
+
+use bdk_wallet::chain::ChainPosition;
+use bitcoin::{BlockHash, OutPoint, Txid};
+use lumo_types::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::wallet::{Wallet, WalletId};
+
+/// Confirmation depths reported with [`WalletEventKind::TxConfirmed`] unless configured otherwise
+pub const DEFAULT_CONFIRMATION_DEPTHS: [u32; 2] = [1, 6];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletEvent {
+    pub wallet_id: WalletId,
+    #[serde(flatten)]
+    pub kind: WalletEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WalletEventKind {
+    SyncStarted,
+    SyncFinished,
+    SyncFailed {
+        error: String,
+    },
+    /// The wallet's chain tip moved
+    NewBlock {
+        height: u32,
+        hash: BlockHash,
+    },
+    /// A transaction paying the wallet was seen for the first time
+    TxReceived {
+        txid: Txid,
+        amount: Amount,
+        confirmed: bool,
+    },
+    /// A transaction reached one of the watched confirmation depths
+    TxConfirmed {
+        txid: Txid,
+        height: u32,
+        confirmations: u32,
+    },
+    /// An unconfirmed transaction lost to one spending the same coins
+    TxReplaced {
+        txid: Txid,
+        replaced_by: Txid,
+    },
+    /// An unconfirmed transaction dropped without a replacement
+    TxEvicted {
+        txid: Txid,
+    },
+    BalanceChanged {
+        confirmed: Amount,
+        spendable: Amount,
+        total: Amount,
+    },
+}
+
+/// What a sync can change, captured from a wallet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WalletSnapshot {
+    tip: Option<(u32, BlockHash)>,
+    balance: (Amount, Amount, Amount),
+    transactions: HashMap<Txid, TxSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TxSnapshot {
+    confirmation_height: Option<u32>,
+    inputs: Vec<OutPoint>,
+    sent: Amount,
+    received: Amount,
+}
+
+impl WalletSnapshot {
+    pub fn of(wallet: &Wallet) -> Self {
+        let checkpoint = wallet.bdk.latest_checkpoint();
+        let balance = wallet.balance();
+
+        let transactions = wallet
+            .bdk
+            .transactions()
+            .map(|canonical_tx| {
+                let tx = &canonical_tx.tx_node.tx;
+                let (sent, received) = wallet.bdk.sent_and_received(tx);
+                let confirmation_height = match canonical_tx.chain_position {
+                    ChainPosition::Confirmed { anchor, .. } => Some(anchor.block_id.height),
+                    ChainPosition::Unconfirmed { .. } => None,
+                };
+
+                let snapshot = TxSnapshot {
+                    confirmation_height,
+                    inputs: tx.input.iter().map(|input| input.previous_output).collect(),
+                    sent: sent.into(),
+                    received: received.into(),
+                };
+                (canonical_tx.tx_node.txid, snapshot)
+            })
+            .collect();
+
+        Self {
+            tip: Some((checkpoint.height(), checkpoint.hash())),
+            balance: (balance.confirmed(), balance.spendable(), balance.total()),
+            transactions,
+        }
+    }
+
+    /// Events that take the wallet from `self` to `after`
+    ///
+    /// A transaction crossing several watched depths in one sync is reported
+    /// once, at the deepest of them.
+    pub fn changes(&self, after: &WalletSnapshot, depths: &[u32]) -> Vec<WalletEventKind> {
+        let mut events = Vec::new();
+
+        if let Some((height, hash)) = after.tip.filter(|tip| Some(*tip) != self.tip) {
+            events.push(WalletEventKind::NewBlock { height, hash });
+        }
+
+        let mut txids: Vec<&Txid> = after.transactions.keys().collect();
+        txids.sort_by_key(|txid| {
+            after.transactions[*txid]
+                .confirmation_height
+                .unwrap_or(u32::MAX)
+        });
+
+        for txid in txids {
+            let tx = &after.transactions[txid];
+            let before = self.transactions.get(txid);
+
+            if before.is_none() && tx.received > tx.sent {
+                events.push(WalletEventKind::TxReceived {
+                    txid: *txid,
+                    amount: tx.received - tx.sent,
+                    confirmed: tx.confirmation_height.is_some(),
+                });
+            }
+
+            let Some(height) = tx.confirmation_height else {
+                continue;
+            };
+            let confirmations = after.confirmations(height);
+            let previous = before
+                .and_then(|before| before.confirmation_height)
+                .map_or(0, |height| self.confirmations(height));
+
+            let crossed = depths
+                .iter()
+                .filter(|depth| previous < **depth && **depth <= confirmations)
+                .max();
+            if let Some(depth) = crossed {
+                events.push(WalletEventKind::TxConfirmed {
+                    txid: *txid,
+                    height,
+                    confirmations: *depth,
+                });
+            }
+        }
+
+        let mut dropped: Vec<(&Txid, &TxSnapshot)> = self
+            .transactions
+            .iter()
+            .filter(|(txid, _)| !after.transactions.contains_key(*txid))
+            .collect();
+        dropped.sort_by_key(|(txid, _)| **txid);
+
+        for (txid, tx) in dropped {
+            let replaced_by = after
+                .transactions
+                .iter()
+                .find(|(_, other)| other.inputs.iter().any(|input| tx.inputs.contains(input)))
+                .map(|(replacement, _)| *replacement);
+
+            events.push(match replaced_by {
+                Some(replaced_by) => WalletEventKind::TxReplaced {
+                    txid: *txid,
+                    replaced_by,
+                },
+                None => WalletEventKind::TxEvicted { txid: *txid },
+            });
+        }
+
+        if after.balance != self.balance {
+            let (confirmed, spendable, total) = after.balance;
+            events.push(WalletEventKind::BalanceChanged {
+                confirmed,
+                spendable,
+                total,
+            });
+        }
+
+        events
+    }
+
+    fn confirmations(&self, height: u32) -> u32 {
+        let tip = self.tip.map_or(0, |(tip, _)| tip);
+        tip.saturating_sub(height) + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    fn txid(byte: u8) -> Txid {
+        Txid::from_byte_array([byte; 32])
+    }
+
+    fn outpoint(byte: u8) -> OutPoint {
+        OutPoint::new(txid(byte), 0)
+    }
+
+    fn snapshot(tip: u32, transactions: Vec<(Txid, TxSnapshot)>) -> WalletSnapshot {
+        WalletSnapshot {
+            tip: Some((tip, BlockHash::from_byte_array([tip as u8; 32]))),
+            balance: Default::default(),
+            transactions: transactions.into_iter().collect(),
+        }
+    }
+
+    fn tx(confirmation_height: Option<u32>, input: u8, received: u64) -> TxSnapshot {
+        TxSnapshot {
+            confirmation_height,
+            inputs: vec![outpoint(input)],
+            sent: Amount::ZERO,
+            received: Amount::from_sat(received),
+        }
+    }
+
+    #[test]
+    fn test_received_and_confirmed() {
+        let before = snapshot(100, vec![]);
+        let after = snapshot(100, vec![(txid(1), tx(None, 9, 5_000))]);
+
+        assert_eq!(
+            before.changes(&after, &DEFAULT_CONFIRMATION_DEPTHS),
+            vec![WalletEventKind::TxReceived {
+                txid: txid(1),
+                amount: Amount::from_sat(5_000),
+                confirmed: false,
+            }]
+        );
+
+        // Mined in 101, then buried to six confirmations
+        let mined = snapshot(101, vec![(txid(1), tx(Some(101), 9, 5_000))]);
+        let events = after.changes(&mined, &DEFAULT_CONFIRMATION_DEPTHS);
+        assert!(events.contains(&WalletEventKind::TxConfirmed {
+            txid: txid(1),
+            height: 101,
+            confirmations: 1,
+        }));
+
+        let buried = snapshot(106, vec![(txid(1), tx(Some(101), 9, 5_000))]);
+        let events = mined.changes(&buried, &DEFAULT_CONFIRMATION_DEPTHS);
+        assert!(events.contains(&WalletEventKind::TxConfirmed {
+            txid: txid(1),
+            height: 101,
+            confirmations: 6,
+        }));
+        assert!(buried
+            .changes(&buried, &DEFAULT_CONFIRMATION_DEPTHS)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_replaced_and_evicted() {
+        let before = snapshot(
+            100,
+            vec![(txid(1), tx(None, 8, 1_000)), (txid(2), tx(None, 9, 2_000))],
+        );
+        let after = snapshot(100, vec![(txid(3), tx(None, 8, 900))]);
+
+        let events = before.changes(&after, &DEFAULT_CONFIRMATION_DEPTHS);
+        assert!(events.contains(&WalletEventKind::TxReplaced {
+            txid: txid(1),
+            replaced_by: txid(3),
+        }));
+        assert!(events.contains(&WalletEventKind::TxEvicted { txid: txid(2) }));
+    }
+
+    #[test]
+    fn test_new_block_and_balance() {
+        let before = snapshot(100, vec![]);
+        let mut after = snapshot(101, vec![]);
+        after.balance.0 = Amount::from_sat(1);
+
+        let events = before.changes(&after, &[]);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                WalletEventKind::NewBlock { height: 101, .. },
+                WalletEventKind::BalanceChanged { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let event = WalletEvent {
+            wallet_id: WalletId::new(),
+            kind: WalletEventKind::TxEvicted { txid: txid(7) },
+        };
+        let encoded = serde_json::to_value(&event).unwrap();
+        assert_eq!(encoded["type"], "tx_evicted");
+        assert_eq!(
+            serde_json::from_value::<WalletEvent>(encoded).unwrap(),
+            event
+        );
+    }
+}
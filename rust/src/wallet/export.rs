@@ -0,0 +1,394 @@
+//! Wallet export in formats other wallets and coordinators can import
+//!
+//! Exports only ever contain public descriptors, never private keys. Supported
+//! formats are lumo's own descriptor JSON (descriptors, birthday and labels),
+//! BIP329 labels, Bitcoin Core `importdescriptors` requests and the Coldcard
+//! generic/multisig files.
+
+use std::str::FromStr;
+
+use bdk_wallet::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use bdk_wallet::miniscript::ForEachKey;
+use bitcoin::bip32::{ChildNumber, DerivationPath};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::metadata::WalletMetadata;
+use crate::wallet::multisig::MultisigConfig;
+use crate::GAP_LIMIT;
+use lumo_types::{Label, Network};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// lumo's own JSON: descriptors, birthday and labels
+    #[default]
+    Descriptors,
+    /// BIP329 labels, one JSON object per line
+    Labels,
+    /// Bitcoin Core `importdescriptors` request
+    BitcoinCore,
+    /// Coldcard generic JSON, or the multisig setup file for multisig wallets
+    Coldcard,
+}
+
+impl FromStr for ExportFormat {
+    type Err = WalletError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "descriptors" => Ok(Self::Descriptors),
+            "labels" | "bip329" => Ok(Self::Labels),
+            "core" | "bitcoin-core" => Ok(Self::BitcoinCore),
+            "coldcard" => Ok(Self::Coldcard),
+            _ => Err(WalletError::Export(format!(
+                "Invalid export format: {s}. Valid options: descriptors, labels, core, coldcard"
+            ))),
+        }
+    }
+}
+
+/// Everything needed to restore a watch-only copy of a wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletExport {
+    pub name: String,
+    pub network: Network,
+    /// Receive descriptor (BIP380, with checksum)
+    pub external: String,
+    /// Change descriptor (BIP380, with checksum)
+    pub internal: String,
+    /// Both keychains in one `<0;1>` descriptor (BIP389), if they only differ there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multipath: Option<String>,
+    /// Unix timestamp before which the wallet had no transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub master_fingerprint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<MultisigConfig>,
+    /// Next unused receive index
+    pub next_index: u32,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+}
+
+impl WalletExport {
+    /// `birthday` is the wallet's birthday as a unix timestamp, `None` if unknown
+    pub fn new(
+        metadata: &WalletMetadata,
+        birthday: Option<i64>,
+        external: String,
+        internal: String,
+        next_index: u32,
+        labels: Vec<Label>,
+    ) -> Self {
+        Self {
+            name: metadata.name.clone(),
+            network: metadata.network,
+            multipath: multipath_descriptor(&external, &internal),
+            external,
+            internal,
+            birthday,
+            master_fingerprint: metadata.master_fingerprint.clone(),
+            multisig: metadata.multisig.clone(),
+            next_index,
+            labels,
+        }
+    }
+
+    pub fn export(&self, format: ExportFormat) -> Result<String> {
+        match format {
+            ExportFormat::Descriptors => self.to_descriptors_json(),
+            ExportFormat::Labels => Ok(Label::to_jsonl(&self.labels)),
+            ExportFormat::BitcoinCore => self.to_core_import(),
+            ExportFormat::Coldcard => match &self.multisig {
+                Some(config) => self.to_coldcard_multisig(config),
+                None => self.to_coldcard_generic(),
+            },
+        }
+    }
+
+    pub fn to_descriptors_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| WalletError::Export(format!("Failed to serialize export: {e}")))
+    }
+
+    /// Request body for Bitcoin Core's `importdescriptors` RPC
+    pub fn to_core_import(&self) -> Result<String> {
+        // Without a known birthday Core has to rescan from genesis
+        let timestamp = self.birthday.unwrap_or(0);
+        let range_end = self.next_index + GAP_LIMIT as u32;
+
+        let requests = [(&self.external, false), (&self.internal, true)]
+            .iter()
+            .map(|(descriptor, internal)| {
+                json!({
+                    "desc": descriptor,
+                    "timestamp": timestamp,
+                    "active": true,
+                    "internal": internal,
+                    "range": [0, range_end],
+                    "next_index": if *internal { 0 } else { self.next_index },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        serde_json::to_string_pretty(&requests)
+            .map_err(|e| WalletError::Export(format!("Failed to serialize export: {e}")))
+    }
+
+    /// Coldcard generic wallet JSON for single-sig wallets
+    pub fn to_coldcard_generic(&self) -> Result<String> {
+        let descriptor = parse_descriptor(&self.external)?;
+        let (section, name) = match &descriptor {
+            Descriptor::Pkh(_) => ("bip44", "p2pkh"),
+            Descriptor::Sh(_) => ("bip49", "p2sh-p2wpkh"),
+            Descriptor::Wpkh(_) => ("bip84", "p2wpkh"),
+            Descriptor::Tr(_) => ("bip86", "p2tr"),
+            _ => {
+                return Err(WalletError::Export(
+                    "Only single key wallets can be exported in the Coldcard generic format"
+                        .to_string(),
+                ))
+            }
+        };
+
+        let (fingerprint, path, xpub) = single_account_key(&descriptor)?;
+        let first_address = descriptor
+            .at_derivation_index(0)
+            .map_err(|e| WalletError::Export(e.to_string()))?
+            .address(self.network.to_bitcoin_network())
+            .map_err(|e| WalletError::Export(e.to_string()))?;
+
+        let account = match (&path).into_iter().last() {
+            Some(ChildNumber::Hardened { index }) => *index,
+            _ => 0,
+        };
+
+        let mut export = json!({
+            "chain": coldcard_chain(self.network),
+            "xfp": fingerprint.to_uppercase(),
+            "account": account,
+        });
+        export[section] = json!({
+            "name": name,
+            "deriv": format_derivation(&path),
+            "xpub": xpub,
+            "desc": self.multipath.as_ref().unwrap_or(&self.external),
+            "first": first_address.to_string(),
+        });
+
+        serde_json::to_string_pretty(&export)
+            .map_err(|e| WalletError::Export(format!("Failed to serialize export: {e}")))
+    }
+
+    /// Coldcard multisig setup file
+    pub fn to_coldcard_multisig(&self, config: &MultisigConfig) -> Result<String> {
+        // Coldcard only accepts short names
+        let name: String = self.name.chars().take(20).collect();
+        let mut file = format!(
+            "# Exported from lumo\nName: {name}\nPolicy: {} of {}\nFormat: P2WSH\n",
+            config.threshold,
+            config.total()
+        );
+
+        for cosigner in &config.cosigners {
+            let key = DescriptorPublicKey::from_str(&cosigner.key)
+                .map_err(|e| WalletError::Export(format!("Invalid cosigner key: {e}")))?;
+            let DescriptorPublicKey::XPub(xkey) = key else {
+                return Err(WalletError::Export(format!(
+                    "Cosigner key is not an xpub: {}",
+                    cosigner.key
+                )));
+            };
+            let (fingerprint, path) = xkey.origin.ok_or(WalletError::Export(format!(
+                "Cosigner key is missing its origin: {}",
+                cosigner.key
+            )))?;
+
+            file.push_str(&format!(
+                "\nDerivation: {}\n{}: {}\n",
+                format_derivation(&path),
+                fingerprint.to_string().to_uppercase(),
+                xkey.xkey
+            ));
+        }
+
+        Ok(file)
+    }
+}
+
+/// Combine receive and change descriptors into one `<0;1>` descriptor (BIP389)
+///
+/// Returns `None` if the descriptors differ anywhere but in the keychain step.
+pub fn multipath_descriptor(external: &str, internal: &str) -> Option<String> {
+    let strip_checksum = |descriptor: &str| {
+        descriptor
+            .split_once('#')
+            .map_or(descriptor, |(descriptor, _)| descriptor)
+            .to_string()
+    };
+
+    let external = strip_checksum(external).replace("/0/*", "/<0;1>/*");
+    let internal = strip_checksum(internal).replace("/1/*", "/<0;1>/*");
+    if external != internal || !external.contains("<0;1>") {
+        return None;
+    }
+
+    // Parsing the descriptor again validates it and adds the checksum
+    parse_descriptor(&external)
+        .ok()
+        .map(|descriptor| descriptor.to_string())
+}
+
+fn parse_descriptor(descriptor: &str) -> Result<Descriptor<DescriptorPublicKey>> {
+    Descriptor::from_str(descriptor)
+        .map_err(|e| WalletError::Export(format!("Invalid descriptor {descriptor}: {e}")))
+}
+
+/// Origin and account xpub of a single key descriptor
+fn single_account_key(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+) -> Result<(String, DerivationPath, String)> {
+    let mut keys = Vec::new();
+    descriptor.for_each_key(|key| {
+        keys.push(key.clone());
+        true
+    });
+
+    match keys.as_slice() {
+        [DescriptorPublicKey::XPub(xkey)] => {
+            let (fingerprint, path) = xkey.origin.clone().ok_or(WalletError::Export(
+                "Descriptor key is missing its origin".to_string(),
+            ))?;
+            Ok((fingerprint.to_string(), path, xkey.xkey.to_string()))
+        }
+        _ => Err(WalletError::Export(
+            "Descriptor must have a single extended public key".to_string(),
+        )),
+    }
+}
+
+fn coldcard_chain(network: Network) -> &'static str {
+    match network {
+        Network::Mainnet => "BTC",
+        Network::Regtest => "XRT",
+        Network::Testnet | Network::Testnet4 | Network::Signet => "XTN",
+    }
+}
+
+/// `m/84'/0'/0'`, the notation used by Coldcard and most coordinators
+fn format_derivation(path: &DerivationPath) -> String {
+    let steps: String = path
+        .into_iter()
+        .map(|child| match child {
+            ChildNumber::Normal { index } => format!("/{index}"),
+            ChildNumber::Hardened { index } => format!("/{index}'"),
+        })
+        .collect();
+    format!("m{steps}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::multisig::LocalCosigner;
+    use bdk_wallet::KeychainKind;
+    use bip39::Mnemonic;
+
+    // BIP84 testnet account of "abandon abandon ... about"
+    const BIP84_ACCOUNT: &str = "[73c5da0a/84'/1'/0']tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+
+    fn single_sig_export() -> WalletExport {
+        let key = BIP84_ACCOUNT;
+        let external = parse_descriptor(&format!("wpkh({key}/0/*)")).unwrap();
+        let internal = parse_descriptor(&format!("wpkh({key}/1/*)")).unwrap();
+
+        let metadata = WalletMetadata::new("Export Test".to_string(), Network::Testnet);
+        WalletExport::new(
+            &metadata,
+            None,
+            external.to_string(),
+            internal.to_string(),
+            3,
+            vec![],
+        )
+    }
+
+    #[test]
+    fn test_multipath_descriptor() {
+        let export = single_sig_export();
+        let multipath = export.multipath.clone().unwrap();
+        assert!(multipath.contains("/<0;1>/*"));
+        assert!(multipath.contains('#'));
+
+        // Different keys can't be merged into one multipath descriptor
+        let other = export.internal.replace("wpkh(", "pkh(");
+        assert_eq!(multipath_descriptor(&export.external, &other), None);
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let export = single_sig_export();
+        // Not known, so an import has to scan the whole chain
+        assert_eq!(export.birthday, None);
+
+        let json = export.export(ExportFormat::Descriptors).unwrap();
+        let parsed: WalletExport = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, export);
+
+        let core: serde_json::Value =
+            serde_json::from_str(&export.export(ExportFormat::BitcoinCore).unwrap()).unwrap();
+        assert_eq!(core[0]["desc"], json!(export.external));
+        assert_eq!(core[1]["internal"], json!(true));
+        assert_eq!(core[0]["timestamp"], json!(0));
+
+        let coldcard: serde_json::Value =
+            serde_json::from_str(&export.export(ExportFormat::Coldcard).unwrap()).unwrap();
+        assert_eq!(coldcard["chain"], json!("XTN"));
+        assert_eq!(coldcard["xfp"], json!("73C5DA0A"));
+        assert_eq!(coldcard["bip84"]["deriv"], json!("m/84'/1'/0'"));
+        assert_eq!(
+            coldcard["bip84"]["first"],
+            json!("tb1q6rz28mcfaxtmd6v789l9rrlrusdprr9pqcpvkl")
+        );
+    }
+
+    #[test]
+    fn test_coldcard_multisig_export() {
+        let locals: Vec<LocalCosigner> = [
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        ]
+        .iter()
+        .map(|m| LocalCosigner::from_mnemonic(&Mnemonic::from_str(m).unwrap(), Network::Testnet).unwrap())
+        .collect();
+
+        let config =
+            MultisigConfig::new(2, locals.iter().map(|l| l.cosigner.clone()).collect(), None)
+                .unwrap();
+        let metadata = WalletMetadata::new_multisig(
+            "A multisig with a long name".to_string(),
+            Network::Testnet,
+            config.clone(),
+        );
+        let export = WalletExport::new(
+            &metadata,
+            Some(1_700_000_000),
+            config.descriptor(KeychainKind::External),
+            config.descriptor(KeychainKind::Internal),
+            0,
+            vec![],
+        );
+
+        let file = export.export(ExportFormat::Coldcard).unwrap();
+        assert!(file.contains("Name: A multisig with a lo\n"));
+        assert!(file.contains("Policy: 2 of 2"));
+        assert_eq!(file.matches("Derivation: m/48'/1'/0'/2'").count(), 2);
+
+        let core: serde_json::Value =
+            serde_json::from_str(&export.export(ExportFormat::BitcoinCore).unwrap()).unwrap();
+        assert_eq!(core[0]["timestamp"], json!(1_700_000_000));
+    }
+}
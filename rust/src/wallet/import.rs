@@ -0,0 +1,480 @@
+//! Wallet import from Bitcoin Core and Electrum
+//!
+//! Bitcoin Core: the JSON output of `listdescriptors`, or `listdescriptors true` to
+//! keep signing. Only active descriptors are imported, and only one script type
+//! at a time since a lumo wallet has one receive and one change descriptor.
+//!
+//! Electrum: unencrypted standard (single-sig) wallet files. SLIP-132 keys
+//! (`zpub`, `vprv`, ...) are converted back to plain `xpub`/`tprv` keys, with the
+//! prefix deciding the script type.
+
+use std::collections::HashMap;
+
+use bdk_wallet::miniscript::descriptor::{Descriptor, DescriptorPublicKey};
+use bdk_wallet::miniscript::ForEachKey;
+use bitcoin::base58;
+use bitcoin::bip32::Fingerprint;
+use bitcoin::secp256k1::Secp256k1;
+use serde::Deserialize;
+
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::metadata::ScriptType;
+use lumo_types::{Label, LabelType};
+
+/// Script types tried, in order, when the source has several and none was asked for
+const PREFERRED_SCRIPT_TYPES: [ScriptType; 4] = [
+    ScriptType::P2wpkh,
+    ScriptType::P2tr,
+    ScriptType::P2shP2wpkh,
+    ScriptType::P2pkh,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportSource {
+    BitcoinCore,
+    Electrum,
+}
+
+/// A wallet read from another wallet's export, ready to be created in lumo
+#[derive(Debug, Clone)]
+pub struct ImportedWallet {
+    pub source: ImportSource,
+    /// Name of the wallet in the source, if it has one
+    pub name: Option<String>,
+    /// Public receive descriptor, with checksum
+    pub external: String,
+    /// Public change descriptor, with checksum
+    pub internal: String,
+    /// Receive and change descriptors with private keys, if the source had them
+    pub private_descriptors: Option<(String, String)>,
+    pub script_type: ScriptType,
+    pub master_fingerprint: Option<Fingerprint>,
+    /// Unix timestamp of the wallet's first activity
+    pub birthday: Option<i64>,
+    pub labels: Vec<Label>,
+}
+
+impl ImportedWallet {
+    /// Detect the format of a wallet file and read it
+    pub fn parse(data: &str, script_type: Option<ScriptType>) -> Result<Self> {
+        let json: serde_json::Value = serde_json::from_str(data).map_err(|_| {
+            WalletError::Import(
+                "File is not JSON. Encrypted Electrum wallets must be decrypted in Electrum first"
+                    .to_string(),
+            )
+        })?;
+
+        if json.get("descriptors").is_some() {
+            Self::from_core_descriptors(data, script_type)
+        } else if json.get("keystore").is_some() || json.get("wallet_type").is_some() {
+            Self::from_electrum(data)
+        } else {
+            Err(WalletError::Import(
+                "Unknown wallet file, expected Bitcoin Core listdescriptors output or an Electrum wallet"
+                    .to_string(),
+            ))
+        }
+    }
+
+    /// Read the output of Bitcoin Core's `listdescriptors`
+    pub fn from_core_descriptors(data: &str, script_type: Option<ScriptType>) -> Result<Self> {
+        let listing: CoreDescriptors = serde_json::from_str(data)
+            .map_err(|e| WalletError::Import(format!("Invalid listdescriptors output: {e}")))?;
+
+        let mut active = Vec::new();
+        for entry in listing.descriptors.iter().filter(|entry| entry.active) {
+            let keychain = parse_keychain(&entry.desc)?;
+            // Descriptors lumo can't use (e.g. raw scripts) are left behind
+            if let Some(entry_type) = ScriptType::from_descriptor(&keychain.public) {
+                active.push((entry_type, entry, keychain));
+            }
+        }
+
+        let wanted = match script_type {
+            Some(script_type) => vec![script_type],
+            None => PREFERRED_SCRIPT_TYPES.to_vec(),
+        };
+
+        for script_type in wanted {
+            let find = |internal: bool| {
+                active
+                    .iter()
+                    .find(|(entry_type, entry, _)| {
+                        *entry_type == script_type && entry.internal == internal
+                    })
+                    .cloned()
+            };
+
+            let (Some((_, external_entry, external)), Some((_, internal_entry, internal))) =
+                (find(false), find(true))
+            else {
+                continue;
+            };
+
+            if script_type == ScriptType::P2wsh {
+                return Err(WalletError::Import(
+                    "Multisig descriptors can't be imported, use create-multisig instead"
+                        .to_string(),
+                ));
+            }
+
+            let birthday = [external_entry.timestamp, internal_entry.timestamp]
+                .into_iter()
+                .flatten()
+                .min();
+
+            return Ok(Self {
+                source: ImportSource::BitcoinCore,
+                name: listing.wallet_name.clone(),
+                master_fingerprint: single_master_fingerprint(&external.public),
+                external: external.public.to_string(),
+                internal: internal.public.to_string(),
+                private_descriptors: external.private.zip(internal.private),
+                script_type,
+                birthday,
+                labels: Vec::new(),
+            });
+        }
+
+        Err(WalletError::Import(match script_type {
+            Some(script_type) => format!(
+                "No active {} receive and change descriptors found",
+                script_type.description()
+            ),
+            None => "No active receive and change descriptors found".to_string(),
+        }))
+    }
+
+    /// Read an unencrypted Electrum standard wallet file
+    pub fn from_electrum(data: &str) -> Result<Self> {
+        let wallet: ElectrumWallet = serde_json::from_str(data)
+            .map_err(|e| WalletError::Import(format!("Invalid Electrum wallet: {e}")))?;
+
+        if wallet.use_encryption {
+            return Err(WalletError::Import(
+                "Encrypted Electrum wallets must be decrypted in Electrum first".to_string(),
+            ));
+        }
+
+        if wallet.wallet_type != "standard" {
+            return Err(WalletError::Import(format!(
+                "Unsupported Electrum wallet type: {}. Only standard wallets can be imported",
+                wallet.wallet_type
+            )));
+        }
+
+        let keystore = wallet.keystore.ok_or(WalletError::Import(
+            "Electrum wallet has no keystore".to_string(),
+        ))?;
+
+        if keystore.keystore_type != "bip32" {
+            return Err(WalletError::Import(format!(
+                "Unsupported Electrum keystore: {}. Only BIP32 keystores can be imported",
+                keystore.keystore_type
+            )));
+        }
+
+        let (xpub, script_type) = slip132_to_standard(&keystore.xpub)?;
+        let xprv = match &keystore.xprv {
+            Some(xprv) => Some(slip132_to_standard(xprv)?.0),
+            None => None,
+        };
+
+        let origin = match (&keystore.root_fingerprint, &keystore.derivation) {
+            (Some(fingerprint), Some(derivation)) => {
+                let path = derivation.trim_start_matches('m');
+                format!("[{fingerprint}{path}]")
+            }
+            _ => String::new(),
+        };
+
+        let descriptor = |key: &str, keychain: u32| {
+            let key = format!("{origin}{key}/{keychain}/*");
+            match script_type {
+                ScriptType::P2pkh => format!("pkh({key})"),
+                ScriptType::P2shP2wpkh => format!("sh(wpkh({key}))"),
+                _ => format!("wpkh({key})"),
+            }
+        };
+
+        let key = xprv.as_deref().unwrap_or(&xpub);
+        let external = parse_keychain(&descriptor(key, 0))?;
+        let internal = parse_keychain(&descriptor(key, 1))?;
+
+        // verified_tx3 maps txids to [height, timestamp, position, header hash]
+        let birthday = wallet
+            .verified_tx3
+            .values()
+            .filter_map(|verified| verified.get(1).and_then(|timestamp| timestamp.as_i64()))
+            .filter(|timestamp| *timestamp > 0)
+            .min();
+
+        let mut labels: Vec<Label> = wallet
+            .labels
+            .into_iter()
+            .map(|(reference, label)| {
+                let label_type = if reference.contains(':') {
+                    LabelType::Output
+                } else if reference.len() == 64 && hex::decode(&reference).is_ok() {
+                    LabelType::Tx
+                } else {
+                    LabelType::Addr
+                };
+                Label::new(label_type, reference, label)
+            })
+            .collect();
+        labels.sort_by(|a, b| a.reference.cmp(&b.reference));
+
+        Ok(Self {
+            source: ImportSource::Electrum,
+            name: None,
+            master_fingerprint: single_master_fingerprint(&external.public),
+            external: external.public.to_string(),
+            internal: internal.public.to_string(),
+            private_descriptors: external.private.zip(internal.private),
+            script_type,
+            birthday,
+            labels,
+        })
+    }
+
+    pub fn has_private_keys(&self) -> bool {
+        self.private_descriptors.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+struct CoreDescriptors {
+    wallet_name: Option<String>,
+    descriptors: Vec<CoreDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct CoreDescriptor {
+    desc: String,
+    timestamp: Option<i64>,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    internal: bool,
+}
+
+#[derive(Deserialize)]
+struct ElectrumWallet {
+    #[serde(default)]
+    wallet_type: String,
+    #[serde(default)]
+    use_encryption: bool,
+    keystore: Option<ElectrumKeystore>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    verified_tx3: HashMap<String, Vec<serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct ElectrumKeystore {
+    #[serde(rename = "type")]
+    keystore_type: String,
+    xpub: String,
+    xprv: Option<String>,
+    derivation: Option<String>,
+    root_fingerprint: Option<String>,
+}
+
+/// A descriptor split into its public form and, if it had any, the private form
+#[derive(Clone)]
+struct Keychain {
+    public: Descriptor<DescriptorPublicKey>,
+    private: Option<String>,
+}
+
+fn parse_keychain(descriptor: &str) -> Result<Keychain> {
+    let secp = Secp256k1::new();
+    let (public, keymap) = Descriptor::parse_descriptor(&secp, descriptor)
+        .map_err(|e| WalletError::Import(format!("Invalid descriptor: {e}")))?;
+
+    let private = (!keymap.is_empty()).then(|| public.to_string_with_secret(&keymap));
+    Ok(Keychain { public, private })
+}
+
+/// Master fingerprint of a descriptor with a single key origin
+fn single_master_fingerprint(descriptor: &Descriptor<DescriptorPublicKey>) -> Option<Fingerprint> {
+    let mut fingerprints = Vec::new();
+    descriptor.for_each_key(|key| {
+        fingerprints.push(key.master_fingerprint());
+        true
+    });
+
+    match fingerprints.as_slice() {
+        [fingerprint] => Some(*fingerprint),
+        _ => None,
+    }
+}
+
+const XPUB: u32 = 0x0488_b21e;
+const XPRV: u32 = 0x0488_ade4;
+const TPUB: u32 = 0x0435_87cf;
+const TPRV: u32 = 0x0435_8394;
+
+// SLIP-132 version bytes: (version, standard version, script type)
+const SLIP132_VERSIONS: [(u32, u32, ScriptType); 12] = [
+    (XPUB, XPUB, ScriptType::P2pkh),
+    (XPRV, XPRV, ScriptType::P2pkh),
+    (0x049d_7cb2, XPUB, ScriptType::P2shP2wpkh), // ypub
+    (0x049d_7878, XPRV, ScriptType::P2shP2wpkh), // yprv
+    (0x04b2_4746, XPUB, ScriptType::P2wpkh),     // zpub
+    (0x04b2_430c, XPRV, ScriptType::P2wpkh),     // zprv
+    (TPUB, TPUB, ScriptType::P2pkh),
+    (TPRV, TPRV, ScriptType::P2pkh),
+    (0x044a_5262, TPUB, ScriptType::P2shP2wpkh), // upub
+    (0x044a_4e28, TPRV, ScriptType::P2shP2wpkh), // uprv
+    (0x045f_1c1f, TPUB, ScriptType::P2wpkh),     // vpub
+    (0x045f_18bc, TPRV, ScriptType::P2wpkh),     // vprv
+];
+
+/// Convert a SLIP-132 extended key to a plain `xpub`/`xprv`/`tpub`/`tprv` key
+fn slip132_to_standard(key: &str) -> Result<(String, ScriptType)> {
+    let mut data = base58::decode_check(key.trim())
+        .map_err(|e| WalletError::Import(format!("Invalid extended key: {e}")))?;
+
+    if data.len() != 78 {
+        return Err(WalletError::Import(
+            "Invalid extended key length".to_string(),
+        ));
+    }
+
+    let version = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    let (_, standard, script_type) = SLIP132_VERSIONS
+        .iter()
+        .find(|(slip132_version, _, _)| *slip132_version == version)
+        .ok_or(WalletError::Import(format!(
+            "Unknown extended key version: {}",
+            &key[..4.min(key.len())]
+        )))?;
+
+    data[..4].copy_from_slice(&standard.to_be_bytes());
+    Ok((base58::encode_check(&data), *script_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // BIP84 accounts of "abandon abandon ... about", testnet and mainnet
+    const FINGERPRINT: &str = "73c5da0a";
+    const ACCOUNT_TPUB: &str = "tpubDC8msFGeGuwnKG9Upg7DM2b4DaRqg3CUZa5g8v2SRQ6K4NSkxUgd7HsL2XVWbVm39yBA4LAxysQAm397zwQSQoQgewGiYZqrA9DsP4zbQ1M";
+    const ACCOUNT_TPRV: &str = "tprv8fSjiqEQ8YG7Ro7gw2ScwcvweYuuWi1ZzGUtrPz918HvDtBzL5s2voFTrN4y3yUwj5cYD54pLhxk6NKCzHUjcka3zbKjbTEcsuAnkzbjhkL";
+    const ACCOUNT_XPUB: &str = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+    const ACCOUNT_XPRV: &str = "xprv9ybY78BftS5UGANki6oSifuQEjkpyAC8ZmBvBNTshQnCBcxnefjHS7buPMkkqhcRzmoGZ5bokx7GuyDAiktd5HemohAU4wV1ZPMDRmLpBMm";
+
+    /// Re-encode a key with SLIP-132 version bytes
+    fn to_slip132(key: &str, version: u32) -> String {
+        let mut data = base58::decode_check(key).unwrap();
+        data[..4].copy_from_slice(&version.to_be_bytes());
+        base58::encode_check(&data)
+    }
+
+    #[test]
+    fn test_import_core_descriptors() {
+        let secret = format!("[{FINGERPRINT}/84'/1'/0']{ACCOUNT_TPRV}");
+
+        let listing = json!({
+            "wallet_name": "core",
+            "descriptors": [
+                { "desc": format!("pkh({secret}/0/*)"), "timestamp": 1_600_000_000, "active": true, "internal": false },
+                { "desc": format!("wpkh({secret}/0/*)"), "timestamp": 1_700_000_000, "active": true, "internal": false },
+                { "desc": format!("wpkh({secret}/1/*)"), "timestamp": 1_650_000_000, "active": true, "internal": true },
+                { "desc": format!("wpkh({secret}/2/*)"), "timestamp": 1_500_000_000, "active": false },
+            ]
+        })
+        .to_string();
+
+        let imported = ImportedWallet::parse(&listing, None).unwrap();
+        assert_eq!(imported.source, ImportSource::BitcoinCore);
+        assert_eq!(imported.name.as_deref(), Some("core"));
+        assert_eq!(imported.script_type, ScriptType::P2wpkh);
+        assert_eq!(imported.birthday, Some(1_650_000_000));
+        assert!(imported.has_private_keys());
+        assert!(!imported.external.contains("tprv"));
+        assert!(imported.external.contains(ACCOUNT_TPUB));
+        assert_eq!(
+            imported.master_fingerprint.unwrap().to_string(),
+            FINGERPRINT
+        );
+
+        // Legacy only has a receive descriptor, so it can't be imported
+        assert!(ImportedWallet::parse(&listing, Some(ScriptType::P2pkh)).is_err());
+    }
+
+    #[test]
+    fn test_import_electrum_wallet() {
+        let tpub = ACCOUNT_TPUB;
+        let vpub = to_slip132(tpub, 0x045f_1c1f);
+
+        let wallet = json!({
+            "wallet_type": "standard",
+            "use_encryption": false,
+            "keystore": {
+                "type": "bip32",
+                "xpub": vpub,
+                "derivation": "m/84'/1'/0'",
+                "root_fingerprint": FINGERPRINT,
+            },
+            "labels": {
+                "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd": "Rent",
+                "tb1qcr8te4kr609gcawutmrza0j4xv80jy8zmfp6l0": "Savings",
+            },
+            "verified_tx3": {
+                "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd": [2_500_000, 1_690_000_000, 1, "00"],
+            },
+        })
+        .to_string();
+
+        let imported = ImportedWallet::parse(&wallet, None).unwrap();
+        assert_eq!(imported.source, ImportSource::Electrum);
+        assert_eq!(imported.script_type, ScriptType::P2wpkh);
+        assert!(!imported.has_private_keys());
+        assert!(imported
+            .external
+            .starts_with(&format!("wpkh([{FINGERPRINT}/84'/1'/0']{tpub}/0/*)")));
+        assert!(imported.internal.contains(&format!("{tpub}/1/*")));
+        assert_eq!(imported.birthday, Some(1_690_000_000));
+        assert_eq!(imported.labels.len(), 2);
+        assert_eq!(imported.labels[0].label_type, LabelType::Tx);
+        assert_eq!(imported.labels[1].label_type, LabelType::Addr);
+
+        let encrypted = json!({ "wallet_type": "standard", "use_encryption": true }).to_string();
+        assert!(ImportedWallet::parse(&encrypted, None).is_err());
+    }
+
+    #[test]
+    fn test_import_electrum_private_keystore() {
+        let wallet = json!({
+            "wallet_type": "standard",
+            "use_encryption": false,
+            "keystore": {
+                "type": "bip32",
+                "xpub": to_slip132(ACCOUNT_XPUB, 0x04b2_4746),
+                "xprv": to_slip132(ACCOUNT_XPRV, 0x04b2_430c),
+                "derivation": "m/84'/0'/0'",
+                "root_fingerprint": FINGERPRINT,
+            },
+        })
+        .to_string();
+
+        let imported = ImportedWallet::parse(&wallet, None).unwrap();
+        assert_eq!(imported.script_type, ScriptType::P2wpkh);
+        assert!(imported.has_private_keys());
+        assert!(imported.external.starts_with(&format!(
+            "wpkh([{FINGERPRINT}/84'/0'/0']{ACCOUNT_XPUB}/0/*)"
+        )));
+        assert!(!imported.external.contains("xprv"));
+
+        // The zprv comes back as a plain xprv in the private descriptors
+        let (external, internal) = imported.private_descriptors.unwrap();
+        assert!(external.contains(&format!("{ACCOUNT_XPRV}/0/*")));
+        assert!(internal.contains(&format!("{ACCOUNT_XPRV}/1/*")));
+    }
+}
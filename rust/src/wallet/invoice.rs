@@ -0,0 +1,354 @@
+//! Invoices: a request for an amount to a fresh address, tracked until paid
+//!
+//! Every invoice gets its own receive address, so whatever pays that address
+//! pays the invoice. Statuses are worked out again after each sync from the
+//! wallet's transactions and the invoice's confirmation policy.
+
+use std::time::Duration;
+
+use bdk_wallet::chain::ChainPosition;
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::{Address as BdkAddress, Script, Txid};
+use lumo_types::{Address, Amount, Bip21Uri};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::{Wallet, WalletId};
+
+pub const DEFAULT_INVOICE_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub const DEFAULT_REQUIRED_CONFIRMATIONS: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvoiceStatus {
+    Unpaid,
+    /// Some, but not all, of the amount has enough confirmations
+    PartiallyPaid,
+    Paid,
+    Overpaid,
+    /// Expired with nothing received, not even unconfirmed
+    Expired,
+}
+
+impl InvoiceStatus {
+    /// The full amount arrived
+    pub fn is_settled(&self) -> bool {
+        matches!(self, InvoiceStatus::Paid | InvoiceStatus::Overpaid)
+    }
+}
+
+impl std::fmt::Display for InvoiceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            InvoiceStatus::Unpaid => "unpaid",
+            InvoiceStatus::PartiallyPaid => "partially paid",
+            InvoiceStatus::Paid => "paid",
+            InvoiceStatus::Overpaid => "overpaid",
+            InvoiceStatus::Expired => "expired",
+        };
+        f.write_str(status)
+    }
+}
+
+/// What to ask for when creating an invoice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvoiceRequest {
+    pub amount: Amount,
+    pub memo: Option<String>,
+    /// `None` never expires
+    pub expiry: Option<Duration>,
+    /// Confirmations a payment needs before it counts, 0 accepts unconfirmed ones
+    pub required_confirmations: u32,
+    /// Payjoin endpoint to offer in the URI, served by lumod
+    pub payjoin: Option<String>,
+}
+
+impl InvoiceRequest {
+    pub fn new(amount: Amount) -> Self {
+        Self {
+            amount,
+            memo: None,
+            expiry: Some(DEFAULT_INVOICE_EXPIRY),
+            required_confirmations: DEFAULT_REQUIRED_CONFIRMATIONS,
+            payjoin: None,
+        }
+    }
+}
+
+/// A transaction paying an invoice's address
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvoicePayment {
+    pub txid: Txid,
+    pub amount: Amount,
+    /// 0 while unconfirmed
+    pub confirmations: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invoice {
+    pub id: String,
+    pub wallet_id: WalletId,
+    pub address: String,
+    /// Index of `address` in the receive keychain
+    pub index: u32,
+    pub amount: Amount,
+    #[serde(default)]
+    pub memo: Option<String>,
+    /// Unix timestamp
+    pub created_at: i64,
+    /// Unix timestamp, `None` never expires
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    pub required_confirmations: u32,
+    pub status: InvoiceStatus,
+    /// Paid with enough confirmations
+    pub received: Amount,
+    /// Paid but still short of the required confirmations
+    pub pending: Amount,
+    #[serde(default)]
+    pub payments: Vec<InvoicePayment>,
+    /// Payjoin endpoint offered in the URI
+    #[serde(default)]
+    pub payjoin: Option<String>,
+}
+
+impl Invoice {
+    pub fn new(
+        wallet_id: WalletId,
+        address: &Address,
+        index: u32,
+        request: InvoiceRequest,
+        now: i64,
+    ) -> Result<Self> {
+        if request.amount.is_dust() {
+            return Err(WalletError::Invoice(format!(
+                "Invoice amount must be above the dust limit, got {} sats",
+                request.amount.as_sat()
+            )));
+        }
+
+        let memo = request
+            .memo
+            .map(|memo| memo.trim().to_string())
+            .filter(|memo| !memo.is_empty());
+        let expires_at = request
+            .expiry
+            .map(|expiry| now.saturating_add(expiry.as_secs() as i64));
+
+        Ok(Self {
+            id: Uuid::new_v4().simple().to_string(),
+            wallet_id,
+            address: address.to_string(),
+            index,
+            amount: request.amount,
+            memo,
+            created_at: now,
+            expires_at,
+            required_confirmations: request.required_confirmations,
+            status: InvoiceStatus::Unpaid,
+            received: Amount::ZERO,
+            pending: Amount::ZERO,
+            payments: Vec::new(),
+            payjoin: request.payjoin,
+        })
+    }
+
+    /// Shortened id for lists, enough to pick the invoice out again
+    pub fn short_id(&self) -> &str {
+        &self.id[..8.min(self.id.len())]
+    }
+
+    /// BIP21 URI to hand to the payer
+    pub fn uri(&self) -> Bip21Uri {
+        // The address came out of this wallet, so its network is already known good
+        let address: BdkAddress<NetworkUnchecked> =
+            self.address.parse().expect("invoice address is valid");
+        let mut uri =
+            Bip21Uri::new(Address::new(address.assume_checked())).with_amount(self.amount);
+        uri.message = self.memo.clone();
+        uri.payjoin = self.payjoin.clone();
+        uri
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Still owed to reach the full amount
+    pub fn outstanding(&self) -> Amount {
+        Amount::from_sat(self.amount.as_sat().saturating_sub(self.received.as_sat()))
+    }
+
+    /// Work the status out again from `payments`, returns whether anything changed
+    ///
+    /// Money that arrives after expiry still counts, an invoice only shows as
+    /// expired if nothing at all was sent to it.
+    pub fn update(&mut self, mut payments: Vec<InvoicePayment>, now: i64) -> bool {
+        payments.sort_by_key(|payment| payment.txid);
+
+        let (mut received, mut pending) = (0, 0);
+        for payment in &payments {
+            if payment.confirmations >= self.required_confirmations {
+                received += payment.amount.as_sat();
+            } else {
+                pending += payment.amount.as_sat();
+            }
+        }
+
+        let amount = self.amount.as_sat();
+        let status = if received > amount {
+            InvoiceStatus::Overpaid
+        } else if received == amount {
+            InvoiceStatus::Paid
+        } else if received > 0 {
+            InvoiceStatus::PartiallyPaid
+        } else if pending == 0 && self.is_expired(now) {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Unpaid
+        };
+
+        let received = Amount::from_sat(received);
+        let pending = Amount::from_sat(pending);
+        let changed = status != self.status
+            || received != self.received
+            || pending != self.pending
+            || payments != self.payments;
+
+        self.status = status;
+        self.received = received;
+        self.pending = pending;
+        self.payments = payments;
+        changed
+    }
+}
+
+/// Every transaction output in `wallet` paying `script`, summed per transaction
+pub fn payments_to(wallet: &Wallet, script: &Script) -> Vec<InvoicePayment> {
+    let tip = wallet.bdk.latest_checkpoint().height();
+
+    wallet
+        .bdk
+        .transactions()
+        .filter_map(|canonical_tx| {
+            let amount: u64 = canonical_tx
+                .tx_node
+                .tx
+                .output
+                .iter()
+                .filter(|output| output.script_pubkey.as_script() == script)
+                .map(|output| output.value.to_sat())
+                .sum();
+            if amount == 0 {
+                return None;
+            }
+
+            let confirmations = match canonical_tx.chain_position {
+                ChainPosition::Confirmed { anchor, .. } => {
+                    tip.saturating_sub(anchor.block_id.height) + 1
+                }
+                ChainPosition::Unconfirmed { .. } => 0,
+            };
+            Some(InvoicePayment {
+                txid: canonical_tx.tx_node.txid,
+                amount: Amount::from_sat(amount),
+                confirmations,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use lumo_types::Network;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn invoice(memo: Option<&str>) -> Invoice {
+        let address = Address::from_string(
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            Network::Testnet,
+        )
+        .unwrap();
+        let request = InvoiceRequest {
+            memo: memo.map(str::to_string),
+            ..InvoiceRequest::new(Amount::from_sat(10_000))
+        };
+        Invoice::new(WalletId::new(), &address, 3, request, NOW).unwrap()
+    }
+
+    fn payment(byte: u8, sats: u64, confirmations: u32) -> InvoicePayment {
+        InvoicePayment {
+            txid: Txid::from_byte_array([byte; 32]),
+            amount: Amount::from_sat(sats),
+            confirmations,
+        }
+    }
+
+    #[test]
+    fn test_status_follows_payments() {
+        let mut invoice = invoice(None);
+        assert!(!invoice.update(vec![], NOW));
+        assert_eq!(invoice.status, InvoiceStatus::Unpaid);
+
+        // Unconfirmed doesn't count yet with the default policy
+        assert!(invoice.update(vec![payment(1, 4_000, 0)], NOW));
+        assert_eq!(invoice.status, InvoiceStatus::Unpaid);
+        assert_eq!(invoice.pending, Amount::from_sat(4_000));
+
+        invoice.update(vec![payment(1, 4_000, 1)], NOW);
+        assert_eq!(invoice.status, InvoiceStatus::PartiallyPaid);
+        assert_eq!(invoice.outstanding(), Amount::from_sat(6_000));
+
+        invoice.update(vec![payment(1, 4_000, 2), payment(2, 6_000, 1)], NOW);
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+
+        invoice.update(vec![payment(1, 4_000, 3), payment(2, 7_000, 2)], NOW);
+        assert_eq!(invoice.status, InvoiceStatus::Overpaid);
+        assert!(invoice.status.is_settled());
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut invoice = invoice(None);
+        let expired = NOW + DEFAULT_INVOICE_EXPIRY.as_secs() as i64;
+
+        invoice.update(vec![], expired - 1);
+        assert_eq!(invoice.status, InvoiceStatus::Unpaid);
+        invoice.update(vec![], expired);
+        assert_eq!(invoice.status, InvoiceStatus::Expired);
+
+        // A payment in flight keeps it open, a late full payment still settles it
+        invoice.update(vec![payment(1, 10_000, 0)], expired);
+        assert_eq!(invoice.status, InvoiceStatus::Unpaid);
+        invoice.update(vec![payment(1, 10_000, 1)], expired);
+        assert_eq!(invoice.status, InvoiceStatus::Paid);
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(
+            invoice(Some("Order #42 & co")).uri().to_string(),
+            "bitcoin:tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx?amount=0.0001&message=Order%20%2342%20%26%20co"
+        );
+        assert_eq!(
+            invoice(Some("  ")).uri().to_string(),
+            "bitcoin:tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx?amount=0.0001"
+        );
+    }
+
+    #[test]
+    fn test_dust_amount_is_rejected() {
+        let address = Address::from_string(
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            Network::Testnet,
+        )
+        .unwrap();
+        let request = InvoiceRequest::new(Amount::from_sat(100));
+        assert!(Invoice::new(WalletId::new(), &address, 0, request, NOW).is_err());
+    }
+}
@@ -4,6 +4,10 @@ use derive_more::{Display, From, Into};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::wallet::multisig::MultisigConfig;
+use crate::GAP_LIMIT;
+use bdk_wallet::miniscript::descriptor::{Descriptor, ShInner};
+use bdk_wallet::miniscript::MiniscriptKey;
 use lumo_types::Network;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Display, From, Into, Serialize, Deserialize)]
@@ -35,6 +39,7 @@ pub enum WalletType {
     Hot,
     Cold,
     XpubOnly,
+    Multisig,
 }
 
 impl WalletType {
@@ -44,6 +49,7 @@ impl WalletType {
             WalletType::Hot => true,
             WalletType::Cold => true,
             WalletType::XpubOnly => false,
+            WalletType::Multisig => true,
         }
     }
 
@@ -53,10 +59,166 @@ impl WalletType {
             WalletType::Hot => "Hot wallet, software wallet (can sign transactions)",
             WalletType::Cold => "Hardware wallet (requires device to sign)",
             WalletType::XpubOnly => "Watch-only wallet (cannot sign transactions)",
+            WalletType::Multisig => "Multisig wallet (needs signatures from m of n cosigners)",
         }
     }
 }
 
+/// Output script type of a single-sig wallet, or P2WSH for multisig
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    P2pkh,
+    P2shP2wpkh,
+    #[default]
+    P2wpkh,
+    P2tr,
+    P2wsh,
+}
+
+impl ScriptType {
+    // Script type of a descriptor, if it's one lumo supports
+    pub fn from_descriptor<Pk: MiniscriptKey>(descriptor: &Descriptor<Pk>) -> Option<Self> {
+        match descriptor {
+            Descriptor::Pkh(_) => Some(ScriptType::P2pkh),
+            Descriptor::Wpkh(_) => Some(ScriptType::P2wpkh),
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(_) => Some(ScriptType::P2shP2wpkh),
+                _ => None,
+            },
+            Descriptor::Tr(_) => Some(ScriptType::P2tr),
+            Descriptor::Wsh(_) => Some(ScriptType::P2wsh),
+            _ => None,
+        }
+    }
+
+    // Script type description
+    pub fn description(&self) -> &'static str {
+        match self {
+            ScriptType::P2pkh => "Legacy (P2PKH)",
+            ScriptType::P2shP2wpkh => "Nested SegWit (P2SH-P2WPKH)",
+            ScriptType::P2wpkh => "Native SegWit (P2WPKH)",
+            ScriptType::P2tr => "Taproot (P2TR)",
+            ScriptType::P2wsh => "SegWit script (P2WSH)",
+        }
+    }
+}
+
+impl FromStr for ScriptType {
+    type Err = crate::wallet::error::WalletError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "p2pkh" | "legacy" => Ok(ScriptType::P2pkh),
+            "p2sh-p2wpkh" | "nested" => Ok(ScriptType::P2shP2wpkh),
+            "p2wpkh" | "native" => Ok(ScriptType::P2wpkh),
+            "p2tr" | "taproot" => Ok(ScriptType::P2tr),
+            "p2wsh" => Ok(ScriptType::P2wsh),
+            _ => Err(crate::wallet::error::WalletError::Generic(format!(
+                "Invalid script type: {s}. Valid options: p2pkh, p2sh-p2wpkh, p2wpkh, p2tr, p2wsh"
+            ))),
+        }
+    }
+}
+
+/// Earliest point a wallet can have history, as a block height or a date
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Birthday {
+    Height(u32),
+    /// Unix timestamp, resolved to a height on the first sync
+    Date(i64),
+}
+
+impl Birthday {
+    pub fn now() -> Self {
+        Birthday::Date(chrono::Utc::now().timestamp())
+    }
+
+    pub fn height(&self) -> Option<u32> {
+        match self {
+            Birthday::Height(height) => Some(*height),
+            Birthday::Date(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Birthday {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Birthday::Height(height) => write!(f, "block {height}"),
+            Birthday::Date(timestamp) => match chrono::DateTime::from_timestamp(*timestamp, 0) {
+                Some(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+                None => write!(f, "timestamp {timestamp}"),
+            },
+        }
+    }
+}
+
+impl FromStr for Birthday {
+    type Err = crate::wallet::error::WalletError;
+
+    // A block height (`850000`) or a date (`2024-06-30`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(height) = s.parse::<u32>() {
+            return Ok(Birthday::Height(height));
+        }
+
+        chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date| Birthday::Date(date.and_utc().timestamp()))
+            .ok_or_else(|| {
+                crate::wallet::error::WalletError::Generic(format!(
+                    "Invalid birthday: {s}. Use a block height or a date (YYYY-MM-DD)"
+                ))
+            })
+    }
+}
+
+/// Largest stop gap a wallet can be set to
+pub const MAX_STOP_GAP: u32 = 1_000;
+
+/// Most requests a wallet can have in flight at once while scanning
+pub const MAX_PARALLEL_REQUESTS: u32 = 32;
+
+/// How a wallet's history is fetched from the node
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ScanSettings {
+    /// Unused addresses in a row before a keychain's scan stops
+    pub stop_gap: u32,
+    /// Requests in flight at once, public servers cap this lower
+    pub parallel_requests: u32,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            stop_gap: GAP_LIMIT as u32,
+            parallel_requests: 4,
+        }
+    }
+}
+
+impl ScanSettings {
+    pub fn validate(&self) -> crate::wallet::error::Result<()> {
+        if !(1..=MAX_STOP_GAP).contains(&self.stop_gap) {
+            return Err(crate::wallet::error::WalletError::Generic(format!(
+                "Invalid stop gap: {}. Use 1 to {MAX_STOP_GAP}",
+                self.stop_gap
+            )));
+        }
+        if !(1..=MAX_PARALLEL_REQUESTS).contains(&self.parallel_requests) {
+            return Err(crate::wallet::error::WalletError::Generic(format!(
+                "Invalid parallel requests: {}. Use 1 to {MAX_PARALLEL_REQUESTS}",
+                self.parallel_requests
+            )));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalletMetadata {
     pub id: WalletId,
@@ -65,10 +227,31 @@ pub struct WalletMetadata {
     pub created_at: String, // ISO timestamp
     #[serde(default)]
     pub wallet_type: WalletType,
+    #[serde(default)]
+    pub script_type: ScriptType,
     pub master_fingerprint: Option<String>,
     // For hot wallets: store mnemonic for signing capability
     // TODO: Encrypt this in production
     pub mnemonic: Option<String>,
+    // For multisig wallets: threshold and cosigner keys
+    #[serde(default)]
+    pub multisig: Option<MultisigConfig>,
+    // For wallets imported from private descriptors: receive and change descriptors,
+    // stored the same way as the mnemonic
+    #[serde(default)]
+    pub private_descriptors: Option<(String, String)>,
+    // History before the birthday is skipped when scanning, unknown means scan everything
+    #[serde(default)]
+    pub birthday: Option<Birthday>,
+    // Archived wallets are hidden from the wallet list but kept in the database
+    #[serde(default)]
+    pub archived: bool,
+    // Stop gap and parallelism used when syncing
+    #[serde(default)]
+    pub scan: ScanSettings,
+    // Unix timestamp of the last sync that completed, none if never synced
+    #[serde(default)]
+    pub last_synced: Option<i64>,
 }
 
 impl WalletMetadata {
@@ -79,8 +262,15 @@ impl WalletMetadata {
             network,
             created_at: chrono::Utc::now().to_rfc3339(),
             wallet_type: WalletType::Hot, // Default to Hot wallet
+            script_type: ScriptType::P2wpkh,
             master_fingerprint: None,
             mnemonic: None,
+            multisig: None,
+            private_descriptors: None,
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
         }
     }
 
@@ -96,8 +286,15 @@ impl WalletMetadata {
             network,
             created_at: chrono::Utc::now().to_rfc3339(),
             wallet_type: WalletType::Cold,
+            script_type: ScriptType::P2wpkh,
             master_fingerprint: fingerprint,
             mnemonic: None, // Hardware wallets don't store mnemonics
+            multisig: None,
+            private_descriptors: None,
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
         }
     }
 
@@ -114,8 +311,15 @@ impl WalletMetadata {
             network,
             created_at: chrono::Utc::now().to_rfc3339(),
             wallet_type: WalletType::Hot,
+            script_type: ScriptType::P2wpkh,
             master_fingerprint: fingerprint,
             mnemonic: Some(mnemonic),
+            multisig: None,
+            private_descriptors: None,
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
         }
     }
 
@@ -134,8 +338,109 @@ impl WalletMetadata {
                 Some(_) => WalletType::Cold,
                 None => WalletType::XpubOnly,
             },
+            script_type: ScriptType::P2wpkh,
             master_fingerprint: fingerprint,
             mnemonic: None, // Xpub-only wallets don't store mnemonics
+            multisig: None,
+            private_descriptors: None,
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
+        }
+    }
+
+    pub fn new_multisig(name: String, network: Network, config: MultisigConfig) -> Self {
+        Self {
+            id: WalletId::new(),
+            name,
+            network,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            wallet_type: WalletType::Multisig,
+            script_type: ScriptType::P2wsh,
+            master_fingerprint: config
+                .local_fingerprint
+                .as_ref()
+                .map(|fp| fp.to_uppercase()),
+            mnemonic: None, // Set by the caller when we hold one of the cosigner keys
+            multisig: Some(config),
+            private_descriptors: None,
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
+        }
+    }
+
+    pub fn new_from_descriptors(
+        name: String,
+        network: Network,
+        script_type: ScriptType,
+        fingerprint: Option<String>,
+        can_sign: bool,
+    ) -> Self {
+        Self {
+            id: WalletId::new(),
+            name,
+            network,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            wallet_type: if can_sign {
+                WalletType::Hot
+            } else {
+                WalletType::XpubOnly
+            },
+            script_type,
+            master_fingerprint: fingerprint,
+            mnemonic: None,
+            multisig: None,
+            private_descriptors: None, // Set by the caller when the source had private keys
+            birthday: None,
+            archived: false,
+            scan: ScanSettings::default(),
+            last_synced: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_birthday_parsing() {
+        assert_eq!(
+            "850000".parse::<Birthday>().unwrap(),
+            Birthday::Height(850000)
+        );
+        assert_eq!(
+            "2024-06-30".parse::<Birthday>().unwrap(),
+            Birthday::Date(1719705600)
+        );
+        assert_eq!(Birthday::Date(1719705600).to_string(), "2024-06-30");
+        assert!("last summer".parse::<Birthday>().is_err());
+    }
+
+    #[test]
+    fn test_scan_settings() {
+        assert!(ScanSettings::default().validate().is_ok());
+        assert!(ScanSettings {
+            stop_gap: 0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(ScanSettings {
+            parallel_requests: 64,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+
+        // Wallets saved before scan settings existed get the defaults
+        let metadata = WalletMetadata::new("Old".to_string(), Network::Testnet);
+        let mut json = serde_json::to_value(&metadata).unwrap();
+        json.as_object_mut().unwrap().remove("scan");
+        let metadata: WalletMetadata = serde_json::from_value(json).unwrap();
+        assert_eq!(metadata.scan, ScanSettings::default());
+    }
+}
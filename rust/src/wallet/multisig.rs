@@ -0,0 +1,300 @@
+use std::str::FromStr;
+
+use bdk_wallet::miniscript::descriptor::{DescriptorPublicKey, Wildcard};
+use bdk_wallet::KeychainKind;
+use bip39::Mnemonic;
+use bitcoin::bip32::{DerivationPath, Xpriv, Xpub};
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::Secp256k1;
+use serde::{Deserialize, Serialize};
+
+use crate::wallet::error::{Result, WalletError};
+use crate::wallet::metadata::WalletId;
+use lumo_types::Network;
+
+/// Maximum number of keys allowed in a `wsh(sortedmulti(...))` descriptor
+pub const MAX_COSIGNERS: usize = 20;
+
+/// A cosigner in a multisig wallet, identified by its key origin and account xpub
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cosigner {
+    /// Key expression with origin, e.g. `[d34db33f/48h/0h/0h/2h]xpub...`
+    pub key: String,
+    /// Master fingerprint of the cosigner (lowercase hex)
+    pub fingerprint: String,
+}
+
+impl Cosigner {
+    /// Parse a cosigner from a key expression with origin info
+    pub fn from_key_expression(key: &str) -> Result<Self> {
+        let parsed = DescriptorPublicKey::from_str(key.trim())
+            .map_err(|e| WalletError::Multisig(format!("Invalid cosigner key {key}: {e}")))?;
+
+        let DescriptorPublicKey::XPub(xkey) = &parsed else {
+            return Err(WalletError::Multisig(format!(
+                "Cosigner key must be an extended public key: {key}"
+            )));
+        };
+
+        if xkey.origin.is_none() {
+            return Err(WalletError::Multisig(format!(
+                "Cosigner key is missing its origin [fingerprint/path]: {key}"
+            )));
+        }
+
+        if xkey.wildcard != Wildcard::None || !xkey.derivation_path.is_master() {
+            return Err(WalletError::Multisig(format!(
+                "Cosigner key must be an account xpub without derivation steps: {key}"
+            )));
+        }
+
+        Ok(Self {
+            key: parsed.to_string(),
+            fingerprint: parsed.master_fingerprint().to_string(),
+        })
+    }
+}
+
+/// Local cosigner key derived from a mnemonic (BIP48, script type 2 = P2WSH)
+pub struct LocalCosigner {
+    pub cosigner: Cosigner,
+    /// Same key expression as `cosigner.key`, but with the account xprv
+    pub secret_key: String,
+    pub account_xprv: Xpriv,
+}
+
+impl LocalCosigner {
+    /// Derive the BIP48 account key `m/48'/coin'/0'/2'` from a mnemonic
+    pub fn from_mnemonic(mnemonic: &Mnemonic, network: Network) -> Result<Self> {
+        let secp = Secp256k1::new();
+        let seed = mnemonic.to_seed("");
+        let master = Xpriv::new_master(network.to_bitcoin_network(), &seed)?;
+        let fingerprint = master.fingerprint(&secp);
+
+        let coin_type = if network == Network::Mainnet { 0 } else { 1 };
+        let origin_path = format!("48h/{coin_type}h/0h/2h");
+        let path = DerivationPath::from_str(&format!("m/{origin_path}"))?;
+
+        let account_xprv = master.derive_priv(&secp, &path)?;
+        let account_xpub = Xpub::from_priv(&secp, &account_xprv);
+
+        let cosigner =
+            Cosigner::from_key_expression(&format!("[{fingerprint}/{origin_path}]{account_xpub}"))?;
+
+        Ok(Self {
+            cosigner,
+            secret_key: format!("[{fingerprint}/{origin_path}]{account_xprv}"),
+            account_xprv,
+        })
+    }
+}
+
+/// m-of-n multisig configuration using `wsh(sortedmulti(...))`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigConfig {
+    pub threshold: usize,
+    pub cosigners: Vec<Cosigner>,
+    /// Fingerprint of the cosigner whose key we hold, if any
+    #[serde(default)]
+    pub local_fingerprint: Option<String>,
+}
+
+impl MultisigConfig {
+    pub fn new(
+        threshold: usize,
+        cosigners: Vec<Cosigner>,
+        local_fingerprint: Option<String>,
+    ) -> Result<Self> {
+        let n = cosigners.len();
+        if !(2..=MAX_COSIGNERS).contains(&n) {
+            return Err(WalletError::Multisig(format!(
+                "Multisig needs between 2 and {MAX_COSIGNERS} cosigners, got {n}"
+            )));
+        }
+
+        if threshold == 0 || threshold > n {
+            return Err(WalletError::Multisig(format!(
+                "Threshold must be between 1 and {n}, got {threshold}"
+            )));
+        }
+
+        for (i, cosigner) in cosigners.iter().enumerate() {
+            if cosigners[..i].iter().any(|other| other.key == cosigner.key) {
+                return Err(WalletError::Multisig(format!(
+                    "Duplicate cosigner key: {}",
+                    cosigner.key
+                )));
+            }
+        }
+
+        Ok(Self {
+            threshold,
+            cosigners,
+            local_fingerprint,
+        })
+    }
+
+    /// Public descriptor for the given keychain (without checksum)
+    pub fn descriptor(&self, keychain: KeychainKind) -> String {
+        self.build_descriptor(keychain, None)
+    }
+
+    /// Descriptor with the local cosigner's xpub swapped for its xprv, used for signing
+    pub fn signing_descriptor(&self, keychain: KeychainKind, local: &LocalCosigner) -> String {
+        self.build_descriptor(keychain, Some(local))
+    }
+
+    fn build_descriptor(&self, keychain: KeychainKind, local: Option<&LocalCosigner>) -> String {
+        let branch = match keychain {
+            KeychainKind::External => 0,
+            KeychainKind::Internal => 1,
+        };
+
+        let keys: Vec<String> = self
+            .cosigners
+            .iter()
+            .map(|cosigner| {
+                let key = match local {
+                    Some(local) if local.cosigner.key == cosigner.key => &local.secret_key,
+                    _ => &cosigner.key,
+                };
+                format!("{key}/{branch}/*")
+            })
+            .collect();
+
+        format!("wsh(sortedmulti({},{}))", self.threshold, keys.join(","))
+    }
+
+    /// Number of cosigners (n in m-of-n)
+    pub fn total(&self) -> usize {
+        self.cosigners.len()
+    }
+}
+
+/// How many signatures a PSBT has collected towards the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureStatus {
+    pub collected: usize,
+    pub required: usize,
+}
+
+impl SignatureStatus {
+    /// Count signatures, taking the least-signed input as the PSBT's progress
+    pub fn from_psbt(psbt: &Psbt, required: usize) -> Self {
+        let collected = psbt
+            .inputs
+            .iter()
+            .map(|input| {
+                if input.final_script_witness.is_some() || input.final_script_sig.is_some() {
+                    required
+                } else {
+                    input.partial_sigs.len()
+                }
+            })
+            .min()
+            .unwrap_or(0);
+
+        Self {
+            collected,
+            required,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.collected >= self.required
+    }
+}
+
+/// A multisig PSBT waiting for cosigner signatures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPsbt {
+    pub wallet_id: WalletId,
+    pub txid: String,
+    /// Base64 encoded PSBT with all signatures collected so far
+    pub psbt: String,
+    pub signatures: SignatureStatus,
+    pub updated_at: String, // ISO timestamp
+}
+
+impl PendingPsbt {
+    pub fn new(wallet_id: WalletId, psbt: &Psbt, required: usize) -> Self {
+        Self {
+            wallet_id,
+            txid: psbt.unsigned_tx.compute_txid().to_string(),
+            psbt: psbt.to_string(),
+            signatures: SignatureStatus::from_psbt(psbt, required),
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    pub fn psbt(&self) -> Result<Psbt> {
+        Psbt::from_str(&self.psbt)
+            .map_err(|e| WalletError::Generic(format!("Invalid stored PSBT: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk_wallet::miniscript::Descriptor;
+
+    const MNEMONICS: [&str; 3] = [
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+    ];
+
+    fn cosigners() -> Vec<LocalCosigner> {
+        MNEMONICS
+            .iter()
+            .map(|m| {
+                let mnemonic = Mnemonic::from_str(m).unwrap();
+                LocalCosigner::from_mnemonic(&mnemonic, Network::Testnet).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_multisig_descriptor() {
+        let locals = cosigners();
+        let config =
+            MultisigConfig::new(2, locals.iter().map(|l| l.cosigner.clone()).collect(), None)
+                .unwrap();
+
+        let external = config.descriptor(KeychainKind::External);
+        assert!(external.starts_with("wsh(sortedmulti(2,"));
+        assert!(external.contains("/0/*"));
+        assert!(Descriptor::<DescriptorPublicKey>::from_str(&external).is_ok());
+
+        let internal = config.descriptor(KeychainKind::Internal);
+        assert!(internal.contains("/1/*"));
+
+        let signing = config.signing_descriptor(KeychainKind::External, &locals[0]);
+        assert!(signing.contains("tprv"));
+        assert_eq!(signing.matches("tpub").count(), 2);
+    }
+
+    #[test]
+    fn test_multisig_config_validation() {
+        let locals = cosigners();
+        let keys: Vec<Cosigner> = locals.iter().map(|l| l.cosigner.clone()).collect();
+
+        assert!(MultisigConfig::new(0, keys.clone(), None).is_err());
+        assert!(MultisigConfig::new(4, keys.clone(), None).is_err());
+        assert!(MultisigConfig::new(1, keys[..1].to_vec(), None).is_err());
+
+        let duplicated = vec![keys[0].clone(), keys[0].clone()];
+        assert!(MultisigConfig::new(1, duplicated, None).is_err());
+    }
+
+    #[test]
+    fn test_cosigner_requires_origin() {
+        let locals = cosigners();
+        let key = &locals[0].cosigner.key;
+        let without_origin = &key[key.find(']').unwrap() + 1..];
+
+        assert!(Cosigner::from_key_expression(key).is_ok());
+        assert!(Cosigner::from_key_expression(without_origin).is_err());
+        assert!(Cosigner::from_key_expression(&format!("{key}/0/*")).is_err());
+    }
+}
@@ -0,0 +1,954 @@
+//! Payjoin (BIP78): the receiver adds a coin of its own to the payment
+//!
+//! The sender posts its signed original PSBT to the `pj=` endpoint of a BIP21
+//! URI. The receiver answers with a proposal that also spends one of its coins
+//! and pays it into the payment output, which breaks the assumption that every
+//! input of a transaction belongs to the payer. The sender checks that the
+//! proposal can't cost it more than it offered, signs its inputs again and
+//! broadcasts it, falling back to the original if anything goes wrong.
+//!
+//! Output substitution isn't supported: the payment output always stays where
+//! the URI put it.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use bitcoin::psbt::{Input, Output, Psbt};
+use bitcoin::{
+    FeeRate as BtcFeeRate, OutPoint, Script, ScriptBuf, TxIn, TxOut, Txid, Weight, Witness,
+};
+use lumo_types::{Amount, FeeRate};
+use serde::Deserialize;
+
+use crate::wallet::error::WalletError;
+
+/// The only protocol version there is
+pub const VERSION: u32 = 1;
+
+/// How long the sender waits for a proposal before broadcasting the original
+pub const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error)]
+pub enum PayjoinError {
+    /// The receiver can't payjoin right now, e.g. it has no coin to add
+    #[error("Payjoin unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Payjoin version not supported")]
+    VersionUnsupported,
+
+    #[error("Not enough money for the requested fee")]
+    NotEnoughMoney,
+
+    #[error("Original PSBT rejected: {0}")]
+    OriginalRejected(String),
+
+    /// The receiver answered with one of the errors above
+    #[error("Payjoin receiver refused ({code}): {message}")]
+    Receiver { code: String, message: String },
+
+    #[error("Invalid payjoin proposal: {0}")]
+    InvalidProposal(String),
+
+    #[error("Payjoin request failed: {0}")]
+    Http(String),
+}
+
+impl PayjoinError {
+    /// BIP78 error code to send back to the sender
+    pub fn code(&self) -> &'static str {
+        match self {
+            PayjoinError::Unavailable(_) => "unavailable",
+            PayjoinError::VersionUnsupported => "version-unsupported",
+            PayjoinError::NotEnoughMoney => "not-enough-money",
+            _ => "original-psbt-rejected",
+        }
+    }
+}
+
+impl From<PayjoinError> for WalletError {
+    fn from(err: PayjoinError) -> Self {
+        WalletError::Payjoin(err.to_string())
+    }
+}
+
+/// What came of a payjoin send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayjoinOutcome {
+    /// The receiver's proposal was signed and broadcast
+    Payjoin(Txid),
+    /// The payjoin failed, so the original transaction was broadcast instead
+    Fallback { txid: Txid, reason: String },
+}
+
+impl PayjoinOutcome {
+    pub fn txid(&self) -> Txid {
+        match self {
+            PayjoinOutcome::Payjoin(txid) | PayjoinOutcome::Fallback { txid, .. } => *txid,
+        }
+    }
+}
+
+/// Options the sender passes in the endpoint's query string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayjoinParams {
+    /// Output the receiver may take extra fee from, the sender's change
+    pub additional_fee_output_index: Option<usize>,
+    /// Most the sender pays toward the receiver's input
+    pub max_additional_fee_contribution: Amount,
+    /// Fee rate the proposal must not fall below
+    pub min_fee_rate: Option<FeeRate>,
+    pub disable_output_substitution: bool,
+}
+
+impl Default for PayjoinParams {
+    fn default() -> Self {
+        Self {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: Amount::ZERO,
+            min_fee_rate: None,
+            disable_output_substitution: true,
+        }
+    }
+}
+
+impl PayjoinParams {
+    pub fn to_query(&self) -> String {
+        let mut query = format!("v={VERSION}");
+        if let Some(index) = self.additional_fee_output_index {
+            query.push_str(&format!(
+                "&additionalfeeoutputindex={index}&maxadditionalfeecontribution={}",
+                self.max_additional_fee_contribution.as_sat()
+            ));
+        }
+        if let Some(fee_rate) = self.min_fee_rate {
+            query.push_str(&format!("&minfeerate={}", fee_rate.as_sat_per_vb()));
+        }
+        if self.disable_output_substitution {
+            query.push_str("&disableoutputsubstitution=true");
+        }
+        query
+    }
+
+    /// Parse the receiver's side, unknown parameters are ignored
+    pub fn from_query(query: &str) -> Result<Self, PayjoinError> {
+        let invalid =
+            |key: &str| PayjoinError::OriginalRejected(format!("Invalid query parameter {key}"));
+
+        let mut params = Self {
+            disable_output_substitution: false,
+            ..Self::default()
+        };
+        let mut version = None;
+        let mut fee_index = None;
+        let mut max_fee = None;
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key {
+                "v" => version = Some(value.parse::<u32>().map_err(|_| invalid(key))?),
+                "additionalfeeoutputindex" => {
+                    fee_index = Some(value.parse::<usize>().map_err(|_| invalid(key))?)
+                }
+                "maxadditionalfeecontribution" => {
+                    max_fee = Some(value.parse::<u64>().map_err(|_| invalid(key))?)
+                }
+                "minfeerate" => {
+                    let fee_rate = value.parse::<f32>().map_err(|_| invalid(key))?;
+                    if !fee_rate.is_finite() || fee_rate < 0.0 {
+                        return Err(invalid(key));
+                    }
+                    params.min_fee_rate = Some(FeeRate::from_sat_per_vb(fee_rate));
+                }
+                "disableoutputsubstitution" => {
+                    params.disable_output_substitution =
+                        value.parse::<bool>().map_err(|_| invalid(key))?
+                }
+                _ => {}
+            }
+        }
+
+        if version != Some(VERSION) {
+            return Err(PayjoinError::VersionUnsupported);
+        }
+        // Only one half of the fee contribution means none at all
+        if let (Some(index), Some(max_fee)) = (fee_index, max_fee) {
+            params.additional_fee_output_index = Some(index);
+            params.max_additional_fee_contribution = Amount::from_sat(max_fee);
+        }
+        Ok(params)
+    }
+}
+
+/// The sender's half: post the original and check what comes back
+#[derive(Debug, Clone)]
+pub struct PayjoinSender {
+    endpoint: String,
+    /// Before signing, still holding what's needed to sign our inputs again
+    unsigned: Psbt,
+    /// Signed and finalized, broadcast if the payjoin fails
+    original: Psbt,
+    payee: ScriptBuf,
+    params: PayjoinParams,
+}
+
+impl PayjoinSender {
+    /// `change` is the index of our change output, which pays our share of the
+    /// receiver's input fee at `fee_rate`
+    pub fn new(
+        endpoint: &str,
+        unsigned: Psbt,
+        original: Psbt,
+        payee: ScriptBuf,
+        change: Option<usize>,
+        fee_rate: FeeRate,
+    ) -> Result<Self, PayjoinError> {
+        if !is_secure_endpoint(endpoint) {
+            return Err(PayjoinError::Http(format!(
+                "Payjoin endpoint must use https or be an onion service: {endpoint}"
+            )));
+        }
+        if !original.inputs.iter().all(is_finalized) {
+            return Err(PayjoinError::OriginalRejected(
+                "Original transaction isn't fully signed".to_string(),
+            ));
+        }
+
+        // Offer to pay for one input like ours, the receiver's should match it
+        let max_additional_fee_contribution = first_prevout(&original)
+            .and_then(|prevout| input_weight(&prevout.script_pubkey))
+            .map_or(Amount::ZERO, |weight| fee_for(fee_rate.into(), weight));
+
+        Ok(Self {
+            endpoint: endpoint.to_string(),
+            unsigned,
+            original,
+            payee,
+            params: PayjoinParams {
+                additional_fee_output_index: change,
+                max_additional_fee_contribution,
+                min_fee_rate: Some(fee_rate),
+                disable_output_substitution: true,
+            },
+        })
+    }
+
+    pub fn params(&self) -> &PayjoinParams {
+        &self.params
+    }
+
+    /// Endpoint with our parameters appended
+    pub fn url(&self) -> String {
+        let separator = if self.endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!("{}{separator}{}", self.endpoint, self.params.to_query())
+    }
+
+    /// The original transaction, to broadcast when the payjoin doesn't work out
+    pub fn original_transaction(&self) -> Result<bitcoin::Transaction, PayjoinError> {
+        self.original
+            .clone()
+            .extract_tx()
+            .map_err(|e| PayjoinError::OriginalRejected(e.to_string()))
+    }
+
+    /// Post the original PSBT and return the receiver's proposal, unchecked
+    pub async fn post(&self) -> Result<Psbt, PayjoinError> {
+        let http = |e: reqwest::Error| PayjoinError::Http(e.to_string());
+
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(http)?;
+        let response = client
+            .post(self.url())
+            .header("Content-Type", "text/plain")
+            .body(self.original.to_string())
+            .send()
+            .await
+            .map_err(http)?;
+
+        let status = response.status();
+        let body = response.text().await.map_err(http)?;
+        if !status.is_success() {
+            return Err(receiver_error(status.as_u16(), &body));
+        }
+
+        body.trim()
+            .parse::<Psbt>()
+            .map_err(|e| PayjoinError::InvalidProposal(format!("Not a PSBT: {e}")))
+    }
+
+    /// Check `proposal` against the BIP78 sender rules and get it ready to sign
+    ///
+    /// `is_mine` tells whether a script belongs to the sending wallet. On
+    /// success our inputs hold what they held before signing the original, so
+    /// the wallet can sign them again.
+    pub fn check_proposal(
+        &self,
+        mut proposal: Psbt,
+        is_mine: impl Fn(&Script) -> bool,
+    ) -> Result<Psbt, PayjoinError> {
+        let invalid = |reason: &str| PayjoinError::InvalidProposal(reason.to_string());
+        let original_tx = &self.original.unsigned_tx;
+        let proposal_tx = &proposal.unsigned_tx;
+
+        if proposal_tx.version != original_tx.version {
+            return Err(invalid("Transaction version changed"));
+        }
+        if proposal_tx.lock_time != original_tx.lock_time {
+            return Err(invalid("Lock time changed"));
+        }
+
+        let original_inputs: HashMap<OutPoint, usize> = original_tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(index, txin)| (txin.previous_output, index))
+            .collect();
+        let sequence = original_tx.input[0].sequence;
+        let input_type =
+            first_prevout(&self.original).map(|prevout| script_type(&prevout.script_pubkey));
+
+        let mut ours = Vec::new();
+        let mut input_value = 0;
+        let mut receiver_inputs = 0;
+        for (index, (txin, input)) in proposal_tx.input.iter().zip(&proposal.inputs).enumerate() {
+            if txin.sequence != sequence {
+                return Err(invalid("Input sequence changed"));
+            }
+
+            if let Some(&original_index) = original_inputs.get(&txin.previous_output) {
+                if is_finalized(input) {
+                    return Err(invalid("One of our inputs is already finalized"));
+                }
+                if ours.iter().any(|&(_, seen)| seen == original_index) {
+                    return Err(invalid("One of our inputs is spent twice"));
+                }
+                let prevout = prevout(
+                    &original_tx.input[original_index],
+                    &self.original.inputs[original_index],
+                )
+                .ok_or_else(|| invalid("Original input has no UTXO"))?;
+                input_value += prevout.value.to_sat();
+                ours.push((index, original_index));
+                continue;
+            }
+
+            if !is_finalized(input) {
+                return Err(invalid("Receiver input isn't signed"));
+            }
+            let prevout =
+                prevout(txin, input).ok_or_else(|| invalid("Receiver input has no UTXO"))?;
+            if is_mine(prevout.script_pubkey.as_script()) {
+                return Err(invalid("Receiver input spends one of our coins"));
+            }
+            if input_type
+                .is_some_and(|input_type| input_type != script_type(&prevout.script_pubkey))
+            {
+                return Err(invalid("Receiver input is of a different script type"));
+            }
+            input_value += prevout.value.to_sat();
+            receiver_inputs += 1;
+        }
+        if ours.len() != original_inputs.len() {
+            return Err(invalid("One of our inputs is missing"));
+        }
+        if receiver_inputs == 0 {
+            return Err(invalid("Receiver added no inputs"));
+        }
+
+        // Every original output has to still be there, at least as big unless it's
+        // the one we offered fee from
+        let mut matched = vec![false; proposal_tx.output.len()];
+        for (index, original) in original_tx.output.iter().enumerate() {
+            let found = proposal_tx
+                .output
+                .iter()
+                .enumerate()
+                .position(|(i, output)| {
+                    !matched[i] && output.script_pubkey == original.script_pubkey
+                })
+                .ok_or_else(|| invalid("One of the original outputs is missing"))?;
+            matched[found] = true;
+
+            let (before, after) = (original.value, proposal_tx.output[found].value);
+            if original.script_pubkey == self.payee {
+                if after < before {
+                    return Err(invalid("Payment output was lowered"));
+                }
+            } else if Some(index) == self.params.additional_fee_output_index {
+                let contribution = before.checked_sub(after).unwrap_or_default();
+                if contribution.to_sat() > self.params.max_additional_fee_contribution.as_sat() {
+                    return Err(invalid("Takes more fee from us than we offered"));
+                }
+            } else if after != before {
+                return Err(invalid("One of our outputs changed"));
+            }
+        }
+        let added_outputs = proposal_tx
+            .output
+            .iter()
+            .zip(&matched)
+            .filter(|(_, matched)| !**matched);
+        for (output, _) in added_outputs {
+            if is_mine(output.script_pubkey.as_script()) {
+                return Err(invalid("Receiver added an output paying us"));
+            }
+        }
+
+        // Weigh it as it will be signed: our witnesses from the original, the receiver's as sent
+        let mut signed = proposal_tx.clone();
+        for (txin, input) in signed.input.iter_mut().zip(&proposal.inputs) {
+            let input = match original_inputs.get(&txin.previous_output) {
+                Some(&original_index) => &self.original.inputs[original_index],
+                None => input,
+            };
+            txin.script_sig = input.final_script_sig.clone().unwrap_or_default();
+            txin.witness = input.final_script_witness.clone().unwrap_or_default();
+        }
+        let output_value: u64 = signed
+            .output
+            .iter()
+            .map(|output| output.value.to_sat())
+            .sum();
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| invalid("Outputs are worth more than the inputs"))?;
+        if let Some(min_fee_rate) = self.params.min_fee_rate {
+            let min_fee_rate: BtcFeeRate = min_fee_rate.into();
+            if fee * 1000 < min_fee_rate.to_sat_per_kwu() * signed.weight().to_wu() {
+                return Err(invalid("Fee rate is below the minimum"));
+            }
+        }
+
+        for (index, original_index) in ours {
+            proposal.inputs[index] = self.unsigned.inputs[original_index].clone();
+        }
+        Ok(proposal)
+    }
+}
+
+/// A receiver coin to add to the original
+#[derive(Debug, Clone)]
+pub struct Contribution {
+    pub outpoint: OutPoint,
+    /// With `witness_utxo` or `non_witness_utxo` filled in
+    pub input: Input,
+    /// Where it goes among the inputs, past the end appends it
+    pub position: usize,
+}
+
+/// The receiver's half: check the original and add `contribution` to it
+///
+/// The proposal pays the coin into `receiver_output`, less whatever the fee
+/// for the extra input comes to beyond what the sender offered. Sign the
+/// receiver's input, then call [`finish_proposal`] before sending it back.
+pub fn contribute(
+    original: &Psbt,
+    params: &PayjoinParams,
+    receiver_output: usize,
+    contribution: Contribution,
+) -> Result<Psbt, PayjoinError> {
+    let rejected = |reason: &str| PayjoinError::OriginalRejected(reason.to_string());
+
+    if !original.inputs.iter().all(is_finalized) {
+        return Err(rejected("Original transaction isn't fully signed"));
+    }
+    if receiver_output >= original.unsigned_tx.output.len() {
+        return Err(rejected("Original doesn't pay us"));
+    }
+    if original
+        .unsigned_tx
+        .input
+        .iter()
+        .any(|txin| txin.previous_output == contribution.outpoint)
+    {
+        return Err(rejected("Original spends our coin"));
+    }
+    let original_fee = original
+        .fee()
+        .map_err(|e| PayjoinError::OriginalRejected(e.to_string()))?;
+    let original_weight = original.clone().extract_tx_unchecked_fee_rate().weight();
+
+    let coin_txin = TxIn {
+        previous_output: contribution.outpoint,
+        script_sig: ScriptBuf::new(),
+        sequence: original.unsigned_tx.input[0].sequence,
+        witness: Witness::new(),
+    };
+    let coin = prevout(&coin_txin, &contribution.input)
+        .ok_or_else(|| PayjoinError::Unavailable("Coin has no UTXO".to_string()))?;
+    let added_weight = input_weight(&coin.script_pubkey)
+        .ok_or_else(|| PayjoinError::Unavailable("Unsupported coin script type".to_string()))?;
+
+    // Keep the original's fee rate, or the sender's minimum if that's higher
+    let original_rate =
+        BtcFeeRate::from_sat_per_kwu(original_fee.to_sat() * 1000 / original_weight.to_wu().max(1));
+    let target_rate = match params.min_fee_rate {
+        Some(min_fee_rate) => original_rate.max(min_fee_rate.into()),
+        None => original_rate,
+    };
+    let extra_fee = fee_for(target_rate, original_weight + added_weight)
+        .as_sat()
+        .saturating_sub(original_fee.to_sat());
+
+    let mut proposal = original.clone();
+    let outputs = &mut proposal.unsigned_tx.output;
+
+    let mut sender_share = 0;
+    if let Some(index) = params.additional_fee_output_index {
+        if index >= outputs.len() || index == receiver_output {
+            return Err(rejected("Invalid additional fee output"));
+        }
+        let fee_output = &mut outputs[index];
+        let spare = fee_output
+            .value
+            .to_sat()
+            .saturating_sub(fee_output.script_pubkey.minimal_non_dust().to_sat());
+        sender_share = extra_fee
+            .min(params.max_additional_fee_contribution.as_sat())
+            .min(spare);
+        fee_output.value -= bitcoin::Amount::from_sat(sender_share);
+    }
+
+    let receiver_share = extra_fee - sender_share;
+    let added = coin
+        .value
+        .to_sat()
+        .checked_sub(receiver_share)
+        .ok_or(PayjoinError::NotEnoughMoney)?;
+    outputs[receiver_output].value += bitcoin::Amount::from_sat(added);
+
+    let position = contribution.position.min(proposal.inputs.len());
+    proposal.unsigned_tx.input.insert(position, coin_txin);
+    proposal.inputs.insert(position, contribution.input);
+
+    Ok(proposal)
+}
+
+/// Strip the proposal down to what BIP78 lets the receiver send back
+///
+/// The sender's inputs lose their signatures and UTXOs and every output its key
+/// paths, leaving only the receiver's signed inputs filled in.
+pub fn finish_proposal(proposal: &mut Psbt, original: &Psbt) -> Result<(), PayjoinError> {
+    let original_inputs: Vec<OutPoint> = original
+        .unsigned_tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect();
+
+    for (txin, input) in proposal.unsigned_tx.input.iter().zip(&mut proposal.inputs) {
+        if original_inputs.contains(&txin.previous_output) {
+            *input = Input::default();
+        } else if !is_finalized(input) {
+            return Err(PayjoinError::Unavailable(
+                "Couldn't sign our input".to_string(),
+            ));
+        }
+    }
+    for output in &mut proposal.outputs {
+        *output = Output::default();
+    }
+    proposal.xpub.clear();
+    Ok(())
+}
+
+/// Answer for a sender when the receiver refuses, BIP78's JSON error body
+pub fn error_body(error: &PayjoinError) -> Vec<u8> {
+    // Details stay in our logs, a probing sender only learns the code
+    let message = match error {
+        PayjoinError::OriginalRejected(reason) => reason.clone(),
+        _ => error.code().to_string(),
+    };
+    serde_json::json!({ "errorCode": error.code(), "message": message })
+        .to_string()
+        .into_bytes()
+}
+
+/// Script type for matching inputs, so the receiver's coin doesn't stand out
+pub fn script_type(script: &Script) -> &'static str {
+    if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2tr() {
+        "p2tr"
+    } else if script.is_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else {
+        "other"
+    }
+}
+
+/// Weight an input spending `script` has once signed, for single key scripts
+fn input_weight(script: &Script) -> Option<Weight> {
+    // Outpoint, sequence and an empty script sig
+    const TXIN_BYTES: u64 = 36 + 4 + 1;
+
+    if script.is_p2wpkh() {
+        // Item count, a 72 byte signature and a compressed key
+        Some(Weight::from_wu(TXIN_BYTES * 4 + 1 + 73 + 34))
+    } else if script.is_p2tr() {
+        // Item count and a 64 byte Schnorr signature
+        Some(Weight::from_wu(TXIN_BYTES * 4 + 1 + 65))
+    } else {
+        None
+    }
+}
+
+/// Fee at `fee_rate` for `weight`, rounded up
+fn fee_for(fee_rate: BtcFeeRate, weight: Weight) -> Amount {
+    Amount::from_sat((fee_rate.to_sat_per_kwu() * weight.to_wu()).div_ceil(1000))
+}
+
+/// Output an input spends, from whichever UTXO field it has
+fn prevout(txin: &TxIn, input: &Input) -> Option<TxOut> {
+    input.witness_utxo.clone().or_else(|| {
+        let previous = input.non_witness_utxo.as_ref()?;
+        if previous.compute_txid() != txin.previous_output.txid {
+            return None;
+        }
+        previous
+            .output
+            .get(txin.previous_output.vout as usize)
+            .cloned()
+    })
+}
+
+/// Output the first input of `psbt` spends
+fn first_prevout(psbt: &Psbt) -> Option<TxOut> {
+    prevout(psbt.unsigned_tx.input.first()?, psbt.inputs.first()?)
+}
+
+fn is_finalized(input: &Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+/// https anywhere, plain http only for onion services and this machine
+fn is_secure_endpoint(endpoint: &str) -> bool {
+    if endpoint.starts_with("https://") {
+        return true;
+    }
+    let Some(rest) = endpoint.strip_prefix("http://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?']).next().unwrap_or_default();
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    host.ends_with(".onion") || host == "localhost" || host == "127.0.0.1" || host == "[::1]"
+}
+
+fn receiver_error(status: u16, body: &str) -> PayjoinError {
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        #[serde(rename = "errorCode")]
+        error_code: String,
+        #[serde(default)]
+        message: String,
+    }
+
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(error) => PayjoinError::Receiver {
+            code: error.error_code,
+            message: error.message,
+        },
+        Err(_) => PayjoinError::Http(format!("Receiver answered {status}: {}", body.trim())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::http::{self, HttpResponse};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Sequence, Transaction, WPubkeyHash};
+    use tokio::io::BufReader;
+    use tokio::net::TcpListener;
+
+    fn script(byte: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+    }
+
+    fn signature() -> Witness {
+        Witness::from_slice(&[vec![0x30; 72], vec![0x02; 33]])
+    }
+
+    fn txin(byte: u8) -> TxIn {
+        TxIn {
+            previous_output: OutPoint::new(Txid::from_byte_array([byte; 32]), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }
+    }
+
+    fn utxo(sats: u64, script: ScriptBuf) -> Input {
+        Input {
+            witness_utxo: Some(TxOut {
+                value: bitcoin::Amount::from_sat(sats),
+                script_pubkey: script,
+            }),
+            ..Input::default()
+        }
+    }
+
+    const PAYEE: u8 = 1;
+    const CHANGE: u8 = 2;
+    const SENDER_COIN: u8 = 3;
+    const RECEIVER_COIN: u8 = 4;
+
+    /// Named change applied to a good proposal
+    type Tamper = (&'static str, fn(&mut Psbt));
+
+    /// Unsigned and signed original: 100k in, 50k to the payee, 49k change
+    fn original() -> (Psbt, Psbt) {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![txin(SENDER_COIN)],
+            output: vec![
+                TxOut {
+                    value: bitcoin::Amount::from_sat(50_000),
+                    script_pubkey: script(PAYEE),
+                },
+                TxOut {
+                    value: bitcoin::Amount::from_sat(49_000),
+                    script_pubkey: script(CHANGE),
+                },
+            ],
+        };
+        let mut unsigned = Psbt::from_unsigned_tx(tx).unwrap();
+        unsigned.inputs[0] = utxo(100_000, script(SENDER_COIN));
+
+        let mut signed = unsigned.clone();
+        signed.inputs[0].final_script_witness = Some(signature());
+        (unsigned, signed)
+    }
+
+    fn sender(endpoint: &str) -> PayjoinSender {
+        let (unsigned, signed) = original();
+        PayjoinSender::new(
+            endpoint,
+            unsigned,
+            signed,
+            script(PAYEE),
+            Some(1),
+            FeeRate::from_sat_per_vb(5.0),
+        )
+        .unwrap()
+    }
+
+    fn is_mine(script: &Script) -> bool {
+        script == self::script(CHANGE).as_script()
+            || script == self::script(SENDER_COIN).as_script()
+    }
+
+    /// What a receiver holding a 200k coin sends back
+    fn proposal(original: &Psbt, params: &PayjoinParams) -> Result<Psbt, PayjoinError> {
+        let contribution = Contribution {
+            outpoint: txin(RECEIVER_COIN).previous_output,
+            input: utxo(200_000, script(RECEIVER_COIN)),
+            position: 0,
+        };
+        let mut proposal = contribute(original, params, 0, contribution)?;
+        proposal.inputs[0].final_script_witness = Some(signature());
+        finish_proposal(&mut proposal, original)?;
+        Ok(proposal)
+    }
+
+    /// Stand-in payjoin endpoint answering each request with `respond`
+    async fn serve(
+        respond: impl Fn(&str, &[u8]) -> HttpResponse + Send + Sync + 'static,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = tokio::io::split(stream);
+                let mut reader = BufReader::new(reader);
+                let head = http::read_request_head(&mut reader).await.unwrap();
+                let request = head
+                    .read_body(&mut reader, http::MAX_PUBLIC_BODY_BYTES)
+                    .await
+                    .unwrap();
+                let response = respond(&request.path, &request.body);
+                http::write_response(&mut writer, &response).await.unwrap();
+            }
+        });
+        format!("http://{addr}/payjoin")
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let sender = sender("https://example.com/pj");
+        let params = sender.params();
+        assert_eq!(params.additional_fee_output_index, Some(1));
+        // One p2wpkh input at 5 sat/vB
+        assert_eq!(
+            params.max_additional_fee_contribution,
+            Amount::from_sat(340)
+        );
+
+        let url = sender.url();
+        let query = url.split_once('?').unwrap().1;
+        assert_eq!(&PayjoinParams::from_query(query).unwrap(), params);
+
+        assert!(matches!(
+            PayjoinParams::from_query("v=2"),
+            Err(PayjoinError::VersionUnsupported)
+        ));
+        // Half a fee contribution is ignored
+        let params = PayjoinParams::from_query("v=1&additionalfeeoutputindex=1").unwrap();
+        assert_eq!(params.additional_fee_output_index, None);
+    }
+
+    #[test]
+    fn test_endpoint_must_be_private() {
+        let (unsigned, signed) = original();
+        for (endpoint, secure) in [
+            ("https://example.com/pj", true),
+            ("http://example.onion/pj", true),
+            ("http://127.0.0.1:3000/pj", true),
+            ("http://example.com/pj", false),
+            ("http://127.0.0.1.example.com/pj", false),
+        ] {
+            let sender = PayjoinSender::new(
+                endpoint,
+                unsigned.clone(),
+                signed.clone(),
+                script(PAYEE),
+                None,
+                FeeRate::from_sat_per_vb(1.0),
+            );
+            assert_eq!(sender.is_ok(), secure, "{endpoint}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payjoin_with_stand_in_endpoint() {
+        let endpoint = serve(|path, body| {
+            let query = path.split_once('?').map_or("", |(_, query)| query);
+            let params = PayjoinParams::from_query(query).unwrap();
+            let original: Psbt = std::str::from_utf8(body).unwrap().parse().unwrap();
+            match proposal(&original, &params) {
+                Ok(proposal) => HttpResponse {
+                    status: 200,
+                    body: proposal.to_string().into_bytes(),
+                },
+                Err(e) => HttpResponse {
+                    status: 400,
+                    body: error_body(&e),
+                },
+            }
+        })
+        .await;
+
+        let sender = sender(&endpoint);
+        let proposal = sender.post().await.unwrap();
+        let ready = sender.check_proposal(proposal, is_mine).unwrap();
+
+        // Our input holds its UTXO again, ready to sign, the receiver's stays signed
+        assert_eq!(ready.inputs.len(), 2);
+        assert!(ready.inputs[1].witness_utxo.is_some());
+        assert!(ready.inputs[1].final_script_witness.is_none());
+        assert!(ready.inputs[0].final_script_witness.is_some());
+
+        // The receiver's coin goes to the payee, we chip in for its input
+        let outputs = &ready.unsigned_tx.output;
+        let contribution = 49_000 - outputs[1].value.to_sat();
+        assert!(contribution > 0 && contribution <= 340);
+        assert!(outputs[0].value.to_sat() > 240_000);
+    }
+
+    #[tokio::test]
+    async fn test_receiver_errors_are_reported() {
+        let endpoint = serve(|_, _| HttpResponse {
+            status: 400,
+            body: error_body(&PayjoinError::Unavailable("wallet is empty".to_string())),
+        })
+        .await;
+
+        match sender(&endpoint).post().await {
+            Err(PayjoinError::Receiver { code, message }) => {
+                assert_eq!(code, "unavailable");
+                // The reason stays with the receiver
+                assert!(!message.contains("empty"));
+            }
+            other => panic!("expected a receiver error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_bad_proposals_are_rejected() {
+        let sender = sender("https://example.com/pj");
+        let good = proposal(&sender.original, &sender.params).unwrap();
+        assert!(sender.check_proposal(good.clone(), is_mine).is_ok());
+
+        let tampered: [Tamper; 7] = [
+            ("payment lowered", |psbt| {
+                psbt.unsigned_tx.output[0].value = bitcoin::Amount::from_sat(49_000)
+            }),
+            ("too much fee taken", |psbt| {
+                psbt.unsigned_tx.output[1].value -= bitcoin::Amount::from_sat(1_000)
+            }),
+            ("receiver input unsigned", |psbt| {
+                psbt.inputs[0].final_script_witness = None
+            }),
+            ("our input signed", |psbt| {
+                psbt.inputs[1].final_script_witness = Some(signature())
+            }),
+            ("our input dropped", |psbt| {
+                psbt.unsigned_tx.input.remove(1);
+                psbt.inputs.remove(1);
+            }),
+            ("lock time changed", |psbt| {
+                psbt.unsigned_tx.lock_time = LockTime::from_consensus(1)
+            }),
+            ("output paying us added", |psbt| {
+                psbt.unsigned_tx.output.push(TxOut {
+                    value: bitcoin::Amount::from_sat(1_000),
+                    script_pubkey: script(CHANGE),
+                });
+                psbt.outputs.push(Output::default());
+            }),
+        ];
+        for (name, tamper) in tampered {
+            let mut proposal = good.clone();
+            tamper(&mut proposal);
+            assert!(
+                matches!(
+                    sender.check_proposal(proposal, is_mine),
+                    Err(PayjoinError::InvalidProposal(_))
+                ),
+                "{name}"
+            );
+        }
+
+        // A receiver input we own is a probe for our coins
+        assert!(sender
+            .check_proposal(good, |script| is_mine(script)
+                || script == self::script(RECEIVER_COIN).as_script())
+            .is_err());
+    }
+
+    #[test]
+    fn test_receiver_refuses_unsigned_original() {
+        let (unsigned, _) = original();
+        assert!(matches!(
+            proposal(&unsigned, &PayjoinParams::default()),
+            Err(PayjoinError::OriginalRejected(_))
+        ));
+    }
+}
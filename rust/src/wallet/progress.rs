@@ -0,0 +1,159 @@
+//! Progress reports from a running sync
+//!
+//! Scans call into a [`ProgressReporter`] as they look up scripts and download
+//! transactions, and it hands a running [`SyncProgress`] total to a callback.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use bdk_wallet::KeychainKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// Keychain being scanned
+    pub keychain: KeychainKind,
+    /// Scripts looked up so far, over all keychains
+    pub scripts_checked: u32,
+    /// Highest index with history found in `keychain` so far
+    pub last_active_index: Option<u32>,
+    /// Serialized size of the transactions downloaded so far
+    pub bytes_fetched: u64,
+}
+
+type Callback = dyn Fn(&SyncProgress) + Send + Sync;
+
+/// Collects progress from a scan, clones report into the same totals
+#[derive(Clone)]
+pub struct ProgressReporter {
+    state: Arc<Mutex<State>>,
+    callback: Option<Arc<Callback>>,
+}
+
+struct State {
+    progress: SyncProgress,
+    last_active: BTreeMap<KeychainKind, u32>,
+}
+
+impl ProgressReporter {
+    /// Call `callback` every time the sync moves forward, from the scanning task
+    pub fn new(callback: impl Fn(&SyncProgress) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Some(Arc::new(callback)),
+            ..Self::silent()
+        }
+    }
+
+    /// Keeps the totals but reports them to nobody
+    pub fn silent() -> Self {
+        let progress = SyncProgress {
+            keychain: KeychainKind::External,
+            scripts_checked: 0,
+            last_active_index: None,
+            bytes_fetched: 0,
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(State {
+                progress,
+                last_active: BTreeMap::new(),
+            })),
+            callback: None,
+        }
+    }
+
+    /// The totals so far
+    pub fn progress(&self) -> SyncProgress {
+        self.lock().progress
+    }
+
+    /// A script of `keychain` is about to be looked up
+    pub fn script_checked(&self, keychain: KeychainKind) {
+        self.update(|state| {
+            state.progress.scripts_checked += 1;
+            state.switch_to(keychain);
+        });
+    }
+
+    /// The script at `index` has history
+    pub fn script_used(&self, keychain: KeychainKind, index: u32) {
+        self.update(|state| {
+            let last_active = state.last_active.entry(keychain).or_insert(index);
+            *last_active = (*last_active).max(index);
+            state.switch_to(keychain);
+        });
+    }
+
+    /// A transaction of `bytes` was downloaded
+    pub fn fetched(&self, bytes: u64) {
+        self.update(|state| state.progress.bytes_fetched += bytes);
+    }
+
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let progress = {
+            let mut state = self.lock();
+            change(&mut state);
+            state.progress
+        };
+
+        // Outside the lock, so a slow callback doesn't hold up other reports
+        if let Some(callback) = &self.callback {
+            callback(&progress);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl State {
+    fn switch_to(&mut self, keychain: KeychainKind) {
+        self.progress.keychain = keychain;
+        self.progress.last_active_index = self.last_active.get(&keychain).copied();
+    }
+}
+
+impl Default for ProgressReporter {
+    fn default() -> Self {
+        Self::silent()
+    }
+}
+
+impl std::fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("progress", &self.progress())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals_are_shared_and_reported() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let reporter = ProgressReporter::new(move |progress| seen.lock().unwrap().push(*progress));
+        let clone = reporter.clone();
+
+        reporter.script_checked(KeychainKind::External);
+        clone.script_used(KeychainKind::External, 4);
+        clone.script_used(KeychainKind::External, 2);
+        reporter.fetched(250);
+        reporter.script_checked(KeychainKind::Internal);
+
+        let progress = reporter.progress();
+        assert_eq!(progress.scripts_checked, 2);
+        assert_eq!(progress.bytes_fetched, 250);
+        assert_eq!(progress.keychain, KeychainKind::Internal);
+        assert_eq!(progress.last_active_index, None);
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), 5);
+        assert_eq!(reports[3].last_active_index, Some(4));
+    }
+}
@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use bitcoin::psbt::Psbt;
+
+use crate::wallet::error::{Result, WalletError};
+
+/// Magic bytes at the start of every binary PSBT (BIP174)
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// Encoding used when writing a PSBT to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PsbtFormat {
+    #[default]
+    Base64,
+    Binary,
+}
+
+impl FromStr for PsbtFormat {
+    type Err = WalletError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "base64" => Ok(Self::Base64),
+            "binary" | "bin" => Ok(Self::Binary),
+            _ => Err(WalletError::Psbt(format!(
+                "Invalid PSBT format: {s}. Valid options: base64, binary"
+            ))),
+        }
+    }
+}
+
+/// Decode a PSBT from binary, base64 or hex
+pub fn decode_psbt(data: &[u8]) -> Result<Psbt> {
+    if data.starts_with(PSBT_MAGIC) {
+        return Psbt::deserialize(data)
+            .map_err(|e| WalletError::Psbt(format!("Invalid binary PSBT: {e}")));
+    }
+
+    let text = std::str::from_utf8(data)
+        .map_err(|_| WalletError::Psbt("PSBT is neither binary nor text".to_string()))?
+        .trim();
+
+    // Hex encoded PSBTs start with the hex of the magic bytes
+    if text.starts_with("70736274ff") {
+        let bytes =
+            hex::decode(text).map_err(|e| WalletError::Psbt(format!("Invalid hex PSBT: {e}")))?;
+        return Psbt::deserialize(&bytes)
+            .map_err(|e| WalletError::Psbt(format!("Invalid hex PSBT: {e}")));
+    }
+
+    Psbt::from_str(text).map_err(|e| WalletError::Psbt(format!("Invalid base64 PSBT: {e}")))
+}
+
+/// Encode a PSBT in the given format
+pub fn encode_psbt(psbt: &Psbt, format: PsbtFormat) -> Vec<u8> {
+    match format {
+        PsbtFormat::Base64 => format!("{psbt}\n").into_bytes(),
+        PsbtFormat::Binary => psbt.serialize(),
+    }
+}
+
+pub fn read_psbt_file(path: &Path) -> Result<Psbt> {
+    let data = std::fs::read(path).map_err(|e| {
+        WalletError::Psbt(format!("Failed to read PSBT file {}: {e}", path.display()))
+    })?;
+    decode_psbt(&data)
+}
+
+pub fn write_psbt_file(path: &Path, psbt: &Psbt, format: PsbtFormat) -> Result<()> {
+    std::fs::write(path, encode_psbt(psbt, format)).map_err(|e| {
+        WalletError::Psbt(format!("Failed to write PSBT file {}: {e}", path.display()))
+    })
+}
+
+/// Combine partially signed copies of the same PSBT (BIP174 combiner)
+pub fn combine_psbts(psbts: Vec<Psbt>) -> Result<Psbt> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts
+        .next()
+        .ok_or(WalletError::Psbt("No PSBTs to combine".to_string()))?;
+
+    for psbt in psbts {
+        combined
+            .combine(psbt)
+            .map_err(|e| WalletError::Psbt(format!("Error combining PSBTs: {e}")))?;
+    }
+
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{absolute, transaction, Amount, OutPoint, ScriptBuf, Transaction, TxIn, TxOut};
+
+    fn test_psbt(value: u64) -> Psbt {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        Psbt::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn test_psbt_encoding_roundtrip() {
+        let psbt = test_psbt(1000);
+
+        let base64 = encode_psbt(&psbt, PsbtFormat::Base64);
+        assert_eq!(decode_psbt(&base64).unwrap(), psbt);
+
+        let binary = encode_psbt(&psbt, PsbtFormat::Binary);
+        assert!(binary.starts_with(PSBT_MAGIC));
+        assert_eq!(decode_psbt(&binary).unwrap(), psbt);
+
+        let hex = hex::encode(psbt.serialize());
+        assert_eq!(decode_psbt(hex.as_bytes()).unwrap(), psbt);
+
+        assert!(decode_psbt(b"not a psbt").is_err());
+    }
+
+    #[test]
+    fn test_combine_psbts() {
+        let psbt = test_psbt(1000);
+        let combined = combine_psbts(vec![psbt.clone(), psbt.clone()]).unwrap();
+        assert_eq!(combined, psbt);
+
+        // Different unsigned transactions can't be combined
+        assert!(combine_psbts(vec![psbt, test_psbt(2000)]).is_err());
+        assert!(combine_psbts(vec![]).is_err());
+    }
+}
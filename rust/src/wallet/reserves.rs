@@ -0,0 +1,564 @@
+//! Proof of reserves (BIP127)
+//!
+//! A proof is a PSBT spending the coins it covers plus a commitment input whose
+//! txid is the hash of the auditor's message. That input doesn't exist, so the
+//! transaction can never be mined, but every signature commits to it and so to
+//! the message. A single output takes the whole amount, there's no fee.
+//!
+//! Verifying checks each signature and looks each coin up in the current UTXO
+//! set, a coin that's been spent or isn't confirmed yet doesn't count. Only
+//! P2WPKH, wrapped P2WPKH and taproot key path inputs can be verified.
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::absolute::LockTime;
+use bitcoin::hashes::{hash160, sha256d, Hash};
+use bitcoin::opcodes::OP_TRUE;
+use bitcoin::psbt::{Input, Psbt};
+use bitcoin::script::{Builder, Instruction};
+use bitcoin::secp256k1::{Message, Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::transaction::Version;
+use bitcoin::{
+    ecdsa, taproot, CompressedPublicKey, OutPoint, PubkeyHash, Script, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use lumo_types::Amount;
+
+use crate::node::client::esplora::EsploraClient;
+use crate::wallet::error::WalletError;
+
+/// Prepended to the message before it's hashed into the commitment txid
+pub const MESSAGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ReservesError {
+    #[error("Proof covers no coins")]
+    NoCoins,
+
+    #[error("Proof doesn't commit to this message")]
+    WrongChallenge,
+
+    #[error("Proof must have exactly one output")]
+    WrongOutputs,
+
+    #[error("Output doesn't pay the whole amount of the coins")]
+    AmountMismatch,
+
+    #[error("Coin {0} is included twice")]
+    DuplicateCoin(OutPoint),
+
+    #[error("Coin {0} has no previous output in the proof")]
+    MissingUtxo(OutPoint),
+
+    #[error("Coin {0} is spent or doesn't exist")]
+    Spent(OutPoint),
+
+    #[error("Coin {0} isn't confirmed")]
+    Unconfirmed(OutPoint),
+
+    #[error("Input {0} isn't signed")]
+    NotFinalized(usize),
+
+    #[error("Input {0} spends a script type proofs don't support")]
+    UnsupportedInput(usize),
+
+    #[error("Input {index} has an invalid signature: {reason}")]
+    InvalidSignature { index: usize, reason: String },
+
+    #[error("Chain backend error: {0}")]
+    Backend(String),
+}
+
+impl From<ReservesError> for WalletError {
+    fn from(err: ReservesError) -> Self {
+        WalletError::Reserves(err.to_string())
+    }
+}
+
+/// What a valid proof shows is under the prover's control
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvenReserves {
+    pub amount: Amount,
+    pub coins: Vec<OutPoint>,
+}
+
+/// The commitment input, spending output 0 of a txid made from the message
+pub fn challenge_txin(message: &str) -> TxIn {
+    let hash = sha256d::Hash::hash(format!("{MESSAGE_PREFIX}{message}").as_bytes());
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_raw_hash(hash), 0),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+/// Unsigned proof spending `coins` and the commitment to `message`
+///
+/// Each coin's PSBT input needs its `witness_utxo`, which gives its amount, and
+/// whatever key origins signing it takes.
+pub fn unsigned_proof(message: &str, coins: Vec<(OutPoint, Input)>) -> Result<Psbt, ReservesError> {
+    if coins.is_empty() {
+        return Err(ReservesError::NoCoins);
+    }
+
+    let mut amount = bitcoin::Amount::ZERO;
+    for (outpoint, input) in &coins {
+        let utxo = input
+            .witness_utxo
+            .as_ref()
+            .ok_or(ReservesError::MissingUtxo(*outpoint))?;
+        amount += utxo.value;
+    }
+
+    let mut input = vec![challenge_txin(message)];
+    input.extend(coins.iter().map(|(outpoint, _)| TxIn {
+        previous_output: *outpoint,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }));
+    let transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output: vec![TxOut {
+            value: amount,
+            script_pubkey: unspendable_script(),
+        }],
+    };
+
+    let mut psbt = Psbt::from_unsigned_tx(transaction).expect("inputs aren't signed yet");
+    psbt.inputs = std::iter::once(challenge_input())
+        .chain(coins.into_iter().map(|(_, input)| input))
+        .collect();
+    Ok(psbt)
+}
+
+/// Verify a signed proof for `message` against the chain backend's UTXO set
+///
+/// Every coin must be confirmed, an unconfirmed one could be double spent.
+pub async fn verify_proof(
+    psbt: &Psbt,
+    message: &str,
+    client: &EsploraClient,
+) -> Result<ProvenReserves, ReservesError> {
+    let mut utxos = HashMap::new();
+    for txin in psbt.unsigned_tx.input.iter().skip(1) {
+        let outpoint = txin.previous_output;
+        let utxo = client
+            .unspent_output(outpoint)
+            .await
+            .map_err(|e| ReservesError::Backend(e.to_string()))?;
+        if let Some(utxo) = utxo {
+            let confirmed = client
+                .is_confirmed(outpoint.txid)
+                .await
+                .map_err(|e| ReservesError::Backend(e.to_string()))?;
+            if !confirmed {
+                return Err(ReservesError::Unconfirmed(outpoint));
+            }
+            utxos.insert(outpoint, utxo);
+        }
+    }
+
+    check_proof(psbt, message, &utxos)
+}
+
+/// Verify a signed proof for `message`, with `utxos` the unspent outputs it may spend
+pub fn check_proof(
+    psbt: &Psbt,
+    message: &str,
+    utxos: &HashMap<OutPoint, TxOut>,
+) -> Result<ProvenReserves, ReservesError> {
+    let unsigned = &psbt.unsigned_tx;
+    let challenge = challenge_txin(message).previous_output;
+    if unsigned.input.first().map(|txin| txin.previous_output) != Some(challenge) {
+        return Err(ReservesError::WrongChallenge);
+    }
+    if unsigned.input.len() < 2 {
+        return Err(ReservesError::NoCoins);
+    }
+    let [output] = unsigned.output.as_slice() else {
+        return Err(ReservesError::WrongOutputs);
+    };
+
+    // The prevouts come from the UTXO set, never from the proof itself
+    let mut prevouts = vec![challenge_txout()];
+    let mut coins = Vec::new();
+    let mut seen = HashSet::new();
+    for txin in &unsigned.input[1..] {
+        let outpoint = txin.previous_output;
+        if !seen.insert(outpoint) {
+            return Err(ReservesError::DuplicateCoin(outpoint));
+        }
+        let utxo = utxos.get(&outpoint).ok_or(ReservesError::Spent(outpoint))?;
+        prevouts.push(utxo.clone());
+        coins.push(outpoint);
+    }
+
+    let amount: bitcoin::Amount = prevouts.iter().map(|prevout| prevout.value).sum();
+    if output.value != amount {
+        return Err(ReservesError::AmountMismatch);
+    }
+
+    if let Some(index) = (1..psbt.inputs.len()).find(|&index| !is_finalized(&psbt.inputs[index])) {
+        return Err(ReservesError::NotFinalized(index));
+    }
+    let signed = psbt.clone().extract_tx_unchecked_fee_rate();
+
+    let secp = Secp256k1::verification_only();
+    let mut cache = SighashCache::new(&signed);
+    for index in 1..signed.input.len() {
+        verify_input(&secp, &mut cache, &signed.input[index], index, &prevouts)?;
+    }
+
+    Ok(ProvenReserves {
+        amount: Amount::from(amount),
+        coins,
+    })
+}
+
+/// PSBT input of the commitment, final already since it's never really spent
+fn challenge_input() -> Input {
+    Input {
+        witness_utxo: Some(challenge_txout()),
+        final_script_sig: Some(ScriptBuf::new()),
+        ..Input::default()
+    }
+}
+
+fn challenge_txout() -> TxOut {
+    TxOut {
+        value: bitcoin::Amount::ZERO,
+        script_pubkey: Builder::new().push_opcode(OP_TRUE).into_script(),
+    }
+}
+
+/// P2PKH to a hash no key is known for
+fn unspendable_script() -> ScriptBuf {
+    ScriptBuf::new_p2pkh(&PubkeyHash::from_raw_hash(hash160::Hash::hash(&[0])))
+}
+
+fn is_finalized(input: &Input) -> bool {
+    input.final_script_sig.is_some() || input.final_script_witness.is_some()
+}
+
+/// Check the signature of a coin's input, which must sign every input and output
+fn verify_input<C: Verification>(
+    secp: &Secp256k1<C>,
+    cache: &mut SighashCache<&Transaction>,
+    txin: &TxIn,
+    index: usize,
+    prevouts: &[TxOut],
+) -> Result<(), ReservesError> {
+    let script_pubkey = &prevouts[index].script_pubkey;
+    let invalid = |reason: String| ReservesError::InvalidSignature { index, reason };
+
+    if script_pubkey.is_p2tr() {
+        let [signature] = txin
+            .witness
+            .to_vec()
+            .try_into()
+            .map_err(|_| invalid("only taproot key path spends are supported".to_string()))?;
+        let signature =
+            taproot::Signature::from_slice(&signature).map_err(|e| invalid(e.to_string()))?;
+        if !matches!(
+            signature.sighash_type,
+            TapSighashType::Default | TapSighashType::All
+        ) {
+            return Err(invalid(
+                "signature doesn't commit to every input".to_string(),
+            ));
+        }
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|e| invalid(e.to_string()))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                index,
+                &Prevouts::All(prevouts),
+                signature.sighash_type,
+            )
+            .map_err(|e| invalid(e.to_string()))?;
+        return secp
+            .verify_schnorr(
+                &signature.signature,
+                &Message::from_digest(sighash.to_byte_array()),
+                &output_key,
+            )
+            .map_err(|e| invalid(e.to_string()));
+    }
+
+    // P2WPKH, spent directly or through a P2SH redeem script
+    let program = if script_pubkey.is_p2wpkh() && txin.script_sig.is_empty() {
+        script_pubkey.as_script()
+    } else if script_pubkey.is_p2sh() {
+        let redeem_script =
+            single_push(&txin.script_sig).ok_or(ReservesError::UnsupportedInput(index))?;
+        if !redeem_script.is_p2wpkh()
+            || ScriptBuf::new_p2sh(&redeem_script.script_hash()) != *script_pubkey
+        {
+            return Err(ReservesError::UnsupportedInput(index));
+        }
+        redeem_script
+    } else {
+        return Err(ReservesError::UnsupportedInput(index));
+    };
+
+    let [signature, pubkey] = txin
+        .witness
+        .to_vec()
+        .try_into()
+        .map_err(|_| invalid("P2WPKH witness needs two items".to_string()))?;
+    let signature = ecdsa::Signature::from_slice(&signature).map_err(|e| invalid(e.to_string()))?;
+    if signature.sighash_type != EcdsaSighashType::All {
+        return Err(invalid(
+            "signature doesn't commit to every input".to_string(),
+        ));
+    }
+    let pubkey = CompressedPublicKey::from_slice(&pubkey).map_err(|e| invalid(e.to_string()))?;
+    if ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()) != *program {
+        return Err(invalid("key doesn't match the coin's script".to_string()));
+    }
+    let sighash = cache
+        .p2wpkh_signature_hash(
+            index,
+            program,
+            prevouts[index].value,
+            signature.sighash_type,
+        )
+        .map_err(|e| invalid(e.to_string()))?;
+    secp.verify_ecdsa(
+        &Message::from_digest(sighash.to_byte_array()),
+        &signature.signature,
+        &pubkey.0,
+    )
+    .map_err(|e| invalid(e.to_string()))
+}
+
+/// The data of a script made of exactly one push
+fn single_push(script: &Script) -> Option<&Script> {
+    let mut instructions = script.instructions();
+    match (instructions.next(), instructions.next()) {
+        (Some(Ok(Instruction::PushBytes(bytes))), None) => {
+            Some(Script::from_bytes(bytes.as_bytes()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::{Keypair, TapTweak};
+    use bitcoin::secp256k1::{All, SecretKey};
+    use bitcoin::PrivateKey;
+
+    const MESSAGE: &str = "Audit 2026-Q3";
+
+    struct Coin {
+        outpoint: OutPoint,
+        utxo: TxOut,
+        key: PrivateKey,
+    }
+
+    fn key(byte: u8) -> PrivateKey {
+        PrivateKey::new(
+            SecretKey::from_slice(&[byte; 32]).unwrap(),
+            bitcoin::Network::Regtest,
+        )
+    }
+
+    fn coin(secp: &Secp256k1<All>, byte: u8, sats: u64, taproot: bool) -> Coin {
+        let key = key(byte);
+        let script_pubkey = if taproot {
+            let (internal_key, _) = Keypair::from_secret_key(secp, &key.inner).x_only_public_key();
+            ScriptBuf::new_p2tr(secp, internal_key, None)
+        } else {
+            let pubkey = CompressedPublicKey::from_private_key(secp, &key).unwrap();
+            ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash())
+        };
+        Coin {
+            outpoint: OutPoint::new(Txid::from_byte_array([byte; 32]), 1),
+            utxo: TxOut {
+                value: bitcoin::Amount::from_sat(sats),
+                script_pubkey,
+            },
+            key,
+        }
+    }
+
+    fn coins(secp: &Secp256k1<All>) -> Vec<Coin> {
+        vec![coin(secp, 1, 50_000, false), coin(secp, 2, 120_000, true)]
+    }
+
+    fn utxos(coins: &[Coin]) -> HashMap<OutPoint, TxOut> {
+        coins
+            .iter()
+            .map(|coin| (coin.outpoint, coin.utxo.clone()))
+            .collect()
+    }
+
+    /// Sign every coin's input the way a wallet would
+    fn signed_proof(secp: &Secp256k1<All>, message: &str, coins: &[Coin]) -> Psbt {
+        let inputs = coins
+            .iter()
+            .map(|coin| {
+                let input = Input {
+                    witness_utxo: Some(coin.utxo.clone()),
+                    ..Input::default()
+                };
+                (coin.outpoint, input)
+            })
+            .collect();
+        let mut psbt = unsigned_proof(message, inputs).unwrap();
+
+        let prevouts: Vec<TxOut> = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.clone().unwrap())
+            .collect();
+        let mut cache = SighashCache::new(psbt.unsigned_tx.clone());
+        for (position, coin) in coins.iter().enumerate() {
+            let index = position + 1;
+            let witness = if coin.utxo.script_pubkey.is_p2tr() {
+                let sighash = cache
+                    .taproot_key_spend_signature_hash(
+                        index,
+                        &Prevouts::All(&prevouts),
+                        TapSighashType::Default,
+                    )
+                    .unwrap();
+                let keypair = Keypair::from_secret_key(secp, &coin.key.inner)
+                    .tap_tweak(secp, None)
+                    .to_keypair();
+                let signature = secp.sign_schnorr_no_aux_rand(
+                    &Message::from_digest(sighash.to_byte_array()),
+                    &keypair,
+                );
+                Witness::p2tr_key_spend(&taproot::Signature {
+                    signature,
+                    sighash_type: TapSighashType::Default,
+                })
+            } else {
+                let sighash = cache
+                    .p2wpkh_signature_hash(
+                        index,
+                        &coin.utxo.script_pubkey,
+                        coin.utxo.value,
+                        EcdsaSighashType::All,
+                    )
+                    .unwrap();
+                let signature = ecdsa::Signature {
+                    signature: secp.sign_ecdsa(
+                        &Message::from_digest(sighash.to_byte_array()),
+                        &coin.key.inner,
+                    ),
+                    sighash_type: EcdsaSighashType::All,
+                };
+                let pubkey = CompressedPublicKey::from_private_key(secp, &coin.key).unwrap();
+                Witness::p2wpkh(&signature, &pubkey.0)
+            };
+            psbt.inputs[index].final_script_witness = Some(witness);
+        }
+        psbt
+    }
+
+    #[test]
+    fn test_valid_proof() {
+        let secp = Secp256k1::new();
+        let coins = coins(&secp);
+        let psbt = signed_proof(&secp, MESSAGE, &coins);
+
+        let proven = check_proof(&psbt, MESSAGE, &utxos(&coins)).unwrap();
+        assert_eq!(proven.amount, Amount::from_sat(170_000));
+        assert_eq!(proven.coins, vec![coins[0].outpoint, coins[1].outpoint]);
+
+        // The commitment input is the only one without a real coin behind it
+        let challenge = &psbt.unsigned_tx.input[0];
+        assert_eq!(
+            challenge.previous_output,
+            challenge_txin(MESSAGE).previous_output
+        );
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_proof_for_another_message() {
+        let secp = Secp256k1::new();
+        let coins = coins(&secp);
+        let psbt = signed_proof(&secp, MESSAGE, &coins);
+
+        assert_eq!(
+            check_proof(&psbt, "Audit 2026-Q4", &utxos(&coins)),
+            Err(ReservesError::WrongChallenge)
+        );
+
+        // Swapping in another commitment breaks the signatures
+        let mut forged = psbt.clone();
+        forged.unsigned_tx.input[0] = challenge_txin("Audit 2026-Q4");
+        assert!(matches!(
+            check_proof(&forged, "Audit 2026-Q4", &utxos(&coins)),
+            Err(ReservesError::InvalidSignature { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_spent_coin() {
+        let secp = Secp256k1::new();
+        let coins = coins(&secp);
+        let psbt = signed_proof(&secp, MESSAGE, &coins);
+
+        let mut unspent = utxos(&coins);
+        unspent.remove(&coins[1].outpoint);
+        assert_eq!(
+            check_proof(&psbt, MESSAGE, &unspent),
+            Err(ReservesError::Spent(coins[1].outpoint))
+        );
+    }
+
+    #[test]
+    fn test_amount_must_match() {
+        let secp = Secp256k1::new();
+        let coins = coins(&secp);
+
+        // A coin worth more in the UTXO set than the proof claims
+        let mut psbt = signed_proof(&secp, MESSAGE, &coins);
+        psbt.unsigned_tx.output[0].value = bitcoin::Amount::from_sat(200_000);
+        assert_eq!(
+            check_proof(&psbt, MESSAGE, &utxos(&coins)),
+            Err(ReservesError::AmountMismatch)
+        );
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let secp = Secp256k1::new();
+        let mut coins = coins(&secp);
+        coins[0].key = key(9);
+        let psbt = signed_proof(&secp, MESSAGE, &coins);
+
+        assert!(matches!(
+            check_proof(&psbt, MESSAGE, &utxos(&coins)),
+            Err(ReservesError::InvalidSignature { index: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_unsigned_proof() {
+        let secp = Secp256k1::new();
+        let coins = coins(&secp);
+        let mut psbt = signed_proof(&secp, MESSAGE, &coins);
+        psbt.inputs[2].final_script_witness = None;
+
+        assert_eq!(
+            check_proof(&psbt, MESSAGE, &utxos(&coins)),
+            Err(ReservesError::NotFinalized(2))
+        );
+        assert_eq!(
+            unsigned_proof(MESSAGE, Vec::new()),
+            Err(ReservesError::NoCoins)
+        );
+    }
+}
@@ -1,71 +1,390 @@
+use crate::cancel::CancelToken;
+use crate::database::Database;
+use crate::wallet::balance::Balance;
 use crate::wallet::error::{Result, WalletError};
+use crate::wallet::events::{
+    WalletEvent, WalletEventKind, WalletSnapshot, DEFAULT_CONFIRMATION_DEPTHS,
+};
+use crate::wallet::payjoin::PayjoinOutcome;
+use crate::wallet::progress::ProgressReporter;
 use crate::wallet::{Wallet, WalletId};
-use lumo_types::{Network, Transaction};
+use bitcoin::psbt::Psbt;
+use lumo_types::{Address, Amount, FeeRate, Network, Transaction};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
 
-#[derive(Default)]
+/// Events a subscriber can fall behind by before it starts missing them
+const EVENT_CAPACITY: usize = 1024;
+
+/// A loaded wallet, locked for the length of each operation on it
+pub type WalletHandle = Arc<tokio::sync::Mutex<Wallet>>;
+
+/// What came of a send
+#[derive(Debug)]
+pub enum SendOutcome {
+    /// Signed and broadcast
+    Broadcast(bitcoin::Txid),
+    /// A multisig spend saved as pending until cosigners sign it
+    Pending(Psbt),
+}
+
+/// The loaded wallets, cheap to clone and share between tasks
+///
+/// Each wallet has its own lock, so a long sync of one wallet doesn't hold up
+/// calls on the others. The active wallet is the one selected in the global
+/// config, so it stays in step with the CLI's `select-wallet`.
+///
+/// Every sync publishes [`WalletEvent`]s describing what changed, see
+/// [`WalletManager::subscribe`].
+#[derive(Clone)]
 pub struct WalletManager {
-    wallets: HashMap<WalletId, Wallet>,
-    active_wallet_id: Option<WalletId>,
+    wallets: Arc<RwLock<HashMap<WalletId, WalletHandle>>>,
+    events: broadcast::Sender<WalletEvent>,
+    confirmation_depths: Arc<[u32]>,
 }
 
-impl WalletManager {
-    pub fn new() -> Self {
+impl Default for WalletManager {
+    fn default() -> Self {
         Self {
-            wallets: HashMap::new(),
-            active_wallet_id: None,
+            wallets: Default::default(),
+            events: broadcast::Sender::new(EVENT_CAPACITY),
+            confirmation_depths: Arc::new(DEFAULT_CONFIRMATION_DEPTHS),
         }
     }
+}
+
+impl WalletManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    // Load or create a wallet
-    pub fn add_wallet(&mut self, wallet: Wallet) -> WalletId {
+    /// Depths at which `TxConfirmed` events are sent, 1 and 6 by default
+    pub fn with_confirmation_depths(mut self, depths: &[u32]) -> Self {
+        self.confirmation_depths = depths.into();
+        self
+    }
+
+    /// Events from every wallet's syncs, from now on
+    ///
+    /// A receiver that falls more than 1024 events behind gets
+    /// `RecvError::Lagged` and skips ahead.
+    pub fn subscribe(&self) -> broadcast::Receiver<WalletEvent> {
+        self.events.subscribe()
+    }
+
+    /// Add a loaded wallet, selecting it if no wallet is selected yet
+    pub fn add_wallet(&self, wallet: Wallet) -> Result<WalletId> {
         let wallet_id = wallet.id.clone();
-        self.wallets.insert(wallet_id.clone(), wallet);
-        if self.active_wallet_id.is_none() {
-            self.active_wallet_id = Some(wallet_id.clone());
+        self.write()
+            .insert(wallet_id.clone(), Arc::new(tokio::sync::Mutex::new(wallet)));
+
+        if self.active_wallet_id()?.is_none() {
+            self.set_active_wallet(wallet_id.clone())?;
         }
-        wallet_id
+        Ok(wallet_id)
     }
 
-    pub fn load_existing_wallet(&mut self, wallet_id: &WalletId, network: Network) -> Result<()> {
+    /// Load every wallet that isn't archived
+    ///
+    /// A wallet that fails to load is logged and skipped, so one broken store
+    /// doesn't keep the others from loading.
+    pub fn load_all(&self) -> Result<()> {
+        for metadata in Wallet::list_all(None)? {
+            if self.read().contains_key(&metadata.id) {
+                continue;
+            }
+
+            match Wallet::try_load_persisted(&metadata.id, metadata.network) {
+                Ok(wallet) => {
+                    self.write()
+                        .insert(metadata.id, Arc::new(tokio::sync::Mutex::new(wallet)));
+                }
+                Err(e) => tracing::warn!("Unable to load wallet {}: {e}", metadata.name),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load_existing_wallet(&self, wallet_id: &WalletId, network: Network) -> Result<()> {
         let wallet = Wallet::try_load_persisted(wallet_id, network)?;
-        self.wallets.insert(wallet_id.clone(), wallet);
+        self.write()
+            .insert(wallet_id.clone(), Arc::new(tokio::sync::Mutex::new(wallet)));
         Ok(())
     }
 
-    pub fn get_transactions(&self, wallet_id: &WalletId) -> Result<Vec<Transaction>> {
-        self.wallets
+    /// Unload a wallet, the selection in the global config is left alone
+    pub fn remove_wallet(&self, wallet_id: &WalletId) -> Option<WalletHandle> {
+        self.write().remove(wallet_id)
+    }
+
+    pub fn wallet(&self, wallet_id: &WalletId) -> Result<WalletHandle> {
+        self.read()
             .get(wallet_id)
+            .cloned()
             .ok_or(WalletError::WalletNotFound(format!(
                 "Wallet {wallet_id} not found"
-            )))?
-            .transactions()
+            )))
     }
 
-    pub fn set_active_wallet(&mut self, wallet_id: WalletId) -> Result<()> {
-        if self.wallets.contains_key(&wallet_id) {
-            self.active_wallet_id = Some(wallet_id);
-            Ok(())
-        } else {
-            Err(WalletError::WalletNotFound(format!(
-                "Wallet {wallet_id} not found"
+    /// Find a loaded wallet by id or name
+    pub fn find_wallet(&self, id_or_name: &str) -> Result<WalletId> {
+        let loaded = self.list_wallet_ids();
+        if let Some(wallet_id) = loaded
+            .iter()
+            .find(|wallet_id| wallet_id.to_string() == id_or_name)
+        {
+            return Ok(wallet_id.clone());
+        }
+
+        // Names come from the database so a lookup never waits on a busy wallet
+        Database::global()?
+            .wallets
+            .get_all(None)?
+            .into_iter()
+            .find(|metadata| metadata.name == id_or_name && loaded.contains(&metadata.id))
+            .map(|metadata| metadata.id)
+            .ok_or(WalletError::WalletNotFound(format!(
+                "Wallet {id_or_name} not found"
             )))
+    }
+
+    pub fn list_wallet_ids(&self) -> Vec<WalletId> {
+        self.read().keys().cloned().collect()
+    }
+
+    /// Select a loaded wallet, saved to the global config
+    pub fn set_active_wallet(&self, wallet_id: WalletId) -> Result<()> {
+        if !self.read().contains_key(&wallet_id) {
+            return Err(WalletError::WalletNotFound(format!(
+                "Wallet {wallet_id} not found"
+            )));
         }
+
+        Database::global()?
+            .global_config
+            .select_wallet(&wallet_id)?;
+        Ok(())
     }
 
-    pub fn active_wallet(&self) -> Result<&Wallet> {
+    /// The selected wallet, if it's loaded
+    pub fn active_wallet_id(&self) -> Result<Option<WalletId>> {
+        let selected = Database::global()?.global_config.selected_wallet()?;
+        Ok(selected.filter(|wallet_id| self.read().contains_key(wallet_id)))
+    }
+
+    pub fn active_wallet(&self) -> Result<WalletHandle> {
         let wallet_id = self
-            .active_wallet_id
-            .as_ref()
+            .active_wallet_id()?
             .ok_or(WalletError::Generic("No active wallet".to_string()))?;
-        self.wallets
-            .get(wallet_id)
-            .ok_or(WalletError::WalletNotFound(format!(
-                "Active wallet {wallet_id} not found"
-            )))
+        self.wallet(&wallet_id)
     }
 
-    pub fn list_wallet_ids(&self) -> Vec<WalletId> {
-        self.wallets.keys().cloned().collect()
+    /// Sync one wallet, dropping the scan if `cancel` fires first
+    ///
+    /// Chain data is only applied once a scan completes, so a cancelled sync
+    /// leaves the wallet as it was.
+    pub async fn sync(&self, wallet_id: &WalletId, cancel: &CancelToken) -> Result<()> {
+        let wallet = self.wallet(wallet_id)?;
+        let mut wallet = wallet.lock().await;
+        self.sync_locked(&mut wallet, cancel).await
+    }
+
+    /// Sync a wallet the caller has locked, publishing what changed
+    async fn sync_locked(&self, wallet: &mut Wallet, cancel: &CancelToken) -> Result<()> {
+        let before = WalletSnapshot::of(wallet);
+        self.publish(&wallet.id, WalletEventKind::SyncStarted);
+
+        let result = wallet.sync_with(ProgressReporter::silent(), cancel).await;
+
+        match &result {
+            Ok(()) => {
+                let after = WalletSnapshot::of(wallet);
+                for kind in before.changes(&after, &self.confirmation_depths) {
+                    self.publish(&wallet.id, kind);
+                }
+                self.publish(&wallet.id, WalletEventKind::SyncFinished);
+            }
+            Err(e) => self.publish(
+                &wallet.id,
+                WalletEventKind::SyncFailed {
+                    error: e.to_string(),
+                },
+            ),
+        }
+        result
+    }
+
+    fn publish(&self, wallet_id: &WalletId, kind: WalletEventKind) {
+        // No subscribers isn't an error, the event just has nobody to go to
+        let _ = self.events.send(WalletEvent {
+            wallet_id: wallet_id.clone(),
+            kind,
+        });
+    }
+
+    /// Sync every loaded wallet at the same time
+    pub async fn sync_all(&self, cancel: &CancelToken) -> Vec<(WalletId, Result<()>)> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for wallet_id in self.list_wallet_ids() {
+            let manager = self.clone();
+            let cancel = cancel.clone();
+            tasks.spawn(async move {
+                let result = manager.sync(&wallet_id, &cancel).await;
+                (wallet_id, result)
+            });
+        }
+
+        let mut results = Vec::with_capacity(tasks.len());
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok(result) => results.push(result),
+                Err(e) => tracing::warn!("Wallet sync task failed: {e}"),
+            }
+        }
+        results
+    }
+
+    pub async fn balance(&self, wallet_id: &WalletId) -> Result<Balance> {
+        Ok(self.wallet(wallet_id)?.lock().await.balance())
+    }
+
+    pub async fn has_unconfirmed(&self, wallet_id: &WalletId) -> Result<bool> {
+        Ok(self.wallet(wallet_id)?.lock().await.has_unconfirmed())
+    }
+
+    pub async fn get_transactions(&self, wallet_id: &WalletId) -> Result<Vec<Transaction>> {
+        self.wallet(wallet_id)?.lock().await.transactions()
+    }
+
+    /// Reveal and save the next receive address
+    pub async fn new_address(&self, wallet_id: &WalletId) -> Result<Address> {
+        let wallet = self.wallet(wallet_id)?;
+        let mut wallet = wallet.lock().await;
+        let address = wallet.get_new_address()?;
+        wallet.persist()?;
+        Ok(address)
+    }
+
+    pub async fn current_address(&self, wallet_id: &WalletId) -> Result<Address> {
+        self.wallet(wallet_id)?.lock().await.get_current_address()
+    }
+
+    /// Sync, build, sign and broadcast a payment
+    ///
+    /// `cancel` only stops the sync, once a transaction is signed it's broadcast.
+    pub async fn send(
+        &self,
+        wallet_id: &WalletId,
+        recipient: Address,
+        amount: Amount,
+        fee_rate: FeeRate,
+        cancel: &CancelToken,
+    ) -> Result<SendOutcome> {
+        let wallet = self.wallet(wallet_id)?;
+        let mut wallet = wallet.lock().await;
+
+        // Spend from the latest UTXOs, not the last background sync
+        self.sync_locked(&mut wallet, cancel).await?;
+        let mut psbt = wallet.build_transaction(recipient, amount, fee_rate)?;
+
+        if !wallet.sign_psbt(&mut psbt)? {
+            if wallet.metadata.multisig.is_none() {
+                return Err(WalletError::Psbt(
+                    "Transaction could not be finalized".to_string(),
+                ));
+            }
+            return Ok(SendOutcome::Pending(psbt));
+        }
+
+        let transaction = wallet.extract_transaction(psbt)?;
+        let txid = transaction.compute_txid();
+        wallet.broadcast_transaction(transaction).await?;
+        Ok(SendOutcome::Broadcast(txid))
+    }
+
+    /// Sync, then pay through a BIP78 payjoin endpoint
+    ///
+    /// Falls back to broadcasting the plain transaction if the payjoin fails.
+    pub async fn send_payjoin(
+        &self,
+        wallet_id: &WalletId,
+        recipient: Address,
+        amount: Amount,
+        fee_rate: FeeRate,
+        endpoint: &str,
+        cancel: &CancelToken,
+    ) -> Result<PayjoinOutcome> {
+        let wallet = self.wallet(wallet_id)?;
+        let mut wallet = wallet.lock().await;
+
+        self.sync_locked(&mut wallet, cancel).await?;
+        wallet
+            .send_payjoin(recipient, amount, fee_rate, endpoint)
+            .await
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<WalletId, WalletHandle>> {
+        self.wallets.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<WalletId, WalletHandle>> {
+        self.wallets.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wallets_are_locked_separately() {
+        let (first, _) = Wallet::new_random("Manager First".to_string(), Network::Regtest).unwrap();
+        let (second, _) =
+            Wallet::new_random("Manager Second".to_string(), Network::Regtest).unwrap();
+
+        let manager = WalletManager::new();
+        let first_id = manager.add_wallet(first).unwrap();
+        let second_id = manager.add_wallet(second).unwrap();
+
+        // A clone shares the same wallets
+        let shared = manager.clone();
+        assert_eq!(shared.find_wallet("Manager Second").unwrap(), second_id);
+        assert_eq!(shared.find_wallet(&first_id.to_string()).unwrap(), first_id);
+
+        // Holding one wallet doesn't block calls on another
+        let first = manager.wallet(&first_id).unwrap();
+        let _busy = first.lock().await;
+        assert!(shared.new_address(&second_id).await.is_ok());
+        assert!(first.try_lock().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_sync_and_unknown_wallet() {
+        let (wallet, _) =
+            Wallet::new_random("Manager Cancel".to_string(), Network::Regtest).unwrap();
+        let manager = WalletManager::new();
+        let wallet_id = manager.add_wallet(wallet).unwrap();
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        assert!(matches!(
+            manager.sync(&wallet_id, &cancel).await,
+            Err(WalletError::Cancelled)
+        ));
+        assert!(matches!(
+            manager.sync_all(&cancel).await.as_slice(),
+            [(_, Err(WalletError::Cancelled))]
+        ));
+
+        let unknown = WalletId::new();
+        assert!(matches!(
+            manager.balance(&unknown).await,
+            Err(WalletError::WalletNotFound(_))
+        ));
+        assert!(matches!(
+            manager.set_active_wallet(unknown),
+            Err(WalletError::WalletNotFound(_))
+        ));
     }
 }